@@ -11,13 +11,14 @@
 //! - `wharf build` - Compile zone files and artifacts
 //! - `wharf moor <yacht>` - Connect to a yacht and sync state
 //! - `wharf state` - State management (freeze, thaw, diff)
-//! - `wharf sec` - Security operations (audit, rotate-keys, gen-firewall)
+//! - `wharf sec` - Security operations (audit, audit-log, rotate-keys, gen-firewall)
 //! - `wharf gen-keys` - Generate cryptographic keys (DKIM, SSH, TLS)
 //! - `wharf db` - Database configuration commands
+//! - `wharf policy` - Push, roll back and list yacht policy versions
 
 use clap::{Args, Parser, Subcommand};
 use std::path::PathBuf;
-use tracing::{info, warn, Level};
+use tracing::{info, Level};
 use tracing_subscriber::FmtSubscriber;
 
 mod ops;
@@ -154,6 +155,9 @@ enum Commands {
     /// Fleet management commands
     Fleet(FleetArgs),
 
+    /// Yacht policy versions (push, rollback, history)
+    Policy(PolicyArgs),
+
     /// Container management commands
     Container(ContainerArgs),
 
@@ -280,6 +284,35 @@ enum SecCommands {
         /// Path to manifest file
         #[arg(long)]
         manifest: Option<String>,
+
+        /// Ask the yacht agent to verify (signed request) instead of using SSH
+        #[arg(long)]
+        api: bool,
+
+        /// Captain name the request is signed as (must be in the agent's trust store)
+        #[arg(long, default_value = ops::captain::DEFAULT_CAPTAIN)]
+        captain: String,
+    },
+
+    /// Create the captain key that signs mooring commands
+    CaptainKey {
+        /// Replace an existing key
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Fetch a yacht's security audit log and verify its hash chain
+    AuditLog {
+        /// Target yacht
+        target: String,
+
+        /// Verify a local copy of the log instead of fetching it
+        #[arg(long)]
+        file: Option<PathBuf>,
+
+        /// Save the fetched log to this path
+        #[arg(short, long)]
+        output: Option<PathBuf>,
     },
 
     /// Scan for vulnerabilities
@@ -398,6 +431,87 @@ enum FleetCommands {
         #[arg(default_value = "all")]
         name: String,
     },
+
+    /// Follow the live security events of the fleet
+    Watch {
+        /// Yacht name (or 'all')
+        #[arg(default_value = "all")]
+        name: String,
+
+        /// Only show these event types (e.g. query_blocked,policy_change)
+        #[arg(long = "type", value_delimiter = ',')]
+        types: Vec<String>,
+    },
+
+    /// Write the yacht agent config file for a yacht (path rules from adapters/)
+    AgentConfig {
+        /// Yacht name/ID
+        name: String,
+
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+// =============================================================================
+// POLICY COMMANDS
+// =============================================================================
+
+#[derive(Args)]
+struct PolicyArgs {
+    #[command(subcommand)]
+    command: PolicyCommands,
+}
+
+#[derive(Subcommand)]
+enum PolicyCommands {
+    /// Validate a policy bundle and push it to a yacht
+    Push {
+        /// Target yacht
+        target: String,
+
+        /// Bundle file with database and header sections (JSON, TOML or Nickel)
+        bundle: PathBuf,
+
+        /// Probation period in seconds (overrides the agent's default; 0 disables)
+        #[arg(long)]
+        grace: Option<u64>,
+
+        /// Captain name the command is signed as
+        #[arg(long, default_value = ops::captain::DEFAULT_CAPTAIN)]
+        captain: String,
+    },
+
+    /// Enforce an earlier policy version on a yacht
+    Rollback {
+        /// Target yacht
+        target: String,
+
+        /// Version (or prefix) to restore; the previous version if omitted
+        #[arg(long)]
+        to: Option<String>,
+
+        /// Captain name the command is signed as
+        #[arg(long, default_value = ops::captain::DEFAULT_CAPTAIN)]
+        captain: String,
+    },
+
+    /// List the policy versions a yacht keeps
+    History {
+        /// Target yacht
+        target: String,
+    },
+
+    /// Show the Content-Security-Policy violations browsers reported to a yacht
+    CspReports {
+        /// Target yacht
+        target: String,
+
+        /// Show at most this many violations
+        #[arg(long, default_value = "20")]
+        limit: usize,
+    },
 }
 
 // =============================================================================
@@ -610,7 +724,7 @@ async fn main() -> anyhow::Result<()> {
                 println!("Generating eBPF firewall for {}", arch);
                 println!("Output: {}", output);
             }
-            SecCommands::Verify { target, manifest } => {
+            SecCommands::Verify { target, manifest, api, captain } => {
                 let config_dir = PathBuf::from(&cli.config);
 
                 // Determine manifest path
@@ -665,19 +779,31 @@ async fn main() -> anyhow::Result<()> {
                         .ok_or_else(|| anyhow::anyhow!("Yacht '{}' not found in fleet", target))?;
 
                     println!("Verifying remote yacht: {}", target);
-                    println!("Host: {}@{}:{}", yacht.ssh_user, yacht.ip, yacht.ssh_port);
+                    if api {
+                        println!("Agent: {}", yacht.agent_url());
+                    } else {
+                        println!("Host: {}@{}:{}", yacht.ssh_user, yacht.ip, yacht.ssh_port);
+                    }
                     println!("Remote root: {}", yacht.web_root);
                     println!("Using manifest: {:?}", manifest_path);
                     println!();
 
-                    match ops::integrity::verify_remote(
-                        &manifest_path,
-                        &yacht.ssh_user,
-                        &yacht.ip,
-                        yacht.ssh_port,
-                        &yacht.web_root,
-                        None, // TODO: Support identity file from config
-                    ) {
+                    let result = if api {
+                        let key = ops::captain::load_key(&ops::captain::key_path(&config_dir))?;
+                        let client = ops::agent::client(&config_dir)?;
+                        ops::integrity::verify_remote_api(&client, &manifest_path, yacht, &captain, &key).await
+                    } else {
+                        ops::integrity::verify_remote(
+                            &manifest_path,
+                            &yacht.ssh_user,
+                            &yacht.ip,
+                            yacht.ssh_port,
+                            &yacht.web_root,
+                            None, // TODO: Support identity file from config
+                        )
+                    };
+
+                    match result {
                         Ok(result) => {
                             println!();
                             if result.is_ok() {
@@ -711,6 +837,49 @@ async fn main() -> anyhow::Result<()> {
                     }
                 }
             }
+            SecCommands::CaptainKey { force } => {
+                let path = ops::captain::key_path(&PathBuf::from(&cli.config));
+                let key = ops::captain::generate_key(&path, force)?;
+
+                println!("✓ Captain key written to {}", path.display());
+                println!();
+                println!("Add the public key to each yacht agent's trust store:");
+                println!();
+                println!("  [captains]");
+                println!("  {} = \"{}\"", ops::captain::DEFAULT_CAPTAIN, ops::captain::public_key_hex(&key));
+            }
+            SecCommands::AuditLog { target, file, output } => {
+                let config_dir = PathBuf::from(&cli.config);
+
+                let content = match &file {
+                    Some(path) => std::fs::read_to_string(path)?,
+                    None => {
+                        let fleet = ops::fleet::load_fleet(&config_dir.join("fleet.json"))?;
+                        let yacht = fleet.get_yacht(&target)
+                            .ok_or_else(|| anyhow::anyhow!("Yacht '{}' not found in fleet", target))?;
+                        println!("Fetching audit log from {}", yacht.agent_url());
+                        ops::audit::fetch_log(&ops::agent::client(&config_dir)?, yacht).await?
+                    }
+                };
+
+                if let Some(path) = &output {
+                    std::fs::write(path, &content)?;
+                    println!("Saved audit log to {:?}", path);
+                }
+
+                let pin_path = ops::audit::pin_path(&config_dir, &target);
+                match ops::audit::verify_and_pin(&content, &pin_path) {
+                    Ok(summary) => {
+                        println!("✓ Audit log chain VERIFIED");
+                        println!("  {} records", summary.records);
+                        println!("  Head: {}", summary.head.hash);
+                    }
+                    Err(e) => {
+                        eprintln!("✗ Audit log verification FAILED: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
             SecCommands::Scan { target, scan_type } => {
                 println!("Scanning {} for vulnerabilities (type: {})", target, scan_type);
             }
@@ -772,6 +941,95 @@ async fn main() -> anyhow::Result<()> {
                     let fleet = ops::fleet::load_fleet(&fleet_path)?;
                     ops::fleet::show_status(&fleet, &name);
                 }
+                FleetCommands::Watch { name, types } => {
+                    let fleet = ops::fleet::load_fleet(&fleet_path)?;
+                    let yachts: Vec<_> = if name == "all" {
+                        fleet.list_enabled().into_iter().cloned().collect()
+                    } else {
+                        let yacht = fleet.get_yacht(&name)
+                            .ok_or_else(|| anyhow::anyhow!("Yacht '{}' not found in fleet", name))?;
+                        vec![yacht.clone()]
+                    };
+                    println!("Watching {} yachts (Ctrl-C to stop)", yachts.len());
+                    ops::watch::watch(ops::agent::client(&config_dir)?, yachts, types).await?;
+                }
+                FleetCommands::AgentConfig { name, output } => {
+                    let fleet = ops::fleet::load_fleet(&fleet_path)?;
+                    let config = ops::fleet::agent_config(&fleet, &name, &config_dir.join("adapters"))?;
+                    match output {
+                        Some(path) => {
+                            std::fs::write(&path, config)
+                                .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", path.display(), e))?;
+                            println!("✓ Wrote agent config for '{}' to {}", name, path.display());
+                        }
+                        None => print!("{}", config),
+                    }
+                }
+            }
+        }
+
+        Commands::Policy(args) => {
+            let config_dir = PathBuf::from(&cli.config);
+            let fleet = ops::fleet::load_fleet(&config_dir.join("fleet.json"))?;
+            let target = match &args.command {
+                PolicyCommands::Push { target, .. }
+                | PolicyCommands::Rollback { target, .. }
+                | PolicyCommands::History { target }
+                | PolicyCommands::CspReports { target, .. } => target.clone(),
+            };
+            let yacht = fleet.get_yacht(&target)
+                .ok_or_else(|| anyhow::anyhow!("Yacht '{}' not found in fleet", target))?;
+            let client = ops::agent::client(&config_dir)?;
+
+            match args.command {
+                PolicyCommands::Push { bundle, grace, captain, .. } => {
+                    let bundle = ops::policy::load_bundle(&bundle)?;
+                    println!("✓ Policy bundle is valid (version {})", bundle.version());
+
+                    let key = ops::captain::load_key(&ops::captain::key_path(&config_dir))?;
+                    let result = ops::policy::push(&client, yacht, bundle, grace, &captain, &key).await?;
+                    println!("✓ Policy pushed to {}", yacht.name);
+                    println!("  Version: {}", result["version"].as_str().unwrap_or_default());
+                    match result["probation_secs"].as_u64().unwrap_or_default() {
+                        0 => println!("  No probation - roll back with 'wharf policy rollback {}'", yacht.name),
+                        secs => println!("  On probation for {}s (rolled back if the site stops answering)", secs),
+                    }
+                }
+                PolicyCommands::Rollback { to, captain, .. } => {
+                    let key = ops::captain::load_key(&ops::captain::key_path(&config_dir))?;
+                    let result = ops::policy::rollback(&client, yacht, to, &captain, &key).await?;
+                    println!("✓ {} rolled back", yacht.name);
+                    println!("  Version: {}", result["version"].as_str().unwrap_or_default());
+                }
+                PolicyCommands::History { .. } => {
+                    let versions = ops::policy::history(&client, yacht).await?;
+                    println!("Policy versions on {} (newest first):", yacht.name);
+                    for (i, entry) in versions.iter().enumerate() {
+                        let applied = chrono::DateTime::from_timestamp(entry.applied_at, 0)
+                            .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+                            .unwrap_or_default();
+                        let marker = if i == 0 { "*" } else { " " };
+                        println!("{} {}  {}  {}", marker, &entry.version[..entry.version.len().min(16)], applied, entry.source);
+                    }
+                }
+                PolicyCommands::CspReports { limit, .. } => {
+                    let csp = ops::policy::csp_reports(&client, yacht).await?;
+                    if csp.reports.is_empty() {
+                        println!("No CSP violations reported to {}", yacht.name);
+                        return Ok(());
+                    }
+                    println!("CSP violations on {} ({} reported):", yacht.name, csp.received);
+                    println!("{:>8}  {:<24} {:<40} LAST PAGE", "COUNT", "DIRECTIVE", "BLOCKED");
+                    for report in csp.reports.iter().take(limit) {
+                        println!("{:>8}  {:<24} {:<40} {}", report.count, report.directive, report.blocked_uri, report.document_uri);
+                    }
+                    if csp.reports.len() > limit {
+                        println!("  ... and {} more", csp.reports.len() - limit);
+                    }
+                    if csp.untracked > 0 {
+                        println!("  {} further violations were not broken down", csp.untracked);
+                    }
+                }
             }
         }
 
@@ -0,0 +1,107 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Agent API Client
+//!
+//! The HTTP client used to talk to yacht agents. Agents that serve their
+//! API over TLS with a client certificate allowlist need two files in
+//! `<config>/keys/`:
+//!
+//! - `agent-ca.pem`: the CA (or self-signed certificate) the agents' API
+//!   certificates chain to
+//! - `api-client.pem`: the Wharf's client certificate and private key
+//!
+//! Both are optional; without them the client speaks plain HTTP or
+//! public-CA TLS.
+//!
+//! Mooring commands are signed with the captain key and posted to the
+//! agent's `/moor` endpoint.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use wharf_core::crypto::SigningKey;
+use wharf_core::fleet::Yacht;
+use wharf_core::mooring::{CommandEnvelope, MooringCommand};
+
+/// How long a signed command stays valid
+const COMMAND_LIFETIME_SECS: i64 = 60;
+
+/// Where the agent CA certificate is kept
+pub fn ca_path(config_dir: &Path) -> PathBuf {
+    config_dir.join("keys").join("agent-ca.pem")
+}
+
+/// Where the Wharf's API client certificate and key are kept
+pub fn identity_path(config_dir: &Path) -> PathBuf {
+    config_dir.join("keys").join("api-client.pem")
+}
+
+/// Build an agent API client from the files under `config_dir`
+pub fn client(config_dir: &Path) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder();
+
+    let ca = ca_path(config_dir);
+    if ca.exists() {
+        let pem = std::fs::read(&ca).with_context(|| format!("Failed to read {}", ca.display()))?;
+        for cert in reqwest::Certificate::from_pem_bundle(&pem).context("Invalid agent CA certificate")? {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+
+    let identity = identity_path(config_dir);
+    if identity.exists() {
+        let pem = std::fs::read(&identity).with_context(|| format!("Failed to read {}", identity.display()))?;
+        builder = builder.identity(reqwest::Identity::from_pem(&pem).context("Invalid API client certificate")?);
+    }
+
+    builder.build().context("Failed to build agent API client")
+}
+
+/// Sign a command for `yacht`, send it, and return the agent's result
+pub async fn send_command(
+    client: &reqwest::Client,
+    yacht: &Yacht,
+    command: MooringCommand,
+    captain: &str,
+    key: &SigningKey,
+) -> Result<Value> {
+    let name = command.name();
+    let signed = CommandEnvelope::new(&yacht.name, command, COMMAND_LIFETIME_SECS)
+        .sign(captain, key)
+        .context("Failed to sign command")?;
+
+    let url = format!("{}/moor", yacht.agent_url());
+    let response = client
+        .post(&url)
+        .json(&signed)
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach agent at {}", url))?;
+
+    let status = response.status();
+    let body: Value = response.json().await.unwrap_or_default();
+    if !status.is_success() {
+        let error = body["error"].as_str().unwrap_or("no details");
+        anyhow::bail!("Agent refused '{}' ({}): {}", name, status, error);
+    }
+    Ok(body["result"].clone())
+}
+
+/// GET a JSON document from the agent
+pub async fn get_json<T: DeserializeOwned>(client: &reqwest::Client, yacht: &Yacht, path: &str) -> Result<T> {
+    let url = format!("{}{}", yacht.agent_url(), path);
+    client
+        .get(&url)
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach agent at {}", url))?
+        .error_for_status()
+        .with_context(|| format!("Agent at {} refused the request", url))?
+        .json()
+        .await
+        .with_context(|| format!("Unexpected response from {}", url))
+}
@@ -0,0 +1,63 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Audit Log Operations
+//!
+//! Fetch a yacht's security audit log and verify its hash chain. The last
+//! verified record is pinned under `<config>/audit/<yacht>.head`, so a log
+//! that was truncated or rewritten since the previous check is caught even
+//! though its chain is internally consistent.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use wharf_core::audit::{self, ChainPin, ChainSummary};
+use wharf_core::fleet::Yacht;
+
+/// Download the raw audit log from a yacht agent
+pub async fn fetch_log(client: &reqwest::Client, yacht: &Yacht) -> Result<String> {
+    let url = format!("{}/audit/log", yacht.agent_url());
+    let response = client
+        .get(&url)
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach agent at {}", url))?
+        .error_for_status()
+        .with_context(|| format!("Agent at {} refused the audit log request", url))?;
+    response.text().await.context("Failed to read audit log body")
+}
+
+/// Where the pin for a yacht's log is kept
+pub fn pin_path(config_dir: &Path, yacht: &str) -> PathBuf {
+    config_dir.join("audit").join(format!("{}.head", yacht))
+}
+
+/// Load the pin from the last successful verification, if any
+pub fn load_pin(path: &Path) -> Result<Option<ChainPin>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(path).context("Failed to read audit pin")?;
+    let pin = serde_json::from_str(&content).context("Failed to parse audit pin")?;
+    Ok(Some(pin))
+}
+
+/// Verify a log against the stored pin and move the pin to the new head
+pub fn verify_and_pin(content: &str, pin_path: &Path) -> Result<ChainSummary> {
+    let pin = load_pin(pin_path)?;
+    let summary = audit::verify_chain(content, pin.as_ref())?;
+
+    if summary.records > 0 {
+        let pin = ChainPin {
+            seq: summary.records - 1,
+            hash: summary.head.hash.clone(),
+        };
+        if let Some(parent) = pin_path.parent() {
+            std::fs::create_dir_all(parent).context("Failed to create audit pin directory")?;
+        }
+        std::fs::write(pin_path, serde_json::to_string_pretty(&pin)?).context("Failed to write audit pin")?;
+    }
+
+    Ok(summary)
+}
@@ -0,0 +1,61 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Captain Key
+//!
+//! The Ed25519 key the Wharf signs mooring commands with. It is kept as a
+//! hex secret key in `<config>/keys/captain.key` (mode 0600); each yacht
+//! lists the matching public key in its agent's trust store.
+
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use wharf_core::crypto::{self, SigningKey};
+
+/// Captain name used when none is given
+pub const DEFAULT_CAPTAIN: &str = "wharf";
+
+/// Where the captain key is kept
+pub fn key_path(config_dir: &Path) -> PathBuf {
+    config_dir.join("keys").join("captain.key")
+}
+
+/// Load the captain key
+pub fn load_key(path: &Path) -> Result<SigningKey> {
+    let content = std::fs::read_to_string(path).with_context(|| {
+        format!(
+            "Failed to read captain key {}. Run 'wharf sec captain-key' to create one.",
+            path.display()
+        )
+    })?;
+    crypto::decode_signing_key(&content).context("Invalid captain key")
+}
+
+/// Create a new captain key, refusing to replace one unless `force` is set
+pub fn generate_key(path: &Path, force: bool) -> Result<SigningKey> {
+    if path.exists() && !force {
+        anyhow::bail!("Captain key {} already exists (use --force to replace it)", path.display());
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).context("Failed to create key directory")?;
+    }
+
+    let key = crypto::generate_signing_key();
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("Failed to write captain key {}", path.display()))?;
+    writeln!(file, "{}", hex::encode(key.to_bytes()))?;
+    Ok(key)
+}
+
+/// The public half, as it goes into a trust store
+pub fn public_key_hex(key: &SigningKey) -> String {
+    hex::encode(key.verifying_key().to_bytes())
+}
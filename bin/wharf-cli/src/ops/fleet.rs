@@ -9,7 +9,11 @@
 use anyhow::{Context, Result};
 use tracing::info;
 
+use wharf_core::agent_config::AgentConfig;
 use wharf_core::fleet::{Fleet, Yacht, Adapter};
+use wharf_core::path_rules;
+use wharf_core::policy::load_policy_file;
+use wharf_core::types::PathPolicy;
 
 /// Load fleet configuration from file
 pub fn load_fleet(config_path: &Path) -> Result<Fleet> {
@@ -98,6 +102,31 @@ pub fn list_yachts(fleet: &Fleet, detailed: bool) {
     }
 }
 
+/// The yacht agent config file for a yacht, as TOML
+///
+/// The airlock's path rules are compiled from the yacht's adapter
+/// (`<adapters>/<adapter>/filesystem.ncl`) when there is one.
+pub fn agent_config(fleet: &Fleet, name: &str, adapters: &Path) -> Result<String> {
+    let yacht = fleet.get_yacht(name)
+        .ok_or_else(|| anyhow::anyhow!("Yacht '{}' not found in fleet", name))?;
+    let paths = adapter_paths(adapters, &yacht.adapter)?;
+    toml::to_string_pretty(&AgentConfig::for_yacht_with_paths(yacht, paths))
+        .context("Failed to serialize agent configuration")
+}
+
+/// The path rules of an adapter's filesystem policy; the defaults if it has none
+pub fn adapter_paths(adapters: &Path, adapter: &Adapter) -> Result<PathPolicy> {
+    let path = adapters.join(adapter.name()).join("filesystem.ncl");
+    if !path.exists() {
+        info!("No filesystem policy at {}, using the default path rules", path.display());
+        return Ok(PathPolicy::default());
+    }
+    let filesystem: serde_json::Value = load_policy_file(&path)
+        .with_context(|| format!("Failed to load {}", path.display()))?;
+    path_rules::from_adapter(&filesystem)
+        .with_context(|| format!("Failed to compile path rules from {}", path.display()))
+}
+
 /// Show status of a specific yacht or all yachts
 pub fn show_status(fleet: &Fleet, name: &str) {
     if name == "all" {
@@ -9,6 +9,8 @@
 use anyhow::{Context, Result};
 use tracing::info;
 
+use wharf_core::crypto::SigningKey;
+use wharf_core::fleet::Yacht;
 use wharf_core::integrity::{self, Manifest, VerifyResult};
 
 /// Generate an integrity manifest for a directory
@@ -112,7 +114,34 @@ pub fn verify_remote(
         identity_file,
     ).context("Remote verification failed")?;
 
-    // Report results
+    report_remote(&result);
+    Ok(result)
+}
+
+/// Verify a remote yacht's file integrity through its agent API
+pub async fn verify_remote_api(
+    client: &reqwest::Client,
+    manifest_path: &Path,
+    yacht: &Yacht,
+    captain: &str,
+    key: &SigningKey,
+) -> Result<integrity::RemoteVerifyResult> {
+    info!("Verifying remote yacht {} via agent API", yacht.name);
+
+    let manifest = integrity::load_manifest(manifest_path)
+        .context("Failed to load manifest")?;
+
+    info!("Loaded manifest with {} files", manifest.files.len());
+
+    let result = integrity::verify_remote_api(client, &yacht.agent_url(), &yacht.name, &manifest, captain, key)
+        .await
+        .context("Remote verification failed")?;
+
+    report_remote(&result);
+    Ok(result)
+}
+
+fn report_remote(result: &integrity::RemoteVerifyResult) {
     if result.passed {
         info!("Remote verification PASSED");
         info!("  {} files verified", result.files_checked);
@@ -137,6 +166,4 @@ pub fn verify_remote(
             }
         }
     }
-
-    Ok(result)
 }
@@ -8,3 +8,8 @@
 pub mod moor;
 pub mod integrity;
 pub mod fleet;
+pub mod agent;
+pub mod audit;
+pub mod captain;
+pub mod policy;
+pub mod watch;
@@ -10,7 +10,7 @@
 use tracing::{info, warn};
 
 use wharf_core::fleet::{Fleet, Yacht};
-use wharf_core::integrity::{generate_manifest, save_manifest, verify_manifest};
+use wharf_core::integrity::{generate_manifest, save_manifest};
 use wharf_core::sync::{sync_to_remote, check_rsync, check_ssh_connection, SyncConfig};
 
 /// Options for the mooring process
@@ -0,0 +1,89 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Policy Operations
+//!
+//! Push policy bundles to yachts, roll them back, list the versions an
+//! agent keeps, and read the CSP violations its airlock has collected. A bundle is a JSON, TOML or Nickel file with a `database`
+//! and a `header` section; it is validated here before it is signed, and
+//! again by the agent before it is enforced.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+use wharf_core::crypto::SigningKey;
+use wharf_core::csp::CspReportSummary;
+use wharf_core::fleet::Yacht;
+use wharf_core::mooring::MooringCommand;
+use wharf_core::policy::{load_policy_file, PolicyBundle};
+
+use super::agent;
+
+/// One entry of an agent's `/policy/history`
+#[derive(Debug, Deserialize)]
+pub struct HistoryEntry {
+    pub version: String,
+    pub applied_at: i64,
+    pub source: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct History {
+    versions: Vec<HistoryEntry>,
+}
+
+/// An agent's `/csp/reports`
+#[derive(Debug, Deserialize)]
+pub struct CspReports {
+    /// Violations reported since the agent started
+    pub received: u64,
+    /// Violations not broken down (the agent's table was full)
+    pub untracked: u64,
+    /// Most reported first
+    pub reports: Vec<CspReportSummary>,
+}
+
+/// Load a bundle and check it
+pub fn load_bundle(path: &Path) -> Result<PolicyBundle> {
+    let bundle: PolicyBundle =
+        load_policy_file(path).with_context(|| format!("Failed to load policy bundle {}", path.display()))?;
+    bundle.validate()?;
+    Ok(bundle)
+}
+
+/// Push a bundle; `grace_secs` overrides the agent's probation period
+pub async fn push(
+    client: &reqwest::Client,
+    yacht: &Yacht,
+    bundle: PolicyBundle,
+    grace_secs: Option<u64>,
+    captain: &str,
+    key: &SigningKey,
+) -> Result<Value> {
+    agent::send_command(client, yacht, MooringCommand::PushPolicy { bundle: Box::new(bundle), grace_secs }, captain, key).await
+}
+
+/// Roll back to `version` (a hash or a prefix), or to the previous version
+pub async fn rollback(
+    client: &reqwest::Client,
+    yacht: &Yacht,
+    version: Option<String>,
+    captain: &str,
+    key: &SigningKey,
+) -> Result<Value> {
+    agent::send_command(client, yacht, MooringCommand::RollbackPolicy { version }, captain, key).await
+}
+
+/// The versions the agent keeps, newest (enforced) first
+pub async fn history(client: &reqwest::Client, yacht: &Yacht) -> Result<Vec<HistoryEntry>> {
+    let history: History = agent::get_json(client, yacht, "/policy/history").await?;
+    Ok(history.versions)
+}
+
+/// The CSP violations reported to the yacht's airlock
+pub async fn csp_reports(client: &reqwest::Client, yacht: &Yacht) -> Result<CspReports> {
+    agent::get_json(client, yacht, "/csp/reports").await
+}
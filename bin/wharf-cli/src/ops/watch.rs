@@ -0,0 +1,121 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Fleet Watch
+//!
+//! Follows the `/events` feed of every yacht at once and prints one line
+//! per security event. A yacht that drops off is retried with backoff, and
+//! events missed in the meantime (a gap in the sequence numbers) are
+//! reported rather than silently skipped.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use tokio::sync::mpsc;
+
+use wharf_core::events::{EventRecord, EventStreamDecoder};
+use wharf_core::fleet::Yacht;
+
+/// Longest wait between reconnection attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// What a yacht's feed reports to the printer
+enum FeedItem {
+    Connected,
+    Event(EventRecord),
+    Missed(u64),
+    Disconnected(String),
+}
+
+/// Watch `yachts` until interrupted, printing events of the given types (all if empty)
+pub async fn watch(client: reqwest::Client, yachts: Vec<Yacht>, types: Vec<String>) -> Result<()> {
+    if yachts.is_empty() {
+        anyhow::bail!("No yachts to watch");
+    }
+
+    let (sender, mut receiver) = mpsc::channel(256);
+    for yacht in yachts {
+        tokio::spawn(follow(client.clone(), yacht, sender.clone()));
+    }
+    drop(sender);
+
+    loop {
+        tokio::select! {
+            item = receiver.recv() => match item {
+                Some((yacht, item)) => print_item(&yacht, item, &types),
+                None => return Ok(()),
+            },
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+    }
+}
+
+fn print_item(yacht: &str, item: FeedItem, types: &[String]) {
+    let now = chrono::Local::now().format("%H:%M:%S");
+    match item {
+        FeedItem::Connected => println!("{} {:<16} connected", now, yacht),
+        FeedItem::Missed(count) => println!("{} {:<16} ! {} events missed", now, yacht, count),
+        FeedItem::Disconnected(reason) => println!("{} {:<16} disconnected: {}", now, yacht, reason),
+        FeedItem::Event(record) => {
+            let name = record.event.name();
+            if types.is_empty() || types.iter().any(|t| t == name) {
+                println!("{} {:<16} {:<20} {}", now, yacht, name, record.event);
+            }
+        }
+    }
+}
+
+/// Keep one yacht's feed open, reconnecting when it drops
+async fn follow(client: reqwest::Client, yacht: Yacht, sender: mpsc::Sender<(String, FeedItem)>) {
+    let mut backoff = Duration::from_secs(1);
+    let mut last_seq = None;
+
+    loop {
+        let result = stream_events(&client, &yacht, &sender, &mut last_seq, &mut backoff).await;
+        let reason = match result {
+            Ok(()) => "stream ended".to_string(),
+            Err(e) => format!("{:#}", e),
+        };
+        if sender.send((yacht.name.clone(), FeedItem::Disconnected(reason))).await.is_err() {
+            return;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn stream_events(
+    client: &reqwest::Client,
+    yacht: &Yacht,
+    sender: &mpsc::Sender<(String, FeedItem)>,
+    last_seq: &mut Option<u64>,
+    backoff: &mut Duration,
+) -> Result<()> {
+    let url = format!("{}/events", yacht.agent_url());
+    let mut response = client
+        .get(&url)
+        .header(reqwest::header::ACCEPT, "text/event-stream")
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach agent at {}", url))?
+        .error_for_status()
+        .with_context(|| format!("Agent at {} refused the event feed", url))?;
+
+    *backoff = Duration::from_secs(1);
+    let _ = sender.send((yacht.name.clone(), FeedItem::Connected)).await;
+
+    let mut decoder = EventStreamDecoder::new();
+    while let Some(chunk) = response.chunk().await.context("Event feed interrupted")? {
+        for record in decoder.feed(&chunk) {
+            // A lower number means the agent restarted; only a jump forward is a gap
+            if let Some(last) = *last_seq {
+                if record.seq > last + 1 {
+                    let _ = sender.send((yacht.name.clone(), FeedItem::Missed(record.seq - last - 1))).await;
+                }
+            }
+            *last_seq = Some(record.seq);
+            let _ = sender.send((yacht.name.clone(), FeedItem::Event(record))).await;
+        }
+    }
+    Ok(())
+}
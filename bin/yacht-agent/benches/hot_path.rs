@@ -0,0 +1,128 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Query Inspection Throughput
+//!
+//! Measures queries/sec through the proxy's inspection path with an
+//! increasing number of concurrent connections, each simulated by a tokio
+//! task issuing a realistic WordPress query mix.
+//!
+//! For comparison the same workload is run against the old design, where
+//! every query took the write half of a single global `RwLock`.
+//!
+//! Run with: `cargo bench -p yacht-agent --bench hot_path`
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::RwLock;
+
+use wharf_core::db_policy::{DatabasePolicy, PolicyEngine, QueryAction};
+use yacht_agent::proxy::inspect_query;
+use yacht_agent::state::AgentState;
+
+/// Queries issued by each simulated connection
+const QUERIES_PER_CONNECTION: usize = 5_000;
+
+/// Connection counts to measure
+const CONNECTIONS: &[usize] = &[1, 2, 4, 8, 16, 32, 64];
+
+const QUERY_MIX: &[&str] = &[
+    "SELECT option_value FROM wp_options WHERE option_name = 'siteurl' LIMIT 1",
+    "SELECT * FROM wp_posts WHERE post_status = 'publish' ORDER BY post_date DESC LIMIT 10",
+    "SELECT meta_key, meta_value FROM wp_postmeta WHERE post_id IN (1, 2, 3)",
+    "INSERT INTO wp_comments (comment_post_ID, comment_content) VALUES (1, 'hello')",
+    "UPDATE wp_users SET user_pass = 'x' WHERE ID = 1",
+    "SELECT u.ID, u.user_login FROM wp_users u JOIN wp_usermeta m ON u.ID = m.user_id",
+];
+
+/// The pre-lock-free design: one global lock around engine and counters
+struct LockedState {
+    engine: PolicyEngine,
+    allowed: u64,
+    blocked: u64,
+}
+
+async fn run_lock_free(state: Arc<AgentState>, connections: usize) -> Duration {
+    let start = Instant::now();
+    let tasks: Vec<_> = (0..connections)
+        .map(|c| {
+            let state = state.clone();
+            tokio::spawn(async move {
+                for i in 0..QUERIES_PER_CONNECTION {
+                    let query = QUERY_MIX[(c + i) % QUERY_MIX.len()];
+                    std::hint::black_box(inspect_query(&state, query));
+                    tokio::task::yield_now().await;
+                }
+            })
+        })
+        .collect();
+
+    for task in tasks {
+        task.await.expect("benchmark task panicked");
+    }
+    start.elapsed()
+}
+
+async fn run_global_lock(state: Arc<RwLock<LockedState>>, connections: usize) -> Duration {
+    let start = Instant::now();
+    let tasks: Vec<_> = (0..connections)
+        .map(|c| {
+            let state = state.clone();
+            tokio::spawn(async move {
+                for i in 0..QUERIES_PER_CONNECTION {
+                    let query = QUERY_MIX[(c + i) % QUERY_MIX.len()];
+                    let mut guard = state.write().await;
+                    match guard.engine.analyze(query) {
+                        Ok(QueryAction::Allow) | Ok(QueryAction::Audit) => guard.allowed += 1,
+                        Ok(QueryAction::Block) | Err(_) => guard.blocked += 1,
+                    }
+                    drop(guard);
+                    tokio::task::yield_now().await;
+                }
+            })
+        })
+        .collect();
+
+    for task in tasks {
+        task.await.expect("benchmark task panicked");
+    }
+    start.elapsed()
+}
+
+fn queries_per_sec(connections: usize, elapsed: Duration) -> f64 {
+    (connections * QUERIES_PER_CONNECTION) as f64 / elapsed.as_secs_f64()
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build tokio runtime");
+
+    let workers = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    println!("Query inspection throughput ({} worker threads)", workers);
+    println!("{:>12} {:>16} {:>16} {:>9}", "connections", "lock-free q/s", "global lock q/s", "speedup");
+
+    for &connections in CONNECTIONS {
+        let state = Arc::new(AgentState::new());
+        let lock_free = runtime.block_on(run_lock_free(state, connections));
+
+        let locked = Arc::new(RwLock::new(LockedState {
+            engine: PolicyEngine::new(DatabasePolicy::default()),
+            allowed: 0,
+            blocked: 0,
+        }));
+        let global_lock = runtime.block_on(run_global_lock(locked, connections));
+
+        let lock_free_qps = queries_per_sec(connections, lock_free);
+        let global_lock_qps = queries_per_sec(connections, global_lock);
+        println!(
+            "{:>12} {:>16.0} {:>16.0} {:>8.2}x",
+            connections,
+            lock_free_qps,
+            global_lock_qps,
+            lock_free_qps / global_lock_qps
+        );
+    }
+}
@@ -0,0 +1,241 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # API Access Control
+//!
+//! Who may call which agent API route. Clients present a TLS certificate
+//! and are identified by its SHA-256 fingerprint (see `tls`); the access
+//! file names each client and the roles it holds:
+//!
+//! ```toml
+//! [clients.prometheus]
+//! # openssl x509 -in prometheus.pem -noout -fingerprint -sha256
+//! fingerprint = "3F:A4:...:9C"
+//! roles = ["metrics"]
+//!
+//! [clients.wharf]
+//! fingerprint = "..."
+//! roles = ["status", "audit", "moor"]
+//! ```
+//!
+//! A certificate that is not listed fails the TLS handshake. The health
+//! checks (`/health`, `/health/ready`, `/health/live`) need no certificate
+//! at all, so load balancer and local probes keep working.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use thiserror::Error;
+use tracing::warn;
+
+#[derive(Error, Debug)]
+pub enum AccessError {
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Access file parse error: {0}")]
+    ParseError(String),
+
+    #[error("Client '{0}': fingerprint must be 32 bytes of hex")]
+    BadFingerprint(String),
+
+    #[error("Clients '{0}' and '{1}' share a certificate")]
+    DuplicateFingerprint(String, String),
+}
+
+/// What a client is allowed to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// `/status`, `/stats`, `/policy/history` and `/csp/reports`
+    Status,
+    /// `/metrics`
+    Metrics,
+    /// `/audit/log` and `/events`
+    Audit,
+    /// `/moor` and `/verify`
+    Moor,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Status => "status",
+            Self::Metrics => "metrics",
+            Self::Audit => "audit",
+            Self::Moor => "moor",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientEntry {
+    fingerprint: String,
+    #[serde(default)]
+    roles: HashSet<Role>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AccessFile {
+    #[serde(default)]
+    clients: HashMap<String, ClientEntry>,
+}
+
+/// A known API client
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub name: String,
+    pub roles: HashSet<Role>,
+}
+
+/// The client certificate allowlist and the roles of each client
+#[derive(Debug, Default)]
+pub struct AccessPolicy {
+    /// Keyed by certificate fingerprint (lowercase hex)
+    clients: HashMap<String, Client>,
+}
+
+impl AccessPolicy {
+    /// Load an access file (TOML if the extension says so, JSON otherwise)
+    pub fn load(path: &Path) -> Result<Self, AccessError> {
+        let content = std::fs::read_to_string(path)?;
+        let file: AccessFile = match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => toml::from_str(&content).map_err(|e| AccessError::ParseError(e.to_string()))?,
+            _ => serde_json::from_str(&content).map_err(|e| AccessError::ParseError(e.to_string()))?,
+        };
+        Self::from_file(file)
+    }
+
+    fn from_file(file: AccessFile) -> Result<Self, AccessError> {
+        let mut policy = Self::default();
+        for (name, entry) in file.clients {
+            let fingerprint =
+                normalize_fingerprint(&entry.fingerprint).ok_or_else(|| AccessError::BadFingerprint(name.clone()))?;
+            if let Some(other) = policy.clients.get(&fingerprint) {
+                return Err(AccessError::DuplicateFingerprint(other.name.clone(), name));
+            }
+            policy.clients.insert(fingerprint, Client { name, roles: entry.roles });
+        }
+        Ok(policy)
+    }
+
+    /// The client a certificate fingerprint belongs to
+    pub fn client(&self, fingerprint: &str) -> Option<&Client> {
+        self.clients.get(fingerprint)
+    }
+
+    /// Fingerprints of every allowed certificate
+    pub fn fingerprints(&self) -> impl Iterator<Item = &str> {
+        self.clients.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+}
+
+/// Accept `AB:CD:...`, `abcd...` and openssl's `SHA256 Fingerprint=AB:CD:...`
+fn normalize_fingerprint(fingerprint: &str) -> Option<String> {
+    let fingerprint = fingerprint
+        .rsplit('=')
+        .next()
+        .unwrap_or_default()
+        .replace([':', ' '], "")
+        .to_ascii_lowercase();
+    (fingerprint.len() == 64 && fingerprint.bytes().all(|b| b.is_ascii_hexdigit())).then_some(fingerprint)
+}
+
+/// The certificate a connection's client presented
+#[derive(Debug, Clone)]
+pub struct ClientIdentity {
+    /// SHA-256 fingerprint of the certificate (lowercase hex)
+    pub fingerprint: String,
+}
+
+/// Middleware: let the request through only if its client holds `role`
+pub async fn authorize(
+    State((policy, role)): State<(Arc<AccessPolicy>, Role)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(identity) = request.extensions().get::<ClientIdentity>() else {
+        return (StatusCode::UNAUTHORIZED, "client certificate required").into_response();
+    };
+
+    match policy.client(&identity.fingerprint) {
+        Some(client) if client.roles.contains(&role) => next.run(request).await,
+        Some(client) => {
+            warn!(
+                "API client '{}' denied {} (needs role '{}')",
+                client.name,
+                request.uri().path(),
+                role.as_str()
+            );
+            (StatusCode::FORBIDDEN, "client is not allowed to use this endpoint").into_response()
+        }
+        None => (StatusCode::FORBIDDEN, "unknown client certificate").into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FINGERPRINT: &str = "3fa4000000000000000000000000000000000000000000000000000000009c01";
+
+    #[test]
+    fn test_fingerprint_formats() {
+        let colons = FINGERPRINT
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| std::str::from_utf8(pair).unwrap().to_ascii_uppercase())
+            .collect::<Vec<_>>()
+            .join(":");
+
+        assert_eq!(normalize_fingerprint(&colons).as_deref(), Some(FINGERPRINT));
+        assert_eq!(
+            normalize_fingerprint(&format!("SHA256 Fingerprint={}", colons)).as_deref(),
+            Some(FINGERPRINT)
+        );
+        assert_eq!(normalize_fingerprint("3fa4"), None);
+        assert_eq!(normalize_fingerprint(&FINGERPRINT.replace('3', "g")), None);
+    }
+
+    #[test]
+    fn test_access_file() {
+        let file: AccessFile = toml::from_str(&format!(
+            "[clients.prometheus]\nfingerprint = \"{}\"\nroles = [\"metrics\"]\n",
+            FINGERPRINT.to_ascii_uppercase()
+        ))
+        .unwrap();
+        let policy = AccessPolicy::from_file(file).unwrap();
+
+        let client = policy.client(FINGERPRINT).unwrap();
+        assert_eq!(client.name, "prometheus");
+        assert!(client.roles.contains(&Role::Metrics));
+        assert!(!client.roles.contains(&Role::Moor));
+    }
+
+    #[test]
+    fn test_shared_certificate_is_rejected() {
+        let file: AccessFile = toml::from_str(&format!(
+            "[clients.a]\nfingerprint = \"{0}\"\n[clients.b]\nfingerprint = \"{0}\"\n",
+            FINGERPRINT
+        ))
+        .unwrap();
+        assert!(matches!(
+            AccessPolicy::from_file(file),
+            Err(AccessError::DuplicateFingerprint(_, _))
+        ));
+    }
+}
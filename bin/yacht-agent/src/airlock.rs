@@ -0,0 +1,1113 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # HTTP Header Airlock
+//!
+//! A reverse proxy in front of the web backend (PHP behind nginx, Apache,
+//! php-fpm's HTTP front...) that enforces the header policy
+//! (`wharf_core::types::HeaderPolicy`, `configs/policies/airlock.ncl`):
+//!
+//! - **Ingress**: a request with a header longer than `max_header_length`
+//!   (or the header's own `max_length`) is refused. Otherwise only headers
+//!   on the allow list reach the backend (unless `default_action` is
+//!   `allow`), and `force_drop` headers never do. What is left must pass
+//!   its constraint (`wharf_core::header_constraints`): a Host that is not
+//!   served here, a denied pattern or an oversized Content-Length gets the
+//!   request refused, and cookies are passed on re-serialized.
+//! - **Paths**: requests for scripts in upload directories, dotfiles,
+//!   backup files and denied paths are refused with 403 (see
+//!   `wharf_core::path_rules`; the rules come from the adapter's
+//!   filesystem policy). With admin lockdown, the CMS admin answers 404
+//!   (or 403) to everyone outside the admin networks.
+//! - **Rate limits**: logins, XML-RPC, the REST API and search are limited
+//!   per client (see `ratelimit`) and answered 429 with `Retry-After`
+//!   once the bucket is empty. A client that keeps at it is reported on
+//!   the event feed and handed to the shield for a kernel-level block.
+//! - **Uploads**: multipart bodies are inspected as they stream to the
+//!   backend (see `wharf_core::uploads`). A PHP file, a polyglot or a file
+//!   that is not what it claims is refused, and the backend is cut off
+//!   before that part of the body is complete.
+//! - **Egress**: `strip` headers are removed from every response and
+//!   `force` headers are set on it, whatever the backend sent. With `csp`
+//!   enabled the Content-Security-Policy is set too (see `wharf_core::csp`),
+//!   with a fresh nonce per response if asked for. Session cookies get
+//!   the `Secure`, `HttpOnly` and `SameSite` attributes their rule asks for
+//!   (see `wharf_core::cookies`), each rewrite logged and counted.
+//!
+//! Violation reports sent to a `report_uri` on the site are taken by the
+//! airlock itself and aggregated for `/csp/reports` (see `csp`); they never
+//! reach the backend.
+//!
+//! Before any of this, every connection is read through the desync guard
+//! (see `desync`): a request that could be framed two ways - conflicting
+//! Content-Length and Transfer-Encoding, folded or malformed headers, an
+//! absolute-form target for another host - is refused with its reason and
+//! the connection closed, so it can never be smuggled past the backend.
+//!
+//! The backend is told who the client is by the airlock itself: incoming
+//! `X-Forwarded-For` is spoofable and dropped, and the one the backend sees
+//! is set from the connection.
+//!
+//! Every dropped, stripped or refused header is counted per header name
+//! for `/metrics`. Only names that appear in the policy are used as
+//! labels; anything else a client makes up is counted as `other`.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::body::{Body, Bytes};
+use axum::BoxError;
+use futures_util::stream::{self, StreamExt};
+use hyper::body::Incoming;
+use hyper::header::{self, HeaderMap, HeaderName, HeaderValue};
+use hyper::server::conn::http1;
+use hyper::{Method, Request, Response, StatusCode, Uri};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use thiserror::Error;
+use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+use tracing::{debug, error, info, warn};
+
+use wharf_core::cookies::CookieRules;
+use wharf_core::csp::{generate_nonce, parse_reports, CspHeader, NONCE_HEADER};
+use wharf_core::header_constraints::{ConstraintSet, ConstraintViolation, Verdict};
+use wharf_core::events::SecurityEvent;
+use wharf_core::path_rules::{PathRule, PathRules};
+use wharf_core::rate_limit::{client_key, RateClasses};
+use wharf_core::types::HeaderPolicy;
+use wharf_core::uploads::{Inspector, UploadRule, UploadRules, UploadViolation};
+
+use crate::desync::{Desync, DesyncCounters, Guarded, HeadLimits};
+use crate::ebpf::ShieldMonitor;
+use crate::lifecycle::ShutdownSignal;
+use crate::metrics::{Collector, Exposition, MetricType};
+use crate::ratelimit::RateLimiter;
+use crate::state::AgentState;
+use crate::stats::ShardedCounter;
+
+/// Headers that describe one connection, not the message; never forwarded
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Largest CSP report body accepted
+const MAX_REPORT_BODY: usize = 64 * 1024;
+
+/// Label for header names the policy does not mention
+const OTHER: &str = "other";
+
+#[derive(Error, Debug)]
+pub enum AirlockError {
+    #[error("Invalid airlock backend '{0}': expected http://host:port")]
+    InvalidBackend(String),
+
+    #[error("Header policy cannot be enforced: {0}")]
+    Policy(String),
+}
+
+// =============================================================================
+// HEADER FILTERING
+// =============================================================================
+
+/// Which way a header was travelling
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Ingress,
+    Egress,
+}
+
+/// What the airlock did to a header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HeaderAction {
+    /// Request header kept from the backend
+    Dropped,
+    /// Response header kept from the client
+    Stripped,
+    /// Header that got the whole request refused
+    Rejected,
+    /// Request header passed on cleaned up (a strictly parsed cookie)
+    Rewritten,
+}
+
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Ingress => "ingress",
+            Self::Egress => "egress",
+        }
+    }
+}
+
+impl HeaderAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Dropped => "dropped",
+            Self::Stripped => "stripped",
+            Self::Rejected => "rejected",
+            Self::Rewritten => "rewritten",
+        }
+    }
+}
+
+/// Header decisions made while handling one request
+#[derive(Debug, Default)]
+pub struct Tally(Vec<(Direction, HeaderAction, String)>);
+
+impl Tally {
+    fn add(&mut self, policy: &HeaderPolicy, direction: Direction, action: HeaderAction, name: &str) {
+        self.0.push((direction, action, label(policy, name)));
+    }
+
+    /// How often `name` (as labelled) was given `action`
+    pub fn count(&self, action: HeaderAction, name: &str) -> usize {
+        self.0.iter().filter(|(_, a, n)| *a == action && n == name).count()
+    }
+}
+
+/// Why a request was refused before it reached the backend
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    pub status: StatusCode,
+    pub reason: String,
+}
+
+fn listed(names: &[String], name: &str) -> bool {
+    names.iter().any(|n| n.eq_ignore_ascii_case(name))
+}
+
+/// The metric label for a header: its name if the policy names it
+fn label(policy: &HeaderPolicy, name: &str) -> String {
+    let named = [
+        &policy.blocked_headers,
+        &policy.ingress.allow_list,
+        &policy.ingress.force_drop,
+        &policy.egress.strip,
+    ]
+    .into_iter()
+    .any(|names| listed(names, name))
+        || name.eq_ignore_ascii_case("host");
+    if named {
+        name.to_ascii_lowercase()
+    } else {
+        OTHER.to_string()
+    }
+}
+
+/// Remove hop-by-hop headers, including any the Connection header names
+fn remove_hop_by_hop(headers: &mut HeaderMap) {
+    let named: Vec<HeaderName> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|token| HeaderName::from_bytes(token.trim().as_bytes()).ok())
+        .collect();
+    for name in named {
+        headers.remove(name);
+    }
+    for name in HOP_BY_HOP {
+        headers.remove(*name);
+    }
+}
+
+/// Apply the ingress policy to request headers
+///
+/// An overlong header gets the request refused before anything else; then
+/// the allow list decides what stays, and what stays must pass its
+/// constraint.
+pub fn filter_request(
+    policy: &HeaderPolicy,
+    constraints: &ConstraintSet,
+    headers: &mut HeaderMap,
+    tally: &mut Tally,
+) -> Result<(), Rejection> {
+    remove_hop_by_hop(headers);
+
+    for (name, value) in headers.iter() {
+        if let Err(violation) = constraints.check_length(name.as_str(), value.len()) {
+            return Err(reject(policy, violation, tally));
+        }
+    }
+
+    let allow_unlisted = policy.ingress.default_action == "allow";
+    let names: Vec<HeaderName> = headers.keys().cloned().collect();
+    for name in names {
+        let forced = listed(&policy.ingress.force_drop, name.as_str()) || listed(&policy.blocked_headers, name.as_str());
+        let allowed = listed(&policy.ingress.allow_list, name.as_str()) || allow_unlisted;
+        if forced || !allowed {
+            headers.remove(&name);
+            tally.add(policy, Direction::Ingress, HeaderAction::Dropped, name.as_str());
+        }
+    }
+
+    if !headers.contains_key(header::HOST) {
+        constraints
+            .check_missing("host")
+            .map_err(|violation| reject(policy, violation, tally))?;
+    }
+    let names: Vec<HeaderName> = headers.keys().cloned().collect();
+    for name in names {
+        let mut kept = Vec::new();
+        let mut rewritten = false;
+        for value in headers.get_all(&name) {
+            match constraints.check(name.as_str(), value.as_bytes()) {
+                Ok(Verdict::Keep) => kept.push(value.clone()),
+                Ok(Verdict::Replace(cleaned)) => {
+                    kept.extend(HeaderValue::from_str(&cleaned).ok());
+                    rewritten = true;
+                }
+                Ok(Verdict::Drop) => rewritten = true,
+                Err(violation) => return Err(reject(policy, violation, tally)),
+            }
+        }
+        if rewritten {
+            headers.remove(&name);
+            for value in kept {
+                headers.append(name.clone(), value);
+            }
+            tally.add(policy, Direction::Ingress, HeaderAction::Rewritten, name.as_str());
+        }
+    }
+    Ok(())
+}
+
+fn reject(policy: &HeaderPolicy, violation: ConstraintViolation, tally: &mut Tally) -> Rejection {
+    tally.add(policy, Direction::Ingress, HeaderAction::Rejected, violation.header());
+    let status = match violation {
+        ConstraintViolation::TooLong { .. } => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+        ConstraintViolation::ValueTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+        ConstraintViolation::UnknownHost { .. } => StatusCode::MISDIRECTED_REQUEST,
+        ConstraintViolation::DeniedPattern { .. } | ConstraintViolation::NotANumber { .. } => StatusCode::BAD_REQUEST,
+    };
+    Rejection {
+        status,
+        reason: violation.to_string(),
+    }
+}
+
+/// Apply the egress policy to response headers
+pub fn filter_response(policy: &HeaderPolicy, headers: &mut HeaderMap, tally: &mut Tally) {
+    remove_hop_by_hop(headers);
+
+    let names: Vec<HeaderName> = headers.keys().cloned().collect();
+    for name in names {
+        if listed(&policy.egress.strip, name.as_str()) || listed(&policy.blocked_headers, name.as_str()) {
+            headers.remove(&name);
+            tally.add(policy, Direction::Egress, HeaderAction::Stripped, name.as_str());
+        }
+    }
+
+    // egress.force is the newer setting, so it wins over forced_headers
+    for (name, value) in policy.forced_headers.iter().chain(&policy.egress.force) {
+        match (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+            (Ok(name), Ok(value)) => {
+                headers.insert(name, value);
+            }
+            _ => warn!("Skipping invalid forced header {}", name),
+        }
+    }
+}
+
+// =============================================================================
+// PROXY
+// =============================================================================
+
+/// Counters for `/metrics`
+#[derive(Default)]
+pub struct AirlockStats {
+    pub forwarded: ShardedCounter,
+    pub rejected: ShardedCounter,
+    pub backend_errors: ShardedCounter,
+    pub csp_reports: ShardedCounter,
+    /// Requests refused by the desync guard, by reason
+    pub desync: Arc<DesyncCounters>,
+    /// Requests refused by a path rule, indexed by `PathRule`
+    paths_denied: [ShardedCounter; PathRule::ALL.len()],
+    /// Clients escalated for repeated rate limit violations
+    pub escalations: ShardedCounter,
+    /// Requests refused by a rate limit, by class
+    rate_limited: Mutex<HashMap<String, u64>>,
+    /// Uploads refused, indexed by `UploadRule`
+    uploads_rejected: [ShardedCounter; UploadRule::ALL.len()],
+    /// Set-Cookie headers rewritten, by cookie rule
+    cookies_hardened: Mutex<HashMap<String, u64>>,
+    headers: Mutex<HashMap<(Direction, HeaderAction, String), u64>>,
+}
+
+impl AirlockStats {
+    fn record(&self, tally: Tally) {
+        if tally.0.is_empty() {
+            return;
+        }
+        let mut headers = self.headers.lock().unwrap_or_else(|e| e.into_inner());
+        for key in tally.0 {
+            *headers.entry(key).or_default() += 1;
+        }
+    }
+
+    /// How often a path rule refused a request
+    pub fn path_denials(&self, rule: PathRule) -> u64 {
+        self.paths_denied[rule as usize].get()
+    }
+
+    /// How often a rate limit class refused a request
+    pub fn rate_limited(&self, class: &str) -> u64 {
+        let rate_limited = self.rate_limited.lock().unwrap_or_else(|e| e.into_inner());
+        rate_limited.get(class).copied().unwrap_or(0)
+    }
+
+    /// How often an upload was refused for `rule`
+    pub fn upload_rejections(&self, rule: UploadRule) -> u64 {
+        self.uploads_rejected[rule as usize].get()
+    }
+
+    /// How often a cookie rule (by pattern) rewrote a Set-Cookie
+    pub fn cookies_hardened(&self, rule: &str) -> u64 {
+        let hardened = self.cookies_hardened.lock().unwrap_or_else(|e| e.into_inner());
+        hardened.get(rule).copied().unwrap_or(0)
+    }
+
+    /// How often a header (by label) was dropped, stripped or rejected
+    pub fn header_count(&self, direction: Direction, action: HeaderAction, name: &str) -> u64 {
+        let headers = self.headers.lock().unwrap_or_else(|e| e.into_inner());
+        headers
+            .get(&(direction, action, name.to_string()))
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+/// Parse a backend URL; only plain HTTP (the backend is on the same host)
+pub fn parse_backend(backend: &str) -> Result<Uri, AirlockError> {
+    let uri: Uri = backend
+        .parse()
+        .map_err(|_| AirlockError::InvalidBackend(backend.to_string()))?;
+    if uri.scheme_str() != Some("http") || uri.authority().is_none() {
+        return Err(AirlockError::InvalidBackend(backend.to_string()));
+    }
+    Ok(uri)
+}
+
+/// The reverse proxy
+pub struct Airlock {
+    state: Arc<AgentState>,
+    backend: Uri,
+    client: Client<HttpConnector, Body>,
+    /// Compiled from the policy last seen
+    compiled: Mutex<Option<(Arc<HeaderPolicy>, Arc<Compiled>)>>,
+    limits: HeadLimits,
+    rate: RateLimiter,
+    /// Where repeat rate limit offenders are blocked
+    shield: Option<Arc<ShieldMonitor>>,
+    pub stats: AirlockStats,
+}
+
+/// The parts of a header policy that are compiled before use
+struct Compiled {
+    constraints: ConstraintSet,
+    csp: Option<CspHeader>,
+    paths: PathRules,
+    rate: RateClasses,
+    cookies: CookieRules,
+    uploads: Option<UploadRules>,
+}
+
+impl Airlock {
+    /// Proxy to `backend` (an `http://host:port` URL) under the state's header policy
+    pub fn new(state: Arc<AgentState>, backend: &str) -> Result<Self, AirlockError> {
+        Ok(Self {
+            state,
+            backend: parse_backend(backend)?,
+            client: Client::builder(TokioExecutor::new()).build_http(),
+            compiled: Mutex::new(None),
+            limits: HeadLimits::default(),
+            rate: RateLimiter::new(),
+            shield: None,
+            stats: AirlockStats::default(),
+        })
+    }
+
+    /// Limit the request line and head (the defaults are 8 and 64 KiB)
+    pub fn with_limits(mut self, limits: HeadLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Escalate repeat rate limit offenders to the shield's blocklist
+    pub fn with_shield(mut self, shield: Arc<ShieldMonitor>) -> Self {
+        self.shield = Some(shield);
+        self
+    }
+
+    /// Handle one request from `peer`
+    pub async fn handle(&self, mut request: Request<Body>, peer: SocketAddr) -> Response<Body> {
+        let policy = self.state.header_policy.load_full();
+        let compiled = self.compiled(&policy);
+        let mut tally = Tally::default();
+        let mut nonce = None;
+
+        let mut response = if self.state.in_maintenance() {
+            maintenance()
+        } else {
+            match &compiled {
+                Ok(compiled) => match filter_request(&policy, &compiled.constraints, request.headers_mut(), &mut tally) {
+                    Ok(()) => {
+                        let csp = compiled.csp.as_ref();
+                        if let Err(rule) = compiled.paths.check(request.uri().path(), request.uri().query(), peer.ip()) {
+                            debug!("Airlock refused {} from {}: {}", request.uri().path(), peer, rule.as_str());
+                            self.stats.paths_denied[rule as usize].inc();
+                            self.stats.rejected.inc();
+                            let status = StatusCode::from_u16(compiled.paths.status(rule)).unwrap_or(StatusCode::FORBIDDEN);
+                            plain(status, status.canonical_reason().unwrap_or("Forbidden"))
+                        } else if let Some(limited) = self.rate_limit(&compiled.rate, &request, peer) {
+                            limited
+                        } else if csp.and_then(CspHeader::report_path) == Some(request.uri().path()) {
+                            self.collect_reports(request).await
+                        } else {
+                            if csp.is_some_and(CspHeader::uses_nonce) {
+                                let fresh = generate_nonce();
+                                if let Ok(value) = HeaderValue::from_str(&fresh) {
+                                    request.headers_mut().insert(NONCE_HEADER, value);
+                                }
+                                nonce = Some(fresh);
+                            }
+                            unprefix_cookies(&compiled.cookies, request.headers_mut());
+                            match inspect_upload(compiled.uploads.as_ref(), request) {
+                                Ok((request, verdict)) => self.forward(request, peer, verdict).await,
+                                Err(violation) => self.refuse_upload(&violation, peer),
+                            }
+                        }
+                    }
+                    Err(rejection) => {
+                        debug!("Airlock refused a request from {}: {}", peer, rejection.reason);
+                        self.stats.rejected.inc();
+                        plain(rejection.status, rejection.status.canonical_reason().unwrap_or("Rejected"))
+                    }
+                },
+                // Unreachable with a validated policy; fail closed all the same
+                Err(e) => {
+                    error!("{}", e);
+                    self.stats.rejected.inc();
+                    plain(StatusCode::SERVICE_UNAVAILABLE, "Service Unavailable")
+                }
+            }
+        };
+
+        filter_response(&policy, response.headers_mut(), &mut tally);
+        if let Ok(compiled) = &compiled {
+            self.harden_cookies(&compiled.cookies, response.headers_mut());
+            if let Some(csp) = &compiled.csp {
+                set_csp(csp, nonce.as_deref(), response.headers_mut());
+            }
+        }
+        self.stats.record(tally);
+        response
+    }
+
+    /// The compiled parts of `policy`, compiled once per policy version
+    fn compiled(&self, policy: &Arc<HeaderPolicy>) -> Result<Arc<Compiled>, AirlockError> {
+        let mut cached = self.compiled.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((seen, compiled)) = cached.as_ref() {
+            if Arc::ptr_eq(seen, policy) {
+                return Ok(compiled.clone());
+            }
+        }
+        let compiled = Arc::new(Compiled {
+            constraints: ConstraintSet::compile(policy).map_err(|e| AirlockError::Policy(e.to_string()))?,
+            csp: CspHeader::compile(&policy.csp).map_err(|e| AirlockError::Policy(e.to_string()))?,
+            paths: PathRules::compile(&policy.paths).map_err(|e| AirlockError::Policy(e.to_string()))?,
+            rate: RateClasses::compile(&policy.rate_limits).map_err(|e| AirlockError::Policy(e.to_string()))?,
+            cookies: CookieRules::compile(&policy.cookies).map_err(|e| AirlockError::Policy(e.to_string()))?,
+            uploads: UploadRules::compile(&policy.uploads).map_err(|e| AirlockError::Policy(e.to_string()))?,
+        });
+        *cached = Some((policy.clone(), compiled.clone()));
+        Ok(compiled)
+    }
+
+    /// The 429 for a request over its class's rate limit, if it is
+    fn rate_limit(&self, classes: &RateClasses, request: &Request<Body>, peer: SocketAddr) -> Option<Response<Body>> {
+        let uri = request.uri();
+        let class = classes.classify(request.method().as_str(), uri.path(), uri.query(), peer.ip())?;
+        let limited = self.rate.check(classes, class, peer.ip()).err()?;
+
+        debug!("Airlock rate limited {} from {} ({})", uri.path(), peer, class.name);
+        self.stats.rejected.inc();
+        *self
+            .stats
+            .rate_limited
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(class.name.clone())
+            .or_default() += 1;
+        if let Some(violations) = limited.escalate {
+            self.escalate(client_key(peer.ip()), &class.name, violations);
+        }
+
+        let mut response = plain(StatusCode::TOO_MANY_REQUESTS, "Too Many Requests");
+        response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(limited.retry_after));
+        Some(response)
+    }
+
+    /// Report a repeat offender and block it, if the shield can
+    fn escalate(&self, client: IpAddr, class: &str, violations: u32) {
+        warn!("Airlock client {} keeps hitting the {} rate limit ({} violations)", client, class, violations);
+        self.stats.escalations.inc();
+        self.state.events.publish(SecurityEvent::RateLimited {
+            client: client.to_string(),
+            class: class.to_string(),
+            violations,
+        });
+
+        let (Some(shield), IpAddr::V4(ip)) = (&self.shield, client) else {
+            return;
+        };
+        match shield.block_ip(ip) {
+            Ok(()) => self.state.events.publish(SecurityEvent::ShieldBlock {
+                ip,
+                reason: format!("rate limited on {}", class),
+            }),
+            Err(e) => warn!("Could not block {}: {:#}", ip, e),
+        }
+    }
+
+    /// Enforce the cookie rules on every Set-Cookie of a response
+    fn harden_cookies(&self, rules: &CookieRules, headers: &mut HeaderMap) {
+        if !headers.contains_key(header::SET_COOKIE) {
+            return;
+        }
+        let mut cookies = Vec::new();
+        let mut hardened = Vec::new();
+        for value in headers.get_all(header::SET_COOKIE) {
+            match value.to_str().ok().and_then(|v| rules.harden(v)) {
+                Some(rewrite) => match HeaderValue::from_str(&rewrite.value) {
+                    Ok(rewritten) => {
+                        info!("Airlock hardened cookie {} ({}: {})", rewrite.name, rewrite.rule, rewrite.changes.join(", "));
+                        cookies.push(rewritten);
+                        hardened.push(rewrite.rule);
+                    }
+                    Err(_) => cookies.push(value.clone()),
+                },
+                None => cookies.push(value.clone()),
+            }
+        }
+        if hardened.is_empty() {
+            return;
+        }
+        headers.remove(header::SET_COOKIE);
+        for cookie in cookies {
+            headers.append(header::SET_COOKIE, cookie);
+        }
+        let mut counts = self.stats.cookies_hardened.lock().unwrap_or_else(|e| e.into_inner());
+        for rule in hardened {
+            *counts.entry(rule).or_default() += 1;
+        }
+    }
+
+    /// Take a CSP violation report instead of passing it to the backend
+    async fn collect_reports(&self, request: Request<Body>) -> Response<Body> {
+        self.stats.csp_reports.inc();
+        if request.method() != Method::POST {
+            return plain(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed");
+        }
+        let body = match axum::body::to_bytes(request.into_body(), MAX_REPORT_BODY).await {
+            Ok(body) => body,
+            Err(_) => return plain(StatusCode::PAYLOAD_TOO_LARGE, "Payload Too Large"),
+        };
+        match parse_reports(&body) {
+            Ok(violations) => {
+                self.state.csp_reports.record(violations);
+                let mut response = Response::new(Body::empty());
+                *response.status_mut() = StatusCode::NO_CONTENT;
+                response
+            }
+            Err(e) => {
+                debug!("Ignoring CSP report: {}", e);
+                plain(StatusCode::BAD_REQUEST, "Bad Request")
+            }
+        }
+    }
+
+    fn refuse_upload(&self, violation: &UploadViolation, peer: SocketAddr) -> Response<Body> {
+        warn!("Airlock refused an upload from {}: {}", peer, violation);
+        self.stats.uploads_rejected[violation.rule as usize].inc();
+        self.stats.rejected.inc();
+        let status = StatusCode::from_u16(violation.rule.status()).unwrap_or(StatusCode::BAD_REQUEST);
+        plain(status, status.canonical_reason().unwrap_or("Rejected"))
+    }
+
+    async fn forward(&self, mut request: Request<Body>, peer: SocketAddr, verdict: Option<UploadVerdict>) -> Response<Body> {
+        let path = request.uri().path_and_query().map_or("/", |p| p.as_str());
+        let uri = format!(
+            "{}://{}{}",
+            self.backend.scheme_str().unwrap_or("http"),
+            self.backend.authority().map_or("", |a| a.as_str()),
+            path
+        );
+        match uri.parse() {
+            Ok(uri) => *request.uri_mut() = uri,
+            Err(_) => {
+                self.stats.rejected.inc();
+                return plain(StatusCode::BAD_REQUEST, "bad request target");
+            }
+        }
+        if let Ok(value) = HeaderValue::from_str(&peer.ip().to_string()) {
+            request.headers_mut().insert("x-forwarded-for", value);
+        }
+
+        match self.client.request(request).await {
+            Ok(response) => {
+                self.stats.forwarded.inc();
+                response.map(Body::new)
+            }
+            Err(e) => {
+                // A refused upload ends the body early, which the client reports as an error
+                let refused = verdict.and_then(|v| v.lock().unwrap_or_else(|e| e.into_inner()).take());
+                if let Some(violation) = refused {
+                    return self.refuse_upload(&violation, peer);
+                }
+                warn!("Airlock backend {} failed: {}", self.backend, e);
+                self.stats.backend_errors.inc();
+                plain(StatusCode::BAD_GATEWAY, "backend unavailable")
+            }
+        }
+    }
+}
+
+/// Where an upload inspector leaves the reason it cut a body off
+type UploadVerdict = Arc<Mutex<Option<UploadViolation>>>;
+
+/// Put a multipart body behind an upload inspector
+fn inspect_upload(
+    rules: Option<&UploadRules>,
+    request: Request<Body>,
+) -> Result<(Request<Body>, Option<UploadVerdict>), UploadViolation> {
+    let Some(rules) = rules else {
+        return Ok((request, None));
+    };
+    let mut types = request.headers().get_all(header::CONTENT_TYPE).iter();
+    let content_type = match (types.next(), types.next()) {
+        (None, _) => return Ok((request, None)),
+        (Some(value), None) => value.to_str().map_err(|_| UploadViolation {
+            rule: UploadRule::Malformed,
+            detail: "Content-Type is not ASCII".to_string(),
+        })?,
+        (Some(_), Some(_)) => {
+            return Err(UploadViolation {
+                rule: UploadRule::Malformed,
+                detail: "more than one Content-Type".to_string(),
+            })
+        }
+    };
+    let Some(inspector) = rules.inspector(content_type)? else {
+        return Ok((request, None));
+    };
+    let verdict = UploadVerdict::default();
+    let request = request.map(|body| inspected(body, inspector, verdict.clone()));
+    Ok((request, Some(verdict)))
+}
+
+/// `body`, passed on only as far as `inspector` has checked it
+fn inspected(body: Body, inspector: Inspector, verdict: UploadVerdict) -> Body {
+    let chunks = stream::unfold(Some((body.into_data_stream(), inspector)), move |state| {
+        let verdict = verdict.clone();
+        async move {
+            let (mut body, mut inspector) = state?;
+            let mut out = Vec::new();
+            loop {
+                let checked = match body.next().await {
+                    Some(Ok(chunk)) => inspector.feed(&chunk, &mut out),
+                    Some(Err(e)) => return Some((Err(BoxError::from(e)), None)),
+                    None => match inspector.finish(&mut out) {
+                        Ok(()) if out.is_empty() => return None,
+                        Ok(()) => return Some((Ok(Bytes::from(out)), None)),
+                        Err(violation) => Err(violation),
+                    },
+                };
+                match checked {
+                    Ok(()) if out.is_empty() => continue,
+                    Ok(()) => return Some((Ok(Bytes::from(out)), Some((body, inspector)))),
+                    Err(violation) => {
+                        *verdict.lock().unwrap_or_else(|e| e.into_inner()) = Some(violation.clone());
+                        return Some((Err(BoxError::from(violation)), None));
+                    }
+                }
+            }
+        }
+    });
+    Body::from_stream(chunks)
+}
+
+/// Give the application its cookies under the names it set them with
+fn unprefix_cookies(rules: &CookieRules, headers: &mut HeaderMap) {
+    let mut changed = false;
+    let values: Vec<HeaderValue> = headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| match value.to_str().ok().and_then(|v| rules.unprefix(v)) {
+            Some(cookie) => {
+                changed = true;
+                HeaderValue::from_str(&cookie).ok().filter(|_| !cookie.is_empty())
+            }
+            None => Some(value.clone()),
+        })
+        .collect();
+    if changed {
+        headers.remove(header::COOKIE);
+        for value in values {
+            headers.append(header::COOKIE, value);
+        }
+    }
+}
+
+/// Set the CSP header (and where `report-to` sends reports) on a response
+fn set_csp(csp: &CspHeader, nonce: Option<&str>, headers: &mut HeaderMap) {
+    match HeaderValue::from_str(&csp.value(nonce)) {
+        Ok(value) => {
+            headers.insert(csp.name(), value);
+        }
+        Err(_) => warn!("Skipping invalid CSP header"),
+    }
+    if let Some(value) = csp.reporting_endpoints().and_then(|e| HeaderValue::from_str(&e).ok()) {
+        headers.insert("reporting-endpoints", value);
+    }
+}
+
+fn plain(status: StatusCode, text: &str) -> Response<Body> {
+    let mut response = Response::new(Body::from(format!("{}\n", text)));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static("text/plain; charset=utf-8"));
+    response
+}
+
+/// What every request gets once enforcement has failed closed
+fn maintenance() -> Response<Body> {
+    let mut response = plain(StatusCode::SERVICE_UNAVAILABLE, "Down for maintenance");
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from_static("300"));
+    response
+}
+
+/// Serve the airlock until shutdown, then give open requests `drain_timeout`
+pub async fn serve(
+    listener: TcpListener,
+    airlock: Arc<Airlock>,
+    shutdown: ShutdownSignal,
+    drain_timeout: Duration,
+) -> io::Result<()> {
+    info!("Airlock listening on {} (backend {})", listener.local_addr()?, airlock.backend);
+
+    let mut connections = JoinSet::new();
+    let stop = shutdown.clone().wait();
+    tokio::pin!(stop);
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // Usually out of file descriptors; back off instead of spinning
+                    warn!("Airlock accept failed: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+            Some(_) = connections.join_next() => continue,
+            _ = &mut stop => break,
+        };
+
+        let (limits, desync) = (airlock.limits, airlock.stats.desync.clone());
+        let airlock = airlock.clone();
+        let shutdown = shutdown.clone();
+        connections.spawn(async move {
+            let service = hyper::service::service_fn(move |request: Request<Incoming>| {
+                let airlock = airlock.clone();
+                async move { Ok::<_, Infallible>(airlock.handle(request.map(Body::new), peer).await) }
+            });
+            let stream = Guarded::new(stream, limits, desync, peer);
+            // Half-close: a refused request ends the read side, not the responses owed
+            let connection = http1::Builder::new()
+                .half_close(true)
+                .serve_connection(TokioIo::new(stream), service);
+            tokio::pin!(connection);
+
+            let result = tokio::select! {
+                result = connection.as_mut() => result,
+                _ = shutdown.wait() => {
+                    connection.as_mut().graceful_shutdown();
+                    connection.await
+                }
+            };
+            if let Err(e) = result {
+                debug!("Airlock connection from {} ended: {}", peer, e);
+            }
+        });
+    }
+
+    drop(listener);
+    if !connections.is_empty() {
+        let drained = tokio::time::timeout(drain_timeout, async {
+            while connections.join_next().await.is_some() {}
+        })
+        .await;
+        if drained.is_err() {
+            warn!("Drain deadline passed - closing {} airlock connections", connections.len());
+            connections.shutdown().await;
+        }
+    }
+    Ok(())
+}
+
+impl Collector for Airlock {
+    fn collect(&self, out: &mut Exposition) {
+        out.family(
+            "yacht_airlock_requests_total",
+            "HTTP requests through the airlock, by outcome",
+            MetricType::Counter,
+        );
+        for (outcome, counter) in [
+            ("forwarded", &self.stats.forwarded),
+            ("rejected", &self.stats.rejected),
+            ("backend_error", &self.stats.backend_errors),
+            ("csp_report", &self.stats.csp_reports),
+        ] {
+            out.sample("yacht_airlock_requests_total", &[("outcome", outcome)], counter.get() as f64);
+        }
+
+        out.family(
+            "yacht_airlock_desync_rejected_total",
+            "Requests refused as ambiguously framed (request smuggling), by reason",
+            MetricType::Counter,
+        );
+        for reason in Desync::ALL {
+            out.sample(
+                "yacht_airlock_desync_rejected_total",
+                &[("reason", reason.as_str())],
+                self.stats.desync.get(reason) as f64,
+            );
+        }
+
+        out.family(
+            "yacht_airlock_paths_denied_total",
+            "Requests refused by a path rule, by rule",
+            MetricType::Counter,
+        );
+        for rule in PathRule::ALL {
+            out.sample(
+                "yacht_airlock_paths_denied_total",
+                &[("rule", rule.as_str())],
+                self.stats.path_denials(rule) as f64,
+            );
+        }
+
+        out.family(
+            "yacht_airlock_rate_limited_total",
+            "Requests refused by a rate limit, by class",
+            MetricType::Counter,
+        );
+        let mut rate_limited: Vec<_> = self
+            .stats
+            .rate_limited
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(class, count)| (class.clone(), *count))
+            .collect();
+        rate_limited.sort();
+        for (class, count) in rate_limited {
+            out.sample("yacht_airlock_rate_limited_total", &[("class", &class)], count as f64);
+        }
+
+        out.family(
+            "yacht_airlock_uploads_rejected_total",
+            "Uploads refused by the upload inspection, by rule",
+            MetricType::Counter,
+        );
+        for rule in UploadRule::ALL {
+            out.sample(
+                "yacht_airlock_uploads_rejected_total",
+                &[("rule", rule.as_str())],
+                self.stats.upload_rejections(rule) as f64,
+            );
+        }
+
+        out.family(
+            "yacht_airlock_cookies_hardened_total",
+            "Set-Cookie headers rewritten by a cookie rule, by rule",
+            MetricType::Counter,
+        );
+        let mut hardened: Vec<_> = self
+            .stats
+            .cookies_hardened
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(rule, count)| (rule.clone(), *count))
+            .collect();
+        hardened.sort();
+        for (rule, count) in hardened {
+            out.sample("yacht_airlock_cookies_hardened_total", &[("rule", &rule)], count as f64);
+        }
+
+        out.single(
+            "yacht_airlock_rate_limit_escalations_total",
+            "Clients reported for repeated rate limit violations",
+            MetricType::Counter,
+            self.stats.escalations.get() as f64,
+        );
+
+        out.family(
+            "yacht_airlock_headers_total",
+            "Headers dropped, stripped or rejected by the airlock",
+            MetricType::Counter,
+        );
+        let mut headers: Vec<_> = self
+            .stats
+            .headers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(key, count)| (key.clone(), *count))
+            .collect();
+        headers.sort_by(|a, b| (a.0 .0.as_str(), a.0 .1.as_str(), &a.0 .2).cmp(&(b.0 .0.as_str(), b.0 .1.as_str(), &b.0 .2)));
+        for ((direction, action, name), count) in headers {
+            out.sample(
+                "yacht_airlock_headers_total",
+                &[("direction", direction.as_str()), ("action", action.as_str()), ("header", &name)],
+                count as f64,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        headers
+    }
+
+    fn filter(policy: &HeaderPolicy, headers: &mut HeaderMap, tally: &mut Tally) -> Result<(), Rejection> {
+        filter_request(policy, &ConstraintSet::compile(policy).unwrap(), headers, tally)
+    }
+
+    #[test]
+    fn test_only_allow_list_reaches_backend() {
+        let policy = HeaderPolicy::default();
+        let mut headers = request_headers(&[
+            ("host", "example.com"),
+            ("cookie", "a=1"),
+            ("x-forwarded-for", "10.0.0.1"),
+            ("x-debug-mode", "1"),
+            ("connection", "keep-alive"),
+        ]);
+        let mut tally = Tally::default();
+        filter(&policy, &mut headers, &mut tally).unwrap();
+
+        let kept: Vec<_> = headers.keys().map(|k| k.as_str()).collect();
+        assert_eq!(kept, vec!["host", "cookie"]);
+        assert_eq!(tally.count(HeaderAction::Dropped, "x-forwarded-for"), 1);
+        // Made-up names are not used as labels; hop-by-hop is not counted
+        assert_eq!(tally.count(HeaderAction::Dropped, OTHER), 1);
+    }
+
+    #[test]
+    fn test_force_drop_beats_allow() {
+        let mut policy = HeaderPolicy::default();
+        policy.ingress.default_action = "allow".to_string();
+        let mut headers = request_headers(&[("proxy", "http://evil"), ("x-custom", "1")]);
+        filter(&policy, &mut headers, &mut Tally::default()).unwrap();
+        assert!(headers.get("proxy").is_none());
+        assert!(headers.get("x-custom").is_some());
+    }
+
+    #[test]
+    fn test_oversized_header_and_unknown_host_are_refused() {
+        let mut policy = HeaderPolicy::default();
+        let long = "x".repeat(policy.max_header_length);
+        let mut headers = request_headers(&[("user-agent", &long)]);
+        let rejection = filter(&policy, &mut headers, &mut Tally::default()).unwrap_err();
+        assert_eq!(rejection.status, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+
+        policy.allowed_hosts = vec!["example.com".to_string()];
+        let mut headers = request_headers(&[("host", "example.com:8080")]);
+        assert!(filter(&policy, &mut headers, &mut Tally::default()).is_ok());
+        let mut headers = request_headers(&[("host", "attacker.test")]);
+        let mut tally = Tally::default();
+        let rejection = filter(&policy, &mut headers, &mut tally).unwrap_err();
+        assert_eq!(rejection.status, StatusCode::MISDIRECTED_REQUEST);
+        assert_eq!(tally.count(HeaderAction::Rejected, "host"), 1);
+    }
+
+    #[test]
+    fn test_constraints_applied_to_allowed_headers() {
+        let policy = HeaderPolicy::default();
+        let mut headers = request_headers(&[("host", "example.com"), ("cookie", "sid=abc; bad\\name=1")]);
+        let mut tally = Tally::default();
+        filter(&policy, &mut headers, &mut tally).unwrap();
+        assert_eq!(headers["cookie"], "sid=abc");
+        assert_eq!(tally.count(HeaderAction::Rewritten, "cookie"), 1);
+
+        let mut headers = request_headers(&[("user-agent", "<script>alert(1)</script>")]);
+        let rejection = filter(&policy, &mut headers, &mut Tally::default()).unwrap_err();
+        assert_eq!(rejection.status, StatusCode::BAD_REQUEST);
+
+        let mut headers = request_headers(&[("content-length", "999999999999")]);
+        let rejection = filter(&policy, &mut headers, &mut Tally::default()).unwrap_err();
+        assert_eq!(rejection.status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        // Dropped headers are not held to constraints
+        let mut headers = request_headers(&[("x-evil", "<script>")]);
+        assert!(filter(&policy, &mut headers, &mut Tally::default()).is_ok());
+    }
+
+    #[test]
+    fn test_egress_strips_and_forces() {
+        let mut policy = HeaderPolicy::default();
+        policy.egress.force.insert("X-Frame-Options".to_string(), "SAMEORIGIN".to_string());
+        let mut headers = request_headers(&[
+            ("server", "Apache/2.4.1"),
+            ("x-powered-by", "PHP/8.1"),
+            ("x-frame-options", "ALLOWALL"),
+            ("content-type", "text/html"),
+        ]);
+        let mut tally = Tally::default();
+        filter_response(&policy, &mut headers, &mut tally);
+
+        assert!(headers.get("server").is_none());
+        assert!(headers.get("x-powered-by").is_none());
+        assert_eq!(headers["x-frame-options"], "SAMEORIGIN");
+        assert_eq!(headers["x-content-type-options"], "nosniff");
+        assert_eq!(tally.count(HeaderAction::Stripped, "server"), 1);
+    }
+
+    #[test]
+    fn test_backend_must_be_http() {
+        let state = Arc::new(AgentState::new());
+        assert!(Airlock::new(state.clone(), "http://127.0.0.1:8081").is_ok());
+        assert!(Airlock::new(state.clone(), "https://127.0.0.1:8443").is_err());
+        assert!(Airlock::new(state, "127.0.0.1:8081").is_err());
+    }
+}
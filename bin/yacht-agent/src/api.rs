@@ -0,0 +1,392 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Agent API
+//!
+//! The HTTP API that the Wharf, monitoring and local tooling talk to:
+//! health (see `health`), status, statistics, metrics, the audit log, the
+//! live event feed, the policy history, CSP violation reports and the
+//! mooring endpoints.
+//!
+//! The API is served over plain HTTP (bound to localhost by default) or
+//! over TLS. With an access policy, clients authenticate with a pinned
+//! certificate and every route except the `/health` ones requires a role, so a
+//! metrics scraper cannot send mooring commands and the Wharf's key is not
+//! handed to the scraper.
+
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::{Extension, Json, State};
+use axum::http::{header, StatusCode};
+use axum::middleware;
+use axum::response::sse::{KeepAlive, Sse};
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::Router;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper_util::rt::TokioIo;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+use tokio_rustls::TlsAcceptor;
+use tower::Service;
+use tracing::{debug, error, info, warn};
+
+use wharf_core::mooring::SignedEnvelope;
+
+use crate::access::{self, AccessPolicy, ClientIdentity, Role};
+use crate::ebpf::ShieldMonitor;
+use crate::events;
+use crate::health::{HealthRegistry, HealthStatus};
+use crate::lifecycle::ShutdownSignal;
+use crate::metrics::{self, Registry};
+use crate::moor::MooringService;
+use crate::policy::PolicyStore;
+use crate::shadow::ShadowDb;
+use crate::state::AgentState;
+use crate::tls;
+
+/// How long a client gets to complete the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Everything the API handlers read
+pub struct ApiState {
+    pub agent: Arc<AgentState>,
+    pub shadow: Arc<ShadowDb>,
+    pub shield: Arc<ShieldMonitor>,
+    pub policies: Arc<PolicyStore>,
+    pub mooring: Option<MooringService>,
+    pub registry: Registry,
+    pub health: Arc<HealthRegistry>,
+}
+
+/// Build the API routes
+///
+/// With an access policy every route but the health checks is guarded by the role
+/// it needs; without one the routes are open (plain HTTP on localhost).
+pub fn router(state: Arc<ApiState>, metrics_enabled: bool, access: Option<Arc<AccessPolicy>>) -> Router {
+    let guarded = |routes: Router<Arc<ApiState>>, role: Role| match &access {
+        Some(policy) => routes.route_layer(middleware::from_fn_with_state((policy.clone(), role), access::authorize)),
+        None => routes,
+    };
+
+    let mut app = Router::new()
+        .route("/health", get(health_check))
+        .route("/health/ready", get(readiness))
+        .route("/health/live", get(liveness))
+        .merge(guarded(
+            Router::new()
+                .route("/status", get(status))
+                .route("/stats", get(stats))
+                .route("/policy/history", get(policy_history))
+                .route("/csp/reports", get(csp_reports)),
+            Role::Status,
+        ))
+        .merge(guarded(
+            Router::new()
+                .route("/audit/log", get(audit_log_file))
+                .route("/events", get(event_feed)),
+            Role::Audit,
+        ))
+        .merge(guarded(
+            Router::new().route("/moor", post(moor)).route("/verify", post(verify)),
+            Role::Moor,
+        ));
+
+    if metrics_enabled {
+        app = app.merge(guarded(Router::new().route("/metrics", get(prometheus_metrics)), Role::Metrics));
+        info!("Prometheus metrics enabled at /metrics");
+    }
+
+    app.with_state(state)
+}
+
+// =============================================================================
+// SERVER
+// =============================================================================
+
+/// Serve the API until shutdown, then give open requests `drain_timeout`
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    tls: Option<TlsAcceptor>,
+    shutdown: ShutdownSignal,
+    drain_timeout: Duration,
+) -> io::Result<()> {
+    info!(
+        "API listening on {} ({})",
+        listener.local_addr()?,
+        if tls.is_some() { "https" } else { "http" }
+    );
+
+    let mut connections = JoinSet::new();
+    let stop = shutdown.clone().wait();
+    tokio::pin!(stop);
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // Usually out of file descriptors; back off instead of spinning
+                    warn!("API accept failed: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+            Some(_) = connections.join_next() => continue,
+            _ = &mut stop => break,
+        };
+
+        let app = app.clone();
+        let shutdown = shutdown.clone();
+        match tls.clone() {
+            None => connections.spawn(serve_http(stream, app, None, shutdown)),
+            Some(acceptor) => connections.spawn(async move {
+                let stream = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(e)) => {
+                        warn!("API client {} rejected during TLS handshake: {}", peer, e);
+                        return;
+                    }
+                    Err(_) => {
+                        warn!("API client {} timed out during TLS handshake", peer);
+                        return;
+                    }
+                };
+                let identity = stream
+                    .get_ref()
+                    .1
+                    .peer_certificates()
+                    .and_then(|certs| certs.first())
+                    .map(|cert| ClientIdentity {
+                        fingerprint: tls::fingerprint(cert),
+                    });
+                serve_http(stream, app, identity, shutdown).await
+            }),
+        };
+    }
+
+    drop(listener);
+    if !connections.is_empty() {
+        let drained = tokio::time::timeout(drain_timeout, async {
+            while connections.join_next().await.is_some() {}
+        })
+        .await;
+        if drained.is_err() {
+            warn!("Drain deadline passed - closing {} API connections", connections.len());
+            connections.shutdown().await;
+        }
+    }
+    Ok(())
+}
+
+/// Serve HTTP/1.1 on one connection, finishing the current request on shutdown
+///
+/// Handlers get the shutdown signal as a request extension, so streams
+/// such as `/events` can end instead of holding up the drain.
+async fn serve_http<S>(stream: S, app: Router, identity: Option<ClientIdentity>, shutdown: ShutdownSignal)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let signal = shutdown.clone();
+    let service = hyper::service::service_fn(move |mut request: hyper::Request<Incoming>| {
+        if let Some(identity) = &identity {
+            request.extensions_mut().insert(identity.clone());
+        }
+        request.extensions_mut().insert(signal.clone());
+        // A Router is always ready, so there is no need to poll it first
+        app.clone().call(request)
+    });
+
+    let connection = http1::Builder::new()
+        .serve_connection(TokioIo::new(stream), service)
+        .with_upgrades();
+    tokio::pin!(connection);
+
+    let result = tokio::select! {
+        result = connection.as_mut() => result,
+        _ = shutdown.wait() => {
+            connection.as_mut().graceful_shutdown();
+            connection.await
+        }
+    };
+    if let Err(e) = result {
+        debug!("API connection ended: {}", e);
+    }
+}
+
+// =============================================================================
+// HANDLERS
+// =============================================================================
+
+/// Health check endpoint (every component; 503 once one has failed)
+async fn health_check(State(api): State<Arc<ApiState>>) -> impl IntoResponse {
+    let report = api.health.report_all();
+    let code = match report.status {
+        HealthStatus::Failed => StatusCode::SERVICE_UNAVAILABLE,
+        HealthStatus::Healthy | HealthStatus::Degraded => StatusCode::OK,
+    };
+    (code, Json(report))
+}
+
+/// Readiness: whether the agent should be sent traffic
+async fn readiness(
+    State(api): State<Arc<ApiState>>,
+    shutdown: Option<Extension<ShutdownSignal>>,
+) -> impl IntoResponse {
+    if shutdown.is_some_and(|Extension(signal)| signal.is_triggered()) {
+        return (StatusCode::SERVICE_UNAVAILABLE, "shutting down");
+    }
+    match api.health.report_all().status {
+        HealthStatus::Failed => (StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+        HealthStatus::Healthy | HealthStatus::Degraded => (StatusCode::OK, "ready"),
+    }
+}
+
+/// Liveness: the agent answers, whatever the state of its dependencies
+async fn liveness() -> &'static str {
+    "OK"
+}
+
+/// Status endpoint (returns agent state as JSON)
+async fn status(State(api): State<Arc<ApiState>>) -> axum::Json<serde_json::Value> {
+    let agent = &api.agent;
+    Json(serde_json::json!({
+        "status": "active",
+        "moored": agent.is_moored(),
+        "maintenance": agent.in_maintenance(),
+        "version": wharf_core::VERSION,
+        "policy": {
+            "version": api.policies.current().version,
+            "database": agent.db_policy_hash(),
+            "header": agent.header_policy_hash(),
+        },
+        "components": {
+            "db_proxy": api.shadow.breaker().state().as_str(),
+            "shield": api.shield.mode(),
+            "integrity": agent.integrity_status(),
+        }
+    }))
+}
+
+/// Statistics endpoint
+async fn stats(State(api): State<Arc<ApiState>>) -> axum::Json<serde_json::Value> {
+    let stats = &api.agent.stats;
+    let queries = stats.snapshot();
+    Json(serde_json::json!({
+        "queries": {
+            "allowed": queries.allowed,
+            "blocked": queries.blocked,
+            "audited": queries.audited,
+            "result_limits_exceeded": queries.result_limits_exceeded,
+        },
+        "connections": {
+            "active": api.shadow.active_connections(),
+            "max": api.shadow.config().max_connections,
+        },
+        "latency": {
+            "inspect": stats.inspect_latency.snapshot(),
+            "query": stats.query_latency.snapshot(),
+        },
+        // Only the XDP shield counts packets
+        "packets": api.shield.packet_counts(),
+    }))
+}
+
+/// Policy history endpoint (newest first; bundles left out)
+async fn policy_history(State(api): State<Arc<ApiState>>) -> axum::Json<serde_json::Value> {
+    let versions: Vec<_> = api
+        .policies
+        .history()
+        .into_iter()
+        .map(|v| {
+            serde_json::json!({
+                "version": v.version,
+                "applied_at": v.applied_at,
+                "source": v.source,
+            })
+        })
+        .collect();
+    Json(serde_json::json!({ "versions": versions }))
+}
+
+/// CSP violations reported to the airlock, most reported first
+async fn csp_reports(State(api): State<Arc<ApiState>>) -> axum::Json<serde_json::Value> {
+    let reports = &api.agent.csp_reports;
+    Json(serde_json::json!({
+        "received": reports.received(),
+        "untracked": reports.untracked(),
+        "reports": reports.summary(),
+    }))
+}
+
+/// Prometheus metrics endpoint
+async fn prometheus_metrics(State(api): State<Arc<ApiState>>) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, metrics::CONTENT_TYPE)], api.registry.render())
+}
+
+/// Audit log endpoint (the raw chain, for `wharf sec audit-log`)
+async fn audit_log_file(State(api): State<Arc<ApiState>>) -> impl IntoResponse {
+    let Some(path) = api.agent.audit.path() else {
+        return (StatusCode::NOT_FOUND, "audit log disabled").into_response();
+    };
+
+    match tokio::fs::read(path).await {
+        Ok(mut content) => {
+            // Only hand out complete records
+            let complete = content.iter().rposition(|&b| b == b'\n').map_or(0, |end| end + 1);
+            content.truncate(complete);
+            ([(header::CONTENT_TYPE, "application/x-ndjson")], content).into_response()
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            ([(header::CONTENT_TYPE, "application/x-ndjson")], Vec::new()).into_response()
+        }
+        Err(e) => {
+            error!("Failed to read audit log: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "failed to read audit log").into_response()
+        }
+    }
+}
+
+/// Live security events (server-sent events, for `wharf fleet watch`)
+async fn event_feed(
+    State(api): State<Arc<ApiState>>,
+    shutdown: Option<Extension<ShutdownSignal>>,
+) -> impl IntoResponse {
+    let stream = events::sse_stream(api.agent.events.subscribe(), shutdown.map(|Extension(signal)| signal));
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// Mooring endpoint (signed commands from the Wharf)
+async fn moor(State(api): State<Arc<ApiState>>, Json(envelope): Json<SignedEnvelope>) -> impl IntoResponse {
+    let Some(mooring) = &api.mooring else {
+        return refused(StatusCode::SERVICE_UNAVAILABLE, "mooring is not configured");
+    };
+
+    match mooring.handle(&envelope).await {
+        Ok(result) => Json(serde_json::json!({ "ok": true, "result": result })).into_response(),
+        Err(e) => refused(e.status(), &e.to_string()),
+    }
+}
+
+/// Integrity verification endpoint (signed manifest, for `verify_remote_api`)
+async fn verify(State(api): State<Arc<ApiState>>, Json(envelope): Json<SignedEnvelope>) -> impl IntoResponse {
+    let Some(mooring) = &api.mooring else {
+        return refused(StatusCode::SERVICE_UNAVAILABLE, "mooring is not configured");
+    };
+
+    match mooring.verify(&envelope).await {
+        Ok(result) => Json(result).into_response(),
+        Err(e) => refused(e.status(), &e.to_string()),
+    }
+}
+
+/// Error body for the command endpoints
+fn refused(status: StatusCode, error: &str) -> axum::response::Response {
+    let body = serde_json::json!({ "ok": false, "error": error });
+    (status, Json(body)).into_response()
+}
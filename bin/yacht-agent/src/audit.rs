@@ -0,0 +1,211 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Audit Log Writer
+//!
+//! Appends hash-chained records (see `wharf_core::audit`) to the audit log
+//! file. The proxy hands events to a channel and never waits on the disk;
+//! a single writer task seals them onto the chain, appends and fsyncs.
+//!
+//! On startup the existing log is verified. A log whose chain is broken is
+//! moved aside as evidence and a new chain is started.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+use wharf_core::audit::{self, AuditEvent, ChainHead};
+
+/// Events queued for the writer before new ones are dropped
+const QUEUE_DEPTH: usize = 4096;
+
+/// Handle for recording audit events
+pub struct AuditLog {
+    sender: Option<mpsc::Sender<AuditEvent>>,
+    path: Option<PathBuf>,
+    dropped: AtomicU64,
+}
+
+impl AuditLog {
+    /// An audit log that records nothing (tests, benchmarks)
+    pub fn disabled() -> Self {
+        Self {
+            sender: None,
+            path: None,
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Open (or create) the log at `path` and start the writer task
+    pub async fn open(path: &Path) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let head = recover_head(path).await?;
+        info!("Audit log {} ({} records)", path.display(), head.next_seq);
+
+        let file = OpenOptions::new().create(true).append(true).open(path).await?;
+        let (sender, receiver) = mpsc::channel(QUEUE_DEPTH);
+        tokio::spawn(run_writer(file, head, receiver));
+
+        Ok(Self {
+            sender: Some(sender),
+            path: Some(path.to_path_buf()),
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// Queue an event for the log
+    ///
+    /// Never blocks. If the writer has fallen behind, the event is dropped
+    /// and counted rather than stalling the query that produced it.
+    pub fn record(&self, event: AuditEvent) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        if sender.try_send(event).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            warn!("Audit log queue full - {} events dropped so far", dropped);
+        }
+    }
+
+    /// The log file, if logging is enabled
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Whether the writer task is still taking events (always true when disabled)
+    pub fn is_running(&self) -> bool {
+        self.sender.as_ref().is_none_or(|sender| !sender.is_closed())
+    }
+
+    /// Events dropped because the writer fell behind
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Read the existing log and return the head to continue from
+async fn recover_head(path: &Path) -> io::Result<ChainHead> {
+    let content = match tokio::fs::read_to_string(path).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(ChainHead::genesis()),
+        Err(e) => return Err(e),
+    };
+
+    match audit::verify_chain(&content, None) {
+        // A torn last line (crash mid-write) is not part of the chain
+        Ok(summary) if content.is_empty() || content.ends_with('\n') => Ok(summary.head),
+        Ok(summary) => {
+            warn!("Audit log {} ends in a partial record - discarding it", path.display());
+            let complete = content.len() - content.rsplit('\n').next().map_or(0, str::len);
+            let file = OpenOptions::new().write(true).open(path).await?;
+            file.set_len(complete as u64).await?;
+            Ok(summary.head)
+        }
+        Err(e) => {
+            let aside = path.with_extension(format!("broken-{}", chrono::Utc::now().format("%Y%m%dT%H%M%SZ")));
+            error!(
+                "Audit log {} failed verification ({}) - moved to {}, starting a new chain",
+                path.display(),
+                e,
+                aside.display()
+            );
+            tokio::fs::rename(path, &aside).await?;
+            Ok(ChainHead::genesis())
+        }
+    }
+}
+
+async fn run_writer(mut file: File, mut head: ChainHead, mut receiver: mpsc::Receiver<AuditEvent>) {
+    let mut batch = Vec::new();
+    while let Some(event) = receiver.recv().await {
+        // Take whatever else is already queued so one fsync covers it all
+        batch.clear();
+        for event in std::iter::once(event).chain(std::iter::from_fn(|| receiver.try_recv().ok())) {
+            let record = head.append(event);
+            match serde_json::to_vec(&record) {
+                Ok(line) => {
+                    batch.extend_from_slice(&line);
+                    batch.push(b'\n');
+                }
+                Err(e) => error!("Failed to serialize audit record: {}", e),
+            }
+        }
+
+        let written = async {
+            file.write_all(&batch).await?;
+            file.sync_data().await
+        };
+        if let Err(e) = written.await {
+            error!("Failed to write audit log: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use wharf_core::audit::AuditDecision;
+
+    fn event(query: &str) -> AuditEvent {
+        AuditEvent::now("127.0.0.1:1", None, AuditDecision::Block, query, "blocked_operation:DROP")
+    }
+
+    async fn wait_for_records(path: &Path, records: u64) -> String {
+        for _ in 0..100 {
+            let content = tokio::fs::read_to_string(path).await.unwrap_or_default();
+            if audit::verify_chain(&content, None).ok().map(|s| s.records) == Some(records) {
+                return content;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("audit log never reached {} records", records);
+    }
+
+    #[tokio::test]
+    async fn test_chain_continues_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+
+        let log = AuditLog::open(&path).await.unwrap();
+        log.record(event("DROP TABLE a"));
+        log.record(event("DROP TABLE b"));
+        wait_for_records(&path, 2).await;
+        drop(log);
+
+        let log = AuditLog::open(&path).await.unwrap();
+        log.record(event("DROP TABLE c"));
+        let content = wait_for_records(&path, 3).await;
+        assert!(content.contains("DROP TABLE c"));
+    }
+
+    #[tokio::test]
+    async fn test_broken_log_is_moved_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+
+        let log = AuditLog::open(&path).await.unwrap();
+        log.record(event("DROP TABLE a"));
+        let content = wait_for_records(&path, 1).await;
+        drop(log);
+        tokio::fs::write(&path, content.replace("DROP TABLE a", "SELECT 1")).await.unwrap();
+
+        let log = AuditLog::open(&path).await.unwrap();
+        log.record(event("DROP TABLE b"));
+        wait_for_records(&path, 1).await;
+
+        let moved = std::fs::read_dir(dir.path())
+            .unwrap()
+            .filter(|e| e.as_ref().unwrap().file_name().to_string_lossy().contains("broken"))
+            .count();
+        assert_eq!(moved, 1);
+    }
+}
@@ -0,0 +1,199 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Configuration Check
+//!
+//! Backs `yacht-agent --check-config`: everything startup would load from
+//! an `AgentConfig` (see `wharf_core::agent_config`) is loaded here too, and
+//! every problem is collected instead of stopping at the first one, so a
+//! deployment can be checked before the agent is restarted into it.
+
+use wharf_core::agent_config::{AgentConfig, ApiConfig};
+use wharf_core::mooring::{ReplayGuard, TrustStore};
+use wharf_core::policy::{BundleError, PolicyBundle};
+
+use crate::access::AccessPolicy;
+use crate::airlock;
+use crate::net;
+use crate::tls;
+
+/// Database protocols the proxy understands (redis is passed through)
+const PROTOCOLS: &[&str] = &["mysql", "mariadb", "postgres", "redis"];
+
+/// Firewall modes the agent knows
+const FIREWALL_MODES: &[&str] = &["ebpf", "nftables", "none"];
+
+/// Check a configuration, returning every problem found (empty if it is usable)
+pub fn check(config: &AgentConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if !PROTOCOLS.contains(&config.protocol.as_str()) {
+        problems.push(format!("unknown protocol '{}'", config.protocol));
+    }
+    if !FIREWALL_MODES.contains(&config.firewall.mode.as_str()) {
+        problems.push(format!("unknown firewall mode '{}'", config.firewall.mode));
+    }
+
+    // Listeners
+    if config.listen.unix_only && config.listen.socket.is_none() {
+        problems.push("listen.unix_only is set without listen.socket".to_string());
+    }
+    if let Err(e) = net::parse_mode(&config.listen.socket_mode) {
+        problems.push(format!("listen.socket_mode: {}", e));
+    }
+
+    // Policies: what SIGHUP and startup would enforce
+    let mut bundle = PolicyBundle::default();
+    let mut loaded = true;
+    if let Some(database) = &config.policy.database {
+        match database.load() {
+            Ok(policy) => bundle.database = policy,
+            Err(e) => {
+                problems.push(format!("database policy: {}", e));
+                loaded = false;
+            }
+        }
+    }
+    if let Some(header) = &config.policy.header {
+        match header.load() {
+            Ok(policy) => bundle.header = policy,
+            Err(e) => {
+                problems.push(format!("header policy: {}", e));
+                loaded = false;
+            }
+        }
+    }
+    if loaded {
+        match bundle.validate() {
+            Ok(()) => {}
+            Err(BundleError::Invalid(found)) => problems.extend(found.into_iter().map(|p| format!("policy: {}", p))),
+            Err(e) => problems.push(format!("policy: {}", e)),
+        }
+    }
+    if config.policy.versions == 0 {
+        problems.push("policy.versions must keep at least one version".to_string());
+    }
+    if let Some(url) = &config.policy.probe_url {
+        if reqwest::Url::parse(url).is_err() {
+            problems.push(format!("policy.probe_url '{}' is not a URL", url));
+        }
+    }
+
+    if let Some(backend) = &config.airlock.backend {
+        if let Err(e) = airlock::parse_backend(backend) {
+            problems.push(e.to_string());
+        }
+    }
+    if config.airlock.max_request_line == 0 || config.airlock.max_request_line > config.airlock.max_head_size {
+        problems.push("airlock: max_request_line must be between 1 and max_head_size".to_string());
+    }
+
+    // Integrity and audit
+    if !config.integrity.web_root.is_dir() {
+        problems.push(format!("web root {} is not a directory", config.integrity.web_root.display()));
+    }
+    if let Some(dir) = config.audit.log.parent() {
+        if !dir.as_os_str().is_empty() && !dir.is_dir() {
+            problems.push(format!("audit log directory {} does not exist", dir.display()));
+        }
+    }
+
+    // API access
+    if let Some(path) = &config.api.trust_store {
+        if let Err(e) = TrustStore::load(path) {
+            problems.push(format!("trust store {}: {}", path.display(), e));
+        }
+        if config.yacht_id.is_none() {
+            problems.push("api.trust_store is set without yacht_id; mooring stays off".to_string());
+        }
+    }
+    if let Some(path) = &config.api.nonces {
+        if let Err(e) = ReplayGuard::open(path) {
+            problems.push(format!("nonce file {}: {}", path.display(), e));
+        }
+    }
+    let access = match &config.api.clients {
+        Some(path) => match AccessPolicy::load(path) {
+            Ok(access) => Some(access),
+            Err(e) => {
+                problems.push(format!("API clients {}: {}", path.display(), e));
+                None
+            }
+        },
+        None => None,
+    };
+    if let Some(problem) = api_tls_problem(&config.api) {
+        problems.push(problem.to_string());
+    } else if let (Some(cert), Some(key)) = (&config.api.tls_cert, &config.api.tls_key) {
+        if let Err(e) = tls::server_config(cert, key, access.as_ref()) {
+            problems.push(format!("API TLS: {:#}", e));
+        }
+    }
+
+    problems
+}
+
+/// API TLS settings the agent must not start with: half a key pair would
+/// serve plain HTTP, and client certificates without TLS lock every
+/// guarded route
+pub fn api_tls_problem(api: &ApiConfig) -> Option<&'static str> {
+    match (&api.tls_cert, &api.tls_key) {
+        (Some(_), None) | (None, Some(_)) => Some("api.tls_cert and api.tls_key must be set together"),
+        (None, None) if api.clients.is_some() => Some("api.clients needs api.tls_cert and api.tls_key"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wharf_core::agent_config::PolicyRef;
+    use wharf_core::db_policy::DatabasePolicy;
+
+    fn usable() -> (tempfile::TempDir, AgentConfig) {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AgentConfig::default();
+        config.integrity.web_root = dir.path().to_path_buf();
+        config.audit.log = dir.path().join("audit.log");
+        (dir, config)
+    }
+
+    #[test]
+    fn test_defaults_pass() {
+        let (_dir, config) = usable();
+        assert_eq!(check(&config), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_every_problem_is_reported() {
+        let (dir, mut config) = usable();
+        config.protocol = "oracle".to_string();
+        config.listen.socket_mode = "999".to_string();
+        config.api.clients = Some(dir.path().join("clients.toml"));
+        config.airlock.backend = Some("https://127.0.0.1:8443".to_string());
+        config.airlock.max_request_line = 0;
+
+        let mut policy = DatabasePolicy::default();
+        policy.allow_write.push(policy.lock_down[0].clone());
+        config.policy.database = Some(PolicyRef::Inline(policy));
+
+        let problems = check(&config);
+        assert_eq!(problems.len(), 7, "{:?}", problems);
+        assert!(problems[0].contains("oracle"));
+        assert!(problems.iter().any(|p| p.starts_with("policy: ")));
+        assert!(problems.iter().any(|p| p.contains("needs api.tls_cert")));
+        assert!(problems.iter().any(|p| p.contains("airlock backend")));
+        assert!(problems.iter().any(|p| p.contains("max_request_line")));
+    }
+
+    #[test]
+    fn test_api_tls_pairing() {
+        let mut api = ApiConfig::default();
+        assert_eq!(api_tls_problem(&api), None);
+        api.tls_cert = Some("/etc/wharf/api.pem".into());
+        assert!(api_tls_problem(&api).unwrap().contains("set together"));
+        api.tls_cert = None;
+        api.clients = Some("/etc/wharf/clients.toml".into());
+        assert!(api_tls_problem(&api).unwrap().contains("needs api.tls_cert"));
+    }
+}
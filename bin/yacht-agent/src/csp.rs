@@ -0,0 +1,129 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # CSP Violation Reports
+//!
+//! Aggregates the violation reports the airlock collects on the CSP
+//! `report_uri` (see `wharf_core::csp`), by directive and blocked source,
+//! for `/csp/reports` and `wharf policy csp-reports`.
+//!
+//! Browsers send a report for every violation on every page view, so
+//! reports are counted rather than kept. At most `MAX_ENTRIES` distinct
+//! violations are tracked; reports of any others are only counted, which
+//! keeps a flood of made-up reports from growing the agent without bound.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use wharf_core::csp::{CspReportSummary, CspViolation};
+
+/// Distinct violations tracked
+const MAX_ENTRIES: usize = 1000;
+
+/// The violations reported since the agent started
+#[derive(Default)]
+pub struct CspReports {
+    /// By (directive, blocked URI)
+    entries: Mutex<HashMap<(String, String), CspReportSummary>>,
+    received: AtomicU64,
+    untracked: AtomicU64,
+}
+
+impl CspReports {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count the violations of one report
+    pub fn record(&self, violations: Vec<CspViolation>) {
+        let now = chrono::Utc::now().timestamp();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        for violation in violations {
+            self.received.fetch_add(1, Ordering::Relaxed);
+            let key = (violation.directive, violation.blocked_uri);
+            if let Some(entry) = entries.get_mut(&key) {
+                entry.count += 1;
+                entry.last_seen = now;
+                entry.document_uri = violation.document_uri;
+            } else if entries.len() < MAX_ENTRIES {
+                let summary = CspReportSummary {
+                    directive: key.0.clone(),
+                    blocked_uri: key.1.clone(),
+                    count: 1,
+                    first_seen: now,
+                    last_seen: now,
+                    document_uri: violation.document_uri,
+                };
+                entries.insert(key, summary);
+            } else {
+                self.untracked.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Every tracked violation, most reported first
+    pub fn summary(&self) -> Vec<CspReportSummary> {
+        let mut summary: Vec<_> = self
+            .entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .cloned()
+            .collect();
+        summary.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then_with(|| (&a.directive, &a.blocked_uri).cmp(&(&b.directive, &b.blocked_uri)))
+        });
+        summary
+    }
+
+    /// Violations reported since the agent started
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    /// Violations counted but not tracked (the table was full)
+    pub fn untracked(&self) -> u64 {
+        self.untracked.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn violation(directive: &str, blocked: &str) -> CspViolation {
+        CspViolation {
+            document_uri: "https://example.com/".to_string(),
+            directive: directive.to_string(),
+            blocked_uri: blocked.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_aggregated_by_directive_and_source() {
+        let reports = CspReports::new();
+        reports.record(vec![violation("script-src-elem", "https://cdn.test/a.js")]);
+        reports.record(vec![violation("style-src-attr", "inline")]);
+        reports.record(vec![violation("style-src-attr", "inline")]);
+
+        let summary = reports.summary();
+        assert_eq!(summary.len(), 2);
+        assert_eq!(summary[0].directive, "style-src-attr");
+        assert_eq!(summary[0].count, 2);
+        assert_eq!(reports.received(), 3);
+    }
+
+    #[test]
+    fn test_table_is_bounded() {
+        let reports = CspReports::new();
+        let flood = (0..MAX_ENTRIES + 10)
+            .map(|i| violation("img-src", &format!("https://{}.test/", i)))
+            .collect();
+        reports.record(flood);
+        assert_eq!(reports.summary().len(), MAX_ENTRIES);
+        assert_eq!(reports.untracked(), 10);
+    }
+}
@@ -0,0 +1,651 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Request Desync Guard
+//!
+//! Request smuggling works by sending a request that the airlock and the
+//! backend frame differently: one believes the Content-Length, the other
+//! the Transfer-Encoding, and the tail of one request becomes the start of
+//! the next. hyper is strict, but it resolves most ambiguities quietly
+//! (it drops Content-Length next to chunked, folds duplicate lengths) and
+//! by the time a request reaches the airlock's handler the evidence is
+//! gone.
+//!
+//! So the airlock reads every connection through [`Guarded`], which frames
+//! the raw bytes itself before hyper sees them. Each request head is held
+//! back until it is complete and has passed [`check_head`]; bodies are
+//! followed (by length or chunk by chunk) only to find where the next head
+//! starts. A request that can be read more than one way is refused:
+//!
+//! - Content-Length and Transfer-Encoding together
+//! - more than one Content-Length, or one that is not a number
+//! - Transfer-Encoding other than a single `chunked` (or any on HTTP/1.0)
+//! - a chunked body that does not follow the grammar to the letter
+//! - obsolete line folding, bare CR or LF, control or non-ASCII bytes
+//! - a missing or repeated Host, or an absolute-form target naming
+//!   another host than the Host header
+//! - a request line or head over the configured limits
+//!
+//! Requests before the bad one are answered normally. Then hyper is told
+//! the client went away, and once it has written its last response the
+//! guard writes the refusal (400, 414 or 431, with the reason) and closes
+//! the connection; nothing after the bad head is read.
+
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+use hyper::StatusCode;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::warn;
+
+/// Bytes read from the client at a time
+const READ_CHUNK: usize = 8 * 1024;
+
+/// Hex digits allowed in a chunk size
+const MAX_CHUNK_DIGITS: u8 = 16;
+
+/// Why a request was refused before it reached the airlock
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Desync {
+    #[error("both Content-Length and Transfer-Encoding are present")]
+    ConflictingFraming,
+
+    #[error("more than one Content-Length")]
+    DuplicateContentLength,
+
+    #[error("Content-Length is not a number")]
+    InvalidContentLength,
+
+    #[error("Transfer-Encoding other than a single chunked")]
+    InvalidTransferEncoding,
+
+    #[error("malformed chunked body")]
+    InvalidChunk,
+
+    #[error("obsolete line folding")]
+    ObsoleteLineFolding,
+
+    #[error("invalid bytes in the request head")]
+    InvalidHeaderBytes,
+
+    #[error("missing or repeated Host")]
+    AmbiguousHost,
+
+    #[error("absolute-form target does not match Host")]
+    HostMismatch,
+
+    #[error("malformed request line")]
+    InvalidRequestLine,
+
+    #[error("request line too long")]
+    RequestLineTooLong,
+
+    #[error("request head too large")]
+    HeadTooLarge,
+}
+
+impl Desync {
+    /// Every reason, in metrics order
+    pub const ALL: [Desync; 12] = [
+        Self::ConflictingFraming,
+        Self::DuplicateContentLength,
+        Self::InvalidContentLength,
+        Self::InvalidTransferEncoding,
+        Self::InvalidChunk,
+        Self::ObsoleteLineFolding,
+        Self::InvalidHeaderBytes,
+        Self::AmbiguousHost,
+        Self::HostMismatch,
+        Self::InvalidRequestLine,
+        Self::RequestLineTooLong,
+        Self::HeadTooLarge,
+    ];
+
+    /// The reason as a metrics label
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ConflictingFraming => "conflicting_framing",
+            Self::DuplicateContentLength => "duplicate_content_length",
+            Self::InvalidContentLength => "invalid_content_length",
+            Self::InvalidTransferEncoding => "invalid_transfer_encoding",
+            Self::InvalidChunk => "invalid_chunk",
+            Self::ObsoleteLineFolding => "obsolete_line_folding",
+            Self::InvalidHeaderBytes => "invalid_header_bytes",
+            Self::AmbiguousHost => "ambiguous_host",
+            Self::HostMismatch => "host_mismatch",
+            Self::InvalidRequestLine => "invalid_request_line",
+            Self::RequestLineTooLong => "request_line_too_long",
+            Self::HeadTooLarge => "head_too_large",
+        }
+    }
+
+    /// The status the client is refused with
+    pub fn status(self) -> StatusCode {
+        match self {
+            Self::RequestLineTooLong => StatusCode::URI_TOO_LONG,
+            Self::HeadTooLarge => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// The complete response written before the connection is closed
+    fn response(self) -> Vec<u8> {
+        let status = self.status();
+        let body = format!("{}: {}\n", status.canonical_reason().unwrap_or("Rejected"), self);
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\nX-Content-Type-Options: nosniff\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )
+        .into_bytes()
+    }
+}
+
+/// Size limits on a request head
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeadLimits {
+    /// Longest request line (method, target and version)
+    pub request_line: usize,
+    /// Largest head (request line and all headers)
+    pub head: usize,
+}
+
+impl Default for HeadLimits {
+    fn default() -> Self {
+        Self {
+            request_line: 8 * 1024,
+            head: 64 * 1024,
+        }
+    }
+}
+
+/// Refusals by reason, for `/metrics`
+#[derive(Default)]
+pub struct DesyncCounters([AtomicU64; Desync::ALL.len()]);
+
+impl DesyncCounters {
+    pub fn record(&self, reason: Desync) {
+        self.0[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, reason: Desync) -> u64 {
+        self.0[reason as usize].load(Ordering::Relaxed)
+    }
+}
+
+// =============================================================================
+// HEAD CHECKS
+// =============================================================================
+
+/// How the body of a checked request is framed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    Length(u64),
+    Chunked,
+}
+
+fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+fn is_field_byte(b: u8) -> bool {
+    (b' '..=b'~').contains(&b) || b == b'\t'
+}
+
+/// Check a complete request head (request line through the blank line)
+pub fn check_head(head: &[u8], limits: HeadLimits) -> Result<Framing, Desync> {
+    let head = head.strip_suffix(b"\r\n\r\n").ok_or(Desync::InvalidHeaderBytes)?;
+    if head.len() + 4 > limits.head {
+        return Err(Desync::HeadTooLarge);
+    }
+    let bare = head.iter().enumerate().any(|(i, &b)| match b {
+        b'\n' => i == 0 || head[i - 1] != b'\r',
+        b'\r' => head.get(i + 1) != Some(&b'\n'),
+        _ => false,
+    });
+    if bare {
+        return Err(Desync::InvalidHeaderBytes);
+    }
+    let mut lines = head.split(|&b| b == b'\n').map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+    let request_line = lines.next().unwrap_or_default();
+    if request_line.len() > limits.request_line {
+        return Err(Desync::RequestLineTooLong);
+    }
+    if request_line.iter().any(|&b| !(b' '..=b'~').contains(&b)) {
+        return Err(Desync::InvalidHeaderBytes);
+    }
+
+    let mut parts = request_line.split(|&b| b == b' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version), None) => (method, target, version),
+        _ => return Err(Desync::InvalidRequestLine),
+    };
+    let http10 = match version {
+        b"HTTP/1.1" => false,
+        b"HTTP/1.0" => true,
+        _ => return Err(Desync::InvalidRequestLine),
+    };
+    // The airlock tunnels nothing
+    if method.is_empty() || !method.iter().all(|&b| is_tchar(b)) || method == b"CONNECT" {
+        return Err(Desync::InvalidRequestLine);
+    }
+    let authority = request_authority(method, target)?;
+
+    let mut content_length = Vec::new();
+    let mut transfer_encoding = Vec::new();
+    let mut hosts = Vec::new();
+    for line in lines {
+        if line.first().is_some_and(|&b| b == b' ' || b == b'\t') {
+            return Err(Desync::ObsoleteLineFolding);
+        }
+        let colon = line.iter().position(|&b| b == b':').ok_or(Desync::InvalidHeaderBytes)?;
+        let (name, value) = (&line[..colon], &line[colon + 1..]);
+        // Whitespace before the colon is how "Transfer-Encoding : chunked" hides
+        if name.is_empty() || !name.iter().all(|&b| is_tchar(b)) || !value.iter().all(|&b| is_field_byte(b)) {
+            return Err(Desync::InvalidHeaderBytes);
+        }
+        let value = value.trim_ascii();
+        if name.eq_ignore_ascii_case(b"content-length") {
+            content_length.push(value);
+        } else if name.eq_ignore_ascii_case(b"transfer-encoding") {
+            transfer_encoding.push(value);
+        } else if name.eq_ignore_ascii_case(b"host") {
+            hosts.push(value);
+        }
+    }
+
+    match (hosts.as_slice(), authority) {
+        ([], _) if http10 => {}
+        ([host], Some(authority)) if !host.eq_ignore_ascii_case(authority) => return Err(Desync::HostMismatch),
+        ([_], _) => {}
+        _ => return Err(Desync::AmbiguousHost),
+    }
+
+    if !transfer_encoding.is_empty() {
+        if !content_length.is_empty() {
+            return Err(Desync::ConflictingFraming);
+        }
+        if http10 || transfer_encoding.len() > 1 || !transfer_encoding[0].eq_ignore_ascii_case(b"chunked") {
+            return Err(Desync::InvalidTransferEncoding);
+        }
+        return Ok(Framing::Chunked);
+    }
+    match content_length.as_slice() {
+        [] => Ok(Framing::Length(0)),
+        [value] if value.contains(&b',') => Err(Desync::DuplicateContentLength),
+        [value] => parse_length(value).map(Framing::Length),
+        _ => Err(Desync::DuplicateContentLength),
+    }
+}
+
+/// The authority of an absolute-form target; `None` for origin or asterisk form
+fn request_authority<'a>(method: &[u8], target: &'a [u8]) -> Result<Option<&'a [u8]>, Desync> {
+    if target.first() == Some(&b'/') {
+        return Ok(None);
+    }
+    if target == b"*" && method == b"OPTIONS" {
+        return Ok(None);
+    }
+    let rest = [&b"http://"[..], b"https://"]
+        .iter()
+        .find(|scheme| target.len() > scheme.len() && target[..scheme.len()].eq_ignore_ascii_case(scheme))
+        .map(|scheme| &target[scheme.len()..])
+        .ok_or(Desync::InvalidRequestLine)?;
+    let end = rest.iter().position(|&b| b"/?#".contains(&b)).unwrap_or(rest.len());
+    let authority = &rest[..end];
+    if authority.is_empty() || authority.contains(&b'@') {
+        return Err(Desync::InvalidRequestLine);
+    }
+    Ok(Some(authority))
+}
+
+fn parse_length(value: &[u8]) -> Result<u64, Desync> {
+    if value.is_empty() || value.len() > 19 || !value.iter().all(u8::is_ascii_digit) {
+        return Err(Desync::InvalidContentLength);
+    }
+    Ok(value.iter().fold(0, |n, &d| n * 10 + u64::from(d - b'0')))
+}
+
+// =============================================================================
+// FRAMING
+// =============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Reading a request head
+    Head,
+    /// Bytes left of a fixed-length body
+    Length(u64),
+    /// Chunk size digits (and whether an extension has started)
+    ChunkSize { digits: u8, size: u64, extension: bool },
+    ChunkSizeLf { size: u64 },
+    ChunkData(u64),
+    ChunkDataCr,
+    ChunkDataLf,
+    /// Trailer line of this many bytes so far
+    Trailer(usize),
+    TrailerLf { empty: bool },
+}
+
+/// Follows the requests on one connection, byte by byte
+pub struct Framer {
+    limits: HeadLimits,
+    state: State,
+    head: Vec<u8>,
+    /// Whether the head so far has a complete request line
+    line_done: bool,
+}
+
+impl Framer {
+    pub fn new(limits: HeadLimits) -> Self {
+        Self {
+            limits,
+            state: State::Head,
+            head: Vec::new(),
+            line_done: false,
+        }
+    }
+
+    /// Take bytes from the client; what may be passed on is appended to `out`
+    ///
+    /// Head bytes are only passed on once the whole head has been checked.
+    pub fn feed(&mut self, mut input: &[u8], out: &mut Vec<u8>) -> Result<(), Desync> {
+        while let Some((&b, rest)) = input.split_first() {
+            match self.state {
+                State::Head => {
+                    self.head_byte(b, out)?;
+                    input = rest;
+                }
+                State::Length(left) | State::ChunkData(left) => {
+                    let n = input.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+                    out.extend_from_slice(&input[..n]);
+                    input = &input[n..];
+                    let left = left - n as u64;
+                    self.state = match (self.state, left) {
+                        (State::Length(_), 0) => State::Head,
+                        (State::Length(_), left) => State::Length(left),
+                        (_, 0) => State::ChunkDataCr,
+                        (_, left) => State::ChunkData(left),
+                    };
+                }
+                _ => {
+                    self.chunk_byte(b)?;
+                    out.push(b);
+                    input = rest;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn head_byte(&mut self, b: u8, out: &mut Vec<u8>) -> Result<(), Desync> {
+        let after_cr = self.head.last() == Some(&b'\r');
+        if (b == b'\n') != after_cr {
+            return Err(Desync::InvalidHeaderBytes);
+        }
+        self.head.push(b);
+
+        if b == b'\n' {
+            // Empty lines before a request are tolerated and dropped
+            if self.head.len() == 2 {
+                self.head.clear();
+                return Ok(());
+            }
+            self.line_done = true;
+            if self.head.ends_with(b"\r\n\r\n") {
+                let framing = check_head(&self.head, self.limits)?;
+                out.append(&mut self.head);
+                self.line_done = false;
+                self.state = match framing {
+                    Framing::Length(0) => State::Head,
+                    Framing::Length(n) => State::Length(n),
+                    Framing::Chunked => State::ChunkSize { digits: 0, size: 0, extension: false },
+                };
+            }
+        } else if !self.line_done && self.head.len() > self.limits.request_line + 1 {
+            return Err(Desync::RequestLineTooLong);
+        } else if self.head.len() > self.limits.head {
+            return Err(Desync::HeadTooLarge);
+        }
+        Ok(())
+    }
+
+    fn chunk_byte(&mut self, b: u8) -> Result<(), Desync> {
+        self.state = match (self.state, b) {
+            (State::ChunkSize { digits, size, extension: false }, b) if b.is_ascii_hexdigit() => {
+                if digits == MAX_CHUNK_DIGITS {
+                    return Err(Desync::InvalidChunk);
+                }
+                let digit = (b as char).to_digit(16).unwrap_or_default();
+                State::ChunkSize { digits: digits + 1, size: (size << 4) | u64::from(digit), extension: false }
+            }
+            (State::ChunkSize { digits, size, .. }, b';') if digits > 0 => {
+                State::ChunkSize { digits, size, extension: true }
+            }
+            (State::ChunkSize { digits, size, .. }, b'\r') if digits > 0 => State::ChunkSizeLf { size },
+            (State::ChunkSize { extension: true, .. }, b) if is_field_byte(b) => self.state,
+            (State::ChunkSizeLf { size: 0 }, b'\n') => State::Trailer(0),
+            (State::ChunkSizeLf { size }, b'\n') => State::ChunkData(size),
+            (State::ChunkDataCr, b'\r') => State::ChunkDataLf,
+            (State::ChunkDataLf, b'\n') => State::ChunkSize { digits: 0, size: 0, extension: false },
+            (State::Trailer(len), b'\r') => State::TrailerLf { empty: len == 0 },
+            (State::Trailer(len), b) if is_field_byte(b) && len < self.limits.head => State::Trailer(len + 1),
+            (State::TrailerLf { empty: true }, b'\n') => State::Head,
+            (State::TrailerLf { empty: false }, b'\n') => State::Trailer(0),
+            _ => return Err(Desync::InvalidChunk),
+        };
+        Ok(())
+    }
+}
+
+// =============================================================================
+// CONNECTION
+// =============================================================================
+
+/// A client connection read through a [`Framer`]
+pub struct Guarded<S> {
+    inner: S,
+    framer: Framer,
+    /// Checked bytes not yet read by hyper
+    ready: Vec<u8>,
+    offset: usize,
+    refused: Option<Desync>,
+    /// The refusal still to be written, and how much of it has been
+    refusal: Option<(Vec<u8>, usize)>,
+    counters: Arc<DesyncCounters>,
+    peer: SocketAddr,
+}
+
+impl<S> Guarded<S> {
+    pub fn new(inner: S, limits: HeadLimits, counters: Arc<DesyncCounters>, peer: SocketAddr) -> Self {
+        Self {
+            inner,
+            framer: Framer::new(limits),
+            ready: Vec::new(),
+            offset: 0,
+            refused: None,
+            refusal: None,
+            counters,
+            peer,
+        }
+    }
+
+    fn refuse(&mut self, reason: Desync) {
+        warn!("Airlock refused a request from {}: {}", self.peer, reason);
+        self.counters.record(reason);
+        self.refused = Some(reason);
+        self.refusal = Some((reason.response(), 0));
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Guarded<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.offset < this.ready.len() {
+                let n = buf.remaining().min(this.ready.len() - this.offset);
+                buf.put_slice(&this.ready[this.offset..this.offset + n]);
+                this.offset += n;
+                if this.offset == this.ready.len() {
+                    this.ready.clear();
+                    this.offset = 0;
+                }
+                return Poll::Ready(Ok(()));
+            }
+            // hyper sees the end of the stream where the bad request began
+            if this.refused.is_some() {
+                return Poll::Ready(Ok(()));
+            }
+
+            let mut chunk = [0u8; READ_CHUNK];
+            let mut read = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read))?;
+            if read.filled().is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            if let Err(reason) = this.framer.feed(read.filled(), &mut this.ready) {
+                this.refuse(reason);
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Guarded<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    /// hyper is done with the connection; write the refusal first, if any
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while let Some((response, written)) = this.refusal.as_mut() {
+            if *written == response.len() {
+                ready!(Pin::new(&mut this.inner).poll_flush(cx))?;
+                this.refusal = None;
+                break;
+            }
+            let n = ready!(Pin::new(&mut this.inner).poll_write(cx, &response[*written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            *written += n;
+        }
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(head: &str) -> Result<Framing, Desync> {
+        check_head(head.as_bytes(), HeadLimits::default())
+    }
+
+    /// Everything `feed` passed on, or the first refusal
+    fn frame(input: &[u8]) -> Result<Vec<u8>, Desync> {
+        let mut framer = Framer::new(HeadLimits::default());
+        let mut out = Vec::new();
+        // One byte at a time, as the worst a network can do
+        for b in input {
+            framer.feed(std::slice::from_ref(b), &mut out)?;
+        }
+        Ok(out)
+    }
+
+    #[test]
+    fn test_plain_requests_pass() {
+        assert_eq!(check("GET /index.php HTTP/1.1\r\nHost: example.com\r\n\r\n"), Ok(Framing::Length(0)));
+        assert_eq!(
+            check("POST /wp-login.php HTTP/1.1\r\nHost: example.com\r\nContent-Length:  12 \r\n\r\n"),
+            Ok(Framing::Length(12))
+        );
+        assert_eq!(
+            check("POST / HTTP/1.1\r\nHost: example.com\r\nTransfer-Encoding: Chunked\r\n\r\n"),
+            Ok(Framing::Chunked)
+        );
+        assert_eq!(check("GET http://Example.com/ HTTP/1.1\r\nHost: example.com\r\n\r\n"), Ok(Framing::Length(0)));
+        assert_eq!(check("OPTIONS * HTTP/1.1\r\nHost: example.com\r\n\r\n"), Ok(Framing::Length(0)));
+        assert_eq!(check("GET / HTTP/1.0\r\n\r\n"), Ok(Framing::Length(0)));
+    }
+
+    #[test]
+    fn test_ambiguous_framing_refused() {
+        let host = "Host: example.com\r\n";
+        for (extra, reason) in [
+            ("Content-Length: 5\r\nTransfer-Encoding: chunked\r\n", Desync::ConflictingFraming),
+            ("Content-Length: 5\r\nContent-Length: 5\r\n", Desync::DuplicateContentLength),
+            ("Content-Length: 5, 6\r\n", Desync::DuplicateContentLength),
+            ("Content-Length: +5\r\n", Desync::InvalidContentLength),
+            ("Content-Length: 0x10\r\n", Desync::InvalidContentLength),
+            ("Transfer-Encoding: gzip, chunked\r\n", Desync::InvalidTransferEncoding),
+            ("Transfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n", Desync::InvalidTransferEncoding),
+            ("Transfer-Encoding : chunked\r\n", Desync::InvalidHeaderBytes),
+            ("X-Padding: a\r\n chunked\r\n", Desync::ObsoleteLineFolding),
+            ("X-Padding: caf\u{e9}\r\n", Desync::InvalidHeaderBytes),
+            ("X-Padding: a\x01b\r\n", Desync::InvalidHeaderBytes),
+            ("Host: evil.test\r\n", Desync::AmbiguousHost),
+        ] {
+            let head = format!("POST / HTTP/1.1\r\n{}{}\r\n", host, extra);
+            assert_eq!(check(&head), Err(reason), "{:?}", extra);
+        }
+
+        assert_eq!(check("POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n"), Err(Desync::InvalidTransferEncoding));
+        assert_eq!(check("GET / HTTP/1.1\r\n\r\n"), Err(Desync::AmbiguousHost));
+        assert_eq!(check("GET http://evil.test/ HTTP/1.1\r\nHost: example.com\r\n\r\n"), Err(Desync::HostMismatch));
+        assert_eq!(check("GET ftp://example.com/ HTTP/1.1\r\nHost: example.com\r\n\r\n"), Err(Desync::InvalidRequestLine));
+        assert_eq!(check("GET  / HTTP/1.1\r\nHost: example.com\r\n\r\n"), Err(Desync::InvalidRequestLine));
+        assert_eq!(check("CONNECT example.com:443 HTTP/1.1\r\nHost: example.com\r\n\r\n"), Err(Desync::InvalidRequestLine));
+    }
+
+    #[test]
+    fn test_framer_finds_every_head() {
+        let requests = b"\r\nPOST /a HTTP/1.1\r\nHost: x\r\nContent-Length: 4\r\n\r\nGET POST /b HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n3;ext=1\r\nGET\r\n0\r\nX-Trailer: 1\r\n\r\nGET /c HTTP/1.1\r\nHost: x\r\n\r\n";
+        // The leading empty line is the only thing not passed on
+        assert_eq!(frame(requests).unwrap(), &requests[2..]);
+
+        // A head hidden in a body is only a body
+        let smuggled = b"POST /a HTTP/1.1\r\nHost: x\r\nContent-Length: 32\r\n\r\nGET /admin HTTP/1.1\r\nHost: x\r\n\r\n";
+        assert_eq!(frame(smuggled).unwrap(), &smuggled[..]);
+
+        // Bad chunks and bare line ends
+        assert_eq!(
+            frame(b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n3\nabc\r\n0\r\n\r\n"),
+            Err(Desync::InvalidChunk)
+        );
+        assert_eq!(
+            frame(b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabcd\r\n"),
+            Err(Desync::InvalidChunk)
+        );
+        assert_eq!(frame(b"GET / HTTP/1.1\nHost: x\n\n"), Err(Desync::InvalidHeaderBytes));
+        assert_eq!(frame(b"GET / HTTP/1.1\r\nHost: x\rX: y\r\n\r\n"), Err(Desync::InvalidHeaderBytes));
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = HeadLimits {
+            request_line: 32,
+            head: 128,
+        };
+        let mut out = Vec::new();
+        let long_line = format!("GET /{} HTTP/1.1\r\n", "a".repeat(40));
+        assert_eq!(Framer::new(limits).feed(long_line.as_bytes(), &mut out), Err(Desync::RequestLineTooLong));
+
+        let big_head = format!("GET / HTTP/1.1\r\nHost: x\r\nCookie: {}\r\n", "a".repeat(200));
+        assert_eq!(Framer::new(limits).feed(big_head.as_bytes(), &mut out), Err(Desync::HeadTooLarge));
+        assert!(out.is_empty());
+
+        assert_eq!(Desync::RequestLineTooLong.status(), StatusCode::URI_TOO_LONG);
+        assert_eq!(Desync::HeadTooLarge.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    }
+}
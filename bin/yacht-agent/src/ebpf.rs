@@ -9,13 +9,16 @@
 //! 2. Attaches it to the XDP hook on the network interface
 //! 3. Populates the maps (allowed ports, blocklist)
 //! 4. Provides runtime updates to the blocklist
+//! 5. Reads the per-CPU packet counters for the metrics endpoint
 
 use anyhow::{Context, Result};
-use aya::maps::HashMap;
+use aya::maps::{HashMap, PerCpuArray};
+use aya::programs::xdp::XdpLinkId;
 use aya::programs::{Xdp, XdpFlags};
 use aya::Bpf;
 use std::net::Ipv4Addr;
 use std::path::Path;
+use std::sync::Mutex;
 use tracing::{info, warn};
 
 /// Default allowed TCP ports for Yacht
@@ -30,12 +33,28 @@
     4242,  // Nebula mesh VPN
 ];
 
+/// XDP action codes, the index into the PACKET_STATS map
+const XDP_ABORTED: u32 = 0;
+const XDP_DROP: u32 = 1;
+const XDP_PASS: u32 = 2;
+
+/// Packets the shield has judged since it was attached
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub struct PacketCounts {
+    pub passed: u64,
+    pub dropped: u64,
+    /// Packets the program failed to parse (dropped, fail-closed)
+    pub aborted: u64,
+}
+
 /// eBPF Shield Manager
 pub struct Shield {
     /// The loaded BPF program
     bpf: Bpf,
     /// Interface the XDP program is attached to
     interface: String,
+    /// The XDP attachment, for detaching on shutdown
+    link_id: Option<XdpLinkId>,
 }
 
 impl Shield {
@@ -83,6 +102,7 @@ pub fn load(ebpf_path: &Path, interface: &str) -> Result<Self> {
         let mut shield = Self {
             bpf,
             interface: interface.to_string(),
+            link_id: Some(attach_result),
         };
 
         // Configure default allowed ports
@@ -196,10 +216,102 @@ pub fn load_blocklist(&mut self, path: &Path) -> Result<usize> {
         Ok(count)
     }
 
+    /// Read the packet counters (summed over all CPUs)
+    pub fn packet_counts(&self) -> Result<PacketCounts> {
+        let stats: PerCpuArray<_, u64> = PerCpuArray::try_from(
+            self.bpf.map("PACKET_STATS")
+                .context("Packet stats map not found")?
+        )?;
+        let total = |action: u32| -> Result<u64> {
+            Ok(stats.get(&action, 0)?.iter().sum())
+        };
+
+        Ok(PacketCounts {
+            passed: total(XDP_PASS)?,
+            dropped: total(XDP_DROP)?,
+            aborted: total(XDP_ABORTED)?,
+        })
+    }
+
     /// Get the interface this shield is attached to
     pub fn interface(&self) -> &str {
         &self.interface
     }
+
+    /// Detach the XDP program from the interface
+    ///
+    /// Dropping the shield also detaches it; this does it explicitly so a
+    /// failure is reported instead of silently leaving the program attached.
+    pub fn detach(mut self) -> Result<()> {
+        let Some(link_id) = self.link_id.take() else {
+            return Ok(());
+        };
+        let program: &mut Xdp = self
+            .bpf
+            .program_mut("wharf_shield")
+            .context("XDP program 'wharf_shield' not found")?
+            .try_into()
+            .context("Failed to convert to XDP program")?;
+        program
+            .detach(link_id)
+            .context("Failed to detach XDP program")?;
+        info!("eBPF XDP firewall detached from {}", self.interface);
+        Ok(())
+    }
+}
+
+/// The active firewall, shared between the API and shutdown
+pub struct ShieldMonitor {
+    mode: String,
+    shield: Mutex<Option<Shield>>,
+}
+
+impl ShieldMonitor {
+    /// `shield` is `None` unless the XDP shield is attached
+    pub fn new(mode: &str, shield: Option<Shield>) -> Self {
+        Self {
+            mode: mode.to_string(),
+            shield: Mutex::new(shield),
+        }
+    }
+
+    /// The firewall mode in effect (ebpf, nftables or none)
+    pub fn mode(&self) -> &str {
+        &self.mode
+    }
+
+    /// Packet counters, if the XDP shield is attached
+    pub fn packet_counts(&self) -> Option<PacketCounts> {
+        let shield = self.shield.lock().unwrap_or_else(|e| e.into_inner());
+        match shield.as_ref()?.packet_counts() {
+            Ok(counts) => Some(counts),
+            Err(e) => {
+                warn!("Failed to read shield packet counters: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Add an address to the XDP blocklist
+    pub fn block_ip(&self, ip: Ipv4Addr) -> Result<()> {
+        match self.shield.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            Some(shield) => shield.block_ip(ip),
+            None => anyhow::bail!("the blocklist needs the XDP shield (firewall mode is {})", self.mode),
+        }
+    }
+
+    /// Remove an address from the XDP blocklist
+    pub fn unblock_ip(&self, ip: Ipv4Addr) -> Result<()> {
+        match self.shield.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            Some(shield) => shield.unblock_ip(ip),
+            None => anyhow::bail!("the blocklist needs the XDP shield (firewall mode is {})", self.mode),
+        }
+    }
+
+    /// Take the shield out for detaching
+    pub fn take(&self) -> Option<Shield> {
+        self.shield.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
 }
 
 /// Try to load the eBPF shield, returning None if it fails
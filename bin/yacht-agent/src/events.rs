@@ -0,0 +1,129 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Event Bus
+//!
+//! Fans security events (see `wharf_core::events`) out to `/events`
+//! subscribers. Publishing never waits: events go into a bounded broadcast
+//! ring, and a subscriber that falls behind is told how many it missed
+//! instead of holding up the proxy.
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use axum::response::sse::Event;
+use futures_util::stream::{self, Stream};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use wharf_core::events::{EventRecord, SecurityEvent};
+
+use crate::lifecycle::ShutdownSignal;
+
+/// Events buffered for slow subscribers
+const CAPACITY: usize = 1024;
+
+/// Publishes security events to whoever is subscribed
+pub struct EventBus {
+    sender: broadcast::Sender<Arc<EventRecord>>,
+    seq: AtomicU64,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self {
+            sender: broadcast::Sender::new(CAPACITY),
+            seq: AtomicU64::new(0),
+        }
+    }
+
+    /// Publish an event (dropped if nobody is listening)
+    pub fn publish(&self, event: SecurityEvent) {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        if self.sender.receiver_count() > 0 {
+            let _ = self.sender.send(Arc::new(EventRecord::now(seq, event)));
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<EventRecord>> {
+        self.sender.subscribe()
+    }
+
+    /// Events published since the agent started
+    pub fn published(&self) -> u64 {
+        self.seq.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Turn a subscription into SSE events, ending at shutdown
+pub fn sse_stream(
+    receiver: broadcast::Receiver<Arc<EventRecord>>,
+    shutdown: Option<ShutdownSignal>,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    let stop: Pin<Box<dyn Future<Output = ()> + Send>> = match shutdown {
+        Some(signal) => Box::pin(signal.wait()),
+        None => Box::pin(std::future::pending()),
+    };
+
+    stream::unfold((receiver, stop), |(mut receiver, mut stop)| async move {
+        let event = tokio::select! {
+            _ = &mut stop => return None,
+            received = receiver.recv() => match received {
+                Ok(record) => Event::default()
+                    .id(record.seq.to_string())
+                    .event(record.event.name())
+                    .json_data(&*record)
+                    .unwrap_or_else(|_| Event::default().comment("unserializable event")),
+                Err(RecvError::Lagged(missed)) => Event::default().comment(format!("missed {} events", missed)),
+                Err(RecvError::Closed) => return None,
+            },
+        };
+        Some((Ok(event), (receiver, stop)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn unblock(last: u8) -> SecurityEvent {
+        SecurityEvent::ShieldUnblock {
+            ip: Ipv4Addr::new(192, 0, 2, last),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_subscribers_see_later_events() {
+        let bus = EventBus::new();
+        bus.publish(unblock(1));
+
+        let mut receiver = bus.subscribe();
+        bus.publish(unblock(2));
+
+        let record = receiver.recv().await.unwrap();
+        assert_eq!(record.seq, 1);
+        assert_eq!(record.event, unblock(2));
+        assert_eq!(bus.published(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_slow_subscriber_is_told_what_it_missed() {
+        let bus = EventBus::new();
+        let mut receiver = bus.subscribe();
+        for _ in 0..CAPACITY + 10 {
+            bus.publish(unblock(1));
+        }
+
+        assert!(matches!(receiver.recv().await, Err(RecvError::Lagged(10))));
+        assert_eq!(receiver.recv().await.unwrap().seq, 10);
+    }
+}
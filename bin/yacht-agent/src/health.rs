@@ -0,0 +1,244 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Health Registry
+//!
+//! Each subsystem reports whether it is healthy, degraded or failed, and
+//! why. Components with state of their own (the shadow database breaker,
+//! the shield, the audit writer, integrity) implement [`HealthCheck`] and
+//! are asked at request time; tasks such as the database proxy listeners
+//! report their own status when it changes.
+//!
+//! The API turns this into three endpoints:
+//!
+//! - `/health`: every component and the worst status; 503 once anything
+//!   has failed, so a load balancer takes the yacht out of rotation.
+//! - `/health/ready`: whether the agent should get traffic (nothing has
+//!   failed and it is not shutting down).
+//! - `/health/live`: whether the process is alive at all; a degraded or
+//!   failed dependency is not a reason to restart the agent.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use serde::Serialize;
+
+use crate::ebpf::ShieldMonitor;
+use crate::shadow::{CircuitState, ShadowDb};
+use crate::state::{AgentState, IntegrityStatus};
+
+/// How a component is doing, from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Healthy,
+    /// Working, but with less protection or capacity than configured
+    Degraded,
+    /// Not doing its job
+    Failed,
+}
+
+impl HealthStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Healthy => "healthy",
+            Self::Degraded => "degraded",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// A component's status and, unless healthy, the reason
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ComponentHealth {
+    pub status: HealthStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl ComponentHealth {
+    pub fn healthy() -> Self {
+        Self {
+            status: HealthStatus::Healthy,
+            reason: None,
+        }
+    }
+
+    pub fn degraded(reason: impl Into<String>) -> Self {
+        Self {
+            status: HealthStatus::Degraded,
+            reason: Some(reason.into()),
+        }
+    }
+
+    pub fn failed(reason: impl Into<String>) -> Self {
+        Self {
+            status: HealthStatus::Failed,
+            reason: Some(reason.into()),
+        }
+    }
+}
+
+/// Something whose health can be read at request time
+pub trait HealthCheck: Send + Sync {
+    fn check(&self) -> ComponentHealth;
+}
+
+/// The health of every component, as served on `/health`
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    /// The worst component status
+    pub status: HealthStatus,
+    pub components: BTreeMap<String, ComponentHealth>,
+}
+
+/// The components behind `/health`
+#[derive(Default)]
+pub struct HealthRegistry {
+    checks: Vec<(String, Arc<dyn HealthCheck>)>,
+    reported: RwLock<BTreeMap<String, ComponentHealth>>,
+}
+
+impl HealthRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a component that is checked on every request
+    pub fn register(&mut self, name: &str, check: Arc<dyn HealthCheck>) {
+        self.checks.push((name.to_string(), check));
+    }
+
+    /// Record the status a task reports for itself
+    pub fn report(&self, name: &str, health: ComponentHealth) {
+        self.reported
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(name.to_string(), health);
+    }
+
+    /// The current health of every component
+    pub fn report_all(&self) -> HealthReport {
+        let mut components = self.reported.read().unwrap_or_else(|e| e.into_inner()).clone();
+        for (name, check) in &self.checks {
+            components.insert(name.clone(), check.check());
+        }
+        let status = components
+            .values()
+            .map(|c| c.status)
+            .max()
+            .unwrap_or(HealthStatus::Healthy);
+        HealthReport { status, components }
+    }
+}
+
+// =============================================================================
+// COMPONENT CHECKS
+// =============================================================================
+
+impl HealthCheck for ShadowDb {
+    fn check(&self) -> ComponentHealth {
+        match self.breaker().state() {
+            CircuitState::Closed => ComponentHealth::healthy(),
+            CircuitState::HalfOpen => ComponentHealth::degraded("shadow database recovering (circuit half-open)"),
+            CircuitState::Open => ComponentHealth::failed(format!(
+                "shadow database at {} unreachable (circuit open)",
+                self.config().endpoint
+            )),
+        }
+    }
+}
+
+impl HealthCheck for ShieldMonitor {
+    fn check(&self) -> ComponentHealth {
+        match self.mode() {
+            "ebpf" => ComponentHealth::healthy(),
+            "none" => ComponentHealth::degraded("firewall disabled"),
+            _ => ComponentHealth::degraded("XDP shield not attached; nftables rules are only validated (dry-run)"),
+        }
+    }
+}
+
+/// The web root's integrity, as of the last check
+pub struct IntegrityCheck(pub Arc<AgentState>);
+
+impl HealthCheck for IntegrityCheck {
+    fn check(&self) -> ComponentHealth {
+        match self.0.integrity_status() {
+            IntegrityStatus::Failed => ComponentHealth::failed("web root does not match the manifest"),
+            IntegrityStatus::Unknown | IntegrityStatus::Verified => ComponentHealth::healthy(),
+        }
+    }
+}
+
+/// The audit writer of the shared state
+pub struct AuditCheck(pub Arc<AgentState>);
+
+impl HealthCheck for AuditCheck {
+    fn check(&self) -> ComponentHealth {
+        let audit = &self.0.audit;
+        if !audit.is_running() {
+            return ComponentHealth::failed("audit writer stopped");
+        }
+        match audit.dropped() {
+            0 => ComponentHealth::healthy(),
+            dropped => ComponentHealth::degraded(format!("{} audit events dropped", dropped)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::net::Endpoint;
+    use crate::shadow::ShadowConfig;
+
+    fn shadow() -> Arc<ShadowDb> {
+        Arc::new(ShadowDb::new(ShadowConfig {
+            endpoint: Endpoint::Tcp("127.0.0.1:1".to_string()),
+            max_connections: 1,
+            connect_timeout: Duration::from_millis(100),
+            probe_interval: Duration::from_secs(1),
+            failure_threshold: 1,
+            open_duration: Duration::from_secs(60),
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn test_worst_component_wins() {
+        let shadow = shadow();
+        let mut registry = HealthRegistry::new();
+        registry.register("shadow_db", shadow.clone());
+        registry.register("shield", Arc::new(ShieldMonitor::new("none", None)));
+        assert_eq!(registry.report_all().status, HealthStatus::Degraded);
+
+        shadow.breaker().record_failure();
+        let report = registry.report_all();
+        assert_eq!(report.status, HealthStatus::Failed);
+        assert!(report.components["shadow_db"].reason.as_deref().unwrap().contains("127.0.0.1:1"));
+    }
+
+    #[test]
+    fn test_tasks_report_their_own_status() {
+        let registry = HealthRegistry::new();
+        assert_eq!(registry.report_all().status, HealthStatus::Healthy);
+
+        registry.report("db_proxy", ComponentHealth::failed("listener stopped"));
+        registry.report("db_proxy", ComponentHealth::healthy());
+        let report = registry.report_all();
+        assert_eq!(report.status, HealthStatus::Healthy);
+        assert_eq!(report.components.len(), 1);
+    }
+
+    #[test]
+    fn test_failed_integrity_fails_health() {
+        let state = Arc::new(AgentState::new());
+        let check = IntegrityCheck(state.clone());
+        assert_eq!(check.check(), ComponentHealth::healthy());
+        state.set_integrity_status(IntegrityStatus::Failed);
+        assert_eq!(check.check().status, HealthStatus::Failed);
+    }
+}
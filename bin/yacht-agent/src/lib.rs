@@ -0,0 +1,55 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Yacht Agent Library
+//!
+//! The runtime components of the Yacht Agent. They live in a library so the
+//! `yacht-agent` binary, the benchmarks and the tests all drive the same code.
+//!
+//! - `api`: The agent HTTP API (routes and server)
+//! - `access`, `tls`: Client certificates and per-route roles for the API
+//! - `config`: Configuration checks (`--check-config`)
+//! - `state`: Shared agent state (policy engines, statistics)
+//! - `stats`: Sharded counters and histograms for the hot path
+//! - `health`: Component health behind `/health`, `/health/ready` and `/health/live`
+//! - `metrics`: Prometheus exposition of the live counters
+//! - `audit`: The hash-chained security audit log
+//! - `events`: The live security event feed (`/events`)
+//! - `supervisor`: Restarts enforcement tasks, fails closed when it cannot
+//! - `lifecycle`: Signal handling (graceful shutdown, policy reload)
+//! - `policy`: Policy versions, rollback and post-push health probes
+//! - `moor`: Signed commands from the Wharf
+//! - `proxy`: The database proxy ("Virtual Sharding")
+//! - `airlock`: The HTTP header airlock (reverse proxy to the web backend)
+//! - `desync`: Request smuggling defenses, in front of the airlock
+//! - `ratelimit`: Per-client token buckets for the airlock's route classes
+//! - `csp`: CSP violation reports collected by the airlock
+//! - `mysql`, `postgres`: Wire protocol framing for the proxy
+//! - `net`: TCP and Unix socket transports for the proxy
+//! - `shadow`: Shadow database connection limits and circuit breaker
+//! - `ebpf`: The XDP shield loader
+
+pub mod access;
+pub mod airlock;
+pub mod api;
+pub mod audit;
+pub mod config;
+pub mod csp;
+pub mod desync;
+pub mod ebpf;
+pub mod events;
+pub mod health;
+pub mod lifecycle;
+pub mod metrics;
+pub mod moor;
+pub mod mysql;
+pub mod net;
+pub mod policy;
+pub mod postgres;
+pub mod proxy;
+pub mod ratelimit;
+pub mod shadow;
+pub mod state;
+pub mod stats;
+pub mod supervisor;
+pub mod tls;
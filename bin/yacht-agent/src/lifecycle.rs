@@ -0,0 +1,302 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Agent Lifecycle
+//!
+//! Signal handling for the agent:
+//!
+//! - **SIGTERM / SIGINT**: stop accepting connections, let in-flight database
+//!   sessions finish up to a deadline, then exit (detaching the shield).
+//! - **SIGHUP**: re-read the config file's policies and the policy files
+//!   and swap them in. Every file is
+//!   parsed before anything is swapped, so a bad edit leaves the running
+//!   policy untouched. The result is recorded as a new policy version (see
+//!   `policy`), so a reload can be rolled back like a push.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+use wharf_core::agent_config::AgentConfig;
+use wharf_core::db_policy::DatabasePolicy;
+use wharf_core::policy::load_policy_file;
+use wharf_core::types::HeaderPolicy;
+
+use crate::policy::PolicyStore;
+
+/// Trigger for a graceful shutdown
+pub struct Shutdown {
+    sender: watch::Sender<bool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            sender: watch::Sender::new(false),
+        }
+    }
+
+    /// Start shutting down; every signal handed out resolves
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    /// Whether shutdown has started
+    pub fn is_triggered(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    /// A handle that resolves once shutdown starts
+    pub fn signal(&self) -> ShutdownSignal {
+        ShutdownSignal(self.sender.subscribe())
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Resolves once shutdown has been triggered
+#[derive(Clone)]
+pub struct ShutdownSignal(watch::Receiver<bool>);
+
+impl ShutdownSignal {
+    /// Whether shutdown has started
+    pub fn is_triggered(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Wait for shutdown (returns at once if it already started)
+    pub async fn wait(mut self) {
+        // The sender lives as long as the agent; an error means it is gone anyway
+        let _ = self.0.wait_for(|&triggered| triggered).await;
+    }
+}
+
+/// The policy files the agent was started with
+#[derive(Debug, Clone, Default)]
+pub struct PolicySources {
+    /// Agent config file whose policies (inline or by path) are enforced
+    pub config: Option<PathBuf>,
+    /// Database policy (JSON, TOML or Nickel)
+    pub db_policy: Option<PathBuf>,
+    /// HTTP header policy (JSON, TOML or Nickel)
+    pub header_policy: Option<PathBuf>,
+}
+
+impl PolicySources {
+    /// Load every configured policy file and enforce them together
+    ///
+    /// A policy file overrides the config file's policy; a policy set in
+    /// neither keeps its current value.
+    pub fn reload(&self, store: &PolicyStore) -> Result<()> {
+        if !self.configured() {
+            return Ok(());
+        }
+
+        // Parse everything first: a reload is all or nothing
+        let mut bundle = store.enforced_bundle();
+        if let Some(path) = &self.config {
+            let config = AgentConfig::load(path)?;
+            if let Some(database) = &config.policy.database {
+                bundle.database = database.load()?;
+            }
+            if let Some(header) = &config.policy.header {
+                bundle.header = header.load()?;
+            }
+        }
+        if let Some(path) = &self.db_policy {
+            bundle.database = load_policy_file::<DatabasePolicy>(path)?;
+        }
+        if let Some(path) = &self.header_policy {
+            bundle.header = load_policy_file::<HeaderPolicy>(path)?;
+        }
+
+        let version = store.apply(bundle, "file")?;
+        info!("Policy files loaded (version {})", version.version);
+        Ok(())
+    }
+
+    /// Load the policy files at startup, unless the restored policy was
+    /// pushed or rolled back to
+    ///
+    /// A restored file or startup version is replaced by the files as they
+    /// are now. Any other version stays in force until the next SIGHUP or
+    /// push, so a restart does not undo what the Wharf sent.
+    pub fn startup(&self, store: &PolicyStore) -> Result<()> {
+        let current = store.current();
+        if self.configured() && store.restored() && !matches!(current.source.as_str(), "file" | "startup") {
+            warn!(
+                "Keeping restored policy {} ({}) over the policy files; SIGHUP applies them",
+                current.version, current.source
+            );
+            return Ok(());
+        }
+        self.reload(store)
+    }
+
+    fn configured(&self) -> bool {
+        self.config.is_some() || self.db_policy.is_some() || self.header_policy.is_some()
+    }
+}
+
+/// Wait for signals until shutdown is requested
+///
+/// SIGHUP reloads the policies; a failed reload is logged and the agent
+/// keeps running with the policy it had.
+pub async fn handle_signals(store: Arc<PolicyStore>, sources: PolicySources, shutdown: Arc<Shutdown>) -> Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut hangup = signal(SignalKind::hangup())?;
+
+    loop {
+        tokio::select! {
+            _ = terminate.recv() => {
+                info!("SIGTERM received - draining connections");
+                break;
+            }
+            _ = interrupt.recv() => {
+                info!("SIGINT received - draining connections");
+                break;
+            }
+            _ = hangup.recv() => {
+                info!("SIGHUP received - reloading policies");
+                if let Err(e) = sources.reload(&store) {
+                    error!("Policy reload failed, keeping current policies: {:#}", e);
+                }
+            }
+        }
+    }
+
+    shutdown.trigger();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::AgentState;
+    use wharf_core::db_policy::QueryAction;
+
+    #[test]
+    fn test_reload_swaps_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("database.json");
+        let mut policy = DatabasePolicy::default();
+        policy.lock_down.clear();
+        policy.allow_write.push("wp_users".to_string());
+        std::fs::write(&path, serde_json::to_string(&policy).unwrap()).unwrap();
+
+        let state = Arc::new(AgentState::new());
+        let store = PolicyStore::open(state.clone(), 5, None).unwrap();
+        let sources = PolicySources {
+            db_policy: Some(path),
+            header_policy: None,
+            ..Default::default()
+        };
+        sources.reload(&store).unwrap();
+        assert_eq!(store.current().source, "file");
+
+        let query = "INSERT INTO wp_users (user_login) VALUES ('x')";
+        assert_eq!(state.db_engine.load().analyze(query).unwrap(), QueryAction::Allow);
+    }
+
+    #[test]
+    fn test_bad_file_keeps_every_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("database.json");
+        let mut policy = DatabasePolicy::default();
+        policy.lock_down.clear();
+        std::fs::write(&db_path, serde_json::to_string(&policy).unwrap()).unwrap();
+        let header_path = dir.path().join("airlock.json");
+        std::fs::write(&header_path, "{ not json").unwrap();
+
+        let state = Arc::new(AgentState::new());
+        let store = PolicyStore::open(state.clone(), 5, None).unwrap();
+        let sources = PolicySources {
+            db_policy: Some(db_path),
+            header_policy: Some(header_path),
+            ..Default::default()
+        };
+        assert!(sources.reload(&store).is_err());
+
+        // The valid database policy was not applied on its own
+        assert_eq!(state.db_engine.load().policy().lock_down, DatabasePolicy::default().lock_down);
+    }
+
+    #[test]
+    fn test_policy_file_overrides_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("agent.toml");
+        std::fs::write(
+            &config,
+            "[policy.database]\nallow_write = [\"wp_users\"]\nlock_down = []\n",
+        )
+        .unwrap();
+
+        let state = Arc::new(AgentState::new());
+        let store = PolicyStore::open(state.clone(), 5, None).unwrap();
+        let mut sources = PolicySources {
+            config: Some(config),
+            ..Default::default()
+        };
+        sources.reload(&store).unwrap();
+        assert_eq!(state.db_engine.load().policy().allow_write, vec!["wp_users".to_string()]);
+
+        let db_path = dir.path().join("database.json");
+        std::fs::write(&db_path, serde_json::to_string(&DatabasePolicy::default()).unwrap()).unwrap();
+        sources.db_policy = Some(db_path);
+        sources.reload(&store).unwrap();
+        assert_eq!(state.db_engine.load().policy().lock_down, DatabasePolicy::default().lock_down);
+    }
+
+    #[test]
+    fn test_restart_keeps_pushed_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = dir.path().join("policy-history.json");
+        let db_path = dir.path().join("database.json");
+        std::fs::write(&db_path, serde_json::to_string(&DatabasePolicy::default()).unwrap()).unwrap();
+        let sources = PolicySources {
+            db_policy: Some(db_path),
+            ..Default::default()
+        };
+
+        let store = PolicyStore::open(Arc::new(AgentState::new()), 5, Some(history.clone())).unwrap();
+        sources.startup(&store).unwrap();
+        let mut pushed = store.enforced_bundle();
+        pushed.database.allow_write.push("wp_links".to_string());
+        let pushed = store.apply(pushed, "push by harbourmaster").unwrap();
+
+        // Restarted with the same files: the push survives
+        let state = Arc::new(AgentState::new());
+        let store = PolicyStore::open(state.clone(), 5, Some(history.clone())).unwrap();
+        sources.startup(&store).unwrap();
+        assert_eq!(store.current().version, pushed.version);
+        assert!(state.db_engine.load().policy().allow_write.contains(&"wp_links".to_string()));
+
+        // Once the files are loaded again, a restart re-reads them
+        sources.reload(&store).unwrap();
+        let store = PolicyStore::open(Arc::new(AgentState::new()), 5, Some(history)).unwrap();
+        sources.startup(&store).unwrap();
+        assert_eq!(store.current().source, "file");
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_signal_resolves() {
+        let shutdown = Shutdown::new();
+        let signal = shutdown.signal();
+        assert!(!shutdown.is_triggered());
+        shutdown.trigger();
+        tokio::time::timeout(std::time::Duration::from_secs(1), signal.wait())
+            .await
+            .unwrap();
+        // Signals taken after the trigger resolve immediately
+        shutdown.signal().wait().await;
+    }
+}
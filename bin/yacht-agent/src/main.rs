@@ -20,21 +20,37 @@
 //! - If it crashes, the site goes offline (better than being hacked)
 //! - Only signed commands from the Wharf are accepted
 
-use std::net::SocketAddr;
+use std::net::IpAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
-use axum::{routing::get, Router};
 use clap::Parser;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::RwLock;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
 use tracing::{error, info, warn, Level};
 use tracing_subscriber::FmtSubscriber;
 
-use wharf_core::db_policy::{DatabasePolicy, PolicyEngine, QueryAction};
-use wharf_core::types::HeaderPolicy;
-
-mod ebpf;
+use yacht_agent::access::AccessPolicy;
+use yacht_agent::airlock::{self, Airlock};
+use yacht_agent::api::{self, ApiState};
+use yacht_agent::audit::AuditLog;
+use yacht_agent::desync::HeadLimits;
+use yacht_agent::ebpf::{self, ShieldMonitor};
+use yacht_agent::health::{AuditCheck, HealthRegistry, IntegrityCheck};
+use yacht_agent::lifecycle::{self, PolicySources, Shutdown};
+use yacht_agent::metrics::Registry;
+use yacht_agent::moor::MooringService;
+use yacht_agent::net::{self, Endpoint, Listener, SocketPermissions};
+use yacht_agent::policy::{HealthProbe, PolicyStore};
+use yacht_agent::proxy::run_db_proxy;
+use yacht_agent::shadow::{self, ShadowConfig, ShadowDb};
+use yacht_agent::state::AgentState;
+use yacht_agent::supervisor::Supervisor;
+use yacht_agent::tls;
+
+use wharf_core::agent_config::{AgentConfig, FailAction, PolicyRef};
+use wharf_core::mooring::{ReplayGuard, TrustStore};
 
 // =============================================================================
 // CLI ARGUMENTS
@@ -45,80 +61,283 @@
 #[command(about = "The Sovereign Web Hypervisor - Runtime Enforcer")]
 #[command(version)]
 struct Args {
-    /// The database protocol to masquerade as (mysql, postgres, redis)
-    #[arg(long, default_value = "mysql", env = "DB_PROTOCOL")]
-    protocol: String,
+    /// Agent config file (TOML, JSON or Nickel); flags below override it
+    #[arg(long, env = "AGENT_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Check the configuration (policies, certificates, paths) and exit
+    #[arg(long)]
+    check_config: bool,
+
+    /// The database protocol to masquerade as (mysql, postgres, redis) [default: mysql]
+    #[arg(long, env = "DB_PROTOCOL")]
+    protocol: Option<String>,
+
+    /// The port to listen on (masquerade port) [default: 3306]
+    #[arg(long, env = "LISTEN_PORT")]
+    listen_port: Option<u16>,
+
+    /// The address the masquerade port binds to [default: 127.0.0.1]
+    #[arg(long, env = "LISTEN_HOST")]
+    listen_host: Option<String>,
+
+    /// Also listen on a Unix socket (e.g. /run/mysqld/mysqld.sock)
+    #[arg(long, env = "LISTEN_SOCKET")]
+    listen_socket: Option<PathBuf>,
+
+    /// Only listen on the Unix socket, not on TCP
+    #[arg(long, env = "UNIX_ONLY")]
+    unix_only: bool,
+
+    /// File mode for the listening Unix socket (octal) [default: 660]
+    #[arg(long, env = "SOCKET_MODE")]
+    socket_mode: Option<String>,
+
+    /// Owner of the listening Unix socket (name or uid)
+    #[arg(long, env = "SOCKET_OWNER")]
+    socket_owner: Option<String>,
+
+    /// Group of the listening Unix socket (name or gid)
+    #[arg(long, env = "SOCKET_GROUP")]
+    socket_group: Option<String>,
+
+    /// The shadow port where the real database hides [default: 33060]
+    #[arg(long, env = "SHADOW_DB_PORT")]
+    shadow_port: Option<u16>,
+
+    /// The shadow database host [default: 127.0.0.1]
+    #[arg(long, env = "SHADOW_DB_HOST")]
+    shadow_host: Option<String>,
+
+    /// Reach the shadow database via a Unix socket instead of host:port
+    #[arg(long, env = "SHADOW_DB_SOCKET")]
+    shadow_socket: Option<PathBuf>,
+
+    /// Maximum concurrent client connections to the database proxy [default: 512]
+    #[arg(long, env = "MAX_CLIENT_CONNECTIONS")]
+    max_connections: Option<usize>,
+
+    /// Largest database message relayed after authentication (bytes) [default: 67108864]
+    #[arg(long, env = "MAX_PACKET_BYTES")]
+    max_packet_bytes: Option<usize>,
+
+    /// Timeout for connecting to the shadow database (milliseconds) [default: 3000]
+    #[arg(long, env = "SHADOW_CONNECT_TIMEOUT_MS")]
+    shadow_connect_timeout_ms: Option<u64>,
+
+    /// Interval between shadow database health probes (seconds) [default: 5]
+    #[arg(long, env = "SHADOW_PROBE_INTERVAL")]
+    shadow_probe_interval: Option<u64>,
+
+    /// Consecutive shadow failures before the circuit breaker opens [default: 5]
+    #[arg(long, env = "SHADOW_FAILURE_THRESHOLD")]
+    shadow_failure_threshold: Option<u32>,
+
+    /// How long the circuit breaker stays open before retrying (seconds) [default: 30]
+    #[arg(long, env = "SHADOW_BREAKER_OPEN_SECS")]
+    shadow_breaker_open_secs: Option<u64>,
+
+    /// Database policy file (JSON, TOML or Nickel); re-read on SIGHUP
+    #[arg(long, env = "DB_POLICY")]
+    db_policy: Option<PathBuf>,
+
+    /// HTTP header policy file (JSON, TOML or Nickel); re-read on SIGHUP
+    #[arg(long, env = "HEADER_POLICY")]
+    header_policy: Option<PathBuf>,
+
+    /// Keep the policy history here so pushed policies survive a restart
+    #[arg(long, env = "POLICY_HISTORY")]
+    policy_history: Option<PathBuf>,
+
+    /// How many policy versions to keep for rollback [default: 5]
+    #[arg(long, env = "POLICY_VERSIONS")]
+    policy_versions: Option<usize>,
+
+    /// URL probed after a policy push; the push is rolled back if it fails
+    #[arg(long, env = "POLICY_PROBE_URL")]
+    policy_probe_url: Option<String>,
+
+    /// How long a pushed policy stays on probation (seconds) [default: 60]
+    #[arg(long, env = "POLICY_GRACE")]
+    policy_grace: Option<u64>,
+
+    /// How long open database sessions may take to finish on shutdown (seconds) [default: 30]
+    #[arg(long, env = "DRAIN_TIMEOUT")]
+    drain_timeout: Option<u64>,
+
+    /// Hash-chained security audit log (JSON Lines) [default: /var/log/wharf/audit.log]
+    #[arg(long, env = "AUDIT_LOG")]
+    audit_log: Option<PathBuf>,
+
+    /// Captain keys allowed to send mooring commands (JSON or TOML)
+    #[arg(long, env = "TRUST_STORE")]
+    trust_store: Option<PathBuf>,
+
+    /// Keep spent mooring nonces here so commands cannot be replayed after a restart
+    #[arg(long, env = "NONCE_FILE")]
+    nonce_file: Option<PathBuf>,
+
+    /// This yacht's name in the fleet; mooring commands must be addressed to it
+    #[arg(long, env = "YACHT_ID")]
+    yacht_id: Option<String>,
 
-    /// The port to listen on (masquerade port)
-    #[arg(long, default_value_t = 3306, env = "LISTEN_PORT")]
-    listen_port: u16,
+    /// The web root checked by integrity commands [default: /var/www/html]
+    #[arg(long, env = "WEB_ROOT")]
+    web_root: Option<PathBuf>,
 
-    /// The shadow port where the real database hides
-    #[arg(long, default_value_t = 33060, env = "SHADOW_DB_PORT")]
-    shadow_port: u16,
+    /// The API port for health checks and Wharf mooring [default: 9001]
+    #[arg(long, env = "API_PORT")]
+    api_port: Option<u16>,
 
-    /// The shadow database host
-    #[arg(long, default_value = "127.0.0.1", env = "SHADOW_DB_HOST")]
-    shadow_host: String,
+    /// The address the API binds to (e.g. the yacht's Nebula IP) [default: 127.0.0.1]
+    #[arg(long, env = "API_HOST")]
+    api_host: Option<IpAddr>,
 
-    /// The API port for health checks and Wharf mooring
-    #[arg(long, default_value_t = 9001, env = "API_PORT")]
-    api_port: u16,
+    /// Serve the API over TLS with this certificate (PEM)
+    #[arg(long, env = "API_TLS_CERT")]
+    api_tls_cert: Option<PathBuf>,
 
-    /// Network interface for eBPF/firewall attachment
-    #[arg(long, default_value = "eth0", env = "XDP_INTERFACE")]
-    xdp_interface: String,
+    /// Private key for the API certificate (PEM)
+    #[arg(long, env = "API_TLS_KEY")]
+    api_tls_key: Option<PathBuf>,
 
-    /// Firewall mode: ebpf, nftables, or none
+    /// Client certificate allowlist and roles (JSON or TOML); needs TLS
+    #[arg(long, env = "API_CLIENTS")]
+    api_clients: Option<PathBuf>,
+
+    /// Network interface for eBPF/firewall attachment [default: eth0]
+    #[arg(long, env = "XDP_INTERFACE")]
+    xdp_interface: Option<String>,
+
+    /// Firewall mode: ebpf, nftables, or none [default: nftables]
     /// - ebpf: Use eBPF XDP for kernel-level packet filtering (requires CAP_BPF)
     /// - nftables: Use nftables for packet filtering (default, more compatible)
     /// - none: Disable firewall (not recommended for production)
-    #[arg(long, default_value = "nftables", env = "FIREWALL_MODE")]
-    firewall_mode: String,
+    #[arg(long, env = "FIREWALL_MODE")]
+    firewall_mode: Option<String>,
 
-    /// Enable Prometheus metrics endpoint
-    #[arg(long, default_value_t = true, env = "METRICS_ENABLED")]
-    metrics_enabled: bool,
+    /// Put the HTTP header airlock in front of this web backend (e.g. http://127.0.0.1:8081)
+    #[arg(long, env = "AIRLOCK_BACKEND")]
+    airlock_backend: Option<String>,
 
-    /// Enable verbose logging
-    #[arg(short, long, action = clap::ArgAction::Count)]
-    verbose: u8,
-}
+    /// The address the airlock listens on [default: 127.0.0.1]
+    #[arg(long, env = "AIRLOCK_HOST")]
+    airlock_host: Option<String>,
 
-// =============================================================================
-// STATE
-// =============================================================================
+    /// The port the airlock listens on [default: 8080]
+    #[arg(long, env = "AIRLOCK_PORT")]
+    airlock_port: Option<u16>,
 
-/// The shared state for the Yacht Agent
-struct AgentState {
-    /// The database policy engine
-    db_engine: PolicyEngine,
+    /// When enforcement cannot be kept running: stop, maintenance or exit [default: exit]
+    #[arg(long, env = "ON_FAILURE")]
+    on_failure: Option<FailAction>,
 
-    /// The HTTP header policy
-    header_policy: HeaderPolicy,
+    /// Enable Prometheus metrics endpoint [default: true]
+    #[arg(long, env = "METRICS_ENABLED", num_args = 0..=1, default_missing_value = "true")]
+    metrics_enabled: Option<bool>,
 
-    /// Whether the Wharf is currently moored (connected)
-    moored: bool,
+    /// Enable verbose logging
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+}
 
-    /// The expected filesystem hashes (from Wharf)
-    integrity_hashes: std::collections::HashMap<String, String>,
+impl Args {
+    /// The config file (or the defaults) with every flag given applied on top
+    fn agent_config(&self) -> anyhow::Result<AgentConfig> {
+        let mut config = match &self.config {
+            Some(path) => AgentConfig::load(path)
+                .map_err(|e| anyhow::anyhow!("cannot load config {}: {}", path.display(), e))?,
+            None => AgentConfig::default(),
+        };
+
+        fn set<T: Clone>(target: &mut T, value: &Option<T>) {
+            if let Some(value) = value {
+                *target = value.clone();
+            }
+        }
+        fn set_some<T: Clone>(target: &mut Option<T>, value: &Option<T>) {
+            if value.is_some() {
+                *target = value.clone();
+            }
+        }
 
-    /// Statistics
-    queries_allowed: u64,
-    queries_blocked: u64,
+        set_some(&mut config.yacht_id, &self.yacht_id);
+        set(&mut config.protocol, &self.protocol);
+        set(&mut config.drain_timeout_secs, &self.drain_timeout);
+
+        set(&mut config.listen.host, &self.listen_host);
+        set(&mut config.listen.port, &self.listen_port);
+        set_some(&mut config.listen.socket, &self.listen_socket);
+        config.listen.unix_only |= self.unix_only;
+        set(&mut config.listen.socket_mode, &self.socket_mode);
+        set_some(&mut config.listen.socket_owner, &self.socket_owner);
+        set_some(&mut config.listen.socket_group, &self.socket_group);
+
+        set(&mut config.shadow.host, &self.shadow_host);
+        set(&mut config.shadow.port, &self.shadow_port);
+        set_some(&mut config.shadow.socket, &self.shadow_socket);
+        set(&mut config.shadow.max_connections, &self.max_connections);
+        set(&mut config.shadow.max_packet_bytes, &self.max_packet_bytes);
+        set(&mut config.shadow.connect_timeout_ms, &self.shadow_connect_timeout_ms);
+        set(&mut config.shadow.probe_interval_secs, &self.shadow_probe_interval);
+        set(&mut config.shadow.failure_threshold, &self.shadow_failure_threshold);
+        set(&mut config.shadow.breaker_open_secs, &self.shadow_breaker_open_secs);
+
+        set(&mut config.firewall.mode, &self.firewall_mode);
+        set(&mut config.firewall.interface, &self.xdp_interface);
+
+        if let Some(path) = &self.db_policy {
+            config.policy.database = Some(PolicyRef::File(path.clone()));
+        }
+        if let Some(path) = &self.header_policy {
+            config.policy.header = Some(PolicyRef::File(path.clone()));
+        }
+        set_some(&mut config.policy.history, &self.policy_history);
+        set(&mut config.policy.versions, &self.policy_versions);
+        set_some(&mut config.policy.probe_url, &self.policy_probe_url);
+        set(&mut config.policy.grace_secs, &self.policy_grace);
+
+        set(&mut config.integrity.web_root, &self.web_root);
+        set(&mut config.audit.log, &self.audit_log);
+
+        set(&mut config.api.host, &self.api_host);
+        set(&mut config.api.port, &self.api_port);
+        set_some(&mut config.api.tls_cert, &self.api_tls_cert);
+        set_some(&mut config.api.tls_key, &self.api_tls_key);
+        set_some(&mut config.api.clients, &self.api_clients);
+        set_some(&mut config.api.trust_store, &self.trust_store);
+        set_some(&mut config.api.nonces, &self.nonce_file);
+        set(&mut config.api.metrics, &self.metrics_enabled);
+        set(&mut config.airlock.host, &self.airlock_host);
+        set(&mut config.airlock.port, &self.airlock_port);
+        set_some(&mut config.airlock.backend, &self.airlock_backend);
+
+        set(&mut config.supervisor.on_failure, &self.on_failure);
+
+        Ok(config)
+    }
 }
 
-impl AgentState {
-    fn new() -> Self {
-        Self {
-            db_engine: PolicyEngine::new(DatabasePolicy::default()),
-            header_policy: HeaderPolicy::default(),
-            moored: false,
-            integrity_hashes: std::collections::HashMap::new(),
-            queries_allowed: 0,
-            queries_blocked: 0,
+/// Print the outcome of `--check-config`; true if the configuration is usable
+fn report_check(config: &AgentConfig) -> bool {
+    let problems = yacht_agent::config::check(config);
+    if problems.is_empty() {
+        println!("Configuration OK");
+        println!("  yacht:    {}", config.yacht_id.as_deref().unwrap_or("(none - mooring off)"));
+        println!("  protocol: {}", config.protocol);
+        println!("  firewall: {}", config.firewall.mode);
+        println!("  api:      {}:{}", config.api.host, config.api.port);
+        if let Some(backend) = &config.airlock.backend {
+            println!("  airlock:  {}:{} -> {}", config.airlock.host, config.airlock.port, backend);
         }
+        return true;
     }
+
+    eprintln!("Configuration has {} problem(s):", problems.len());
+    for problem in &problems {
+        eprintln!("  - {}", problem);
+    }
+    false
 }
 
 // =============================================================================
@@ -128,6 +347,17 @@ fn new() -> Self {
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    let config = args.agent_config()?;
+    if args.check_config {
+        std::process::exit(if report_check(&config) { 0 } else { 1 });
+    }
+    if config.listen.unix_only && config.listen.socket.is_none() {
+        anyhow::bail!("unix_only needs a listen socket");
+    }
+    if let Some(problem) = yacht_agent::config::api_tls_problem(&config.api) {
+        anyhow::bail!(problem);
+    }
+    let socket_mode = net::parse_mode(&config.listen.socket_mode).map_err(|e| anyhow::anyhow!(e))?;
 
     // Set up logging based on verbosity
     let log_level = match args.verbose {
@@ -146,15 +376,24 @@ async fn main() -> anyhow::Result<()> {
 
     info!("Yacht Agent starting...");
     info!("Version: {}", wharf_core::VERSION);
-    info!("Protocol: {}", args.protocol);
-    info!("Masquerade port: {}", args.listen_port);
-    info!("Shadow DB: {}:{}", args.shadow_host, args.shadow_port);
-    info!("Firewall mode: {}", args.firewall_mode);
+    info!("Protocol: {}", config.protocol);
+    if !config.listen.unix_only {
+        info!("Masquerade port: {}:{}", config.listen.host, config.listen.port);
+    }
+    if let Some(socket) = &config.listen.socket {
+        info!("Masquerade socket: {}", socket.display());
+    }
+    let shadow_endpoint = match &config.shadow.socket {
+        Some(socket) => Endpoint::Unix(socket.clone()),
+        None => Endpoint::Tcp(format!("{}:{}", config.shadow.host, config.shadow.port)),
+    };
+    info!("Shadow DB: {}", shadow_endpoint);
+    info!("Firewall mode: {}", config.firewall.mode);
 
     // Initialize firewall based on mode
-    let _shield = match args.firewall_mode.as_str() {
+    let shield = match config.firewall.mode.as_str() {
         "ebpf" => {
-            info!("Attempting to load eBPF XDP firewall on {}", args.xdp_interface);
+            info!("Attempting to load eBPF XDP firewall on {}", config.firewall.interface);
 
             // Look for the eBPF object file in standard locations
             let ebpf_paths = [
@@ -167,9 +406,9 @@ async fn main() -> anyhow::Result<()> {
 
             match ebpf_path {
                 Some(path) => {
-                    match ebpf::try_load_shield(path, &args.xdp_interface) {
+                    match ebpf::try_load_shield(path, &config.firewall.interface) {
                         Some(shield) => {
-                            info!("eBPF XDP firewall loaded successfully on {}", args.xdp_interface);
+                            info!("eBPF XDP firewall loaded successfully on {}", config.firewall.interface);
                             Some(shield)
                         }
                         None => {
@@ -199,280 +438,256 @@ async fn main() -> anyhow::Result<()> {
             None
         }
         _ => {
-            warn!("Unknown firewall mode '{}', using nftables", args.firewall_mode);
+            warn!("Unknown firewall mode '{}', using nftables", config.firewall.mode);
             setup_nftables_firewall().await;
             None
         }
     };
 
-    // Initialize shared state
-    let state = Arc::new(RwLock::new(AgentState::new()));
-
-    // Spawn the database proxy
-    let db_state = state.clone();
-    let shadow_addr = format!("{}:{}", args.shadow_host, args.shadow_port);
-    let listen_port = args.listen_port;
-    let protocol = args.protocol.clone();
+    // Open the audit log before accepting any traffic; no log, no proxy
+    let audit_log = AuditLog::open(&config.audit.log)
+        .await
+        .map_err(|e| anyhow::anyhow!("cannot open audit log {}: {}", config.audit.log.display(), e))?;
 
-    tokio::spawn(async move {
-        if let Err(e) = run_db_proxy(listen_port, &shadow_addr, &protocol, db_state).await {
-            error!("Database proxy error: {}", e);
-        }
-    });
+    // Initialize shared state
+    let state = Arc::new(AgentState::new().with_audit_log(audit_log));
 
-    // Build the API router
-    let api_state = state.clone();
-    let mut app = Router::new()
-        .route("/health", get(health_check))
-        .route("/status", get(status))
-        .route("/stats", get(stats));
-
-    // Add metrics endpoint if enabled
-    if args.metrics_enabled {
-        app = app.route("/metrics", get(prometheus_metrics));
-        info!("Prometheus metrics enabled at /metrics");
+    // Restore the last enforced policy, then apply the policy files on top
+    // unless it was pushed; a policy that does not parse or validate stops
+    // startup
+    if config.policy.history.is_none() {
+        warn!("No policy history file - pushed policies are lost on restart");
+    }
+    let policies = Arc::new(
+        PolicyStore::open(state.clone(), config.policy.versions, config.policy.history.clone())
+            .map_err(|e| anyhow::anyhow!("cannot open policy history: {}", e))?,
+    );
+    let policy_sources = PolicySources {
+        config: args.config.clone(),
+        db_policy: args.db_policy.clone(),
+        header_policy: args.header_policy.clone(),
+    };
+    policy_sources.startup(&policies)?;
+    info!("Policy version: {}", policies.current().version);
+
+    // SIGTERM drains and exits, SIGHUP reloads the policies
+    let shutdown = Arc::new(Shutdown::new());
+    tokio::spawn(lifecycle::handle_signals(policies.clone(), policy_sources, shutdown.clone()));
+
+    // Shadow database access (connection limits, circuit breaker)
+    let shadow_db = Arc::new(ShadowDb::new(ShadowConfig {
+        endpoint: shadow_endpoint,
+        max_connections: config.shadow.max_connections,
+        connect_timeout: Duration::from_millis(config.shadow.connect_timeout_ms),
+        probe_interval: Duration::from_secs(config.shadow.probe_interval_secs.max(1)),
+        failure_threshold: config.shadow.failure_threshold,
+        open_duration: Duration::from_secs(config.shadow.breaker_open_secs),
+        max_packet: config.shadow.max_packet_bytes,
+    }));
+    tokio::spawn(shadow::run_health_probe(shadow_db.clone()));
+
+    // Bind the masquerade listeners up front so a bind failure stops startup
+    let mut listeners = Vec::new();
+    if !config.listen.unix_only {
+        let endpoint = Endpoint::Tcp(format!("{}:{}", config.listen.host, config.listen.port));
+        let listener = Listener::bind(&endpoint, &SocketPermissions::default()).await?;
+        listeners.push((listener, SocketPermissions::default()));
+    }
+    if let Some(socket) = &config.listen.socket {
+        let permissions = SocketPermissions {
+            mode: Some(socket_mode),
+            owner: config.listen.socket_owner.clone(),
+            group: config.listen.socket_group.clone(),
+        };
+        let listener = Listener::bind(&Endpoint::Unix(socket.clone()), &permissions).await?;
+        listeners.push((listener, permissions));
     }
 
-    let app = app.with_state(api_state);
-
-    // Bind API to localhost only (Nebula mesh provides external access)
-    let api_addr = SocketAddr::from(([0, 0, 0, 0], args.api_port));
-    info!("API listening on {}", api_addr);
-
-    let listener = tokio::net::TcpListener::bind(api_addr).await?;
-    axum::serve(listener, app).await?;
-
-    Ok(())
-}
-
-// =============================================================================
-// DATABASE PROXY
-// =============================================================================
-
-/// Run the database proxy server
-async fn run_db_proxy(
-    listen_port: u16,
-    shadow_addr: &str,
-    protocol: &str,
-    state: Arc<RwLock<AgentState>>,
-) -> anyhow::Result<()> {
-    let listen_addr = format!("0.0.0.0:{}", listen_port);
-    let listener = TcpListener::bind(&listen_addr).await?;
-
-    info!("Database proxy listening on {}", listen_addr);
-    info!("Forwarding to shadow DB at {}", shadow_addr);
-
-    loop {
-        let (client_socket, client_addr) = listener.accept().await?;
-        let shadow = shadow_addr.to_string();
-        let proto = protocol.to_string();
-        let conn_state = state.clone();
-
-        tokio::spawn(async move {
-            if let Err(e) = handle_db_connection(client_socket, &shadow, &proto, conn_state).await {
-                warn!("Connection from {} error: {}", client_addr, e);
+    let shield = Arc::new(ShieldMonitor::new(
+        if shield.is_some() { "ebpf" } else { firewall_mode(&config.firewall.mode) },
+        shield,
+    ));
+
+    // Components checked on /health; the supervised tasks report their own status
+    let mut health = HealthRegistry::new();
+    health.register("shadow_db", shadow_db.clone());
+    health.register("shield", shield.clone());
+    health.register("audit_log", Arc::new(AuditCheck(state.clone())));
+    health.register("integrity", Arc::new(IntegrityCheck(state.clone())));
+    let health = Arc::new(health);
+
+    // The database proxy (one supervised task per listener); a restart rebinds
+    let drain_timeout = Duration::from_secs(config.drain_timeout_secs);
+    let mut supervisor = Supervisor::new(state.clone(), health.clone(), shutdown.clone(), config.supervisor.clone());
+    info!("Enforcement failures: {} after {} restarts", config.supervisor.on_failure, config.supervisor.max_restarts);
+    for (listener, permissions) in listeners {
+        let endpoint = listener.local_endpoint()?;
+        let name = format!("db_proxy {}", endpoint);
+        let bound = Arc::new(std::sync::Mutex::new(Some(listener)));
+        let db_state = state.clone();
+        let db_shadow = shadow_db.clone();
+        let protocol = config.protocol.clone();
+        let db_shutdown = shutdown.clone();
+
+        supervisor.spawn(&name, move |ready| {
+            let bound = bound.lock().unwrap_or_else(|e| e.into_inner()).take();
+            let (endpoint, permissions) = (endpoint.clone(), permissions.clone());
+            let (state, shadow, protocol) = (db_state.clone(), db_shadow.clone(), protocol.clone());
+            let signal = db_shutdown.signal();
+            async move {
+                let listener = match bound {
+                    Some(listener) => listener,
+                    None => Listener::bind(&endpoint, &permissions).await?,
+                };
+                ready.up();
+                run_db_proxy(listener, &protocol, shadow, state, signal, drain_timeout).await
             }
         });
     }
-}
 
-/// Handle a single database connection
-async fn handle_db_connection(
-    mut client: TcpStream,
-    shadow_addr: &str,
-    protocol: &str,
-    state: Arc<RwLock<AgentState>>,
-) -> std::io::Result<()> {
-    // Connect to the real database
-    let mut server = TcpStream::connect(shadow_addr).await?;
-
-    let (mut c_read, mut c_write) = client.split();
-    let (mut s_read, mut s_write) = server.split();
-
-    // The proxy loop
-    let client_to_server = async {
-        let mut buf = [0u8; 16384];
-        loop {
-            let n = c_read.read(&mut buf).await?;
-            if n == 0 {
-                return Ok::<_, std::io::Error>(());
-            }
+    // The HTTP header airlock, supervised like the database proxy
+    let airlock = match &config.airlock.backend {
+        Some(backend) => {
+            let limits = HeadLimits {
+                request_line: config.airlock.max_request_line,
+                head: config.airlock.max_head_size,
+            };
+            let airlock = Arc::new(
+                Airlock::new(state.clone(), backend)?
+                    .with_limits(limits)
+                    .with_shield(shield.clone()),
+            );
+            let addr = format!("{}:{}", config.airlock.host, config.airlock.port);
+            let bound = Arc::new(std::sync::Mutex::new(Some(TcpListener::bind(&addr).await?)));
+            let (task_airlock, airlock_shutdown) = (airlock.clone(), shutdown.clone());
+            supervisor.spawn(&format!("airlock {}", addr), move |ready| {
+                let bound = bound.lock().unwrap_or_else(|e| e.into_inner()).take();
+                let (airlock, addr) = (task_airlock.clone(), addr.clone());
+                let signal = airlock_shutdown.signal();
+                async move {
+                    let listener = match bound {
+                        Some(listener) => listener,
+                        None => TcpListener::bind(&addr).await?,
+                    };
+                    ready.up();
+                    airlock::serve(listener, airlock, signal, drain_timeout).await?;
+                    Ok(())
+                }
+            });
+            Some(airlock)
+        }
+        None => {
+            warn!("No airlock backend configured - HTTP headers are not filtered");
+            None
+        }
+    };
 
-            // MySQL/MariaDB protocol inspection
-            // Packet format: 3 bytes length + 1 byte sequence + payload
-            // Command byte is at position 4, COM_QUERY = 0x03
-            if protocol == "mysql" || protocol == "mariadb" {
-                if n > 5 && buf[4] == 0x03 {
-                    // This is a COM_QUERY packet
-                    let query = String::from_utf8_lossy(&buf[5..n]);
-
-                    // Analyze the query
-                    let mut state_guard = state.write().await;
-                    match state_guard.db_engine.analyze(&query) {
-                        Ok(QueryAction::Allow) => {
-                            state_guard.queries_allowed += 1;
-                            drop(state_guard);
-                            // Forward the packet
-                            s_write.write_all(&buf[0..n]).await?;
-                        }
-                        Ok(QueryAction::Audit) => {
-                            state_guard.queries_allowed += 1;
-                            info!("AUDIT: {}", query.chars().take(100).collect::<String>());
-                            drop(state_guard);
-                            s_write.write_all(&buf[0..n]).await?;
-                        }
-                        Ok(QueryAction::Block) | Err(_) => {
-                            state_guard.queries_blocked += 1;
-                            warn!("BLOCKED: {}", query.chars().take(100).collect::<String>());
-                            drop(state_guard);
-                            // Send MySQL error packet back to client
-                            // Error packet: header + 0xff + errno + sqlstate + message
-                            let error_msg = b"Query blocked by Wharf security policy";
-                            let mut error_packet = Vec::with_capacity(error_msg.len() + 13);
-                            // Length (3 bytes) + Sequence (1 byte)
-                            let len = (error_msg.len() + 9) as u32;
-                            error_packet.extend_from_slice(&len.to_le_bytes()[0..3]);
-                            error_packet.push(1); // Sequence number
-                            error_packet.push(0xff); // Error marker
-                            error_packet.extend_from_slice(&1045u16.to_le_bytes()); // Error code
-                            error_packet.push(b'#'); // SQL state marker
-                            error_packet.extend_from_slice(b"HY000"); // SQL state
-                            error_packet.extend_from_slice(error_msg);
-                            c_write.write_all(&error_packet).await?;
-                            return Ok(());
-                        }
-                    }
-                } else {
-                    // Non-query packet (auth, ping, etc.) - pass through
-                    s_write.write_all(&buf[0..n]).await?;
+    // Build the API router
+    // Without a trust store and a yacht id nothing can be verified, so no commands
+    let mooring = match (&config.api.trust_store, &config.yacht_id) {
+        (Some(path), Some(yacht)) => {
+            let trust = TrustStore::load(path)
+                .map_err(|e| anyhow::anyhow!("cannot load trust store {}: {}", path.display(), e))?;
+            info!("Mooring enabled for yacht '{}' ({} captain keys)", yacht, trust.len());
+            let mut mooring = MooringService::new(
+                yacht,
+                trust,
+                state.clone(),
+                policies.clone(),
+                shield.clone(),
+                config.integrity.web_root.clone(),
+            );
+            match &config.api.nonces {
+                Some(path) => {
+                    let replay = ReplayGuard::open(path)
+                        .map_err(|e| anyhow::anyhow!("cannot open nonce file {}: {}", path.display(), e))?;
+                    mooring = mooring.with_replay_guard(replay);
                 }
-            } else if protocol == "postgres" {
-                // PostgreSQL protocol inspection (simplified)
-                // Query message: 'Q' + length + query string
-                if n > 5 && buf[0] == b'Q' {
-                    let query = String::from_utf8_lossy(&buf[5..n]);
-                    let mut state_guard = state.write().await;
-                    match state_guard.db_engine.analyze(&query) {
-                        Ok(QueryAction::Allow) | Ok(QueryAction::Audit) => {
-                            state_guard.queries_allowed += 1;
-                            drop(state_guard);
-                            s_write.write_all(&buf[0..n]).await?;
-                        }
-                        Ok(QueryAction::Block) | Err(_) => {
-                            state_guard.queries_blocked += 1;
-                            warn!("BLOCKED: {}", query.chars().take(100).collect::<String>());
-                            drop(state_guard);
-                            // Send PostgreSQL ErrorResponse
-                            let error = b"EFATAL\0VFATAL\0C42501\0MQuery blocked by Wharf\0\0";
-                            let mut packet = Vec::with_capacity(error.len() + 5);
-                            packet.push(b'E'); // Error message type
-                            let len = (error.len() + 4) as i32;
-                            packet.extend_from_slice(&len.to_be_bytes());
-                            packet.extend_from_slice(error);
-                            c_write.write_all(&packet).await?;
-                            return Ok(());
-                        }
-                    }
-                } else {
-                    s_write.write_all(&buf[0..n]).await?;
+                None => warn!("No nonce file - mooring commands could be replayed after a restart"),
+            }
+            match &config.policy.probe_url {
+                Some(url) => {
+                    info!("Pushed policies are probed via {} for {}s", url, config.policy.grace_secs);
+                    mooring = mooring.with_probe(HealthProbe::new(url), Duration::from_secs(config.policy.grace_secs));
                 }
-            } else {
-                // Unknown protocol - pass through (fail-open for compatibility)
-                s_write.write_all(&buf[0..n]).await?;
+                None => warn!("No policy probe URL - pushed policies are not rolled back automatically"),
             }
+            Some(mooring)
+        }
+        _ => {
+            warn!("No trust store or yacht id configured - mooring commands are refused");
+            None
         }
     };
 
-    let server_to_client = async {
-        tokio::io::copy(&mut s_read, &mut c_write).await
-    };
-
-    tokio::select! {
-        result = client_to_server => result?,
-        result = server_to_client => { result?; }
+    let mut registry = Registry::new();
+    registry.register(state.clone());
+    registry.register(shadow_db.clone());
+    registry.register(shield.clone());
+    if let Some(airlock) = &airlock {
+        registry.register(airlock.clone());
     }
+    let api_state = Arc::new(ApiState {
+        agent: state.clone(),
+        shadow: shadow_db.clone(),
+        shield: shield.clone(),
+        policies,
+        mooring,
+        registry,
+        health,
+    });
 
-    Ok(())
-}
-
-// =============================================================================
-// API ENDPOINTS
-// =============================================================================
+    // Client certificates and their roles; every route but the health checks is guarded
+    let access = match &config.api.clients {
+        Some(path) => {
+            let access = AccessPolicy::load(path)
+                .map_err(|e| anyhow::anyhow!("cannot load API clients {}: {}", path.display(), e))?;
+            info!("API access: {} client certificates", access.len());
+            Some(Arc::new(access))
+        }
+        None => None,
+    };
+    let tls = match (&config.api.tls_cert, &config.api.tls_key) {
+        (Some(cert), Some(key)) => Some(TlsAcceptor::from(tls::server_config(cert, key, access.as_deref())?)),
+        _ => None,
+    };
+    if access.is_none() && !config.api.host.is_loopback() {
+        warn!("API on {} without client certificates - any host that can reach it can use it", config.api.host);
+    }
+    let app = api::router(api_state, config.api.metrics, access);
 
-/// Health check endpoint
-async fn health_check() -> &'static str {
-    "OK"
-}
+    // Bind to localhost by default; on a yacht, the Nebula IP gives the Wharf access
+    let listener = TcpListener::bind((config.api.host, config.api.port)).await?;
+    api::serve(listener, app, tls, shutdown.signal(), drain_timeout).await?;
 
-/// Status endpoint (returns agent state as JSON)
-async fn status() -> axum::Json<serde_json::Value> {
-    axum::Json(serde_json::json!({
-        "status": "active",
-        "moored": false,
-        "version": wharf_core::VERSION,
-        "components": {
-            "db_proxy": "running",
-            "shield": "active",
-            "integrity": "verified"
-        }
-    }))
-}
+    // The API has stopped; wait for the proxies to drain
+    let supervised = supervisor.join().await;
 
-/// Statistics endpoint
-async fn stats() -> axum::Json<serde_json::Value> {
-    axum::Json(serde_json::json!({
-        "queries": {
-            "allowed": 0,
-            "blocked": 0,
-            "audited": 0
-        },
-        "packets": {
-            "allowed": 0,
-            "dropped": 0
+    if let Some(shield) = shield.take() {
+        if let Err(e) = shield.detach() {
+            error!("Failed to detach eBPF shield: {:#}", e);
         }
-    }))
-}
+    }
 
-/// Prometheus metrics endpoint
-async fn prometheus_metrics() -> String {
-    // Basic Prometheus format metrics
-    // In production, would use prometheus crate for proper metric tracking
-    format!(
-        r#"# HELP yacht_queries_total Total number of database queries processed
-# TYPE yacht_queries_total counter
-yacht_queries_total{{status="allowed"}} 0
-yacht_queries_total{{status="blocked"}} 0
-yacht_queries_total{{status="audited"}} 0
-
-# HELP yacht_packets_total Total number of network packets processed
-# TYPE yacht_packets_total counter
-yacht_packets_total{{action="allowed"}} 0
-yacht_packets_total{{action="dropped"}} 0
-
-# HELP yacht_agent_info Agent information
-# TYPE yacht_agent_info gauge
-yacht_agent_info{{version="{}"}} 1
-
-# HELP yacht_firewall_mode Current firewall mode
-# TYPE yacht_firewall_mode gauge
-yacht_firewall_mode{{mode="nftables"}} 1
-
-# HELP yacht_db_proxy_connections Active database proxy connections
-# TYPE yacht_db_proxy_connections gauge
-yacht_db_proxy_connections 0
-
-# HELP yacht_integrity_status File integrity check status (1=ok, 0=failed)
-# TYPE yacht_integrity_status gauge
-yacht_integrity_status 1
-"#,
-        wharf_core::VERSION
-    )
+    // A fail-closed exit leaves a non-zero status for the service manager
+    supervised?;
+    info!("Yacht Agent stopped");
+    Ok(())
 }
 
 // =============================================================================
 // FIREWALL SETUP
 // =============================================================================
 
+/// The firewall mode actually in effect when the XDP shield is not attached
+fn firewall_mode(requested: &str) -> &'static str {
+    match requested {
+        "none" => "none",
+        // ebpf falls back to nftables, and so does anything unrecognised
+        _ => "nftables",
+    }
+}
+
 /// Set up nftables firewall rules for the Yacht
 async fn setup_nftables_firewall() {
     // Generate nftables rules for Yacht security
@@ -0,0 +1,330 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Metrics Registry
+//!
+//! Renders the agent's live counters in the Prometheus text exposition
+//! format (version 0.0.4). Components that own metrics implement
+//! [`Collector`] and are registered once at startup; a scrape asks each of
+//! them to write its families into an [`Exposition`].
+//!
+//! Nothing here sits on the query hot path. The counters themselves live in
+//! `stats` (sharded atomics) and are only read at scrape time.
+
+use std::fmt::Write;
+use std::sync::Arc;
+
+use crate::ebpf::ShieldMonitor;
+use crate::shadow::{CircuitState, ShadowDb};
+use crate::state::{AgentState, IntegrityStatus};
+use crate::stats::HistogramSnapshot;
+
+/// Content type of the text exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// The kind of a metric family
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricType {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl MetricType {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Counter => "counter",
+            Self::Gauge => "gauge",
+            Self::Histogram => "histogram",
+        }
+    }
+}
+
+/// Something that owns metrics and can report them at scrape time
+pub trait Collector: Send + Sync {
+    fn collect(&self, out: &mut Exposition);
+}
+
+/// The set of collectors behind `/metrics`
+#[derive(Default)]
+pub struct Registry {
+    collectors: Vec<Arc<dyn Collector>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a collector; families are rendered in registration order
+    pub fn register(&mut self, collector: Arc<dyn Collector>) {
+        self.collectors.push(collector);
+    }
+
+    /// Render every registered collector
+    pub fn render(&self) -> String {
+        let mut out = Exposition::default();
+        for collector in &self.collectors {
+            collector.collect(&mut out);
+        }
+        out.text
+    }
+}
+
+/// A text exposition being written
+#[derive(Default)]
+pub struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    /// Start a metric family (HELP and TYPE lines)
+    pub fn family(&mut self, name: &str, help: &str, kind: MetricType) {
+        let help = help.replace('\\', "\\\\").replace('\n', "\\n");
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind.as_str());
+    }
+
+    /// Write one sample of the current family
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.text.push_str(name);
+        write_labels(&mut self.text, labels, None);
+        let _ = writeln!(self.text, " {}", format_value(value));
+    }
+
+    /// Write a single-sample family
+    pub fn single(&mut self, name: &str, help: &str, kind: MetricType, value: f64) {
+        self.family(name, help, kind);
+        self.sample(name, &[], value);
+    }
+
+    /// Write the buckets, sum and count of one histogram
+    pub fn histogram(&mut self, name: &str, labels: &[(&str, &str)], snapshot: &HistogramSnapshot) {
+        let bucket = format!("{}_bucket", name);
+        for (bound, count) in &snapshot.buckets {
+            self.text.push_str(&bucket);
+            write_labels(&mut self.text, labels, Some(&format_value(*bound)));
+            let _ = writeln!(self.text, " {}", count);
+        }
+        self.text.push_str(&bucket);
+        write_labels(&mut self.text, labels, Some("+Inf"));
+        let _ = writeln!(self.text, " {}", snapshot.count);
+
+        self.sample(&format!("{}_sum", name), labels, snapshot.sum);
+        self.sample(&format!("{}_count", name), labels, snapshot.count as f64);
+    }
+}
+
+fn write_labels(out: &mut String, labels: &[(&str, &str)], le: Option<&str>) {
+    if labels.is_empty() && le.is_none() {
+        return;
+    }
+    out.push('{');
+    let le = le.map(|le| ("le", le));
+    for (i, (name, value)) in labels.iter().copied().chain(le).enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+        let _ = write!(out, "{}=\"{}\"", name, value);
+    }
+    out.push('}');
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else if value.is_nan() {
+        "NaN".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn flag(on: bool) -> f64 {
+    if on {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+// =============================================================================
+// COLLECTORS
+// =============================================================================
+
+impl Collector for AgentState {
+    fn collect(&self, out: &mut Exposition) {
+        out.family("yacht_agent_info", "Agent information", MetricType::Gauge);
+        out.sample("yacht_agent_info", &[("version", wharf_core::VERSION)], 1.0);
+
+        let stats = self.stats.snapshot();
+        out.family(
+            "yacht_queries_total",
+            "Total number of database queries processed, by verdict",
+            MetricType::Counter,
+        );
+        for (verdict, count) in [("allowed", stats.allowed), ("audited", stats.audited), ("blocked", stats.blocked)] {
+            out.sample("yacht_queries_total", &[("verdict", verdict)], count as f64);
+        }
+        out.single(
+            "yacht_result_limits_exceeded_total",
+            "Queries whose result set crossed its limit",
+            MetricType::Counter,
+            stats.result_limits_exceeded as f64,
+        );
+
+        out.family(
+            "yacht_query_inspect_duration_seconds",
+            "Time spent checking a query against the policy",
+            MetricType::Histogram,
+        );
+        out.histogram("yacht_query_inspect_duration_seconds", &[], &self.stats.inspect_latency.snapshot());
+        out.family(
+            "yacht_query_duration_seconds",
+            "Time from forwarding a query to the first byte of its response",
+            MetricType::Histogram,
+        );
+        out.histogram("yacht_query_duration_seconds", &[], &self.stats.query_latency.snapshot());
+
+        out.single(
+            "yacht_moored",
+            "Whether the Wharf is moored (1) or not (0)",
+            MetricType::Gauge,
+            flag(self.is_moored()),
+        );
+
+        let integrity = self.integrity_status();
+        out.family(
+            "yacht_integrity_status",
+            "File integrity state (1 for the current state)",
+            MetricType::Gauge,
+        );
+        for state in [IntegrityStatus::Unknown, IntegrityStatus::Verified, IntegrityStatus::Failed] {
+            out.sample("yacht_integrity_status", &[("state", state.as_str())], flag(state == integrity));
+        }
+
+        out.family("yacht_policy_info", "Hash of each loaded policy", MetricType::Gauge);
+        out.sample("yacht_policy_info", &[("policy", "database"), ("hash", &self.db_policy_hash())], 1.0);
+        out.sample("yacht_policy_info", &[("policy", "header"), ("hash", &self.header_policy_hash())], 1.0);
+
+        out.single(
+            "yacht_audit_events_dropped_total",
+            "Audit events dropped because the log writer fell behind",
+            MetricType::Counter,
+            self.audit.dropped() as f64,
+        );
+
+        out.single(
+            "yacht_csp_reports_total",
+            "CSP violations reported by browsers",
+            MetricType::Counter,
+            self.csp_reports.received() as f64,
+        );
+    }
+}
+
+impl Collector for ShadowDb {
+    fn collect(&self, out: &mut Exposition) {
+        out.single(
+            "yacht_db_proxy_connections",
+            "Active database proxy connections",
+            MetricType::Gauge,
+            self.active_connections() as f64,
+        );
+        out.single(
+            "yacht_db_proxy_max_connections",
+            "Maximum concurrent database proxy connections",
+            MetricType::Gauge,
+            self.config().max_connections as f64,
+        );
+
+        let current = self.breaker().state();
+        out.family(
+            "yacht_shadow_circuit_state",
+            "Shadow database circuit breaker state (1 for the current state)",
+            MetricType::Gauge,
+        );
+        for state in [CircuitState::Closed, CircuitState::Open, CircuitState::HalfOpen] {
+            out.sample("yacht_shadow_circuit_state", &[("state", state.as_str())], flag(state == current));
+        }
+    }
+}
+
+impl Collector for ShieldMonitor {
+    fn collect(&self, out: &mut Exposition) {
+        out.family("yacht_firewall_mode", "Current firewall mode", MetricType::Gauge);
+        out.sample("yacht_firewall_mode", &[("mode", self.mode())], 1.0);
+
+        // Only the XDP shield counts packets
+        if let Some(counts) = self.packet_counts() {
+            out.family(
+                "yacht_packets_total",
+                "Packets judged by the XDP shield, by action",
+                MetricType::Counter,
+            );
+            for (action, count) in [("passed", counts.passed), ("dropped", counts.dropped), ("aborted", counts.aborted)] {
+                out.sample("yacht_packets_total", &[("action", action)], count as f64);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::Histogram;
+    use std::time::Duration;
+    use wharf_core::db_policy::QueryAction;
+
+    #[test]
+    fn test_exposition_format() {
+        let mut out = Exposition::default();
+        out.family("test_total", "A test\ncounter", MetricType::Counter);
+        out.sample("test_total", &[("path", "C:\\x \"y\"")], 3.0);
+
+        assert_eq!(
+            out.text,
+            "# HELP test_total A test\\ncounter\n# TYPE test_total counter\ntest_total{path=\"C:\\\\x \\\"y\\\"\"} 3\n"
+        );
+    }
+
+    #[test]
+    fn test_histogram_exposition() {
+        let histogram = Histogram::new(&[0.1, 1.0]);
+        histogram.observe(Duration::from_millis(50));
+        histogram.observe(Duration::from_secs(2));
+
+        let mut out = Exposition::default();
+        out.histogram("latency_seconds", &[("db", "mysql")], &histogram.snapshot());
+
+        let lines: Vec<&str> = out.text.lines().collect();
+        assert_eq!(lines[0], "latency_seconds_bucket{db=\"mysql\",le=\"0.1\"} 1");
+        assert_eq!(lines[1], "latency_seconds_bucket{db=\"mysql\",le=\"1\"} 1");
+        assert_eq!(lines[2], "latency_seconds_bucket{db=\"mysql\",le=\"+Inf\"} 2");
+        assert_eq!(lines[3], "latency_seconds_sum{db=\"mysql\"} 2.05");
+        assert_eq!(lines[4], "latency_seconds_count{db=\"mysql\"} 2");
+    }
+
+    #[test]
+    fn test_registry_reports_live_counters() {
+        let state = Arc::new(AgentState::new());
+        state.stats.record(QueryAction::Block);
+        state.stats.record(QueryAction::Allow);
+        state.stats.record(QueryAction::Allow);
+
+        let mut registry = Registry::new();
+        registry.register(state.clone());
+        let text = registry.render();
+
+        assert!(text.contains("yacht_queries_total{verdict=\"allowed\"} 2\n"));
+        assert!(text.contains("yacht_queries_total{verdict=\"blocked\"} 1\n"));
+        assert!(text.contains("yacht_integrity_status{state=\"unknown\"} 1\n"));
+        assert!(text.contains(&format!("hash=\"{}\"", state.db_policy_hash())));
+
+        // Every family is declared exactly once
+        let types: Vec<&str> = text.lines().filter(|l| l.starts_with("# TYPE")).collect();
+        let unique: std::collections::HashSet<&str> = types.iter().copied().collect();
+        assert_eq!(types.len(), unique.len());
+    }
+}
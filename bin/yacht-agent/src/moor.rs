@@ -0,0 +1,427 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Mooring Service
+//!
+//! Executes signed commands from the Wharf (see `wharf_core::mooring`).
+//! An envelope is opened against the trust store and its nonce is spent
+//! before anything runs, so a command that fails half way still cannot be
+//! replayed.
+//!
+//! Policy pushes go through the `PolicyStore`. With a health probe
+//! configured, each push is put on probation for its grace period and
+//! rolled back if the site stops answering.
+//!
+//! Commands arrive on `/moor`. Integrity checks can also be requested on
+//! `/verify`, which takes only `verify_integrity` envelopes and answers with
+//! a `RemoteVerifyResult` for `integrity::verify_remote_api`.
+//!
+//! The yacht reports itself moored for `MOORED_SECS` after each command
+//! that succeeds, or until the Wharf sends `unmoor`.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+use thiserror::Error;
+use tracing::{info, warn};
+
+use wharf_core::events::SecurityEvent;
+use wharf_core::integrity::{verify_manifest, Manifest, RemoteVerifyResult};
+use wharf_core::mooring::{CommandEnvelope, MooringCommand, MooringError, ReplayGuard, SignedEnvelope, TrustStore};
+
+use crate::ebpf::ShieldMonitor;
+use crate::policy::{self, HealthProbe, PolicyStore};
+use crate::state::{AgentState, IntegrityStatus};
+
+#[derive(Error, Debug)]
+pub enum MoorError {
+    /// The envelope was refused; nothing ran
+    #[error(transparent)]
+    Rejected(#[from] MooringError),
+
+    /// A valid envelope sent to the wrong endpoint
+    #[error("Endpoint does not accept '{0}' commands")]
+    WrongCommand(&'static str),
+
+    /// The envelope was accepted but the command could not be carried out
+    #[error("Command failed: {0}")]
+    Failed(String),
+}
+
+impl MoorError {
+    /// HTTP status for the `/moor` response
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Rejected(MooringError::Malformed(_)) | Self::WrongCommand(_) => StatusCode::BAD_REQUEST,
+            Self::Rejected(MooringError::UnknownCaptain(_) | MooringError::BadSignature) => StatusCode::UNAUTHORIZED,
+            Self::Rejected(MooringError::Replay) => StatusCode::CONFLICT,
+            Self::Rejected(MooringError::IoError(_)) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Rejected(_) => StatusCode::FORBIDDEN,
+            Self::Failed(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+/// Opens envelopes and runs the commands inside them
+pub struct MooringService {
+    yacht: String,
+    trust: TrustStore,
+    replay: Mutex<ReplayGuard>,
+    state: Arc<AgentState>,
+    policies: Arc<PolicyStore>,
+    shield: Arc<ShieldMonitor>,
+    web_root: PathBuf,
+    probe: Option<Arc<HealthProbe>>,
+    grace: Duration,
+}
+
+impl MooringService {
+    /// `yacht` is this yacht's name in the fleet; envelopes must match it
+    pub fn new(
+        yacht: &str,
+        trust: TrustStore,
+        state: Arc<AgentState>,
+        policies: Arc<PolicyStore>,
+        shield: Arc<ShieldMonitor>,
+        web_root: PathBuf,
+    ) -> Self {
+        Self {
+            yacht: yacht.to_string(),
+            trust,
+            replay: Mutex::new(ReplayGuard::new()),
+            state,
+            policies,
+            shield,
+            web_root,
+            probe: None,
+            grace: Duration::ZERO,
+        }
+    }
+
+    /// Remember spent nonces with `replay` instead of in memory only
+    pub fn with_replay_guard(mut self, replay: ReplayGuard) -> Self {
+        self.replay = Mutex::new(replay);
+        self
+    }
+
+    /// Probe the site after each policy push, for `grace` unless the push says otherwise
+    pub fn with_probe(mut self, probe: HealthProbe, grace: Duration) -> Self {
+        self.probe = Some(Arc::new(probe));
+        self.grace = grace;
+        self
+    }
+
+    /// Verify an envelope and run its command
+    pub async fn handle(&self, signed: &SignedEnvelope) -> Result<Value, MoorError> {
+        let envelope = self.open(signed)?;
+        let name = envelope.command.name();
+
+        let outcome = self.execute(envelope.command, &signed.captain).await;
+        match &outcome {
+            Ok(_) => {
+                if name != "unmoor" {
+                    self.state.moor();
+                }
+                info!("Mooring command '{}' done", name);
+            }
+            Err(e) => warn!("Mooring command '{}' failed: {}", name, e),
+        }
+        outcome
+    }
+
+    /// Verify a `verify_integrity` envelope and check the web root
+    pub async fn verify(&self, signed: &SignedEnvelope) -> Result<RemoteVerifyResult, MoorError> {
+        let envelope = self.open(signed)?;
+        match envelope.command {
+            MooringCommand::VerifyIntegrity { manifest } => self.verify_integrity(manifest).await,
+            other => {
+                warn!("'{}' command sent to the verify endpoint", other.name());
+                Err(MoorError::WrongCommand(other.name()))
+            }
+        }
+    }
+
+    /// Check the signature and spend the nonce
+    fn open(&self, signed: &SignedEnvelope) -> Result<CommandEnvelope, MoorError> {
+        let now = chrono::Utc::now().timestamp();
+        let envelope = self
+            .trust
+            .open(signed, &self.yacht, now)
+            .and_then(|envelope| {
+                let mut replay = self.replay.lock().unwrap_or_else(|e| e.into_inner());
+                replay.check(&envelope, now).map(|_| envelope)
+            })
+            .inspect_err(|e| warn!("Mooring command from '{}' refused: {}", signed.captain, e))?;
+
+        info!(
+            "Mooring command '{}' from '{}' (nonce {})",
+            envelope.command.name(),
+            signed.captain,
+            envelope.nonce
+        );
+        Ok(envelope)
+    }
+
+    async fn execute(&self, command: MooringCommand, captain: &str) -> Result<Value, MoorError> {
+        match command {
+            MooringCommand::PushPolicy { bundle, grace_secs } => {
+                let version = self
+                    .policies
+                    .apply(*bundle, &format!("push by {}", captain))
+                    .map_err(|e| MoorError::Failed(e.to_string()))?;
+
+                let grace = grace_secs.map_or(self.grace, Duration::from_secs);
+                let probation = match &self.probe {
+                    Some(probe) if !grace.is_zero() => {
+                        let generation = self.policies.generation();
+                        tokio::spawn(policy::probation(self.policies.clone(), generation, probe.clone(), grace));
+                        grace.as_secs()
+                    }
+                    _ => 0,
+                };
+                Ok(json!({ "version": version.version, "probation_secs": probation }))
+            }
+            MooringCommand::RollbackPolicy { version } => {
+                let version = self
+                    .policies
+                    .rollback(version.as_deref())
+                    .map_err(|e| MoorError::Failed(e.to_string()))?;
+                Ok(json!({ "version": version.version }))
+            }
+            MooringCommand::VerifyIntegrity { manifest } => {
+                let result = self.verify_integrity(manifest).await?;
+                serde_json::to_value(result).map_err(|e| MoorError::Failed(e.to_string()))
+            }
+            MooringCommand::BlockIp { ip } => {
+                self.shield.block_ip(ip).map_err(|e| MoorError::Failed(format!("{:#}", e)))?;
+                self.state.events.publish(SecurityEvent::ShieldBlock {
+                    ip,
+                    reason: format!("blocked by {}", captain),
+                });
+                Ok(json!({ "blocked": ip }))
+            }
+            MooringCommand::UnblockIp { ip } => {
+                self.shield.unblock_ip(ip).map_err(|e| MoorError::Failed(format!("{:#}", e)))?;
+                self.state.events.publish(SecurityEvent::ShieldUnblock { ip });
+                Ok(json!({ "unblocked": ip }))
+            }
+            MooringCommand::Unmoor => {
+                self.state.unmoor();
+                Ok(json!({ "moored": false }))
+            }
+        }
+    }
+
+    /// Check the web root against the manifest and keep its hashes
+    async fn verify_integrity(&self, manifest: Manifest) -> Result<RemoteVerifyResult, MoorError> {
+        let expected = manifest
+            .files
+            .iter()
+            .map(|(path, entry)| (path.clone(), entry.hash.clone()))
+            .collect();
+        *self.state.integrity_hashes.write().await = expected;
+
+        let files = manifest.files.len();
+        let root = self.web_root.clone();
+        let result = tokio::task::spawn_blocking(move || verify_manifest(&root, &manifest, false))
+            .await
+            .map_err(|e| MoorError::Failed(e.to_string()))?
+            .map_err(|e| MoorError::Failed(e.to_string()))?;
+
+        let status = if result.is_ok() {
+            IntegrityStatus::Verified
+        } else {
+            IntegrityStatus::Failed
+        };
+        self.state.set_integrity_status(status);
+        let passed = result.is_ok();
+        let outcome = RemoteVerifyResult {
+            yacht: self.yacht.clone(),
+            passed,
+            files_checked: files - result.missing.len(),
+            mismatched: result.mismatched.into_iter().map(|(path, _, _)| path).collect(),
+            missing: result.missing,
+            unexpected: result.unexpected,
+            timestamp: chrono::Utc::now().timestamp() as u64,
+            error: None,
+        };
+
+        if !passed {
+            warn!(
+                "Integrity check failed: {} modified, {} missing, {} unexpected",
+                outcome.mismatched.len(),
+                outcome.missing.len(),
+                outcome.unexpected.len()
+            );
+            self.state.events.publish(SecurityEvent::IntegrityViolation {
+                modified: outcome.mismatched.clone(),
+                missing: outcome.missing.clone(),
+                unexpected: outcome.unexpected.clone(),
+            });
+        }
+        Ok(outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use wharf_core::crypto::{generate_signing_key, SigningKey};
+    use wharf_core::db_policy::QueryAction;
+    use wharf_core::integrity::generate_manifest;
+    use wharf_core::mooring::CommandEnvelope;
+    use wharf_core::policy::PolicyBundle;
+
+    fn service(web_root: PathBuf) -> (SigningKey, MooringService) {
+        let key = generate_signing_key();
+        let mut trust = TrustStore::new();
+        trust.add("harbourmaster", key.verifying_key());
+        let state = Arc::new(AgentState::new());
+        let policies = Arc::new(PolicyStore::open(state.clone(), 5, None).unwrap());
+        let shield = Arc::new(ShieldMonitor::new("nftables", None));
+        (key, MooringService::new("yacht-01", trust, state, policies, shield, web_root))
+    }
+
+    fn signed(key: &SigningKey, command: MooringCommand) -> SignedEnvelope {
+        CommandEnvelope::new("yacht-01", command, 60)
+            .sign("harbourmaster", key)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_policy_update_is_applied_once() {
+        let (key, service) = service(PathBuf::from("/nonexistent"));
+        let mut bundle = PolicyBundle::default();
+        bundle.database.lock_down.clear();
+        bundle.database.allow_write.push("wp_users".to_string());
+        let version = bundle.version();
+        let envelope = signed(&key, MooringCommand::PushPolicy { bundle: Box::new(bundle), grace_secs: None });
+
+        let outcome = service.handle(&envelope).await.unwrap();
+        assert_eq!(outcome["version"], json!(version));
+        let query = "INSERT INTO wp_users (user_login) VALUES ('x')";
+        assert_eq!(service.state.db_engine.load().analyze(query).unwrap(), QueryAction::Allow);
+        assert!(service.state.is_moored());
+        assert_eq!(service.policies.current().source, "push by harbourmaster");
+
+        let replay = service.handle(&envelope).await.unwrap_err();
+        assert_eq!(replay.status(), StatusCode::CONFLICT);
+
+        service
+            .handle(&signed(&key, MooringCommand::RollbackPolicy { version: None }))
+            .await
+            .unwrap();
+        assert!(service.state.db_engine.load().analyze(query).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_bundle_is_not_applied() {
+        let (key, service) = service(PathBuf::from("/nonexistent"));
+        let before = service.state.db_policy_hash();
+        let mut bundle = PolicyBundle::default();
+        bundle.database.allow_write.push(" ".to_string());
+
+        let error = service
+            .handle(&signed(&key, MooringCommand::PushPolicy { bundle: Box::new(bundle), grace_secs: None }))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(error.to_string().contains("empty table name"), "{}", error);
+        assert_eq!(service.state.db_policy_hash(), before);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_envelope_changes_nothing() {
+        let (_, service) = service(PathBuf::from("/nonexistent"));
+        let before = service.state.db_policy_hash();
+        let envelope = signed(
+            &generate_signing_key(),
+            MooringCommand::PushPolicy {
+                bundle: Box::default(),
+                grace_secs: None,
+            },
+        );
+
+        let error = service.handle(&envelope).await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(service.state.db_policy_hash(), before);
+        assert!(!service.state.is_moored());
+    }
+
+    #[tokio::test]
+    async fn test_integrity_check_sets_status() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.php"), "<?php echo 'hi';").unwrap();
+        let manifest = generate_manifest(dir.path(), &[]).unwrap();
+        let (key, service) = service(dir.path().to_path_buf());
+
+        service
+            .handle(&signed(&key, MooringCommand::VerifyIntegrity { manifest: manifest.clone() }))
+            .await
+            .unwrap();
+        assert_eq!(service.state.integrity_status(), IntegrityStatus::Verified);
+        assert_eq!(service.state.integrity_hashes.read().await.len(), 1);
+
+        std::fs::write(dir.path().join("index.php"), "<?php eval($_GET['x']);").unwrap();
+        let outcome = service
+            .handle(&signed(&key, MooringCommand::VerifyIntegrity { manifest }))
+            .await
+            .unwrap();
+        assert_eq!(service.state.integrity_status(), IntegrityStatus::Failed);
+        assert_eq!(outcome["mismatched"], json!(["index.php"]));
+    }
+
+    #[tokio::test]
+    async fn test_verify_endpoint_only_verifies() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.php"), "<?php echo 'hi';").unwrap();
+        let manifest = generate_manifest(dir.path(), &[]).unwrap();
+        let (key, service) = service(dir.path().to_path_buf());
+
+        let result = service
+            .verify(&signed(&key, MooringCommand::VerifyIntegrity { manifest }))
+            .await
+            .unwrap();
+        assert!(result.is_ok());
+        assert_eq!(result.yacht, "yacht-01");
+        assert_eq!(result.files_checked, 1);
+
+        let block = signed(&key, MooringCommand::BlockIp { ip: Ipv4Addr::new(192, 0, 2, 1) });
+        let error = service.verify(&block).await.unwrap_err();
+        assert!(matches!(error, MoorError::WrongCommand("block_ip")));
+    }
+
+    #[tokio::test]
+    async fn test_blocklist_needs_the_shield() {
+        let (key, service) = service(PathBuf::from("/nonexistent"));
+        let error = service
+            .handle(&signed(&key, MooringCommand::BlockIp { ip: Ipv4Addr::new(192, 0, 2, 1) }))
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_unmoor_clears_moored() {
+        let (key, service) = service(PathBuf::from("/nonexistent"));
+        service
+            .handle(&signed(&key, MooringCommand::RollbackPolicy { version: None }))
+            .await
+            .unwrap_err();
+        assert!(!service.state.is_moored());
+
+        service
+            .handle(&signed(&key, MooringCommand::PushPolicy { bundle: Box::default(), grace_secs: None }))
+            .await
+            .unwrap();
+        assert!(service.state.is_moored());
+
+        let result = service.handle(&signed(&key, MooringCommand::Unmoor)).await.unwrap();
+        assert_eq!(result["moored"], false);
+        assert!(!service.state.is_moored());
+    }
+}
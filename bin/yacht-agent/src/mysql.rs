@@ -0,0 +1,492 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # MySQL Wire Protocol
+//!
+//! Just enough of the MySQL/MariaDB client/server protocol for the proxy to
+//! frame packets, pick out commands and follow a result set as it streams
+//! back. Everything else passes through untouched.
+//!
+//! A packet is a 3-byte little-endian length, a sequence id and the payload.
+//! Payloads of 16MB or more are split into full frames followed by a
+//! shorter one; `read_packet` joins them into one logical packet, up to a
+//! size limit that stays small until the client has authenticated.
+
+use std::borrow::Cow;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// COM_QUERY: a text query follows
+pub const COM_QUERY: u8 = 0x03;
+/// COM_STMT_PREPARE: a query to prepare follows
+pub const COM_STMT_PREPARE: u8 = 0x16;
+/// COM_STMT_EXECUTE: execute a prepared statement by id
+pub const COM_STMT_EXECUTE: u8 = 0x17;
+/// COM_STMT_CLOSE: drop a prepared statement
+pub const COM_STMT_CLOSE: u8 = 0x19;
+
+/// ER_CON_COUNT_ERROR
+pub const ER_CON_COUNT: u16 = 1040;
+/// ER_ACCESS_DENIED_ERROR (used for blocked queries)
+pub const ER_ACCESS_DENIED: u16 = 1045;
+/// ER_SERVER_SHUTDOWN (used for "shadow unavailable")
+pub const ER_SERVER_SHUTDOWN: u16 = 1053;
+/// ER_QUERY_INTERRUPTED (used for result sets cut off by a limit)
+pub const ER_QUERY_INTERRUPTED: u16 = 1317;
+
+/// CLIENT_COMPRESS capability flag (zlib-compressed packets)
+pub const CLIENT_COMPRESS: u32 = 0x0020;
+/// CLIENT_SSL capability flag
+pub const CLIENT_SSL: u32 = 0x0800;
+/// CLIENT_ZSTD_COMPRESSION_ALGORITHM capability flag
+pub const CLIENT_ZSTD_COMPRESSION: u32 = 0x0400_0000;
+
+/// Capabilities that would hide the traffic from the proxy
+pub const UNINSPECTABLE: u32 = CLIENT_SSL | CLIENT_COMPRESS | CLIENT_ZSTD_COMPRESSION;
+
+/// SERVER_MORE_RESULTS_EXISTS status flag
+const SERVER_MORE_RESULTS_EXISTS: u16 = 0x0008;
+
+/// Largest payload a single frame carries; a full frame means more follows
+const MAX_FRAME_PAYLOAD: usize = 0xff_ffff;
+
+/// Largest packet accepted before authentication completes
+pub const MAX_AUTH_PACKET: usize = 65_535;
+
+/// Buffer reserved up front for a frame payload
+const PAYLOAD_CHUNK: usize = 8192;
+
+/// A logical MySQL packet, as read off the wire
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    raw: Vec<u8>,
+    frames: usize,
+}
+
+impl Packet {
+    /// The bytes exactly as received, frame headers included
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// Sequence id of the first frame
+    pub fn sequence(&self) -> u8 {
+        self.raw[3]
+    }
+
+    /// The payload with frame headers removed
+    pub fn payload(&self) -> Cow<'_, [u8]> {
+        if self.frames == 1 {
+            return Cow::Borrowed(&self.raw[4..]);
+        }
+
+        let mut payload = Vec::with_capacity(self.raw.len());
+        let mut offset = 0;
+        while offset < self.raw.len() {
+            let len = frame_len(&self.raw[offset..]);
+            payload.extend_from_slice(&self.raw[offset + 4..offset + 4 + len]);
+            offset += 4 + len;
+        }
+        Cow::Owned(payload)
+    }
+
+    /// First payload byte (the command, or the response type)
+    pub fn first_byte(&self) -> Option<u8> {
+        self.raw.get(4).copied()
+    }
+
+    /// Whether the packet fit in a single frame
+    pub fn is_single_frame(&self) -> bool {
+        self.frames == 1
+    }
+}
+
+fn frame_len(header: &[u8]) -> usize {
+    u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize
+}
+
+/// Read one logical packet; `None` on a clean EOF between packets
+///
+/// `max` is the largest logical packet accepted, read as each frame header
+/// arrives so it can be raised while a read is waiting. The buffer grows as
+/// the payload arrives rather than being sized from the declared length.
+pub async fn read_packet<R>(reader: &mut R, max: &AtomicUsize) -> io::Result<Option<Packet>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut raw = Vec::new();
+    let mut frames = 0;
+
+    loop {
+        let mut header = [0u8; 4];
+        match reader.read_exact(&mut header).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && frames == 0 => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let len = frame_len(&header);
+        if raw.len() - 4 * frames + len > max.load(Ordering::Relaxed) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "MySQL packet too large"));
+        }
+
+        raw.reserve(PAYLOAD_CHUNK.min(len + 4));
+        raw.extend_from_slice(&header);
+        if (&mut *reader).take(len as u64).read_to_end(&mut raw).await? != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        frames += 1;
+
+        if len < MAX_FRAME_PAYLOAD {
+            return Ok(Some(Packet { raw, frames }));
+        }
+    }
+}
+
+/// Clear the [`UNINSPECTABLE`] capabilities in the server greeting
+///
+/// The proxy can only inspect plaintext, uncompressed packets, so clients
+/// must not be offered TLS or compression on the masquerade socket. Returns
+/// false if the packet is not a protocol 10 handshake (e.g. the server
+/// answered with an ERR instead).
+pub fn clear_uninspectable_capabilities(greeting: &mut Packet) -> bool {
+    if !greeting.is_single_frame() || greeting.first_byte() != Some(10) {
+        return false;
+    }
+
+    // protocol version, NUL-terminated server version, thread id (4),
+    // auth-plugin-data part 1 (8), filler (1), then the lower capability
+    // flags (2); the upper flags (2) follow charset (1) and status (2)
+    let payload = &greeting.raw[4..];
+    let Some(version_end) = payload[1..].iter().position(|&b| b == 0) else {
+        return false;
+    };
+    let lower = 4 + 1 + version_end + 1 + 4 + 8 + 1;
+    let upper = lower + 2 + 1 + 2;
+    if greeting.raw.len() < lower + 2 {
+        return false;
+    }
+
+    let mask = (!UNINSPECTABLE).to_le_bytes();
+    greeting.raw[lower] &= mask[0];
+    greeting.raw[lower + 1] &= mask[1];
+    if greeting.raw.len() >= upper + 2 {
+        greeting.raw[upper] &= mask[2];
+        greeting.raw[upper + 1] &= mask[3];
+    }
+    true
+}
+
+/// Capability flags from a client handshake response
+pub fn client_capabilities(response: &Packet) -> Option<u32> {
+    let payload = response.payload();
+    let flags = payload.get(0..4)?;
+    Some(u32::from_le_bytes([flags[0], flags[1], flags[2], flags[3]]))
+}
+
+/// User name from a (protocol 4.1) client handshake response
+pub fn handshake_username(response: &Packet) -> Option<String> {
+    // capability flags (4), max packet size (4), charset (1), filler (23)
+    let payload = response.payload();
+    let rest = payload.get(32..)?;
+    let end = rest.iter().position(|&b| b == 0)?;
+    Some(String::from_utf8_lossy(&rest[..end]).into_owned())
+}
+
+/// Statement id from a COM_STMT_PREPARE_OK, COM_STMT_EXECUTE or COM_STMT_CLOSE
+pub fn statement_id(packet: &Packet) -> Option<u32> {
+    let payload = packet.payload();
+    let id = payload.get(1..5)?;
+    Some(u32::from_le_bytes([id[0], id[1], id[2], id[3]]))
+}
+
+/// Read a length-encoded integer, returning it and the bytes it used
+fn read_lenenc(buf: &[u8]) -> Option<(u64, usize)> {
+    let width = match *buf.first()? {
+        b @ 0..=0xfa => return Some((b as u64, 1)),
+        0xfc => 2,
+        0xfd => 3,
+        0xfe => 8,
+        _ => return None,
+    };
+    let bytes = buf.get(1..1 + width)?;
+    let mut value = [0u8; 8];
+    value[..width].copy_from_slice(bytes);
+    Some((u64::from_le_bytes(value), 1 + width))
+}
+
+/// Status flags from an OK packet (header byte 0x00 or 0xfe)
+fn ok_status(payload: &[u8]) -> u16 {
+    let mut offset = 1;
+    for _ in 0..2 {
+        match read_lenenc(&payload[offset.min(payload.len())..]) {
+            Some((_, used)) => offset += used,
+            None => return 0,
+        }
+    }
+    payload
+        .get(offset..offset + 2)
+        .map(|s| u16::from_le_bytes([s[0], s[1]]))
+        .unwrap_or(0)
+}
+
+/// Status flags from a (5-byte) EOF packet
+fn eof_status(payload: &[u8]) -> u16 {
+    payload.get(3..5).map(|s| u16::from_le_bytes([s[0], s[1]])).unwrap_or(0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// Waiting for OK, ERR or a column count
+    Start,
+    /// Column definitions still to come
+    Columns(u64),
+    /// Columns done; an EOF may follow unless CLIENT_DEPRECATE_EOF is on
+    ColumnsDone,
+    /// Rows until an EOF/OK or ERR
+    Rows,
+    /// Response complete
+    Done,
+}
+
+/// Follows the server's response to a query, picking out the rows
+///
+/// Works for text (COM_QUERY) and binary (COM_STMT_EXECUTE) result sets,
+/// with or without CLIENT_DEPRECATE_EOF, and across multiple result sets.
+/// The terminator is told apart from a row by its 0xfe header: a row can
+/// only start with 0xfe if its first value is over 16MB, which always
+/// spans several frames.
+#[derive(Debug)]
+pub struct ResultSetTracker {
+    phase: Phase,
+}
+
+impl ResultSetTracker {
+    pub fn new() -> Self {
+        Self { phase: Phase::Start }
+    }
+
+    /// Whether the whole response has been seen
+    pub fn is_done(&self) -> bool {
+        self.phase == Phase::Done
+    }
+
+    /// Feed the next server packet; returns true if it carried a row
+    pub fn observe(&mut self, packet: &Packet) -> bool {
+        let Some(first) = packet.first_byte() else {
+            return false;
+        };
+
+        match self.phase {
+            Phase::Start => {
+                self.phase = match first {
+                    0x00 => self.after_status(ok_status(&packet.payload())),
+                    0xff | 0xfb => Phase::Done,
+                    _ => match read_lenenc(&packet.payload()) {
+                        Some((columns, _)) if columns > 0 => Phase::Columns(columns),
+                        _ => Phase::Done,
+                    },
+                };
+                false
+            }
+            Phase::Columns(remaining) => {
+                self.phase = if remaining > 1 { Phase::Columns(remaining - 1) } else { Phase::ColumnsDone };
+                false
+            }
+            Phase::ColumnsDone if first == 0xfe && packet.raw().len() == 4 + 5 => {
+                self.phase = Phase::Rows;
+                false
+            }
+            Phase::ColumnsDone | Phase::Rows => {
+                if first == 0xff {
+                    self.phase = Phase::Done;
+                    false
+                } else if first == 0xfe && packet.is_single_frame() {
+                    let payload = packet.payload();
+                    let status = if payload.len() == 5 { eof_status(&payload) } else { ok_status(&payload) };
+                    self.phase = self.after_status(status);
+                    false
+                } else {
+                    self.phase = Phase::Rows;
+                    true
+                }
+            }
+            Phase::Done => false,
+        }
+    }
+
+    fn after_status(&self, status: u16) -> Phase {
+        if status & SERVER_MORE_RESULTS_EXISTS != 0 {
+            Phase::Start
+        } else {
+            Phase::Done
+        }
+    }
+}
+
+impl Default for ResultSetTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Build a MySQL ERR packet
+///
+/// `sql_state` must be `None` before the handshake: the client has not yet
+/// negotiated CLIENT_PROTOCOL_41 and would read the marker as message text.
+pub fn error_packet(sequence: u8, code: u16, sql_state: Option<&str>, message: &str) -> Vec<u8> {
+    // Error packet: header + 0xff + errno + [sqlstate] + message
+    let mut payload = Vec::with_capacity(message.len() + 9);
+    payload.push(0xff); // Error marker
+    payload.extend_from_slice(&code.to_le_bytes()); // Error code
+    if let Some(state) = sql_state {
+        payload.push(b'#'); // SQL state marker
+        payload.extend_from_slice(state.as_bytes()); // SQL state
+    }
+    payload.extend_from_slice(message.as_bytes());
+
+    // Length (3 bytes) + Sequence (1 byte)
+    let mut packet = Vec::with_capacity(payload.len() + 4);
+    packet.extend_from_slice(&(payload.len() as u32).to_le_bytes()[0..3]);
+    packet.push(sequence);
+    packet.extend_from_slice(&payload);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(sequence: u8, payload: &[u8]) -> Packet {
+        let mut raw = (payload.len() as u32).to_le_bytes()[0..3].to_vec();
+        raw.push(sequence);
+        raw.extend_from_slice(payload);
+        Packet { raw, frames: 1 }
+    }
+
+    #[test]
+    fn test_error_packet_layout() {
+        let packet = error_packet(1, 1045, Some("HY000"), "nope");
+        assert_eq!(&packet[0..4], &[13, 0, 0, 1]);
+        assert_eq!(packet[4], 0xff);
+        assert_eq!(u16::from_le_bytes([packet[5], packet[6]]), 1045);
+        assert_eq!(&packet[7..13], b"#HY000");
+        assert_eq!(&packet[13..], b"nope");
+
+        // Pre-handshake packets carry no SQL state
+        let packet = error_packet(0, 1040, None, "Too many connections");
+        assert_eq!(packet[3], 0);
+        assert_eq!(&packet[7..], b"Too many connections");
+    }
+
+    #[tokio::test]
+    async fn test_read_packet_joins_frames() {
+        let mut wire = Vec::new();
+        wire.extend_from_slice(&[0xff, 0xff, 0xff, 0]);
+        wire.resize(wire.len() + MAX_FRAME_PAYLOAD, b'a');
+        wire.extend_from_slice(&[2, 0, 0, 1, b'b', b'c']);
+        wire.extend_from_slice(&[1, 0, 0, 0, COM_QUERY]);
+
+        let mut reader = wire.as_slice();
+        let max = AtomicUsize::new(MAX_AUTH_PACKET);
+        // Too big before authentication
+        assert!(read_packet(&mut reader, &max).await.is_err());
+
+        max.store(MAX_FRAME_PAYLOAD + 2, Ordering::Relaxed);
+        let mut reader = wire.as_slice();
+        let first = read_packet(&mut reader, &max).await.unwrap().unwrap();
+        assert!(!first.is_single_frame());
+        assert_eq!(first.raw().len(), 4 + MAX_FRAME_PAYLOAD + 6);
+        let payload = first.payload();
+        assert_eq!(payload.len(), MAX_FRAME_PAYLOAD + 2);
+        assert_eq!(&payload[MAX_FRAME_PAYLOAD..], b"bc");
+
+        let second = read_packet(&mut reader, &max).await.unwrap().unwrap();
+        assert_eq!(second.first_byte(), Some(COM_QUERY));
+        assert!(read_packet(&mut reader, &max).await.unwrap().is_none());
+
+        // A frame that declares more than arrives is cut short, not allocated
+        let mut reader: &[u8] = &[0xff, 0xff, 0xff, 0, b'a'];
+        let e = read_packet(&mut reader, &max).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_clear_uninspectable_capabilities() {
+        let mut payload = vec![10];
+        payload.extend_from_slice(b"10.11.6-MariaDB\0");
+        payload.extend_from_slice(&[1, 0, 0, 0]); // thread id
+        payload.extend_from_slice(b"abcdefgh\0"); // auth data + filler
+        payload.extend_from_slice(&0xf7ffu16.to_le_bytes()); // capabilities (lower)
+        let mut greeting = packet(0, &payload);
+
+        assert!(clear_uninspectable_capabilities(&mut greeting));
+        let caps = &greeting.payload()[payload.len() - 2..];
+        assert_eq!(u16::from_le_bytes([caps[0], caps[1]]), 0xf7ff & !((CLIENT_SSL | CLIENT_COMPRESS) as u16));
+
+        // The upper flags, when the greeting carries them
+        payload.push(0x21); // charset
+        payload.extend_from_slice(&0x0002u16.to_le_bytes()); // status
+        payload.extend_from_slice(&0x07ffu16.to_le_bytes()); // capabilities (upper)
+        let mut greeting = packet(0, &payload);
+        assert!(clear_uninspectable_capabilities(&mut greeting));
+        let caps = &greeting.payload()[payload.len() - 2..];
+        assert_eq!(u16::from_le_bytes([caps[0], caps[1]]), 0x07ff & !((CLIENT_ZSTD_COMPRESSION >> 16) as u16));
+
+        let mut err = packet(0, &error_packet(0, 1040, None, "full")[4..]);
+        assert!(!clear_uninspectable_capabilities(&mut err));
+    }
+
+    #[test]
+    fn test_handshake_username() {
+        let mut payload = 0x000fa685u32.to_le_bytes().to_vec();
+        payload.extend_from_slice(&[0; 28]);
+        payload.extend_from_slice(b"wordpress\0\x14");
+        assert_eq!(handshake_username(&packet(1, &payload)).as_deref(), Some("wordpress"));
+        assert_eq!(handshake_username(&packet(1, &[0; 8])), None);
+    }
+
+    #[test]
+    fn test_tracker_classic_eof() {
+        let mut tracker = ResultSetTracker::new();
+        let rows: Vec<bool> = [
+            packet(1, &[2]),                   // column count
+            packet(2, b"\x03defcol1"),         // column definitions
+            packet(3, b"\x03defcol2"),
+            packet(4, &[0xfe, 0, 0, 2, 0]),    // EOF after columns
+            packet(5, b"\x011\x05admin"),      // rows
+            packet(6, b"\x012\x06editor"),
+            packet(7, &[0xfe, 0, 0, 2, 0]),    // EOF after rows
+        ]
+        .iter()
+        .map(|p| tracker.observe(p))
+        .collect();
+
+        assert_eq!(rows, [false, false, false, false, true, true, false]);
+        assert!(tracker.is_done());
+    }
+
+    #[test]
+    fn test_tracker_deprecate_eof_and_more_results() {
+        let mut tracker = ResultSetTracker::new();
+        // First result set, terminated by an OK (0xfe) with MORE_RESULTS
+        assert!(!tracker.observe(&packet(1, &[1])));
+        assert!(!tracker.observe(&packet(2, b"\x03def")));
+        assert!(tracker.observe(&packet(3, b"\x011")));
+        assert!(!tracker.observe(&packet(4, &[0xfe, 0, 0, 0x0a, 0, 0, 0])));
+        assert!(!tracker.is_done());
+
+        // Second statement returns a plain OK
+        assert!(!tracker.observe(&packet(5, &[0x00, 1, 0, 0x02, 0, 0, 0])));
+        assert!(tracker.is_done());
+
+        // An ERR in place of a row ends the response
+        let mut tracker = ResultSetTracker::new();
+        tracker.observe(&packet(1, &[1]));
+        tracker.observe(&packet(2, b"\x03def"));
+        assert!(!tracker.observe(&packet(3, &error_packet(3, 1317, Some("70100"), "x")[4..])));
+        assert!(tracker.is_done());
+    }
+}
@@ -0,0 +1,360 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Proxy Transports
+//!
+//! The database proxy speaks the same wire protocol over TCP and Unix domain
+//! sockets. PHP deployments usually reach MySQL through
+//! `/run/mysqld/mysqld.sock`, and a socket file guarded by ownership and mode
+//! is a much smaller attack surface than a TCP port.
+//!
+//! Endpoints are written as `host:port` for TCP and `unix:/path` (or any
+//! absolute path) for Unix sockets.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tracing::{info, warn};
+
+/// Where to listen or connect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// TCP `host:port`
+    Tcp(String),
+    /// Unix domain socket path
+    Unix(PathBuf),
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("unix: endpoint needs a socket path".to_string());
+            }
+            Ok(Self::Unix(PathBuf::from(path)))
+        } else if s.starts_with('/') {
+            Ok(Self::Unix(PathBuf::from(s)))
+        } else if s.contains(':') {
+            Ok(Self::Tcp(s.to_string()))
+        } else {
+            Err(format!("'{}' is neither host:port nor unix:/path", s))
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl Endpoint {
+    /// Connect to this endpoint
+    pub async fn connect(&self) -> io::Result<Stream> {
+        match self {
+            Self::Tcp(addr) => Ok(Stream::Tcp(TcpStream::connect(addr).await?)),
+            Self::Unix(path) => Ok(Stream::Unix(UnixStream::connect(path).await?)),
+        }
+    }
+}
+
+/// A connected TCP or Unix stream
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(s) => Pin::new(s).poll_read(cx, buf),
+            Self::Unix(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(s) => Pin::new(s).poll_write(cx, buf),
+            Self::Unix(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(s) => Pin::new(s).poll_flush(cx),
+            Self::Unix(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(s) => Pin::new(s).poll_shutdown(cx),
+            Self::Unix(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Ownership and permissions applied to a listening Unix socket
+#[derive(Debug, Clone, Default)]
+pub struct SocketPermissions {
+    /// File mode (e.g. 0o660)
+    pub mode: Option<u32>,
+    /// Owner, by name or numeric uid
+    pub owner: Option<String>,
+    /// Group, by name or numeric gid
+    pub group: Option<String>,
+}
+
+/// A bound TCP or Unix listener
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    /// Bind to an endpoint
+    ///
+    /// For Unix sockets a stale socket file left by a previous run is
+    /// removed first, but a socket someone is still listening on is not.
+    pub async fn bind(endpoint: &Endpoint, permissions: &SocketPermissions) -> io::Result<Self> {
+        match endpoint {
+            Endpoint::Tcp(addr) => Ok(Self::Tcp(TcpListener::bind(addr).await?)),
+            Endpoint::Unix(path) => {
+                remove_stale_socket(path).await?;
+                let listener = bind_private(path, permissions)?;
+                Ok(Self::Unix(listener, path.clone()))
+            }
+        }
+    }
+
+    /// Accept a connection, returning the stream and a printable peer name
+    pub async fn accept(&self) -> io::Result<(Stream, String)> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                Ok((Stream::Tcp(stream), addr.to_string()))
+            }
+            Self::Unix(listener, path) => {
+                let (stream, _) = listener.accept().await?;
+                let peer = match stream.peer_cred() {
+                    Ok(cred) => format!("unix:{} (uid {})", path.display(), cred.uid()),
+                    Err(_) => format!("unix:{}", path.display()),
+                };
+                Ok((Stream::Unix(stream), peer))
+            }
+        }
+    }
+
+    /// The endpoint this listener is bound to
+    pub fn local_endpoint(&self) -> io::Result<Endpoint> {
+        match self {
+            Self::Tcp(listener) => Ok(Endpoint::Tcp(listener.local_addr()?.to_string())),
+            Self::Unix(_, path) => Ok(Endpoint::Unix(path.clone())),
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Self::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+async fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+
+    if UnixStream::connect(path).await.is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{} is already being served", path.display()),
+        ));
+    }
+
+    warn!("Removing stale socket {}", path.display());
+    std::fs::remove_file(path)
+}
+
+/// Bind a Unix socket in a private (0700) directory beside `path`, give it
+/// its ownership and mode there, then move it into place
+///
+/// A socket bound at `path` directly would be reachable under the process
+/// umask until its mode was set.
+fn bind_private(path: &Path, permissions: &SocketPermissions) -> io::Result<UnixListener> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    let parent = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    static BINDS: AtomicU32 = AtomicU32::new(0);
+    let bind = BINDS.fetch_add(1, Ordering::Relaxed);
+    let staging = parent.join(format!(".wharf-bind-{}-{}", std::process::id(), bind));
+    let staged = staging.join("s");
+    // Left behind by an earlier process with the same pid
+    let _ = std::fs::remove_file(&staged);
+    let _ = std::fs::remove_dir(&staging);
+    std::fs::DirBuilder::new().mode(0o700).create(&staging)?;
+
+    let bound = UnixListener::bind(&staged).and_then(|listener| {
+        apply_permissions(&staged, permissions)?;
+        std::fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_file(&staged);
+    let _ = std::fs::remove_dir(&staging);
+    let listener = bound?;
+
+    info!(
+        "Socket {} permissions: mode={} owner={} group={}",
+        path.display(),
+        permissions.mode.map(|m| format!("{:o}", m)).unwrap_or_else(|| "default".to_string()),
+        permissions.owner.as_deref().unwrap_or("default"),
+        permissions.group.as_deref().unwrap_or("default"),
+    );
+    Ok(listener)
+}
+
+fn apply_permissions(path: &Path, permissions: &SocketPermissions) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let uid = permissions.owner.as_deref().map(|o| resolve_id(o, "/etc/passwd")).transpose()?;
+    let gid = permissions.group.as_deref().map(|g| resolve_id(g, "/etc/group")).transpose()?;
+    if uid.is_some() || gid.is_some() {
+        std::os::unix::fs::chown(path, uid, gid)?;
+    }
+
+    if let Some(mode) = permissions.mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+/// Resolve a user or group name to its id
+///
+/// Numeric ids are used as-is. Names are looked up in the given database
+/// file (`/etc/passwd` or `/etc/group`) so the static distroless build does
+/// not depend on NSS.
+fn resolve_id(name: &str, database: &str) -> io::Result<u32> {
+    if let Ok(id) = name.parse() {
+        return Ok(id);
+    }
+
+    let content = std::fs::read_to_string(database)?;
+    lookup_id(&content, name).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("'{}' not found in {}", name, database))
+    })
+}
+
+fn lookup_id(database: &str, name: &str) -> Option<u32> {
+    database
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.len() > 2 && fields[0] == name)
+        .and_then(|fields| fields[2].parse().ok())
+}
+
+/// Parse an octal file mode such as `660` or `0o660`
+pub fn parse_mode(s: &str) -> Result<u32, String> {
+    let digits = s.trim_start_matches("0o");
+    u32::from_str_radix(digits, 8)
+        .ok()
+        .filter(|m| *m <= 0o7777)
+        .ok_or_else(|| format!("'{}' is not an octal file mode", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_parse_endpoint() {
+        assert_eq!("127.0.0.1:3306".parse::<Endpoint>().unwrap(), Endpoint::Tcp("127.0.0.1:3306".to_string()));
+        assert_eq!(
+            "unix:/run/mysqld/mysqld.sock".parse::<Endpoint>().unwrap(),
+            Endpoint::Unix(PathBuf::from("/run/mysqld/mysqld.sock"))
+        );
+        assert_eq!("/tmp/db.sock".parse::<Endpoint>().unwrap(), Endpoint::Unix(PathBuf::from("/tmp/db.sock")));
+        assert!("localhost".parse::<Endpoint>().is_err());
+        assert!("unix:".parse::<Endpoint>().is_err());
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("660").unwrap(), 0o660);
+        assert_eq!(parse_mode("0o600").unwrap(), 0o600);
+        assert_eq!(parse_mode("0660").unwrap(), 0o660);
+        assert!(parse_mode("999").is_err());
+    }
+
+    #[test]
+    fn test_lookup_id() {
+        let passwd = "root:x:0:0:root:/root:/bin/sh\nmysql:x:27:27::/var/lib/mysql:/sbin/nologin\n";
+        assert_eq!(lookup_id(passwd, "mysql"), Some(27));
+        assert_eq!(lookup_id(passwd, "nobody"), None);
+    }
+
+    #[tokio::test]
+    async fn test_unix_listener_roundtrip() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proxy.sock");
+        let endpoint = Endpoint::Unix(path.clone());
+
+        let permissions = SocketPermissions { mode: Some(0o600), ..Default::default() };
+        let listener = Listener::bind(&endpoint, &permissions).await.unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode, 0o600);
+        // Bound in a private directory, which is gone again
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let client = tokio::spawn({
+            let endpoint = endpoint.clone();
+            async move {
+                let mut stream = endpoint.connect().await.unwrap();
+                stream.write_all(b"ping").await.unwrap();
+            }
+        });
+
+        let (mut stream, peer) = listener.accept().await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        assert!(peer.starts_with("unix:"));
+        client.await.unwrap();
+
+        // A live socket must not be clobbered by a second bind
+        assert!(Listener::bind(&endpoint, &permissions).await.is_err());
+
+        drop(listener);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,499 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Policy Versions
+//!
+//! Every policy the agent enforces goes through the `PolicyStore`: bundles
+//! pushed by the Wharf, policy files read at startup or on SIGHUP, and
+//! rollbacks. The store validates a bundle, swaps both policies in, and
+//! keeps the last few versions so that any of them can be restored.
+//!
+//! The history is written to disk (if configured) before the new policy
+//! takes effect, so an agent that restarts comes back with the policy it
+//! was last given rather than the defaults.
+//!
+//! A push can be put on probation: a `HealthProbe` checks the site for a
+//! grace period afterwards, and if it fails repeatedly the push is rolled
+//! back on its own. A later push or rollback ends the probation.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+use wharf_core::events::SecurityEvent;
+use wharf_core::policy::{BundleError, PolicyBundle};
+
+use crate::state::AgentState;
+
+/// Consecutive probe failures that trigger an automatic rollback
+const FAILURES_BEFORE_ROLLBACK: u32 = 2;
+
+#[derive(Error, Debug)]
+pub enum PolicyError {
+    #[error(transparent)]
+    Bundle(#[from] BundleError),
+
+    #[error("Failed to write policy history {0}: {1}")]
+    Persist(PathBuf, std::io::Error),
+
+    #[error("Policy history {0} is unreadable: {1}")]
+    Corrupt(PathBuf, String),
+
+    #[error("No earlier policy version to roll back to")]
+    NoPrevious,
+
+    #[error("Policy version '{0}' is not in the history")]
+    UnknownVersion(String),
+}
+
+/// One entry in the policy history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyVersion {
+    /// Content hash of the bundle
+    pub version: String,
+    /// Unix timestamp the version was (last) applied at
+    pub applied_at: i64,
+    /// Where it came from: "startup", "file", "push by <captain>", "rollback", ...
+    pub source: String,
+    pub bundle: PolicyBundle,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct HistoryFile {
+    /// Newest first
+    versions: Vec<PolicyVersion>,
+}
+
+struct History {
+    /// Newest (= enforced) first, one entry per version
+    versions: VecDeque<PolicyVersion>,
+    /// Bumped on every change, so a probation can tell it was superseded
+    generation: u64,
+}
+
+/// The enforced policy and the versions before it
+pub struct PolicyStore {
+    state: Arc<AgentState>,
+    keep: usize,
+    path: Option<PathBuf>,
+    restored: bool,
+    history: Mutex<History>,
+}
+
+impl PolicyStore {
+    /// Open the store, keeping `keep` versions in `path` (memory only if `None`)
+    ///
+    /// A saved history is restored and its newest version enforced;
+    /// otherwise the policies already loaded in `state` become the first
+    /// version.
+    pub fn open(state: Arc<AgentState>, keep: usize, path: Option<PathBuf>) -> Result<Self, PolicyError> {
+        let saved = match &path {
+            Some(path) => load_history(path)?,
+            None => Vec::new(),
+        };
+
+        let store = Self {
+            state,
+            keep: keep.max(1),
+            path,
+            restored: !saved.is_empty(),
+            history: Mutex::new(History {
+                versions: VecDeque::new(),
+                generation: 0,
+            }),
+        };
+
+        let mut history = store.lock();
+        if let Some(head) = saved.first() {
+            head.bundle.validate()?;
+            store.enforce(&head.bundle);
+            info!("Restored policy {} ({} versions in history)", head.version, saved.len());
+            history.versions = saved.into_iter().take(store.keep).collect();
+        } else {
+            let bundle = store.enforced_bundle();
+            history.versions.push_front(PolicyVersion {
+                version: bundle.version(),
+                applied_at: chrono::Utc::now().timestamp(),
+                source: "startup".to_string(),
+                bundle,
+            });
+        }
+        drop(history);
+        Ok(store)
+    }
+
+    /// Validate a bundle and enforce it
+    ///
+    /// Applying the version already in force changes nothing.
+    pub fn apply(&self, bundle: PolicyBundle, source: &str) -> Result<PolicyVersion, PolicyError> {
+        bundle.validate()?;
+        let version = PolicyVersion {
+            version: bundle.version(),
+            applied_at: chrono::Utc::now().timestamp(),
+            source: source.to_string(),
+            bundle,
+        };
+
+        let mut history = self.lock();
+        if let Some(current) = history.versions.front() {
+            if current.version == version.version {
+                return Ok(current.clone());
+            }
+        }
+        let previous = history.versions.front().map(|v| v.version.clone()).unwrap_or_default();
+        self.commit(&mut history, version.clone())?;
+        info!("Policy {} applied from {} (was {})", version.version, source, previous);
+        Ok(version)
+    }
+
+    /// Enforce an earlier version again (the one before the current by default)
+    pub fn rollback(&self, to: Option<&str>) -> Result<PolicyVersion, PolicyError> {
+        let mut history = self.lock();
+        self.rollback_locked(&mut history, to, "rollback")
+    }
+
+    /// Roll back to the previous version, unless the policy changed since `generation`
+    pub fn rollback_if_current(&self, generation: u64) -> Result<Option<PolicyVersion>, PolicyError> {
+        let mut history = self.lock();
+        if history.generation != generation {
+            return Ok(None);
+        }
+        self.rollback_locked(&mut history, None, "auto-rollback").map(Some)
+    }
+
+    /// The version in force
+    pub fn current(&self) -> PolicyVersion {
+        self.lock()
+            .versions
+            .front()
+            .cloned()
+            .expect("policy history is never empty")
+    }
+
+    /// Every kept version, newest first
+    pub fn history(&self) -> Vec<PolicyVersion> {
+        self.lock().versions.iter().cloned().collect()
+    }
+
+    /// Whether `open` restored a saved history
+    pub fn restored(&self) -> bool {
+        self.restored
+    }
+
+    /// Changes so far (see `rollback_if_current`)
+    pub fn generation(&self) -> u64 {
+        self.lock().generation
+    }
+
+    /// The policies currently loaded in the agent state
+    pub fn enforced_bundle(&self) -> PolicyBundle {
+        PolicyBundle {
+            database: self.state.db_engine.load().policy().clone(),
+            header: (**self.state.header_policy.load()).clone(),
+        }
+    }
+
+    fn rollback_locked(
+        &self,
+        history: &mut History,
+        to: Option<&str>,
+        source: &str,
+    ) -> Result<PolicyVersion, PolicyError> {
+        let current = history.versions.front().map(|v| v.version.clone()).unwrap_or_default();
+        let target = match to {
+            None => history.versions.get(1).ok_or(PolicyError::NoPrevious)?,
+            Some(wanted) => find_version(&history.versions, wanted)?,
+        };
+
+        let version = PolicyVersion {
+            version: target.version.clone(),
+            applied_at: chrono::Utc::now().timestamp(),
+            source: source.to_string(),
+            bundle: target.bundle.clone(),
+        };
+        if version.version != current {
+            self.commit(history, version.clone())?;
+            warn!("Policy rolled back from {} to {} ({})", current, version.version, source);
+        }
+        Ok(version)
+    }
+
+    /// Record `version` as the newest entry, save the history, then enforce it
+    fn commit(&self, history: &mut History, version: PolicyVersion) -> Result<(), PolicyError> {
+        let mut versions = history.versions.clone();
+        versions.retain(|v| v.version != version.version);
+        versions.push_front(version);
+        versions.truncate(self.keep);
+
+        // Saved first: if the history cannot be written, nothing changes
+        if let Some(path) = &self.path {
+            save_history(path, &versions).map_err(|e| PolicyError::Persist(path.clone(), e))?;
+        }
+
+        self.enforce(&versions[0].bundle);
+        self.state.events.publish(SecurityEvent::PolicyChange {
+            version: versions[0].version.clone(),
+            previous: history.versions.front().map(|v| v.version.clone()).unwrap_or_default(),
+            source: versions[0].source.clone(),
+        });
+        history.versions = versions;
+        history.generation += 1;
+        Ok(())
+    }
+
+    fn enforce(&self, bundle: &PolicyBundle) {
+        self.state.reload_db_policy(bundle.database.clone());
+        self.state.reload_header_policy(bundle.header.clone());
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, History> {
+        self.history.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Look a version up by its hash or an unambiguous prefix of it
+fn find_version<'a>(versions: &'a VecDeque<PolicyVersion>, wanted: &str) -> Result<&'a PolicyVersion, PolicyError> {
+    let mut matches = versions.iter().filter(|v| !wanted.is_empty() && v.version.starts_with(wanted));
+    match (matches.next(), matches.next()) {
+        (Some(version), None) => Ok(version),
+        _ => Err(PolicyError::UnknownVersion(wanted.to_string())),
+    }
+}
+
+fn load_history(path: &Path) -> Result<Vec<PolicyVersion>, PolicyError> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str::<HistoryFile>(&content)
+            .map(|file| file.versions)
+            .map_err(|e| PolicyError::Corrupt(path.to_path_buf(), e.to_string())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(PolicyError::Corrupt(path.to_path_buf(), e.to_string())),
+    }
+}
+
+/// Write via a temporary file so a crash never leaves half a history
+fn save_history(path: &Path, versions: &VecDeque<PolicyVersion>) -> std::io::Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let file = HistoryFile {
+        versions: versions.iter().cloned().collect(),
+    };
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(&file)?)?;
+    std::fs::rename(&tmp, path)
+}
+
+// =============================================================================
+// PROBATION
+// =============================================================================
+
+/// Checks that the site still works after a policy change
+pub struct HealthProbe {
+    url: String,
+    interval: Duration,
+    client: reqwest::Client,
+}
+
+impl HealthProbe {
+    /// Probe `url` every five seconds
+    pub fn new(url: &str) -> Self {
+        Self::with_interval(url, Duration::from_secs(5))
+    }
+
+    pub fn with_interval(url: &str, interval: Duration) -> Self {
+        Self {
+            url: url.to_string(),
+            interval,
+            client: reqwest::Client::builder()
+                .timeout(interval)
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// A 2xx or 3xx answer counts as healthy
+    pub async fn check(&self) -> Result<(), String> {
+        let response = self.client.get(&self.url).send().await.map_err(|e| e.to_string())?;
+        let status = response.status();
+        if status.is_success() || status.is_redirection() {
+            Ok(())
+        } else {
+            Err(format!("{} answered {}", self.url, status))
+        }
+    }
+}
+
+/// Probe the site for `grace` after the change that made `generation`
+///
+/// Rolls back to the previous version after repeated failures. Returns the
+/// version rolled back to, if any.
+pub async fn probation(
+    store: Arc<PolicyStore>,
+    generation: u64,
+    probe: Arc<HealthProbe>,
+    grace: Duration,
+) -> Option<PolicyVersion> {
+    let deadline = Instant::now() + grace;
+    let mut failures = 0;
+
+    while Instant::now() < deadline {
+        tokio::time::sleep(probe.interval).await;
+        if store.generation() != generation {
+            // Superseded by a newer push or a rollback
+            return None;
+        }
+
+        match probe.check().await {
+            Ok(()) => failures = 0,
+            Err(e) => {
+                failures += 1;
+                warn!("Policy health probe failed ({}/{}): {}", failures, FAILURES_BEFORE_ROLLBACK, e);
+                if failures >= FAILURES_BEFORE_ROLLBACK {
+                    return match store.rollback_if_current(generation) {
+                        Ok(rolled_back) => rolled_back,
+                        Err(e) => {
+                            error!("Automatic policy rollback failed: {}", e);
+                            None
+                        }
+                    };
+                }
+            }
+        }
+    }
+
+    info!("Policy {} passed its {}s grace period", store.current().version, grace.as_secs());
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wharf_core::db_policy::QueryAction;
+
+    const INSERT_USER: &str = "INSERT INTO wp_users (user_login) VALUES ('x')";
+
+    fn writable_users() -> PolicyBundle {
+        let mut bundle = PolicyBundle::default();
+        bundle.database.lock_down.clear();
+        bundle.database.allow_write.push("wp_users".to_string());
+        bundle
+    }
+
+    fn header_only(host: &str) -> PolicyBundle {
+        let mut bundle = PolicyBundle::default();
+        bundle.header.allowed_hosts.push(host.to_string());
+        bundle
+    }
+
+    #[test]
+    fn test_apply_and_rollback() {
+        let state = Arc::new(AgentState::new());
+        let store = PolicyStore::open(state.clone(), 5, None).unwrap();
+        let startup = store.current().version;
+
+        let pushed = store.apply(writable_users(), "push by wharf").unwrap();
+        assert_eq!(state.db_engine.load().analyze(INSERT_USER).unwrap(), QueryAction::Allow);
+        assert_eq!(store.history().len(), 2);
+
+        // Same bundle again: nothing new
+        store.apply(writable_users(), "push by wharf").unwrap();
+        assert_eq!(store.history().len(), 2);
+
+        let restored = store.rollback(None).unwrap();
+        assert_eq!(restored.version, startup);
+        assert!(state.db_engine.load().analyze(INSERT_USER).is_err());
+
+        // Back again by (abbreviated) version
+        store.rollback(Some(&pushed.version[..12])).unwrap();
+        assert_eq!(state.db_engine.load().analyze(INSERT_USER).unwrap(), QueryAction::Allow);
+        assert!(matches!(store.rollback(Some("nope")), Err(PolicyError::UnknownVersion(_))));
+    }
+
+    #[test]
+    fn test_invalid_bundle_is_refused() {
+        let state = Arc::new(AgentState::new());
+        let store = PolicyStore::open(state, 5, None).unwrap();
+        let mut bundle = PolicyBundle::default();
+        bundle.database.allow_write.push(String::new());
+
+        assert!(matches!(store.apply(bundle, "push by wharf"), Err(PolicyError::Bundle(_))));
+        assert_eq!(store.history().len(), 1);
+        assert!(matches!(store.rollback(None), Err(PolicyError::NoPrevious)));
+    }
+
+    #[test]
+    fn test_history_is_bounded_and_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy-history.json");
+
+        let store = PolicyStore::open(Arc::new(AgentState::new()), 3, Some(path.clone())).unwrap();
+        for host in ["a.example", "b.example", "c.example"] {
+            store.apply(header_only(host), "push by wharf").unwrap();
+        }
+        let history = store.history();
+        assert_eq!(history.len(), 3);
+        assert!(history.iter().all(|v| v.source != "startup"));
+
+        let state = Arc::new(AgentState::new());
+        let reopened = PolicyStore::open(state.clone(), 3, Some(path)).unwrap();
+        assert_eq!(reopened.current().version, history[0].version);
+        assert_eq!(state.header_policy.load().allowed_hosts, vec!["c.example".to_string()]);
+    }
+
+    #[test]
+    fn test_unwritable_history_changes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        // A directory where the file should be
+        let path = dir.path().join("history");
+        let state = Arc::new(AgentState::new());
+        let store = PolicyStore::open(state.clone(), 5, Some(path.clone())).unwrap();
+        std::fs::create_dir_all(path.with_extension("tmp")).unwrap();
+
+        assert!(matches!(store.apply(writable_users(), "file"), Err(PolicyError::Persist(_, _))));
+        assert!(state.db_engine.load().analyze(INSERT_USER).is_err());
+        assert_eq!(store.generation(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_probation_rolls_back_a_broken_push() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let app = axum::Router::new().route(
+            "/",
+            axum::routing::get(|| async { axum::http::StatusCode::BAD_GATEWAY }),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let state = Arc::new(AgentState::new());
+        let store = Arc::new(PolicyStore::open(state.clone(), 5, None).unwrap());
+        let startup = store.current().version;
+        store.apply(writable_users(), "push by wharf").unwrap();
+
+        let probe = Arc::new(HealthProbe::with_interval(&url, Duration::from_millis(20)));
+        let rolled_back = probation(store.clone(), store.generation(), probe, Duration::from_secs(5)).await;
+
+        assert_eq!(rolled_back.unwrap().version, startup);
+        assert_eq!(store.current().source, "auto-rollback");
+        assert!(state.db_engine.load().analyze(INSERT_USER).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_probation_ends_when_superseded() {
+        let state = Arc::new(AgentState::new());
+        let store = Arc::new(PolicyStore::open(state, 5, None).unwrap());
+        store.apply(writable_users(), "push by wharf").unwrap();
+        let generation = store.generation();
+        store.apply(header_only("a.example"), "push by wharf").unwrap();
+
+        // Nothing listens here, but the push being watched is no longer current
+        let probe = Arc::new(HealthProbe::with_interval("http://127.0.0.1:9/", Duration::from_millis(10)));
+        assert!(probation(store.clone(), generation, probe, Duration::from_secs(5)).await.is_none());
+        assert_eq!(store.current().version, header_only("a.example").version());
+    }
+}
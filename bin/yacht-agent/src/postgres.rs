@@ -0,0 +1,224 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # PostgreSQL Wire Protocol
+//!
+//! Message framing for the proxy. After the untagged startup packet every
+//! message is a type byte, a 4-byte big-endian length (counting itself) and
+//! the body.
+
+use std::borrow::Cow;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// SSLRequest code in an untagged startup packet
+pub const SSL_REQUEST: u32 = 80877103;
+/// GSSENCRequest code in an untagged startup packet
+pub const GSSENC_REQUEST: u32 = 80877104;
+
+/// Largest startup packet the server itself accepts
+const MAX_STARTUP: usize = 10_000;
+
+/// Largest message accepted before authentication completes (the server's
+/// own limit for authentication tokens)
+pub const MAX_AUTH_MESSAGE: usize = 65_535;
+
+/// Buffer reserved up front for a message body
+const BODY_CHUNK: usize = 8192;
+
+/// A tagged protocol message, as read off the wire
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    raw: Vec<u8>,
+}
+
+impl Message {
+    /// The message type byte
+    pub fn tag(&self) -> u8 {
+        self.raw[0]
+    }
+
+    /// The message body after type and length
+    pub fn body(&self) -> &[u8] {
+        &self.raw[5..]
+    }
+
+    /// The bytes exactly as received
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+}
+
+/// Read the untagged startup-phase packet; `None` on a clean EOF
+pub async fn read_startup<R>(reader: &mut R) -> io::Result<Option<Vec<u8>>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let len = u32::from_be_bytes(len) as usize;
+    if !(8..=MAX_STARTUP).contains(&len) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid startup packet length"));
+    }
+
+    let mut raw = vec![0u8; len];
+    raw[..4].copy_from_slice(&(len as u32).to_be_bytes());
+    reader.read_exact(&mut raw[4..]).await?;
+    Ok(Some(raw))
+}
+
+/// The protocol version or request code of a startup packet
+pub fn startup_code(packet: &[u8]) -> u32 {
+    u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]])
+}
+
+/// The `user` parameter of a StartupMessage
+pub fn startup_user(packet: &[u8]) -> Option<String> {
+    let mut params = packet.get(8..)?;
+    while let Some((key, rest)) = read_cstr(params) {
+        if key.is_empty() {
+            break;
+        }
+        let (value, rest) = read_cstr(rest)?;
+        if key == "user" {
+            return Some(value.to_string());
+        }
+        params = rest;
+    }
+    None
+}
+
+/// Read one tagged message; `None` on a clean EOF between messages
+///
+/// `max` is the largest message accepted, read once the header has arrived
+/// so it can be raised while a read is waiting. The buffer grows as the body
+/// arrives rather than being sized from the length the peer declares.
+pub async fn read_message<R>(reader: &mut R, max: &AtomicUsize) -> io::Result<Option<Message>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut header = [0u8; 5];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if len < 4 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid message length"));
+    }
+    if len > max.load(Ordering::Relaxed) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "PostgreSQL message too large"));
+    }
+
+    let mut raw = Vec::with_capacity(BODY_CHUNK.min(len + 1));
+    raw.extend_from_slice(&header);
+    let body = len as u64 - 4;
+    if (&mut *reader).take(body).read_to_end(&mut raw).await? as u64 != body {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(Some(Message { raw }))
+}
+
+/// Split a NUL-terminated string off the front of a message body
+pub fn read_cstr(body: &[u8]) -> Option<(&str, &[u8])> {
+    let end = body.iter().position(|&b| b == 0)?;
+    let s = std::str::from_utf8(&body[..end]).ok()?;
+    Some((s, &body[end + 1..]))
+}
+
+/// Split a NUL-terminated query off the front of a message body, decoding
+/// it leniently so that stray bytes cannot hide the statement
+pub fn read_query(body: &[u8]) -> Option<(Cow<'_, str>, &[u8])> {
+    let end = body.iter().position(|&b| b == 0)?;
+    Some((String::from_utf8_lossy(&body[..end]), &body[end + 1..]))
+}
+
+/// Build a PostgreSQL ErrorResponse message
+pub fn error_response(severity: &str, code: &str, message: &str) -> Vec<u8> {
+    let mut fields = Vec::with_capacity(message.len() + 32);
+    for (tag, value) in [(b'S', severity), (b'V', severity), (b'C', code), (b'M', message)] {
+        fields.push(tag);
+        fields.extend_from_slice(value.as_bytes());
+        fields.push(0);
+    }
+    fields.push(0);
+
+    let mut packet = Vec::with_capacity(fields.len() + 5);
+    packet.push(b'E'); // Error message type
+    let len = (fields.len() + 4) as i32;
+    packet.extend_from_slice(&len.to_be_bytes());
+    packet.extend_from_slice(&fields);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_response_layout() {
+        let packet = error_response("FATAL", "53300", "full");
+        assert_eq!(packet[0], b'E');
+        let len = i32::from_be_bytes([packet[1], packet[2], packet[3], packet[4]]) as usize;
+        assert_eq!(len, packet.len() - 1);
+        assert_eq!(&packet[5..], b"SFATAL\0VFATAL\0C53300\0Mfull\0\0");
+    }
+
+    #[tokio::test]
+    async fn test_read_startup_and_messages() {
+        let mut wire = Vec::new();
+        wire.extend_from_slice(&8u32.to_be_bytes());
+        wire.extend_from_slice(&SSL_REQUEST.to_be_bytes());
+        wire.push(b'Q');
+        wire.extend_from_slice(&13u32.to_be_bytes());
+        wire.extend_from_slice(b"SELECT 1\0");
+
+        let mut reader = wire.as_slice();
+        let startup = read_startup(&mut reader).await.unwrap().unwrap();
+        assert_eq!(startup_code(&startup), SSL_REQUEST);
+
+        let max = AtomicUsize::new(MAX_AUTH_MESSAGE);
+        let message = read_message(&mut reader, &max).await.unwrap().unwrap();
+        assert_eq!(message.tag(), b'Q');
+        let (query, rest) = read_cstr(message.body()).unwrap();
+        assert_eq!(query, "SELECT 1");
+        assert!(rest.is_empty());
+
+        assert!(read_message(&mut reader, &max).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_startup_user() {
+        let mut packet = vec![0, 0, 0, 0, 0, 3, 0, 0];
+        packet.extend_from_slice(b"database\0wordpress\0user\0wp\0\0");
+        assert_eq!(startup_user(&packet).as_deref(), Some("wp"));
+        assert_eq!(startup_user(&[0, 0, 0, 8, 0, 3, 0, 0, 0]), None);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_bad_lengths() {
+        let max = AtomicUsize::new(MAX_AUTH_MESSAGE);
+        let mut reader: &[u8] = &[b'Q', 0, 0, 0, 2];
+        assert!(read_message(&mut reader, &max).await.is_err());
+
+        // Over the limit in force when the header arrives, however little follows
+        let mut reader: &[u8] = &[b'p', 0, 1, 0, 0, 0];
+        assert!(read_message(&mut reader, &max).await.is_err());
+        max.store(1 << 20, Ordering::Relaxed);
+        let mut reader: &[u8] = &[b'p', 0, 1, 0, 0, 0];
+        let e = read_message(&mut reader, &max).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut reader: &[u8] = &[0xff, 0xff, 0xff, 0xff];
+        assert!(read_startup(&mut reader).await.is_err());
+    }
+}
@@ -0,0 +1,960 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Database Proxy
+//!
+//! The masquerade listener. Applications connect here believing it is the
+//! database; every query is run through the policy engine before it is
+//! forwarded to the shadow database.
+//!
+//! Both directions are framed, not copied blind. Client to server, each
+//! command is checked against the policy. Server to client, the rows of each
+//! result set are counted against the query's result budget, so a permitted
+//! SELECT that dumps a whole table is still caught.
+//!
+//! The proxy can only read plaintext. MySQL clients are not offered TLS or
+//! compression and PostgreSQL SSL/GSS encryption requests are declined on
+//! the server's behalf.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+
+use wharf_core::audit::{AuditDecision, AuditEvent};
+use wharf_core::db_policy::{normalize_query, LimitAction, QueryAction, QueryAnalysis, ResultBudget};
+use wharf_core::events::SecurityEvent;
+
+use crate::lifecycle::ShutdownSignal;
+use crate::mysql::{self, ResultSetTracker};
+use crate::net::{Endpoint, Listener};
+use crate::postgres;
+use crate::shadow::{ShadowDb, ShadowError};
+use crate::state::AgentState;
+
+/// Socket buffer size for each direction
+const BUFFER_SIZE: usize = 16384;
+
+/// Refusals being written at once; beyond this a client is just hung up on
+const MAX_REFUSALS: usize = 64;
+
+/// How long a refused client gets to take its error
+const REFUSAL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Run the database proxy server on a bound listener
+///
+/// Once `shutdown` fires the listener is closed and open sessions get up to
+/// `drain_timeout` to finish; whatever is still running then is cut off.
+pub async fn run_db_proxy(
+    listener: Listener,
+    protocol: &str,
+    shadow: Arc<ShadowDb>,
+    state: Arc<AgentState>,
+    shutdown: ShutdownSignal,
+    drain_timeout: Duration,
+) -> anyhow::Result<()> {
+    let endpoint = listener.local_endpoint()?;
+    info!("Database proxy listening on {}", endpoint);
+    info!("Forwarding to shadow DB at {}", shadow.config().endpoint);
+    info!("Max client connections: {}", shadow.config().max_connections);
+
+    let mut sessions = JoinSet::new();
+    let refusals = Arc::new(Semaphore::new(MAX_REFUSALS));
+    let stop = shutdown.wait();
+    tokio::pin!(stop);
+
+    loop {
+        let (mut client_socket, client_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // Usually out of file descriptors; back off instead of spinning
+                    warn!("Proxy accept failed: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+            // Reap finished sessions so the set does not grow
+            Some(_) = sessions.join_next() => continue,
+            _ = &mut stop => break,
+        };
+        let proto = protocol.to_string();
+        let conn_shadow = shadow.clone();
+        let conn_state = state.clone();
+
+        // Claim a slot before spawning so a flood is refused at the door
+        let permit = match shadow.admit() {
+            Ok(permit) => permit,
+            Err(e) => {
+                warn!("Refusing connection from {}: {}", client_addr, e);
+                if let Ok(refusal) = refusals.clone().try_acquire_owned() {
+                    tokio::spawn(async move {
+                        let _ = tokio::time::timeout(REFUSAL_TIMEOUT, refuse_client(&mut client_socket, &proto, &e)).await;
+                        drop(refusal);
+                    });
+                }
+                continue;
+            }
+        };
+
+        sessions.spawn(async move {
+            if let Err(e) = handle_db_connection(client_socket, &client_addr, &conn_shadow, &proto, conn_state).await {
+                warn!("Connection from {} error: {}", client_addr, e);
+            }
+            drop(permit);
+        });
+    }
+
+    // Stop accepting (removes a Unix socket path) before draining
+    drop(listener);
+    drain_sessions(&endpoint, sessions, drain_timeout).await;
+    Ok(())
+}
+
+/// Wait for open sessions to finish, aborting any left at the deadline
+async fn drain_sessions(endpoint: &Endpoint, mut sessions: JoinSet<()>, drain_timeout: Duration) {
+    if sessions.is_empty() {
+        return;
+    }
+    info!("Draining {} database sessions on {}", sessions.len(), endpoint);
+
+    let drained = tokio::time::timeout(drain_timeout, async {
+        while sessions.join_next().await.is_some() {}
+    })
+    .await;
+
+    if drained.is_err() {
+        warn!(
+            "Drain deadline passed - closing {} database sessions on {}",
+            sessions.len(),
+            endpoint
+        );
+        sessions.shutdown().await;
+    } else {
+        info!("All database sessions on {} closed", endpoint);
+    }
+}
+
+/// Run a query through the current policy engine and record the verdict
+///
+/// This is the hot path: it loads a snapshot of the policy engine without
+/// locking and bumps a sharded counter, so connections never wait on each
+/// other and a policy reload never stalls a query in flight.
+pub fn inspect_query(state: &AgentState, query: &str) -> QueryAnalysis {
+    let started = Instant::now();
+    let analysis = state
+        .db_engine
+        .load()
+        .inspect(query)
+        .unwrap_or_else(|e| QueryAnalysis {
+            action: QueryAction::Block,
+            rule: e.rule(),
+            result_budget: None,
+        });
+    state.stats.record(analysis.action);
+    state.stats.inspect_latency.observe(started.elapsed());
+    analysis
+}
+
+/// Who is on the other end of a proxied connection
+#[derive(Debug, Clone, Default)]
+struct ClientInfo {
+    /// Peer address (or Unix socket and uid)
+    peer: String,
+    /// Database user from the handshake, once seen
+    db_user: Option<String>,
+}
+
+impl ClientInfo {
+    fn new(peer: &str) -> Self {
+        Self {
+            peer: peer.to_string(),
+            db_user: None,
+        }
+    }
+
+    /// Write a decision to the audit log
+    fn audit(&self, state: &AgentState, decision: AuditDecision, query: &str, rule: &str) {
+        state.audit.record(AuditEvent::now(
+            &self.peer,
+            self.db_user.as_deref(),
+            decision,
+            &normalize_query(query),
+            rule,
+        ));
+    }
+
+    /// Tell `/events` subscribers a query was refused
+    fn publish_block(&self, state: &AgentState, query: &str, rule: &str) {
+        state.events.publish(SecurityEvent::QueryBlocked {
+            client: self.peer.clone(),
+            db_user: self.db_user.clone(),
+            query: normalize_query(query),
+            rule: rule.to_string(),
+        });
+    }
+}
+
+/// Inspect a query from a client, logging audited and blocked ones
+fn check_query(state: &AgentState, client: &ClientInfo, query: &str) -> QueryAnalysis {
+    let analysis = inspect_query(state, query);
+    match analysis.action {
+        QueryAction::Allow => {}
+        QueryAction::Audit => {
+            info!("AUDIT: {}", summarize(query));
+            client.audit(state, AuditDecision::Audit, query, &analysis.rule);
+        }
+        QueryAction::Block => {
+            warn!("BLOCKED: {}", summarize(query));
+            client.audit(state, AuditDecision::Block, query, &analysis.rule);
+            client.publish_block(state, query, &analysis.rule);
+        }
+    }
+    analysis
+}
+
+fn summarize(query: &str) -> String {
+    query.chars().take(100).collect()
+}
+
+/// A result budget together with the query it belongs to (for logging)
+#[derive(Debug, Clone)]
+struct Budgeted {
+    budget: ResultBudget,
+    query: Arc<str>,
+}
+
+impl Budgeted {
+    fn from_analysis(analysis: QueryAnalysis, query: &str) -> Option<Self> {
+        analysis.result_budget.map(|budget| Self {
+            budget,
+            query: query.into(),
+        })
+    }
+}
+
+/// Running totals for one query's result set
+#[derive(Debug, Default)]
+struct ResultWatch {
+    limit: Option<Budgeted>,
+    rows: u64,
+    bytes: u64,
+    tripped: bool,
+    /// When the query was forwarded, until its response starts
+    sent_at: Option<Instant>,
+}
+
+impl ResultWatch {
+    fn new(limit: Option<Budgeted>) -> Self {
+        Self {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    /// Start the latency clock for a query being forwarded now
+    fn timed(mut self) -> Self {
+        self.sent_at = Some(Instant::now());
+        self
+    }
+
+    /// Record the query latency on the first response message
+    fn response_started(&mut self, state: &AgentState) {
+        if let Some(sent_at) = self.sent_at.take() {
+            state.stats.query_latency.observe(sent_at.elapsed());
+        }
+    }
+
+    /// Count one row; returns the limit action the first time the budget is crossed
+    fn record_row(&mut self, bytes: usize, state: &AgentState, client: &ClientInfo) -> Option<LimitAction> {
+        self.rows += 1;
+        self.bytes += bytes as u64;
+
+        let limit = self.limit.as_ref()?;
+        if self.tripped || !limit.budget.is_exceeded(self.rows, self.bytes) {
+            return None;
+        }
+
+        self.tripped = true;
+        state.stats.result_limits_exceeded.inc();
+        warn!(
+            "RESULT LIMIT ({}): {} rows / {} bytes from {} exceeds budget (max rows {:?}, max bytes {:?}): {}",
+            match limit.budget.action {
+                LimitAction::Alert => "alert",
+                LimitAction::Terminate => "terminating",
+            },
+            self.rows,
+            self.bytes,
+            limit.budget.tables.join(", "),
+            limit.budget.max_rows,
+            limit.budget.max_bytes,
+            summarize(&limit.query),
+        );
+        let rule = format!("result_limit:{}", limit.budget.tables.join(","));
+        client.audit(state, AuditDecision::ResultLimit, &limit.query, &rule);
+        if matches!(limit.budget.action, LimitAction::Terminate) {
+            client.publish_block(state, &limit.query, &rule);
+        }
+        Some(limit.budget.action)
+    }
+}
+
+/// Handle a single database connection
+pub async fn handle_db_connection<C>(
+    mut client: C,
+    peer: &str,
+    shadow: &ShadowDb,
+    protocol: &str,
+    state: Arc<AgentState>,
+) -> std::io::Result<()>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    // Connect to the real database
+    let mut server = match shadow.connect().await {
+        Ok(server) => server,
+        Err(e) => {
+            warn!("Shadow connection failed: {}", e);
+            return refuse_client(&mut client, protocol, &e).await;
+        }
+    };
+
+    let max_packet = shadow.config().max_packet;
+    match protocol {
+        "mysql" | "mariadb" => proxy_mysql(client, server, &state, ClientInfo::new(peer), max_packet).await,
+        "postgres" => proxy_postgres(client, server, &state, ClientInfo::new(peer), max_packet).await,
+        _ => {
+            // Unknown protocol - pass through (fail-open for compatibility)
+            tokio::io::copy_bidirectional(&mut client, &mut server).await?;
+            Ok(())
+        }
+    }
+}
+
+/// What the server's next MySQL response answers
+#[derive(Debug, Default)]
+enum MysqlPending {
+    /// Handshake, or a command with no result set of interest
+    #[default]
+    Other,
+    /// COM_QUERY or COM_STMT_EXECUTE
+    Query(Option<Budgeted>),
+    /// COM_STMT_PREPARE; the budget is attached to the statement id
+    Prepare(Option<Budgeted>),
+}
+
+/// Per-connection MySQL state shared by both directions
+#[derive(Debug, Default)]
+struct MysqlSession {
+    /// Bumped for every command the client sends
+    generation: u64,
+    pending: MysqlPending,
+    /// Result budgets of prepared statements, by statement id
+    statements: HashMap<u32, Budgeted>,
+    /// Database user from the handshake response
+    db_user: Option<String>,
+    /// When the pending query was forwarded, until its response starts
+    sent_at: Option<Instant>,
+}
+
+/// Proxy a MySQL/MariaDB session
+///
+/// The protocol is strictly request/response: a client does not send a
+/// command until it has read the whole response to the previous one. So
+/// the first server packet after a new command is the start of that
+/// command's response.
+///
+/// Packets in either direction are held to `mysql::MAX_AUTH_PACKET` until
+/// the server accepts the client, and to `max_packet` after that.
+async fn proxy_mysql<C, S>(
+    client: C,
+    server: S,
+    state: &AgentState,
+    mut info: ClientInfo,
+    max_packet: usize,
+) -> std::io::Result<()>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (c_read, c_write) = tokio::io::split(client);
+    let (s_read, s_write) = tokio::io::split(server);
+    let mut c_read = BufReader::with_capacity(BUFFER_SIZE, c_read);
+    let mut c_write = BufWriter::with_capacity(BUFFER_SIZE, c_write);
+    let mut s_read = BufReader::with_capacity(BUFFER_SIZE, s_read);
+    let mut s_write = BufWriter::with_capacity(BUFFER_SIZE, s_write);
+
+    let limit = AtomicUsize::new(mysql::MAX_AUTH_PACKET.min(max_packet));
+
+    // Relay the greeting without the TLS and compression offers
+    let Some(mut greeting) = mysql::read_packet(&mut s_read, &limit).await? else {
+        return Ok(());
+    };
+    mysql::clear_uninspectable_capabilities(&mut greeting);
+    c_write.write_all(greeting.raw()).await?;
+    c_write.flush().await?;
+
+    let session = Mutex::new(MysqlSession::default());
+
+    // Returns the error packet to send if a query was blocked
+    let server_info = info.clone();
+    let client_to_server = async {
+        let mut handshake_done = false;
+        loop {
+            let Some(packet) = mysql::read_packet(&mut c_read, &limit).await? else {
+                return Ok::<_, std::io::Error>(None);
+            };
+
+            if !handshake_done {
+                handshake_done = true;
+                let caps = mysql::client_capabilities(&packet).unwrap_or(0);
+                if caps & mysql::UNINSPECTABLE != 0 {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::Unsupported,
+                        "client requested TLS or compression, which the proxy cannot inspect",
+                    ));
+                }
+                info.db_user = mysql::handshake_username(&packet);
+                session.lock().unwrap_or_else(|e| e.into_inner()).db_user = info.db_user.clone();
+            }
+
+            // Commands start a new sequence; auth packets continue one
+            if packet.sequence() == 0 {
+                let payload = packet.payload();
+                let pending = match payload.first().copied() {
+                    Some(command @ (mysql::COM_QUERY | mysql::COM_STMT_PREPARE)) => {
+                        let query = String::from_utf8_lossy(&payload[1..]);
+                        let analysis = check_query(state, &info, &query);
+                        if analysis.action == QueryAction::Block {
+                            return Ok(Some(mysql::error_packet(
+                                1,
+                                mysql::ER_ACCESS_DENIED,
+                                Some("HY000"),
+                                "Query blocked by Wharf security policy",
+                            )));
+                        }
+
+                        let limit = Budgeted::from_analysis(analysis, &query);
+                        if command == mysql::COM_QUERY {
+                            MysqlPending::Query(limit)
+                        } else {
+                            MysqlPending::Prepare(limit)
+                        }
+                    }
+                    Some(mysql::COM_STMT_EXECUTE) => {
+                        let session = session.lock().unwrap_or_else(|e| e.into_inner());
+                        let limit = mysql::statement_id(&packet).and_then(|id| session.statements.get(&id).cloned());
+                        MysqlPending::Query(limit)
+                    }
+                    Some(mysql::COM_STMT_CLOSE) => {
+                        if let Some(id) = mysql::statement_id(&packet) {
+                            session.lock().unwrap_or_else(|e| e.into_inner()).statements.remove(&id);
+                        }
+                        MysqlPending::Other
+                    }
+                    _ => MysqlPending::Other,
+                };
+
+                let mut session = session.lock().unwrap_or_else(|e| e.into_inner());
+                session.generation += 1;
+                session.sent_at = matches!(pending, MysqlPending::Query(_)).then(Instant::now);
+                session.pending = pending;
+            }
+
+            s_write.write_all(packet.raw()).await?;
+            if c_read.buffer().is_empty() {
+                s_write.flush().await?;
+            }
+        }
+    };
+
+    let server_to_client = async {
+        let mut info = server_info;
+        let mut seen_generation = 0;
+        let mut response: Option<(ResultSetTracker, ResultWatch)> = None;
+        let mut authenticated = false;
+        loop {
+            let Some(packet) = mysql::read_packet(&mut s_read, &limit).await? else {
+                return Ok::<_, std::io::Error>(());
+            };
+
+            // The first OK is the server accepting the client
+            if !authenticated && packet.first_byte() == Some(0x00) {
+                authenticated = true;
+                limit.store(max_packet, Ordering::Relaxed);
+            }
+
+            {
+                let mut guard = session.lock().unwrap_or_else(|e| e.into_inner());
+                let session = &mut *guard;
+                if session.generation != seen_generation {
+                    seen_generation = session.generation;
+                    info.db_user.clone_from(&session.db_user);
+                    if let Some(sent_at) = session.sent_at.take() {
+                        state.stats.query_latency.observe(sent_at.elapsed());
+                    }
+                    response = None;
+                    match &session.pending {
+                        MysqlPending::Query(limit) => {
+                            response = Some((ResultSetTracker::new(), ResultWatch::new(limit.clone())));
+                        }
+                        // COM_STMT_PREPARE_OK carries the new statement id
+                        MysqlPending::Prepare(Some(limit)) if packet.first_byte() == Some(0x00) => {
+                            if let Some(id) = mysql::statement_id(&packet) {
+                                session.statements.insert(id, limit.clone());
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            if let Some((tracker, watch)) = &mut response {
+                if tracker.observe(&packet)
+                    && watch.record_row(packet.raw().len(), state, &info) == Some(LimitAction::Terminate)
+                {
+                    // An ERR may stand in for a row; the client reports it and we hang up
+                    c_write
+                        .write_all(&mysql::error_packet(
+                            packet.sequence(),
+                            mysql::ER_QUERY_INTERRUPTED,
+                            Some("70100"),
+                            "Result set exceeds Wharf security policy limit",
+                        ))
+                        .await?;
+                    c_write.flush().await?;
+                    return Ok(());
+                }
+            }
+
+            c_write.write_all(packet.raw()).await?;
+            if s_read.buffer().is_empty() {
+                c_write.flush().await?;
+            }
+        }
+    };
+
+    let blocked = tokio::select! {
+        result = client_to_server => result?,
+        result = server_to_client => { result?; None }
+    };
+
+    // Both directions are finished, so the client writer is ours again
+    if let Some(packet) = blocked {
+        c_write.write_all(&packet).await?;
+    }
+    c_write.flush().await
+}
+
+/// A PostgreSQL exchange awaiting its response
+#[derive(Debug)]
+enum PostgresPending {
+    /// A simple Query; ends at ReadyForQuery
+    Simple(ResultWatch),
+    /// An extended-protocol Execute; ends at CommandComplete (or an error)
+    Execute(ResultWatch),
+    /// A Sync; ends at ReadyForQuery
+    Sync,
+}
+
+/// Proxy a PostgreSQL session
+///
+/// Extended-protocol clients may pipeline, so outstanding exchanges are
+/// queued in the order sent and retired as the server answers them. After
+/// an error the server skips everything up to the next Sync; ReadyForQuery
+/// retires the skipped entries with it.
+///
+/// FunctionCall messages are refused: they run a function by OID and carry
+/// no SQL for the policy to check.
+///
+/// Messages in either direction are held to `postgres::MAX_AUTH_MESSAGE`
+/// until the server sends AuthenticationOk, and to `max_packet` after that.
+async fn proxy_postgres<C, S>(
+    client: C,
+    server: S,
+    state: &AgentState,
+    mut info: ClientInfo,
+    max_packet: usize,
+) -> std::io::Result<()>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (c_read, c_write) = tokio::io::split(client);
+    let (s_read, s_write) = tokio::io::split(server);
+    let mut c_read = BufReader::with_capacity(BUFFER_SIZE, c_read);
+    let mut c_write = BufWriter::with_capacity(BUFFER_SIZE, c_write);
+    let mut s_read = BufReader::with_capacity(BUFFER_SIZE, s_read);
+    let mut s_write = BufWriter::with_capacity(BUFFER_SIZE, s_write);
+
+    // Decline transport encryption ourselves so the session stays readable
+    loop {
+        let Some(startup) = postgres::read_startup(&mut c_read).await? else {
+            return Ok(());
+        };
+        match postgres::startup_code(&startup) {
+            postgres::SSL_REQUEST | postgres::GSSENC_REQUEST => {
+                c_write.write_all(b"N").await?;
+                c_write.flush().await?;
+            }
+            _ => {
+                info.db_user = postgres::startup_user(&startup);
+                s_write.write_all(&startup).await?;
+                s_write.flush().await?;
+                break;
+            }
+        }
+    }
+
+    let pending = Mutex::new(VecDeque::new());
+    let limit = AtomicUsize::new(postgres::MAX_AUTH_MESSAGE.min(max_packet));
+
+    // Returns the error message to send if a query was blocked
+    let client_to_server = async {
+        let mut statements: HashMap<String, Option<Budgeted>> = HashMap::new();
+        let mut portals: HashMap<String, Option<Budgeted>> = HashMap::new();
+        let blocked = || Some(postgres::error_response("FATAL", "42501", "Query blocked by Wharf"));
+
+        loop {
+            let Some(message) = postgres::read_message(&mut c_read, &limit).await? else {
+                return Ok::<_, std::io::Error>(None);
+            };
+
+            let body = message.body();
+            match message.tag() {
+                b'Q' => {
+                    let Some((query, _)) = postgres::read_query(body) else {
+                        return Ok(blocked());
+                    };
+                    let analysis = check_query(state, &info, &query);
+                    if analysis.action == QueryAction::Block {
+                        return Ok(blocked());
+                    }
+                    let watch = ResultWatch::new(Budgeted::from_analysis(analysis, &query)).timed();
+                    pending.lock().unwrap_or_else(|e| e.into_inner()).push_back(PostgresPending::Simple(watch));
+                }
+                b'P' => {
+                    let Some((name, rest)) = postgres::read_cstr(body) else {
+                        return Ok(blocked());
+                    };
+                    let Some((query, _)) = postgres::read_query(rest) else {
+                        return Ok(blocked());
+                    };
+                    let analysis = check_query(state, &info, &query);
+                    if analysis.action == QueryAction::Block {
+                        return Ok(blocked());
+                    }
+                    statements.insert(name.to_string(), Budgeted::from_analysis(analysis, &query));
+                }
+                b'B' => {
+                    if let Some((portal, rest)) = postgres::read_cstr(body) {
+                        let statement = postgres::read_cstr(rest).map(|(s, _)| s).unwrap_or_default();
+                        let limit = statements.get(statement).cloned().flatten();
+                        portals.insert(portal.to_string(), limit);
+                    }
+                }
+                b'E' => {
+                    let portal = postgres::read_cstr(body).map(|(p, _)| p).unwrap_or_default();
+                    let watch = ResultWatch::new(portals.get(portal).cloned().flatten()).timed();
+                    pending.lock().unwrap_or_else(|e| e.into_inner()).push_back(PostgresPending::Execute(watch));
+                }
+                b'S' => {
+                    pending.lock().unwrap_or_else(|e| e.into_inner()).push_back(PostgresPending::Sync);
+                }
+                // FunctionCall runs a function by OID with no SQL to check
+                b'F' => {
+                    let oid = body.get(..4).map_or(0, |oid| u32::from_be_bytes([oid[0], oid[1], oid[2], oid[3]]));
+                    let call = format!("FunctionCall {}", oid);
+                    let rule = "blocked_operation:FUNCTION_CALL";
+                    warn!("BLOCKED: {}", call);
+                    state.stats.record(QueryAction::Block);
+                    info.audit(state, AuditDecision::Block, &call, rule);
+                    info.publish_block(state, &call, rule);
+                    return Ok(blocked());
+                }
+                b'C' => {
+                    if let Some((&kind, rest)) = body.split_first() {
+                        if let Some((name, _)) = postgres::read_cstr(rest) {
+                            match kind {
+                                b'S' => statements.remove(name),
+                                _ => portals.remove(name),
+                            };
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            s_write.write_all(message.raw()).await?;
+            if c_read.buffer().is_empty() {
+                s_write.flush().await?;
+            }
+        }
+    };
+
+    let server_to_client = async {
+        loop {
+            let Some(message) = postgres::read_message(&mut s_read, &limit).await? else {
+                return Ok::<_, std::io::Error>(());
+            };
+            if message.tag() == b'R' && message.body().starts_with(&[0, 0, 0, 0]) {
+                limit.store(max_packet, Ordering::Relaxed);
+            }
+
+            let verdict = {
+                let mut pending = pending.lock().unwrap_or_else(|e| e.into_inner());
+                if let Some(PostgresPending::Simple(watch) | PostgresPending::Execute(watch)) = pending.front_mut() {
+                    watch.response_started(state);
+                }
+                match message.tag() {
+                    // DataRow, or CopyData from COPY ... TO STDOUT
+                    b'D' | b'd' => match pending.front_mut() {
+                        Some(PostgresPending::Simple(watch) | PostgresPending::Execute(watch)) => {
+                            watch.record_row(message.raw().len(), state, &info)
+                        }
+                        _ => None,
+                    },
+                    // CommandComplete, EmptyQueryResponse, PortalSuspended, ErrorResponse
+                    b'C' | b'I' | b's' | b'E' => {
+                        if matches!(pending.front(), Some(PostgresPending::Execute(_))) {
+                            pending.pop_front();
+                        }
+                        None
+                    }
+                    // ReadyForQuery
+                    b'Z' => {
+                        while let Some(exchange) = pending.pop_front() {
+                            if !matches!(exchange, PostgresPending::Execute(_)) {
+                                break;
+                            }
+                        }
+                        None
+                    }
+                    _ => None,
+                }
+            };
+
+            if verdict == Some(LimitAction::Terminate) {
+                c_write
+                    .write_all(&postgres::error_response(
+                        "FATAL",
+                        "54000",
+                        "Result set exceeds Wharf security policy limit",
+                    ))
+                    .await?;
+                c_write.flush().await?;
+                return Ok(());
+            }
+
+            c_write.write_all(message.raw()).await?;
+            if s_read.buffer().is_empty() {
+                c_write.flush().await?;
+            }
+        }
+    };
+
+    let blocked = tokio::select! {
+        result = client_to_server => result?,
+        result = server_to_client => { result?; None }
+    };
+
+    if let Some(packet) = blocked {
+        c_write.write_all(&packet).await?;
+    }
+    c_write.flush().await
+}
+
+/// Tell a client we cannot serve it, in its own protocol, before any handshake
+///
+/// This mirrors what the real servers do when they are at capacity: MySQL
+/// sends an ERR packet in place of the greeting. PostgreSQL clients speak
+/// first, but nothing is read here: the FATAL ErrorResponse is written at
+/// once and taken as the answer to the startup message (or SSLRequest).
+async fn refuse_client<C>(client: &mut C, protocol: &str, error: &ShadowError) -> std::io::Result<()>
+where
+    C: AsyncWrite + Unpin,
+{
+    let packet = match (protocol, error) {
+        ("mysql" | "mariadb", ShadowError::TooManyConnections(_)) => {
+            mysql::error_packet(0, mysql::ER_CON_COUNT, None, "Too many connections")
+        }
+        ("mysql" | "mariadb", _) => {
+            mysql::error_packet(0, mysql::ER_SERVER_SHUTDOWN, None, "Shadow database unavailable")
+        }
+        ("postgres", ShadowError::TooManyConnections(_)) => {
+            postgres::error_response("FATAL", "53300", "sorry, too many clients already")
+        }
+        ("postgres", _) => {
+            postgres::error_response("FATAL", "57P03", "the database system is not accepting connections")
+        }
+        // Unknown protocol - no way to say why, just hang up
+        _ => return Ok(()),
+    };
+
+    client.write_all(&packet).await?;
+    client.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, AsyncReadExt, DuplexStream};
+
+    fn frame(sequence: u8, payload: &[u8]) -> Vec<u8> {
+        let mut packet = (payload.len() as u32).to_le_bytes()[0..3].to_vec();
+        packet.push(sequence);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    /// A fake MySQL server: greets, reads the handshake response and one
+    /// query, returns `rows` rows
+    async fn fake_mysql_server(mut server: DuplexStream, rows: u8) {
+        server.write_all(&frame(0, b"\x0a5.7.0\0\x01\0\0\0abcdefgh\0\xff\xff")).await.unwrap();
+
+        for _ in 0..2 {
+            let mut header = [0u8; 4];
+            server.read_exact(&mut header).await.unwrap();
+            let mut packet = vec![0u8; u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize];
+            server.read_exact(&mut packet).await.unwrap();
+        }
+
+        let mut response = frame(1, &[1]);
+        response.extend(frame(2, b"\x03def"));
+        response.extend(frame(3, &[0xfe, 0, 0, 2, 0]));
+        for i in 0..rows {
+            response.extend(frame(4 + i, b"\x05alice"));
+        }
+        response.extend(frame(4 + rows, &[0xfe, 0, 0, 2, 0]));
+        let _ = server.write_all(&response).await;
+    }
+
+    /// A protocol 4.1 handshake response for user `wp`
+    fn handshake_response() -> Vec<u8> {
+        let mut response = 0x0000_8200u32.to_le_bytes().to_vec();
+        response.extend_from_slice(&[0; 28]);
+        response.extend_from_slice(b"wp\0\0");
+        response
+    }
+
+    async fn run_query(state: AgentState, rows: u8) -> Vec<u8> {
+        let (mut client, proxy_client) = duplex(65536);
+        let (proxy_server, server) = duplex(65536);
+        tokio::spawn(fake_mysql_server(server, rows));
+        let proxy = tokio::spawn(async move {
+            proxy_mysql(proxy_client, proxy_server, &state, ClientInfo::new("test"), 1 << 20).await
+        });
+
+        let mut greeting = [0u8; 4 + 22];
+        client.read_exact(&mut greeting).await.unwrap();
+        client.write_all(&frame(1, &handshake_response())).await.unwrap();
+        client.write_all(&frame(0, b"\x03SELECT user_login FROM wp_users")).await.unwrap();
+
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).await.unwrap();
+        let _ = proxy.await;
+        received
+    }
+
+    fn limited_state(max_rows: u64, action: LimitAction) -> AgentState {
+        let mut policy = wharf_core::db_policy::DatabasePolicy::default();
+        policy.result_limits[0].max_rows = Some(max_rows);
+        policy.result_limits[0].action = action;
+        AgentState::with_policies(policy, Default::default())
+    }
+
+    #[tokio::test]
+    async fn test_result_limit_terminates() {
+        let state = limited_state(3, LimitAction::Terminate);
+        let received = run_query(state, 10).await;
+
+        // Column count, column, EOF and three rows, then an ERR in place of row four
+        let (rows, err) = received.split_at(5 + 8 + 9 + 3 * 10);
+        assert_eq!(rows.iter().filter(|&&b| b == b'a').count(), 3);
+        assert_eq!(err[3], 7);
+        assert_eq!(err[4], 0xff);
+        assert_eq!(u16::from_le_bytes([err[5], err[6]]), mysql::ER_QUERY_INTERRUPTED);
+    }
+
+    #[tokio::test]
+    async fn test_result_limit_alert_passes_rows() {
+        let state = Arc::new(limited_state(3, LimitAction::Alert));
+        let (mut client, proxy_client) = duplex(65536);
+        let (proxy_server, server) = duplex(65536);
+        tokio::spawn(fake_mysql_server(server, 10));
+        let proxy_state = state.clone();
+        tokio::spawn(async move {
+            proxy_mysql(proxy_client, proxy_server, &proxy_state, ClientInfo::new("test"), 1 << 20).await
+        });
+
+        let mut greeting = [0u8; 4 + 22];
+        client.read_exact(&mut greeting).await.unwrap();
+        client.write_all(&frame(1, &handshake_response())).await.unwrap();
+        // The TLS offer is withheld from the client
+        assert_eq!(greeting[4 + 21] & 0x08, 0);
+
+        client.write_all(&frame(0, b"\x03SELECT user_login FROM wp_users")).await.unwrap();
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).await.unwrap();
+
+        assert_eq!(received.iter().filter(|&&b| b == b'a').count(), 10);
+        assert_eq!(state.stats.result_limits_exceeded.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_cuts_off_sessions_after_drain_deadline() {
+        use crate::lifecycle::Shutdown;
+        use crate::shadow::ShadowConfig;
+        use std::time::Duration;
+
+        // A shadow database that accepts and then never says a word
+        let shadow_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let shadow_addr = shadow_listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((socket, _)) = shadow_listener.accept().await {
+                held.push(socket);
+            }
+        });
+
+        let listener = Listener::bind(&Endpoint::Tcp("127.0.0.1:0".to_string()), &Default::default())
+            .await
+            .unwrap();
+        let Endpoint::Tcp(proxy_addr) = listener.local_endpoint().unwrap() else {
+            unreachable!()
+        };
+        let shadow = Arc::new(ShadowDb::new(ShadowConfig {
+            endpoint: Endpoint::Tcp(shadow_addr),
+            ..Default::default()
+        }));
+        let shutdown = Shutdown::new();
+        let proxy = tokio::spawn(run_db_proxy(
+            listener,
+            "mysql",
+            shadow,
+            Arc::new(AgentState::new()),
+            shutdown.signal(),
+            Duration::from_millis(100),
+        ));
+
+        let mut client = tokio::net::TcpStream::connect(&proxy_addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        shutdown.trigger();
+
+        tokio::time::timeout(Duration::from_secs(2), proxy)
+            .await
+            .expect("proxy did not stop after the drain deadline")
+            .unwrap()
+            .unwrap();
+
+        // The stalled session was closed and nothing listens any more
+        let mut buf = [0u8; 1];
+        assert_eq!(client.read(&mut buf).await.unwrap_or(0), 0);
+        assert!(tokio::net::TcpStream::connect(&proxy_addr).await.is_err());
+    }
+}
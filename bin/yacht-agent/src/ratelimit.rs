@@ -0,0 +1,272 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Airlock Rate Limits
+//!
+//! Token buckets per client and route class (see
+//! `wharf_core::rate_limit`). Each class refills at its `per_minute` rate
+//! up to `burst`; a request that finds its bucket empty is refused with
+//! 429 and told when to come back.
+//!
+//! Clients are counted by address, IPv6 by its /64, so rotating through a
+//! prefix does not buy fresh buckets. A client that is refused
+//! `escalate_after` times within the escalation window is escalated once
+//! per window: the airlock reports it and asks the shield to block it.
+//!
+//! At most `MAX_CLIENTS` clients are tracked, in shards with a lock each.
+//! A shard keeps two generations: clients seen since it last rotated, and
+//! those seen before. A returning client moves back into the current
+//! generation; when that is full the shard rotates and the old generation,
+//! the clients idle longest, is forgotten at once.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use wharf_core::rate_limit::{client_key, RateClassRule, RateClasses};
+
+/// Clients tracked at once
+const MAX_CLIENTS: usize = 100_000;
+
+/// Shards of the client table
+const SHARDS: usize = 16;
+
+/// A refused request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limited {
+    /// Whole seconds until the bucket holds a token again
+    pub retry_after: u64,
+    /// Violations so far, set once per window when the client is escalated
+    pub escalate: Option<u32>,
+}
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+struct Client {
+    buckets: HashMap<String, Bucket>,
+    violations: u32,
+    window_start: Instant,
+    escalated: bool,
+}
+
+impl Client {
+    fn new(now: Instant) -> Self {
+        Self {
+            buckets: HashMap::new(),
+            violations: 0,
+            window_start: now,
+            escalated: false,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Shard {
+    /// Clients seen since the last rotation
+    current: HashMap<IpAddr, Client>,
+    /// Clients seen only before it
+    previous: HashMap<IpAddr, Client>,
+}
+
+impl Shard {
+    /// The client for `key`, moved into (or added to) the current generation
+    fn client(&mut self, key: IpAddr, generation: usize, now: Instant) -> &mut Client {
+        if !self.current.contains_key(&key) {
+            let returning = self.previous.remove(&key);
+            if self.current.len() >= generation {
+                self.previous = std::mem::take(&mut self.current);
+            }
+            if let Some(client) = returning {
+                self.current.insert(key, client);
+            }
+        }
+        self.current.entry(key).or_insert_with(|| Client::new(now))
+    }
+}
+
+/// The buckets of every client seen recently
+pub struct RateLimiter {
+    shards: Box<[Mutex<Shard>]>,
+    /// Clients per generation of a shard
+    generation: usize,
+    hasher: RandomState,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::with_capacity(MAX_CLIENTS)
+    }
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A limiter tracking at most `max_clients` clients
+    pub fn with_capacity(max_clients: usize) -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| Mutex::new(Shard::default())).collect(),
+            generation: (max_clients / (SHARDS * 2)).max(1),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Take a token for a request of `class` from `peer`
+    pub fn check(&self, classes: &RateClasses, class: &RateClassRule, peer: IpAddr) -> Result<(), Limited> {
+        self.check_at(Instant::now(), classes, class, peer)
+    }
+
+    /// [`check`](Self::check) at a given time
+    pub fn check_at(&self, now: Instant, classes: &RateClasses, class: &RateClassRule, peer: IpAddr) -> Result<(), Limited> {
+        let key = client_key(peer);
+        let window = Duration::from_secs(classes.escalate_window_secs);
+        let shard = &self.shards[self.hasher.hash_one(key) as usize % SHARDS];
+        let mut shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+        let client = shard.client(key, self.generation, now);
+
+        let bucket = client.buckets.entry(class.name.clone()).or_insert(Bucket {
+            tokens: class.burst,
+            last: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.last).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * class.per_second).min(class.burst);
+        bucket.last = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        let retry_after = ((1.0 - bucket.tokens) / class.per_second).ceil().max(1.0) as u64;
+
+        if now.saturating_duration_since(client.window_start) > window {
+            client.violations = 0;
+            client.window_start = now;
+            client.escalated = false;
+        }
+        client.violations = client.violations.saturating_add(1);
+        let escalate = classes.escalate_after > 0 && client.violations >= classes.escalate_after && !client.escalated;
+        client.escalated |= escalate;
+
+        Err(Limited {
+            retry_after,
+            escalate: escalate.then_some(client.violations),
+        })
+    }
+
+    /// Clients currently tracked
+    pub fn clients(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| {
+                let shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+                shard.current.len() + shard.previous.len()
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wharf_core::types::RateLimitPolicy;
+
+    fn xmlrpc(classes: &RateClasses) -> RateClassRule {
+        classes
+            .classify("POST", "/xmlrpc.php", None, "203.0.113.7".parse().unwrap())
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn test_burst_then_refill() {
+        // xmlrpc: 6 per minute, burst 3
+        let classes = RateClasses::compile(&RateLimitPolicy::default()).unwrap();
+        let class = xmlrpc(&classes);
+        let limiter = RateLimiter::new();
+        let peer: IpAddr = "203.0.113.7".parse().unwrap();
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check_at(start, &classes, &class, peer).is_ok());
+        }
+        let limited = limiter.check_at(start, &classes, &class, peer).unwrap_err();
+        assert_eq!(limited.retry_after, 10);
+        assert_eq!(limited.escalate, None);
+        // Other clients have their own buckets
+        assert!(limiter.check_at(start, &classes, &class, "203.0.113.8".parse().unwrap()).is_ok());
+
+        let later = start + Duration::from_secs(10);
+        assert!(limiter.check_at(later, &classes, &class, peer).is_ok());
+        assert!(limiter.check_at(later, &classes, &class, peer).is_err());
+    }
+
+    #[test]
+    fn test_ipv6_counted_by_prefix() {
+        let classes = RateClasses::compile(&RateLimitPolicy::default()).unwrap();
+        let class = xmlrpc(&classes);
+        let limiter = RateLimiter::new();
+        let now = Instant::now();
+        for host in 1..=3 {
+            let peer: IpAddr = format!("2001:db8::{}", host).parse().unwrap();
+            assert!(limiter.check_at(now, &classes, &class, peer).is_ok());
+        }
+        assert!(limiter.check_at(now, &classes, &class, "2001:db8::99".parse().unwrap()).is_err());
+        assert_eq!(limiter.clients(), 1);
+    }
+
+    #[test]
+    fn test_escalation_once_per_window() {
+        let policy = RateLimitPolicy {
+            escalate_after: 3,
+            escalate_window_secs: 60,
+            ..Default::default()
+        };
+        let classes = RateClasses::compile(&policy).unwrap();
+        let class = xmlrpc(&classes);
+        let limiter = RateLimiter::new();
+        let peer: IpAddr = "198.51.100.4".parse().unwrap();
+        let start = Instant::now();
+
+        let escalations: Vec<_> = (0..8)
+            .filter_map(|_| limiter.check_at(start, &classes, &class, peer).err())
+            .map(|limited| limited.escalate)
+            .collect();
+        assert_eq!(escalations, vec![None, None, Some(3), None, None]);
+
+        // A new window starts the count again
+        let later = start + Duration::from_secs(61);
+        let escalations: Vec<_> = (0..8)
+            .filter_map(|_| limiter.check_at(later, &classes, &class, peer).err())
+            .map(|limited| limited.escalate)
+            .collect();
+        assert_eq!(escalations, vec![None, None, Some(3), None, None]);
+    }
+
+    #[test]
+    fn test_table_is_bounded_and_keeps_active_clients() {
+        let classes = RateClasses::compile(&RateLimitPolicy::default()).unwrap();
+        let class = xmlrpc(&classes);
+        let limiter = RateLimiter::with_capacity(320);
+        let attacker: IpAddr = "198.51.100.4".parse().unwrap();
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.check_at(now, &classes, &class, attacker).is_ok());
+        }
+
+        // A flood of one-off clients does not buy the attacker a fresh bucket
+        for n in 0..10_000u32 {
+            let peer = IpAddr::from(std::net::Ipv4Addr::from(0x0a00_0000 + n));
+            assert!(limiter.check_at(now, &classes, &class, peer).is_ok());
+            if n % 10 == 0 {
+                assert!(limiter.check_at(now, &classes, &class, attacker).is_err(), "{}", n);
+            }
+        }
+        assert!(limiter.clients() <= 320, "{}", limiter.clients());
+    }
+}
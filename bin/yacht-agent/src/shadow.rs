@@ -0,0 +1,394 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Shadow Database Access
+//!
+//! Guards the path from the proxy to the real ("shadow") database:
+//!
+//! - **Connection slots**: a bounded pool of permits caps how many client
+//!   sessions may hold a shadow connection at once, so a connection flood is
+//!   absorbed by the proxy instead of reaching MariaDB.
+//! - **Connect timeout**: a hung shadow cannot pin proxy tasks forever.
+//! - **Circuit breaker**: after repeated connect failures the circuit opens
+//!   and clients are refused immediately until a health probe (or a trial
+//!   connection once the cool-down has passed) succeeds again.
+//!
+//! While the circuit is closed, client connections are the health signal and
+//! the background probe stays quiet. A probe connects and hangs up without
+//! authenticating, which MySQL counts against the host's `max_connect_errors`
+//! (100 by default); probing a healthy remote server every few seconds would
+//! get the agent's address blocked within minutes. Once a probe succeeds the
+//! circuit closes and probing stops again, so an outage adds at most one such
+//! error. Loopback connections bypass MySQL's host cache entirely.
+//!
+//! A MySQL/PostgreSQL session is bound to the connection it authenticated on,
+//! so shadow connections are never shared between clients; the pool limits
+//! concurrency rather than recycling sockets.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{info, warn};
+
+use crate::net::{Endpoint, Stream};
+
+#[derive(Error, Debug)]
+pub enum ShadowError {
+    #[error("too many connections (limit {0})")]
+    TooManyConnections(usize),
+
+    #[error("shadow database unavailable (circuit open)")]
+    CircuitOpen,
+
+    #[error("timed out connecting to shadow database at {0}")]
+    ConnectTimeout(String),
+
+    #[error("failed to connect to shadow database: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Shadow database connection settings
+#[derive(Debug, Clone)]
+pub struct ShadowConfig {
+    /// Address of the real database (host:port or Unix socket)
+    pub endpoint: Endpoint,
+    /// Maximum concurrent client sessions
+    pub max_connections: usize,
+    /// Timeout for establishing a shadow connection
+    pub connect_timeout: Duration,
+    /// Interval between background health probes
+    pub probe_interval: Duration,
+    /// Consecutive failures before the circuit opens
+    pub failure_threshold: u32,
+    /// How long the circuit stays open before a trial connection
+    pub open_duration: Duration,
+    /// Largest message the proxy relays once a client has authenticated
+    pub max_packet: usize,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: Endpoint::Tcp("127.0.0.1:33060".to_string()),
+            max_connections: 512,
+            connect_timeout: Duration::from_secs(3),
+            probe_interval: Duration::from_secs(5),
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(30),
+            max_packet: 64 << 20,
+        }
+    }
+}
+
+/// State of the circuit breaker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Shadow is healthy, connections flow normally
+    Closed,
+    /// Shadow is failing, connections are refused without trying
+    Open,
+    /// Cool-down elapsed, a single trial connection is in flight
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Closed => "closed",
+            Self::Open => "open",
+            Self::HalfOpen => "half_open",
+        }
+    }
+}
+
+struct BreakerInner {
+    state: CircuitState,
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+}
+
+/// A consecutive-failure circuit breaker
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    open_duration: Duration,
+    inner: Mutex<BreakerInner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            open_duration,
+            inner: Mutex::new(BreakerInner {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+                opened_at: None,
+            }),
+        }
+    }
+
+    /// Whether a connection attempt may proceed
+    ///
+    /// Once the cool-down has elapsed the first caller is let through as a
+    /// trial and the circuit moves to half-open; everyone else keeps being
+    /// refused until the trial reports back.
+    pub fn allow(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        match inner.state {
+            CircuitState::Closed => true,
+            CircuitState::HalfOpen => false,
+            CircuitState::Open => {
+                let cooled = inner
+                    .opened_at
+                    .map(|t| t.elapsed() >= self.open_duration)
+                    .unwrap_or(true);
+                if cooled {
+                    inner.state = CircuitState::HalfOpen;
+                }
+                cooled
+            }
+        }
+    }
+
+    /// Record a successful connection or probe
+    pub fn record_success(&self) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if inner.state != CircuitState::Closed {
+            info!("Shadow database reachable again - circuit closed");
+        }
+        inner.state = CircuitState::Closed;
+        inner.consecutive_failures = 0;
+        inner.opened_at = None;
+    }
+
+    /// Record a failed connection or probe
+    pub fn record_failure(&self) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.consecutive_failures = inner.consecutive_failures.saturating_add(1);
+
+        let trip = inner.state == CircuitState::HalfOpen
+            || inner.consecutive_failures >= self.failure_threshold;
+
+        if trip {
+            if inner.state == CircuitState::Closed {
+                warn!(
+                    "Shadow database failed {} times in a row - circuit open",
+                    inner.consecutive_failures
+                );
+            }
+            inner.state = CircuitState::Open;
+            inner.opened_at = Some(Instant::now());
+        }
+    }
+
+    /// The current circuit state
+    pub fn state(&self) -> CircuitState {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner()).state
+    }
+}
+
+/// Access to the shadow database
+pub struct ShadowDb {
+    config: ShadowConfig,
+    slots: Arc<Semaphore>,
+    breaker: CircuitBreaker,
+}
+
+impl ShadowDb {
+    pub fn new(config: ShadowConfig) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(config.max_connections)),
+            breaker: CircuitBreaker::new(config.failure_threshold, config.open_duration),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &ShadowConfig {
+        &self.config
+    }
+
+    pub fn breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
+
+    /// Claim a connection slot for a new client session
+    ///
+    /// The slot is released when the returned permit is dropped.
+    pub fn admit(&self) -> Result<OwnedSemaphorePermit, ShadowError> {
+        self.slots
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| ShadowError::TooManyConnections(self.config.max_connections))
+    }
+
+    /// Number of client sessions currently holding a slot
+    pub fn active_connections(&self) -> usize {
+        self.config.max_connections - self.slots.available_permits()
+    }
+
+    /// Open a connection to the shadow database
+    pub async fn connect(&self) -> Result<Stream, ShadowError> {
+        if !self.breaker.allow() {
+            return Err(ShadowError::CircuitOpen);
+        }
+
+        match self.dial().await {
+            Ok(stream) => {
+                self.breaker.record_success();
+                Ok(stream)
+            }
+            Err(e) => {
+                self.breaker.record_failure();
+                Err(e)
+            }
+        }
+    }
+
+    /// Check that the shadow database accepts connections
+    ///
+    /// The probe only completes a TCP handshake and hangs up; it never
+    /// authenticates, so it cannot be mistaken for application traffic.
+    /// MySQL still counts it as an aborted connect, which is why
+    /// [`run_health_probe`] only calls this while the circuit is not closed.
+    pub async fn probe(&self) -> bool {
+        match self.dial().await {
+            Ok(_) => {
+                self.breaker.record_success();
+                true
+            }
+            Err(e) => {
+                warn!("Shadow health probe failed: {}", e);
+                self.breaker.record_failure();
+                false
+            }
+        }
+    }
+
+    async fn dial(&self) -> Result<Stream, ShadowError> {
+        match tokio::time::timeout(self.config.connect_timeout, self.config.endpoint.connect()).await {
+            Ok(result) => Ok(result?),
+            Err(_) => Err(ShadowError::ConnectTimeout(self.config.endpoint.to_string())),
+        }
+    }
+}
+
+/// Probe the shadow database at the configured interval while its circuit
+/// is open or half-open
+pub async fn run_health_probe(shadow: Arc<ShadowDb>) {
+    let mut interval = tokio::time::interval(shadow.config.probe_interval);
+    loop {
+        interval.tick().await;
+        if shadow.breaker.state() != CircuitState::Closed {
+            shadow.probe().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breaker_opens_after_threshold() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+
+        breaker.record_failure();
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.allow());
+
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.allow());
+    }
+
+    #[test]
+    fn test_breaker_half_open_trial() {
+        let breaker = CircuitBreaker::new(1, Duration::ZERO);
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        // Cool-down elapsed: exactly one trial goes through
+        assert!(breaker.allow());
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(!breaker.allow());
+
+        // A failed trial re-opens, a successful one closes
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(breaker.allow());
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn test_admit_enforces_limit() {
+        let shadow = ShadowDb::new(ShadowConfig {
+            max_connections: 2,
+            ..Default::default()
+        });
+
+        let first = shadow.admit().unwrap();
+        let _second = shadow.admit().unwrap();
+        assert_eq!(shadow.active_connections(), 2);
+        assert!(matches!(shadow.admit(), Err(ShadowError::TooManyConnections(2))));
+
+        drop(first);
+        assert!(shadow.admit().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_connect_failure_trips_breaker() {
+        // Bind then drop a listener to get a port nobody is listening on
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let shadow = ShadowDb::new(ShadowConfig {
+            endpoint: Endpoint::Tcp(addr),
+            failure_threshold: 1,
+            open_duration: Duration::from_secs(60),
+            ..Default::default()
+        });
+
+        assert!(matches!(shadow.connect().await, Err(ShadowError::Io(_))));
+        assert!(matches!(shadow.connect().await, Err(ShadowError::CircuitOpen)));
+    }
+
+    #[tokio::test]
+    async fn test_probe_only_runs_while_circuit_is_not_closed() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let shadow = Arc::new(ShadowDb::new(ShadowConfig {
+            endpoint: Endpoint::Tcp(addr),
+            probe_interval: Duration::from_millis(10),
+            failure_threshold: 1,
+            open_duration: Duration::from_secs(60),
+            ..Default::default()
+        }));
+        let probe = tokio::spawn(run_health_probe(shadow.clone()));
+
+        // A healthy shadow sees no probe connections at all
+        let quiet = tokio::time::timeout(Duration::from_millis(100), listener.accept()).await;
+        assert!(quiet.is_err());
+
+        // An open circuit is probed, and the first success closes it
+        shadow.breaker().record_failure();
+        assert_eq!(shadow.breaker().state(), CircuitState::Open);
+        tokio::time::timeout(Duration::from_secs(5), listener.accept())
+            .await
+            .unwrap()
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while shadow.breaker().state() != CircuitState::Closed {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        probe.abort();
+    }
+}
@@ -0,0 +1,222 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Agent State
+//!
+//! The state shared between the database proxy, the API and the mooring
+//! channel. Nothing on the query hot path takes a lock:
+//!
+//! - Policies sit behind `ArcSwap` pointers. Readers take a cheap snapshot of
+//!   the current policy; a reload swaps the pointer and in-flight queries keep
+//!   using the snapshot they already hold.
+//! - Statistics are sharded atomic counters (see `stats`).
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU8, Ordering};
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use serde::Serialize;
+use tokio::sync::RwLock;
+
+use wharf_core::crypto::hash_json;
+use wharf_core::db_policy::{DatabasePolicy, PolicyEngine};
+use wharf_core::types::HeaderPolicy;
+
+use crate::audit::AuditLog;
+use crate::csp::CspReports;
+use crate::events::EventBus;
+use crate::stats::AgentStats;
+
+/// How long the Wharf counts as moored after its last command, in seconds
+pub const MOORED_SECS: i64 = 15 * 60;
+
+/// Outcome of the last file integrity check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IntegrityStatus {
+    /// No check has run since the agent started
+    Unknown = 0,
+    Verified = 1,
+    Failed = 2,
+}
+
+impl IntegrityStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Unknown => "unknown",
+            Self::Verified => "verified",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// The shared state for the Yacht Agent
+pub struct AgentState {
+    /// The database policy engine (atomically swappable)
+    pub db_engine: ArcSwap<PolicyEngine>,
+
+    /// The HTTP header policy (atomically swappable)
+    pub header_policy: ArcSwap<HeaderPolicy>,
+
+    /// When the Wharf last sent a command (Unix seconds, 0 for never or unmoored)
+    moored_at: AtomicI64,
+
+    /// Whether the site is down for maintenance (an enforcement task failed closed)
+    maintenance: AtomicBool,
+
+    /// The expected filesystem hashes (from Wharf)
+    pub integrity_hashes: RwLock<HashMap<String, String>>,
+
+    /// Outcome of the last integrity check (an `IntegrityStatus`)
+    integrity: AtomicU8,
+
+    /// Statistics
+    pub stats: AgentStats,
+
+    /// The security audit log
+    pub audit: AuditLog,
+
+    /// Live security events for `/events` subscribers
+    pub events: EventBus,
+
+    /// CSP violations reported to the airlock
+    pub csp_reports: CspReports,
+}
+
+impl AgentState {
+    pub fn new() -> Self {
+        Self::with_policies(DatabasePolicy::default(), HeaderPolicy::default())
+    }
+
+    /// Create the state with explicit policies
+    pub fn with_policies(db_policy: DatabasePolicy, header_policy: HeaderPolicy) -> Self {
+        Self {
+            db_engine: ArcSwap::from_pointee(PolicyEngine::new(db_policy)),
+            header_policy: ArcSwap::from_pointee(header_policy),
+            moored_at: AtomicI64::new(0),
+            maintenance: AtomicBool::new(false),
+            integrity_hashes: RwLock::new(HashMap::new()),
+            integrity: AtomicU8::new(IntegrityStatus::Unknown as u8),
+            stats: AgentStats::new(),
+            audit: AuditLog::disabled(),
+            events: EventBus::new(),
+            csp_reports: CspReports::new(),
+        }
+    }
+
+    /// Record security decisions to the given audit log
+    pub fn with_audit_log(mut self, audit: AuditLog) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Swap in a new database policy
+    ///
+    /// Queries already being analyzed finish against the old policy;
+    /// every query inspected afterwards sees the new one.
+    pub fn reload_db_policy(&self, policy: DatabasePolicy) {
+        self.db_engine.store(Arc::new(PolicyEngine::new(policy)));
+    }
+
+    /// Swap in a new header policy
+    pub fn reload_header_policy(&self, policy: HeaderPolicy) {
+        self.header_policy.store(Arc::new(policy));
+    }
+
+    /// Version of the loaded database policy (hash of its contents)
+    pub fn db_policy_hash(&self) -> String {
+        hash_json(self.db_engine.load().policy()).unwrap_or_default()
+    }
+
+    /// Version of the loaded header policy (hash of its contents)
+    pub fn header_policy_hash(&self) -> String {
+        hash_json(&**self.header_policy.load()).unwrap_or_default()
+    }
+
+    /// Record a command from the Wharf
+    pub fn moor(&self) {
+        self.moored_at.store(chrono::Utc::now().timestamp(), Ordering::Relaxed);
+    }
+
+    /// The Wharf has left
+    pub fn unmoor(&self) {
+        self.moored_at.store(0, Ordering::Relaxed);
+    }
+
+    /// Whether the Wharf sent a command within the last `MOORED_SECS`
+    pub fn is_moored(&self) -> bool {
+        self.is_moored_at(chrono::Utc::now().timestamp())
+    }
+
+    /// [`is_moored`](Self::is_moored) at a given time
+    pub fn is_moored_at(&self, now: i64) -> bool {
+        let at = self.moored_at.load(Ordering::Relaxed);
+        at != 0 && now - at < MOORED_SECS
+    }
+
+    /// Whether the airlock should answer with a maintenance page
+    pub fn in_maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::Relaxed)
+    }
+
+    pub fn set_maintenance(&self, maintenance: bool) {
+        self.maintenance.store(maintenance, Ordering::Relaxed);
+    }
+
+    pub fn integrity_status(&self) -> IntegrityStatus {
+        match self.integrity.load(Ordering::Relaxed) {
+            1 => IntegrityStatus::Verified,
+            2 => IntegrityStatus::Failed,
+            _ => IntegrityStatus::Unknown,
+        }
+    }
+
+    /// Record the outcome of an integrity check
+    pub fn set_integrity_status(&self, status: IntegrityStatus) {
+        self.integrity.store(status as u8, Ordering::Relaxed);
+    }
+}
+
+impl Default for AgentState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wharf_core::db_policy::QueryAction;
+
+    #[test]
+    fn test_reload_does_not_disturb_held_snapshot() {
+        let state = AgentState::new();
+        let query = "INSERT INTO wp_users (user_login) VALUES ('x')";
+
+        // An in-flight query holds the old engine
+        let in_flight = state.db_engine.load_full();
+
+        let mut policy = DatabasePolicy::default();
+        policy.lock_down.clear();
+        policy.allow_write.push("wp_users".to_string());
+        state.reload_db_policy(policy);
+
+        assert!(in_flight.analyze(query).is_err());
+        assert_eq!(state.db_engine.load().analyze(query).unwrap(), QueryAction::Allow);
+    }
+
+    #[test]
+    fn test_moored_expires_and_unmoors() {
+        let state = AgentState::new();
+        assert!(!state.is_moored());
+
+        state.moor();
+        let now = chrono::Utc::now().timestamp();
+        assert!(state.is_moored());
+        assert!(!state.is_moored_at(now + MOORED_SECS + 1));
+
+        state.unmoor();
+        assert!(!state.is_moored());
+    }
+}
@@ -0,0 +1,246 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Hot-Path Statistics
+//!
+//! Every inspected query bumps a counter, so the counters must never become
+//! a point of contention between connections. Each counter is split into
+//! cache-line aligned shards; a thread always writes to its own shard and
+//! readers sum all shards. Reads are therefore slightly more expensive than
+//! writes, which is the right trade-off for `/stats` and `/metrics`.
+//!
+//! Latency histograms are built from the same counters: one per bucket, so
+//! an observation is two uncontended increments.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use serde::Serialize;
+use wharf_core::db_policy::QueryAction;
+
+/// Number of shards per counter
+const SHARDS: usize = 16;
+
+/// A single shard, padded to its own cache line to avoid false sharing
+#[derive(Default)]
+#[repr(align(64))]
+struct Shard(AtomicU64);
+
+/// A monotonically increasing counter sharded across threads
+pub struct ShardedCounter {
+    shards: [Shard; SHARDS],
+}
+
+impl ShardedCounter {
+    pub fn new() -> Self {
+        Self {
+            shards: std::array::from_fn(|_| Shard::default()),
+        }
+    }
+
+    /// Increment the counter by one
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    /// Add `n` to the counter
+    pub fn add(&self, n: u64) {
+        self.shards[shard_index()].0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Read the current value (sum of all shards)
+    pub fn get(&self) -> u64 {
+        self.shards.iter().map(|s| s.0.load(Ordering::Relaxed)).sum()
+    }
+}
+
+impl Default for ShardedCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The shard owned by the current thread (assigned round-robin on first use)
+fn shard_index() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    thread_local! {
+        static INDEX: usize = NEXT.fetch_add(1, Ordering::Relaxed) % SHARDS;
+    }
+
+    INDEX.with(|i| *i)
+}
+
+/// Bucket upper bounds (seconds) for policy inspection: microseconds matter
+pub const INSPECT_BUCKETS: &[f64] = &[0.00001, 0.000025, 0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.01];
+
+/// Bucket upper bounds (seconds) for database round trips
+pub const QUERY_BUCKETS: &[f64] = &[0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0];
+
+/// A latency histogram with fixed buckets
+pub struct Histogram {
+    bounds: &'static [f64],
+    /// Non-cumulative counts; the last bucket is +Inf
+    buckets: Vec<ShardedCounter>,
+    sum_nanos: ShardedCounter,
+}
+
+/// A point-in-time copy of a histogram
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistogramSnapshot {
+    /// (upper bound, cumulative count) for each finite bucket
+    pub buckets: Vec<(f64, u64)>,
+    pub count: u64,
+    /// Sum of all observations, in seconds
+    pub sum: f64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| ShardedCounter::new()).collect(),
+            sum_nanos: ShardedCounter::new(),
+        }
+    }
+
+    /// Record one observation
+    pub fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let bucket = self.bounds.iter().position(|&bound| seconds <= bound).unwrap_or(self.bounds.len());
+        self.buckets[bucket].inc();
+        self.sum_nanos.add(elapsed.as_nanos() as u64);
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let mut cumulative = 0;
+        let mut buckets = Vec::with_capacity(self.bounds.len());
+        for (bound, counter) in self.bounds.iter().zip(&self.buckets) {
+            cumulative += counter.get();
+            buckets.push((*bound, cumulative));
+        }
+        let count = cumulative + self.buckets[self.bounds.len()].get();
+
+        HistogramSnapshot {
+            buckets,
+            count,
+            sum: self.sum_nanos.get() as f64 / 1e9,
+        }
+    }
+}
+
+/// Query statistics for the database proxy
+pub struct AgentStats {
+    pub queries_allowed: ShardedCounter,
+    pub queries_audited: ShardedCounter,
+    pub queries_blocked: ShardedCounter,
+    /// Queries whose result set crossed its budget
+    pub result_limits_exceeded: ShardedCounter,
+    /// Time spent parsing and checking a query against the policy
+    pub inspect_latency: Histogram,
+    /// Time from forwarding a query to the first byte of its response
+    pub query_latency: Histogram,
+}
+
+impl Default for AgentStats {
+    fn default() -> Self {
+        Self {
+            queries_allowed: ShardedCounter::new(),
+            queries_audited: ShardedCounter::new(),
+            queries_blocked: ShardedCounter::new(),
+            result_limits_exceeded: ShardedCounter::new(),
+            inspect_latency: Histogram::new(INSPECT_BUCKETS),
+            query_latency: Histogram::new(QUERY_BUCKETS),
+        }
+    }
+}
+
+/// A point-in-time copy of the agent statistics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct StatsSnapshot {
+    pub allowed: u64,
+    pub audited: u64,
+    pub blocked: u64,
+    pub result_limits_exceeded: u64,
+}
+
+impl AgentStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the verdict for one inspected query
+    pub fn record(&self, action: QueryAction) {
+        match action {
+            QueryAction::Allow => self.queries_allowed.inc(),
+            QueryAction::Audit => self.queries_audited.inc(),
+            QueryAction::Block => self.queries_blocked.inc(),
+        }
+    }
+
+    /// Take a snapshot of all counters
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            allowed: self.queries_allowed.get(),
+            audited: self.queries_audited.get(),
+            blocked: self.queries_blocked.get(),
+            result_limits_exceeded: self.result_limits_exceeded.get(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_counter_sums_across_threads() {
+        let counter = Arc::new(ShardedCounter::new());
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let counter = counter.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        counter.inc();
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(counter.get(), 8000);
+    }
+
+    #[test]
+    fn test_record_verdicts() {
+        let stats = AgentStats::new();
+        stats.record(QueryAction::Allow);
+        stats.record(QueryAction::Allow);
+        stats.record(QueryAction::Audit);
+        stats.record(QueryAction::Block);
+
+        assert_eq!(
+            stats.snapshot(),
+            StatsSnapshot { allowed: 2, audited: 1, blocked: 1, result_limits_exceeded: 0 }
+        );
+    }
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let histogram = Histogram::new(&[0.001, 0.01]);
+        histogram.observe(Duration::from_micros(500));
+        histogram.observe(Duration::from_millis(5));
+        histogram.observe(Duration::from_millis(5));
+        histogram.observe(Duration::from_secs(1));
+
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.buckets, vec![(0.001, 1), (0.01, 3)]);
+        assert_eq!(snapshot.count, 4);
+        assert!((snapshot.sum - 1.0105).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,383 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Enforcement Supervisor
+//!
+//! Keeps the enforcement tasks (the database proxy listeners, the airlock)
+//! running, and keeps the "if it crashes, the site goes offline" promise
+//! when it cannot.
+//!
+//! A task that returns, errors or panics before shutdown is restarted with
+//! exponential backoff, up to `max_restarts` within the restart window; the
+//! backoff starts over once a whole window passes without a failure. From
+//! the moment it dies until the new run reports [`Ready`] its health
+//! component reports failed, so the yacht leaves the load balancer. Once the
+//! budget is spent the configured fail-closed action is taken:
+//!
+//! - `stop`: the task stays down (its listener closed); the agent stays up
+//!   so the API still answers.
+//! - `maintenance`: as `stop`, and the airlock serves a maintenance page.
+//! - `exit`: the agent shuts down and exits non-zero.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+use tokio::task::JoinSet;
+use tracing::{error, warn};
+
+use wharf_core::agent_config::{FailAction, SupervisorConfig};
+use wharf_core::events::SecurityEvent;
+
+use crate::health::{ComponentHealth, HealthRegistry};
+use crate::lifecycle::Shutdown;
+use crate::state::AgentState;
+
+/// First wait before a restart; doubles up to `MAX_BACKOFF`
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SupervisorError {
+    #[error("Enforcement task {task} failed ({reason}) - exiting fail-closed")]
+    FailClosed { task: String, reason: String },
+}
+
+type TaskFuture = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>;
+
+/// Makes a fresh run of a task (each restart calls it again)
+type TaskFactory = Box<dyn Fn(Ready) -> TaskFuture + Send + Sync>;
+
+/// Handed to every run of a task so it can say when it is serving again
+pub struct Ready {
+    name: String,
+    health: Arc<HealthRegistry>,
+}
+
+impl Ready {
+    /// Mark the task healthy (call once its listener is bound)
+    pub fn up(self) {
+        self.health.report(&self.name, ComponentHealth::healthy());
+    }
+}
+
+/// What the supervised tasks share
+struct Context {
+    state: Arc<AgentState>,
+    health: Arc<HealthRegistry>,
+    shutdown: Arc<Shutdown>,
+    config: SupervisorConfig,
+    exit: Mutex<Option<SupervisorError>>,
+}
+
+/// Runs and watches the enforcement tasks
+pub struct Supervisor {
+    context: Arc<Context>,
+    initial_backoff: Duration,
+    tasks: JoinSet<()>,
+}
+
+impl Supervisor {
+    pub fn new(
+        state: Arc<AgentState>,
+        health: Arc<HealthRegistry>,
+        shutdown: Arc<Shutdown>,
+        config: SupervisorConfig,
+    ) -> Self {
+        Self {
+            context: Arc::new(Context {
+                state,
+                health,
+                shutdown,
+                config,
+                exit: Mutex::new(None),
+            }),
+            initial_backoff: INITIAL_BACKOFF,
+            tasks: JoinSet::new(),
+        }
+    }
+
+    /// Start restarts after `initial` instead of the default (tests)
+    pub fn with_backoff(mut self, initial: Duration) -> Self {
+        self.initial_backoff = initial;
+        self
+    }
+
+    /// Supervise a task under `name` (also its health component)
+    ///
+    /// The task is expected to run until shutdown; `task` is called again
+    /// for every restart. The component reports failed until the run calls
+    /// [`Ready::up`].
+    pub fn spawn<F, Fut>(&mut self, name: &str, task: F)
+    where
+        F: Fn(Ready) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let factory: TaskFactory = Box::new(move |ready| Box::pin(task(ready)));
+        self.context.health.report(name, ComponentHealth::failed("starting"));
+        self.tasks
+            .spawn(supervise(name.to_string(), factory, self.context.clone(), self.initial_backoff));
+    }
+
+    /// Wait for every task to finish (they finish at shutdown)
+    ///
+    /// Fails if a task died and the fail-closed action was `exit`.
+    pub async fn join(mut self) -> Result<(), SupervisorError> {
+        while self.tasks.join_next().await.is_some() {}
+        match self.context.exit.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+async fn supervise(name: String, factory: TaskFactory, context: Arc<Context>, initial_backoff: Duration) {
+    let window = Duration::from_secs(context.config.restart_window_secs);
+    let mut failures: VecDeque<Instant> = VecDeque::new();
+    let mut backoff = initial_backoff;
+
+    loop {
+        let ready = Ready {
+            name: name.clone(),
+            health: context.health.clone(),
+        };
+        // Its own task, so a panic ends the run instead of the supervisor
+        let outcome = tokio::spawn(factory(ready)).await;
+        if context.shutdown.is_triggered() {
+            context.health.report(&name, ComponentHealth::failed("stopped"));
+            return;
+        }
+
+        let reason = match outcome {
+            Ok(Ok(())) => "exited".to_string(),
+            Ok(Err(e)) => format!("{:#}", e),
+            Err(e) if e.is_panic() => "panicked".to_string(),
+            Err(e) => e.to_string(),
+        };
+        error!("Enforcement task {} died: {}", name, reason);
+
+        let now = Instant::now();
+        while failures.front().is_some_and(|t| now.duration_since(*t) > window) {
+            failures.pop_front();
+        }
+        // No failure for a whole window (or the run outlived it): start over
+        if failures.is_empty() {
+            backoff = initial_backoff;
+        }
+        failures.push_back(now);
+        if failures.len() > context.config.max_restarts as usize {
+            fail_closed(&context, &name, &reason);
+            return;
+        }
+
+        context.health.report(
+            &name,
+            ComponentHealth::failed(format!("restarting in {:?} after: {}", backoff, reason)),
+        );
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {}
+            _ = context.shutdown.signal().wait() => {
+                context.health.report(&name, ComponentHealth::failed("stopped"));
+                return;
+            }
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+        warn!("Restarting enforcement task {}", name);
+        context
+            .health
+            .report(&name, ComponentHealth::failed(format!("restarting after: {}", reason)));
+    }
+}
+
+fn fail_closed(context: &Context, name: &str, reason: &str) {
+    let action = context.config.on_failure;
+    error!(
+        "Enforcement task {} cannot be kept running - failing closed ({})",
+        name, action
+    );
+    context
+        .health
+        .report(name, ComponentHealth::failed(format!("fail-closed ({}): {}", action, reason)));
+    context.state.events.publish(SecurityEvent::EnforcementFailed {
+        component: name.to_string(),
+        reason: reason.to_string(),
+        action: action.to_string(),
+    });
+
+    match action {
+        // The task's listener went with it; nothing else to do
+        FailAction::Stop => {}
+        FailAction::Maintenance => context.state.set_maintenance(true),
+        FailAction::Exit => {
+            let mut exit = context.exit.lock().unwrap_or_else(|e| e.into_inner());
+            exit.get_or_insert(SupervisorError::FailClosed {
+                task: name.to_string(),
+                reason: reason.to_string(),
+            });
+            context.shutdown.trigger();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use crate::health::HealthStatus;
+
+    fn supervisor(on_failure: FailAction, max_restarts: u32) -> (Supervisor, Arc<AgentState>, Arc<HealthRegistry>, Arc<Shutdown>) {
+        supervisor_with_window(on_failure, max_restarts, 60)
+    }
+
+    fn supervisor_with_window(
+        on_failure: FailAction,
+        max_restarts: u32,
+        restart_window_secs: u64,
+    ) -> (Supervisor, Arc<AgentState>, Arc<HealthRegistry>, Arc<Shutdown>) {
+        let state = Arc::new(AgentState::new());
+        let health = Arc::new(HealthRegistry::new());
+        let shutdown = Arc::new(Shutdown::new());
+        let config = SupervisorConfig {
+            on_failure,
+            max_restarts,
+            restart_window_secs,
+        };
+        let supervisor =
+            Supervisor::new(state.clone(), health.clone(), shutdown.clone(), config).with_backoff(Duration::from_millis(10));
+        (supervisor, state, health, shutdown)
+    }
+
+    #[tokio::test]
+    async fn test_task_is_restarted() {
+        let (mut supervisor, _state, health, shutdown) = supervisor(FailAction::Exit, 5);
+        let runs = Arc::new(AtomicU32::new(0));
+        let counter = runs.clone();
+        let signal = shutdown.clone();
+        supervisor.spawn("db_proxy", move |ready| {
+            let run = counter.fetch_add(1, Ordering::SeqCst);
+            let signal = signal.signal();
+            async move {
+                if run < 2 {
+                    anyhow::bail!("accept failed");
+                }
+                ready.up();
+                signal.wait().await;
+                Ok(())
+            }
+        });
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        assert_eq!(health.report_all().status, HealthStatus::Healthy);
+
+        shutdown.trigger();
+        assert_eq!(supervisor.join().await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_panics_exhaust_budget_and_exit() {
+        let (mut supervisor, state, health, shutdown) = supervisor(FailAction::Exit, 2);
+        let mut events = state.events.subscribe();
+        supervisor.spawn("db_proxy", |_| async { panic!("boom") });
+
+        let result = supervisor.join().await;
+        assert!(matches!(result, Err(SupervisorError::FailClosed { ref reason, .. }) if reason == "panicked"));
+        assert!(shutdown.is_triggered());
+        assert_eq!(health.report_all().status, HealthStatus::Failed);
+        assert_eq!(events.recv().await.unwrap().event.name(), "enforcement_failed");
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_keeps_agent_up() {
+        let (mut supervisor, state, health, shutdown) = supervisor(FailAction::Maintenance, 0);
+        supervisor.spawn("db_proxy", |_| async { Ok(()) });
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(state.in_maintenance());
+        assert!(!shutdown.is_triggered());
+        let report = health.report_all();
+        assert!(report.components["db_proxy"].reason.as_deref().unwrap().contains("fail-closed (maintenance)"));
+
+        shutdown.trigger();
+        assert_eq!(supervisor.join().await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_restart_is_unhealthy_until_ready() {
+        let (mut supervisor, _state, health, shutdown) = supervisor(FailAction::Exit, 5);
+        let runs = Arc::new(AtomicU32::new(0));
+        let bound = Arc::new(tokio::sync::Notify::new());
+        let (counter, gate, signal) = (runs.clone(), bound.clone(), shutdown.clone());
+        supervisor.spawn("db_proxy", move |ready| {
+            let run = counter.fetch_add(1, Ordering::SeqCst);
+            let (gate, signal) = (gate.clone(), signal.signal());
+            async move {
+                if run == 0 {
+                    ready.up();
+                    anyhow::bail!("accept failed");
+                }
+                // The restarted run takes a while to bind again
+                gate.notified().await;
+                ready.up();
+                signal.wait().await;
+                Ok(())
+            }
+        });
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        let report = health.report_all();
+        assert_eq!(report.status, HealthStatus::Failed);
+        assert!(report.components["db_proxy"].reason.as_deref().unwrap().contains("accept failed"));
+
+        bound.notify_one();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(health.report_all().status, HealthStatus::Healthy);
+
+        shutdown.trigger();
+        assert_eq!(supervisor.join().await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_backoff_resets_after_a_quiet_window() {
+        let (mut supervisor, _state, _health, shutdown) = supervisor_with_window(FailAction::Exit, 10, 1);
+        let starts = Arc::new(Mutex::new(Vec::new()));
+        let (log, signal) = (starts.clone(), shutdown.clone());
+        supervisor.spawn("db_proxy", move |ready| {
+            let run = {
+                let mut starts = log.lock().unwrap();
+                starts.push(Instant::now());
+                starts.len() - 1
+            };
+            let signal = signal.signal();
+            async move {
+                // Backoff reaches 320ms over five quick failures
+                if run < 5 {
+                    anyhow::bail!("accept failed");
+                }
+                if run == 5 {
+                    tokio::time::sleep(Duration::from_millis(1100)).await;
+                    anyhow::bail!("accept failed");
+                }
+                ready.up();
+                signal.wait().await;
+                Ok(())
+            }
+        });
+
+        tokio::time::sleep(Duration::from_millis(1800)).await;
+        {
+            let starts = starts.lock().unwrap();
+            assert_eq!(starts.len(), 7);
+            let pause = starts[6] - starts[5] - Duration::from_millis(1100);
+            assert!(pause < Duration::from_millis(200), "restarted after {:?}", pause);
+        }
+
+        shutdown.trigger();
+        assert_eq!(supervisor.join().await, Ok(()));
+    }
+}
@@ -0,0 +1,517 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Airlock Integration Tests
+//!
+//! Requests through the HTTP header airlock to a backend that echoes the
+//! headers it received and leaks its software in its own.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::HeaderMap;
+use axum::response::{AppendHeaders, IntoResponse};
+use axum::body::Bytes;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use reqwest::StatusCode;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use wharf_core::events::SecurityEvent;
+use wharf_core::path_rules::{self, PathRule};
+use wharf_core::types::{CookieRule, HeaderPolicy};
+use wharf_core::uploads::UploadRule;
+use yacht_agent::airlock::{self, Airlock};
+use yacht_agent::desync::Desync;
+use yacht_agent::lifecycle::Shutdown;
+use yacht_agent::metrics::Registry;
+use yacht_agent::state::AgentState;
+
+/// Echo the request headers as JSON, like a chatty PHP app
+async fn echo(headers: HeaderMap) -> impl IntoResponse {
+    let received: BTreeMap<String, String> = headers
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_str().unwrap_or_default().to_string()))
+        .collect();
+    (
+        [("server", "Apache/2.4.41 (Ubuntu)"), ("x-powered-by", "PHP/7.4.3"), ("x-frame-options", "ALLOWALL")],
+        Json(received),
+    )
+}
+
+/// Start a session the way a CMS does: cookies without hardening
+async fn session(headers: HeaderMap) -> impl IntoResponse {
+    let cookies = AppendHeaders([
+        ("set-cookie", "wordpress_logged_in_5d41=admin%7C1700000000; path=/"),
+        ("set-cookie", "SESSa1b2=fresh; path=/; domain=.example.com"),
+        ("set-cookie", "theme=dark; path=/"),
+    ]);
+    (cookies, echo(headers).await)
+}
+
+/// Take an upload; answers with how many bytes arrived
+async fn upload(body: Bytes) -> String {
+    body.len().to_string()
+}
+
+struct Setup {
+    url: String,
+    state: Arc<AgentState>,
+    airlock: Arc<Airlock>,
+    _shutdown: Shutdown,
+}
+
+async fn setup() -> Setup {
+    let mut policy = HeaderPolicy::default();
+    policy.egress.force.insert("X-Frame-Options".to_string(), "DENY".to_string());
+    setup_with(policy).await
+}
+
+async fn setup_with(policy: HeaderPolicy) -> Setup {
+    let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let backend_url = format!("http://{}", backend.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(backend, Router::new().route("/session.php", get(session)).route("/upload.php", post(upload)).route("/*path", get(echo))).await });
+
+    let state = Arc::new(AgentState::new());
+    state.reload_header_policy(policy);
+
+    let airlock = Arc::new(Airlock::new(state.clone(), &backend_url).unwrap());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let shutdown = Shutdown::new();
+    tokio::spawn(airlock::serve(listener, airlock.clone(), shutdown.signal(), Duration::from_secs(1)));
+
+    Setup {
+        url,
+        state,
+        airlock,
+        _shutdown: shutdown,
+    }
+}
+
+#[tokio::test]
+async fn test_headers_filtered_both_ways() {
+    let setup = setup().await;
+    let response = reqwest::Client::new()
+        .get(format!("{}/index.php?id=1", setup.url))
+        .header("Cookie", "session=abc")
+        .header("X-Forwarded-For", "127.0.0.1")
+        .header("X-HTTP-Method-Override", "DELETE")
+        .header("X-Debug-Token", "1")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let headers = response.headers().clone();
+    assert!(headers.get("server").is_none());
+    assert!(headers.get("x-powered-by").is_none());
+    assert_eq!(headers["x-frame-options"], "DENY");
+    assert_eq!(headers["x-content-type-options"], "nosniff");
+
+    let received: BTreeMap<String, String> = response.json().await.unwrap();
+    assert_eq!(received["cookie"], "session=abc");
+    // The backend sees the real peer, not what the client claimed
+    assert_eq!(received["x-forwarded-for"], "127.0.0.1");
+    assert!(!received.contains_key("x-http-method-override"));
+    assert!(!received.contains_key("x-debug-token"));
+
+    let mut registry = Registry::new();
+    registry.register(setup.airlock.clone());
+    let metrics = registry.render();
+    assert!(metrics.contains(r#"yacht_airlock_requests_total{outcome="forwarded"} 1"#), "{}", metrics);
+    assert!(metrics.contains(
+        r#"yacht_airlock_headers_total{direction="ingress",action="dropped",header="x-http-method-override"} 1"#
+    ));
+    assert!(metrics.contains(r#"yacht_airlock_headers_total{direction="ingress",action="dropped",header="other"} 1"#));
+    assert!(metrics.contains(r#"yacht_airlock_headers_total{direction="egress",action="stripped",header="server"} 1"#));
+}
+
+#[tokio::test]
+async fn test_oversized_header_refused() {
+    let setup = setup().await;
+    let response = reqwest::Client::new()
+        .get(format!("{}/", setup.url))
+        .header("Referer", "x".repeat(10_000))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    // Agent-generated responses are hardened too
+    assert_eq!(response.headers()["x-frame-options"], "DENY");
+    assert_eq!(setup.airlock.stats.rejected.get(), 1);
+    assert_eq!(setup.airlock.stats.forwarded.get(), 0);
+}
+
+#[tokio::test]
+async fn test_maintenance_page_when_failed_closed() {
+    let setup = setup().await;
+    setup.state.set_maintenance(true);
+
+    let response = reqwest::get(format!("{}/", setup.url)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(response.headers().contains_key("retry-after"));
+}
+
+#[tokio::test]
+async fn test_constraints_before_backend() {
+    let setup = setup().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!("{}/index.php", setup.url))
+        .header("Cookie", "wordpress_logged_in=abc; x=\"unterminated")
+        .send()
+        .await
+        .unwrap();
+    let received: BTreeMap<String, String> = response.json().await.unwrap();
+    assert_eq!(received["cookie"], "wordpress_logged_in=abc");
+
+    let response = client
+        .get(format!("{}/index.php", setup.url))
+        .header("User-Agent", "sqlmap/1.0 ../../etc/passwd")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(setup.airlock.stats.forwarded.get(), 1);
+    assert_eq!(setup.airlock.stats.rejected.get(), 1);
+}
+
+#[tokio::test]
+async fn test_csp_nonce_and_reports() {
+    let mut policy = HeaderPolicy::default();
+    policy.csp.enabled = true;
+    policy.csp.nonce = true;
+    let setup = setup_with(policy).await;
+    let client = reqwest::Client::new();
+
+    let response = client.get(format!("{}/index.php", setup.url)).send().await.unwrap();
+    let csp = response.headers()["content-security-policy"].to_str().unwrap().to_string();
+    assert!(response.headers().contains_key("reporting-endpoints"));
+    let received: BTreeMap<String, String> = response.json().await.unwrap();
+    // The application gets the nonce the browser is told to accept
+    let nonce = &received["x-csp-nonce"];
+    assert!(csp.contains(&format!("script-src 'self' 'nonce-{}'", nonce)), "{}", csp);
+    assert!(csp.contains("report-uri /.well-known/csp-report"));
+
+    let report = r#"{"csp-report": {"document-uri": "https://example.com/?p=1", "violated-directive": "script-src", "blocked-uri": "inline"}}"#;
+    for _ in 0..2 {
+        let response = client
+            .post(format!("{}/.well-known/csp-report", setup.url))
+            .header("Content-Type", "application/csp-report")
+            .body(report)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+    let response = client
+        .post(format!("{}/.well-known/csp-report", setup.url))
+        .body("garbage")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let summary = setup.state.csp_reports.summary();
+    assert_eq!(summary.len(), 1);
+    assert_eq!(summary[0].count, 2);
+    assert_eq!(summary[0].document_uri, "https://example.com/");
+    // Reports are taken by the airlock, not forwarded
+    assert_eq!(setup.airlock.stats.forwarded.get(), 1);
+}
+
+#[tokio::test]
+async fn test_path_rules_from_adapter() {
+    let mut policy = HeaderPolicy::default();
+    policy.paths.no_execution.push("/wp-content/uploads".to_string());
+    policy.paths.deny.push("/xmlrpc.php".to_string());
+    let setup = setup_with(policy).await;
+    let client = reqwest::Client::new();
+
+    for (path, status) in [
+        ("/wp-content/uploads/2024/photo.jpg", StatusCode::OK),
+        ("/wp-content/uploads/2024/shell.php", StatusCode::FORBIDDEN),
+        ("/wp-content/uploads/%73hell.php", StatusCode::FORBIDDEN),
+        ("/xmlrpc.php", StatusCode::FORBIDDEN),
+        ("/.git/config", StatusCode::FORBIDDEN),
+        ("/wp-config.php.bak", StatusCode::FORBIDDEN),
+    ] {
+        let response = client.get(format!("{}{}", setup.url, path)).send().await.unwrap();
+        assert_eq!(response.status(), status, "{}", path);
+    }
+
+    assert_eq!(setup.airlock.stats.forwarded.get(), 1);
+    assert_eq!(setup.airlock.stats.path_denials(PathRule::NoExecution), 2);
+    assert_eq!(setup.airlock.stats.path_denials(PathRule::Denied), 1);
+    assert_eq!(setup.airlock.stats.path_denials(PathRule::Dotfile), 1);
+    assert_eq!(setup.airlock.stats.path_denials(PathRule::Backup), 1);
+}
+
+#[tokio::test]
+async fn test_admin_lockdown() {
+    let mut policy = HeaderPolicy::default();
+    policy.paths.admin.enabled = true;
+    policy.paths.admin.paths = vec!["/wp-admin".to_string(), "/wp-login.php".to_string()];
+    policy.paths.admin.except = vec!["/wp-admin/admin-ajax.php".to_string()];
+    policy.paths.admin.allow_from = vec!["10.42.0.0/16".to_string()];
+    let setup = setup_with(policy.clone()).await;
+    let client = reqwest::Client::new();
+
+    let response = client.get(format!("{}/wp-login.php", setup.url)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = client.get(format!("{}/wp-admin/admin-ajax.php", setup.url)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(setup.airlock.stats.path_denials(PathRule::Admin), 1);
+
+    // From an admin network (the test client is on loopback)
+    policy.paths.admin.allow_from.push("127.0.0.1".to_string());
+    policy.paths.admin.status = 403;
+    setup.state.reload_header_policy(policy.clone());
+    let response = client.get(format!("{}/wp-admin/options.php", setup.url)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    policy.paths.admin.allow_from.pop();
+    setup.state.reload_header_policy(policy.clone());
+    let response = client.get(format!("{}/wp-admin/options.php", setup.url)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Drupal, through its front controller and Drupal 7's `q` route
+    let (paths, _) = path_rules::admin_paths("drupal");
+    policy.paths.admin.paths = paths.iter().map(|p| p.to_string()).collect();
+    policy.paths.admin.except.clear();
+    policy.paths.admin.route_params = vec!["q".to_string()];
+    policy.paths.admin.language_prefix = true;
+    setup.state.reload_header_policy(policy);
+    for target in [
+        "/user",
+        "/user/reset/1/123/abc",
+        "/en/user/login",
+        "/fr/admin",
+        "/?q=user",
+        "/index.php/user/login",
+        "/index.php/admin/config",
+        "/?q=user/login",
+        "/index.php?q=admin",
+        "/index.php?%71=user%2Flogin",
+    ] {
+        let response = client.get(format!("{}{}", setup.url, target)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", target);
+    }
+    let response = client.get(format!("{}/index.php?q=node/1", setup.url)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_rate_limits() {
+    let mut policy = HeaderPolicy::default();
+    policy.rate_limits.escalate_after = 2;
+    let setup = setup_with(policy).await;
+    let mut events = setup.state.events.subscribe();
+    let client = reqwest::Client::new();
+
+    // xmlrpc: burst 3, then one token every 10 seconds
+    for _ in 0..3 {
+        let response = client.get(format!("{}/xmlrpc.php", setup.url)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    for _ in 0..2 {
+        let response = client.get(format!("{}/xmlrpc.php", setup.url)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "10");
+    }
+    // Other classes have their own buckets
+    let response = client.get(format!("{}/index.php?s=boats", setup.url)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // The second violation gets the client reported
+    let record = events.recv().await.unwrap();
+    assert_eq!(
+        record.event,
+        SecurityEvent::RateLimited {
+            client: "127.0.0.1".to_string(),
+            class: "xmlrpc".to_string(),
+            violations: 2,
+        }
+    );
+    assert_eq!(setup.airlock.stats.rate_limited("xmlrpc"), 2);
+    assert_eq!(setup.airlock.stats.escalations.get(), 1);
+
+    let mut registry = Registry::new();
+    registry.register(setup.airlock.clone());
+    let metrics = registry.render();
+    assert!(metrics.contains(r#"yacht_airlock_rate_limited_total{class="xmlrpc"} 2"#), "{}", metrics);
+    assert!(metrics.contains("yacht_airlock_rate_limit_escalations_total 1"));
+}
+
+#[tokio::test]
+async fn test_cookies_hardened() {
+    let mut policy = HeaderPolicy::default();
+    policy.cookies.rules.insert(
+        0,
+        CookieRule {
+            name: "SESS*".to_string(),
+            host_prefix: true,
+            ..Default::default()
+        },
+    );
+    let setup = setup_with(policy).await;
+    let response = reqwest::Client::new()
+        .get(format!("{}/session.php", setup.url))
+        .header("Cookie", "SESSa1b2=planted; __Host-SESSa1b2=real; theme=dark")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let cookies: Vec<&str> = response
+        .headers()
+        .get_all("set-cookie")
+        .iter()
+        .map(|v| v.to_str().unwrap())
+        .collect();
+    assert_eq!(
+        cookies,
+        vec![
+            "wordpress_logged_in_5d41=admin%7C1700000000; path=/; Secure; HttpOnly; SameSite=Lax",
+            "__Host-SESSa1b2=fresh; Path=/; Secure; HttpOnly; SameSite=Lax",
+            "theme=dark; path=/",
+        ]
+    );
+    assert_eq!(setup.airlock.stats.cookies_hardened("wordpress_*"), 1);
+    assert_eq!(setup.airlock.stats.cookies_hardened("SESS*"), 1);
+
+    // The application sees its own cookie name, never the planted one
+    let received: BTreeMap<String, String> = response.json().await.unwrap();
+    assert_eq!(received["cookie"], "SESSa1b2=real; theme=dark");
+}
+
+/// A one-file multipart form
+fn upload_form(filename: &str, content_type: &str, content: &[u8]) -> Vec<u8> {
+    let mut body = format!(
+        "--b0undary\r\nContent-Disposition: form-data; name=\"async-upload\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+        filename, content_type
+    )
+    .into_bytes();
+    body.extend_from_slice(content);
+    body.extend_from_slice(b"\r\n--b0undary\r\nContent-Disposition: form-data; name=\"action\"\r\n\r\nupload-attachment\r\n--b0undary--\r\n");
+    body
+}
+
+#[tokio::test]
+async fn test_uploads_inspected() {
+    let setup = setup().await;
+    let client = reqwest::Client::new();
+    let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".repeat(1000);
+
+    for (form, status) in [
+        (upload_form("beach.png", "image/png", &png), StatusCode::OK),
+        (upload_form("shell.php.png", "image/png", &png), StatusCode::FORBIDDEN),
+        (upload_form("cat.gif", "image/gif", b"GIF89a<?php eval($_POST[1]); ?>"), StatusCode::FORBIDDEN),
+        (upload_form("beach.jpg", "image/jpeg", &png), StatusCode::UNSUPPORTED_MEDIA_TYPE),
+        (upload_form("page.html", "text/html", b"<html></html>"), StatusCode::UNSUPPORTED_MEDIA_TYPE),
+    ] {
+        let length = form.len();
+        let response = client
+            .post(format!("{}/upload.php", setup.url))
+            .header("Content-Type", "multipart/form-data; boundary=b0undary")
+            .body(form)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status);
+        if status == StatusCode::OK {
+            // Passed on whole
+            assert_eq!(response.text().await.unwrap(), length.to_string());
+        }
+    }
+
+    assert_eq!(setup.airlock.stats.forwarded.get(), 1);
+    assert_eq!(setup.airlock.stats.upload_rejections(UploadRule::BlockedExtension), 1);
+    assert_eq!(setup.airlock.stats.upload_rejections(UploadRule::Executable), 1);
+    assert_eq!(setup.airlock.stats.upload_rejections(UploadRule::TypeMismatch), 1);
+    assert_eq!(setup.airlock.stats.upload_rejections(UploadRule::MimeNotAllowed), 1);
+
+    let mut registry = Registry::new();
+    registry.register(setup.airlock.clone());
+    let metrics = registry.render();
+    assert!(metrics.contains(r#"yacht_airlock_uploads_rejected_total{rule="executable"} 1"#), "{}", metrics);
+}
+
+/// Send raw bytes and read until the airlock closes the connection
+async fn raw(url: &str, request: &[u8]) -> String {
+    let mut stream = TcpStream::connect(url.trim_start_matches("http://")).await.unwrap();
+    stream.write_all(request).await.unwrap();
+    let mut response = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut response))
+        .await
+        .unwrap()
+        .unwrap();
+    String::from_utf8_lossy(&response).into_owned()
+}
+
+#[tokio::test]
+async fn test_smuggling_attempts_refused() {
+    let setup = setup().await;
+
+    // CL.TE: the backend would take "G" as the start of the next request
+    let response = raw(
+        &setup.url,
+        b"POST /index.php HTTP/1.1\r\nHost: localhost\r\nContent-Length: 6\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nG",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+    assert!(response.contains("both Content-Length and Transfer-Encoding"));
+
+    let response = raw(
+        &setup.url,
+        b"GET /index.php HTTP/1.1\r\nHost: localhost\r\nX-Padding: a\r\n Transfer-Encoding: chunked\r\n\r\n",
+    )
+    .await;
+    assert!(response.contains("obsolete line folding"), "{}", response);
+
+    let response = raw(
+        &setup.url,
+        b"GET http://admin.internal/index.php HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    )
+    .await;
+    assert!(response.contains("absolute-form target does not match Host"), "{}", response);
+
+    let long = format!("GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n", "a".repeat(10_000));
+    let response = raw(&setup.url, long.as_bytes()).await;
+    assert!(response.starts_with("HTTP/1.1 414 URI Too Long\r\n"), "{}", response);
+
+    assert_eq!(setup.airlock.stats.desync.get(Desync::ConflictingFraming), 1);
+    assert_eq!(setup.airlock.stats.desync.get(Desync::ObsoleteLineFolding), 1);
+    assert_eq!(setup.airlock.stats.desync.get(Desync::HostMismatch), 1);
+    assert_eq!(setup.airlock.stats.desync.get(Desync::RequestLineTooLong), 1);
+    assert_eq!(setup.airlock.stats.forwarded.get(), 0);
+
+    let mut registry = Registry::new();
+    registry.register(setup.airlock.clone());
+    let metrics = registry.render();
+    assert!(metrics.contains(r#"yacht_airlock_desync_rejected_total{reason="conflicting_framing"} 1"#));
+    assert!(metrics.contains(r#"yacht_airlock_desync_rejected_total{reason="duplicate_content_length"} 0"#));
+}
+
+#[tokio::test]
+async fn test_requests_before_a_bad_one_are_answered() {
+    let setup = setup().await;
+
+    // Pipelined: a good request, then duplicate lengths
+    let response = raw(
+        &setup.url,
+        b"GET /index.php HTTP/1.1\r\nHost: localhost\r\n\r\nPOST /index.php HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\nContent-Length: 4\r\n\r\nabcd",
+    )
+    .await;
+    let ok = response.find("HTTP/1.1 200 OK").expect(&response);
+    let refused = response.find("HTTP/1.1 400 Bad Request").expect(&response);
+    assert!(ok < refused);
+    assert!(response.contains("more than one Content-Length"));
+    assert_eq!(setup.airlock.stats.forwarded.get(), 1);
+    assert_eq!(setup.airlock.stats.desync.get(Desync::DuplicateContentLength), 1);
+}
@@ -0,0 +1,194 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # API Access Integration Tests
+//!
+//! The agent API over TLS with pinned client certificates: which identity
+//! may reach which route, and what happens to clients that are not listed.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+use reqwest::StatusCode;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+
+use yacht_agent::access::AccessPolicy;
+use yacht_agent::api::{self, ApiState};
+use yacht_agent::ebpf::ShieldMonitor;
+use yacht_agent::lifecycle::Shutdown;
+use yacht_agent::health::HealthRegistry;
+use yacht_agent::metrics::Registry;
+use yacht_agent::policy::PolicyStore;
+use yacht_agent::shadow::{ShadowConfig, ShadowDb};
+use yacht_agent::state::AgentState;
+use yacht_agent::tls;
+
+/// A self-signed client certificate (PEM cert + key) and its fingerprint
+struct ClientCert {
+    pem: String,
+    fingerprint: String,
+}
+
+impl ClientCert {
+    fn new(name: &str) -> Self {
+        let cert = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let cert_pem = cert.serialize_pem().unwrap();
+        let der = CertificateDer::from_pem_slice(cert_pem.as_bytes()).unwrap();
+        Self {
+            pem: format!("{}{}", cert_pem, cert.serialize_private_key_pem()),
+            fingerprint: tls::fingerprint(&der),
+        }
+    }
+}
+
+struct Agent {
+    addr: SocketAddr,
+    ca_pem: String,
+    shutdown: Shutdown,
+    server: tokio::task::JoinHandle<std::io::Result<()>>,
+}
+
+impl Agent {
+    /// Serve the API over TLS, allowing `clients` as (name, cert, roles)
+    async fn start(clients: &[(&str, &ClientCert, &[&str])]) -> Self {
+        let mut ca_params = CertificateParams::new(Vec::new());
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = Certificate::from_params(ca_params).unwrap();
+        let server = Certificate::from_params(CertificateParams::new(vec!["localhost".to_string()])).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let cert_path = dir.path().join("api.pem");
+        let key_path = dir.path().join("api.key");
+        let access_path = dir.path().join("clients.toml");
+        std::fs::write(&cert_path, server.serialize_pem_with_signer(&ca).unwrap()).unwrap();
+        std::fs::write(&key_path, server.serialize_private_key_pem()).unwrap();
+
+        let mut access = String::new();
+        for (name, cert, roles) in clients {
+            access.push_str(&format!(
+                "[clients.{}]\nfingerprint = \"{}\"\nroles = {:?}\n",
+                name, cert.fingerprint, roles
+            ));
+        }
+        std::fs::write(&access_path, access).unwrap();
+
+        let access = Arc::new(AccessPolicy::load(&access_path).unwrap());
+        let config = tls::server_config(&cert_path, &key_path, Some(&access)).unwrap();
+
+        let state = Arc::new(AgentState::new());
+        let api_state = Arc::new(ApiState {
+            agent: state.clone(),
+            shadow: Arc::new(ShadowDb::new(ShadowConfig::default())),
+            shield: Arc::new(ShieldMonitor::new("none", None)),
+            policies: Arc::new(PolicyStore::open(state, 5, None).unwrap()),
+            mooring: None,
+            registry: Registry::new(),
+            health: Arc::new(HealthRegistry::new()),
+        });
+        let app = api::router(api_state, true, Some(access));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = Shutdown::new();
+        let server = tokio::spawn(api::serve(
+            listener,
+            app,
+            Some(TlsAcceptor::from(config)),
+            shutdown.signal(),
+            Duration::from_secs(1),
+        ));
+
+        Self {
+            addr,
+            ca_pem: ca.serialize_pem().unwrap(),
+            shutdown,
+            server,
+        }
+    }
+
+    fn client(&self, cert: Option<&ClientCert>) -> reqwest::Client {
+        let mut builder = reqwest::Client::builder()
+            .add_root_certificate(reqwest::Certificate::from_pem(self.ca_pem.as_bytes()).unwrap())
+            .resolve("localhost", self.addr);
+        if let Some(cert) = cert {
+            builder = builder.identity(reqwest::Identity::from_pem(cert.pem.as_bytes()).unwrap());
+        }
+        builder.build().unwrap()
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("https://localhost:{}{}", self.addr.port(), path)
+    }
+
+    async fn get(&self, cert: Option<&ClientCert>, path: &str) -> reqwest::Result<StatusCode> {
+        Ok(self.client(cert).get(self.url(path)).send().await?.status())
+    }
+}
+
+#[tokio::test]
+async fn test_routes_require_their_role() {
+    let prometheus = ClientCert::new("prometheus");
+    let wharf = ClientCert::new("wharf");
+    let agent = Agent::start(&[
+        ("prometheus", &prometheus, &["metrics"]),
+        ("wharf", &wharf, &["status", "audit", "moor"]),
+    ])
+    .await;
+
+    assert_eq!(agent.get(Some(&prometheus), "/metrics").await.unwrap(), StatusCode::OK);
+    assert_eq!(agent.get(Some(&prometheus), "/status").await.unwrap(), StatusCode::FORBIDDEN);
+    assert_eq!(agent.get(Some(&wharf), "/status").await.unwrap(), StatusCode::OK);
+    assert_eq!(agent.get(Some(&wharf), "/metrics").await.unwrap(), StatusCode::FORBIDDEN);
+    assert_eq!(agent.get(Some(&wharf), "/policy/history").await.unwrap(), StatusCode::OK);
+    assert_eq!(agent.get(Some(&prometheus), "/policy/history").await.unwrap(), StatusCode::FORBIDDEN);
+    assert_eq!(agent.get(Some(&wharf), "/csp/reports").await.unwrap(), StatusCode::OK);
+    assert_eq!(agent.get(Some(&prometheus), "/csp/reports").await.unwrap(), StatusCode::FORBIDDEN);
+
+    // The scraper cannot send mooring commands
+    let moor = agent
+        .client(Some(&prometheus))
+        .post(agent.url("/moor"))
+        .json(&serde_json::json!({ "captain": "x", "payload": "{}", "signature": "" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(moor.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_health_needs_no_certificate() {
+    let wharf = ClientCert::new("wharf");
+    let agent = Agent::start(&[("wharf", &wharf, &["status"])]).await;
+
+    assert_eq!(agent.get(None, "/health").await.unwrap(), StatusCode::OK);
+    assert_eq!(agent.get(None, "/health/ready").await.unwrap(), StatusCode::OK);
+    assert_eq!(agent.get(None, "/health/live").await.unwrap(), StatusCode::OK);
+    assert_eq!(agent.get(None, "/status").await.unwrap(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_unlisted_certificate_fails_handshake() {
+    let wharf = ClientCert::new("wharf");
+    let agent = Agent::start(&[("wharf", &wharf, &["status"])]).await;
+
+    let stranger = ClientCert::new("wharf");
+    assert!(agent.get(Some(&stranger), "/health").await.is_err());
+}
+
+#[tokio::test]
+async fn test_server_stops_on_shutdown() {
+    let agent = Agent::start(&[]).await;
+    assert_eq!(agent.get(None, "/health").await.unwrap(), StatusCode::OK);
+
+    agent.shutdown.trigger();
+    tokio::time::timeout(Duration::from_secs(5), agent.server)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+}
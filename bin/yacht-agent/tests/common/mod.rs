@@ -0,0 +1,621 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Mock Database Harness
+//!
+//! In-process fake MySQL and PostgreSQL servers for driving the proxy end to
+//! end without a real database. A server is scripted with a closure that
+//! maps each query to a [`Reply`], and records every query it receives so a
+//! test can check what actually got past the proxy.
+//!
+//! The matching clients speak just enough of each protocol to log in, send
+//! a query and read back one [`Response`].
+
+// Each test binary uses a different slice of the harness
+#![allow(dead_code)]
+
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use yacht_agent::net::Endpoint;
+
+/// What a mock server answers to a query
+#[derive(Debug, Clone)]
+pub enum Reply {
+    /// OK / CommandComplete for a write
+    Ok { affected_rows: u64 },
+    /// An error (MySQL code and SQLSTATE; PostgreSQL uses the SQLSTATE)
+    Error { code: u16, sqlstate: String, message: String },
+    /// A result set of text columns
+    Rows { columns: Vec<String>, rows: Vec<Vec<String>> },
+}
+
+impl Reply {
+    pub fn ok(affected_rows: u64) -> Self {
+        Self::Ok { affected_rows }
+    }
+
+    pub fn error(code: u16, sqlstate: &str, message: &str) -> Self {
+        Self::Error {
+            code,
+            sqlstate: sqlstate.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn rows(columns: &[&str], rows: &[&[&str]]) -> Self {
+        Self::Rows {
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            rows: rows.iter().map(|r| r.iter().map(|v| v.to_string()).collect()).collect(),
+        }
+    }
+}
+
+/// What a mock client read back for a query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    Ok,
+    /// The MySQL error code (as a string) or the PostgreSQL SQLSTATE
+    Error { code: String, message: String },
+    Rows(Vec<Vec<String>>),
+}
+
+type Script = Arc<dyn Fn(&str) -> Reply + Send + Sync>;
+
+/// A scripted fake database listening on loopback
+pub struct MockServer {
+    endpoint: Endpoint,
+    queries: Arc<Mutex<Vec<String>>>,
+    users: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockServer {
+    /// Start a fake MySQL server
+    pub async fn mysql(script: impl Fn(&str) -> Reply + Send + Sync + 'static) -> Self {
+        Self::start(Protocol::Mysql, Arc::new(script)).await
+    }
+
+    /// Start a fake PostgreSQL server
+    pub async fn postgres(script: impl Fn(&str) -> Reply + Send + Sync + 'static) -> Self {
+        Self::start(Protocol::Postgres, Arc::new(script)).await
+    }
+
+    async fn start(protocol: Protocol, script: Script) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind mock server");
+        let endpoint = Endpoint::Tcp(listener.local_addr().unwrap().to_string());
+        let queries = Arc::new(Mutex::new(Vec::new()));
+        let users = Arc::new(Mutex::new(Vec::new()));
+
+        let (conn_queries, conn_users) = (queries.clone(), users.clone());
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let session = Session {
+                    script: script.clone(),
+                    queries: conn_queries.clone(),
+                    users: conn_users.clone(),
+                };
+                tokio::spawn(async move {
+                    let _ = match protocol {
+                        Protocol::Mysql => session.serve_mysql(socket).await,
+                        Protocol::Postgres => session.serve_postgres(socket).await,
+                    };
+                });
+            }
+        });
+
+        Self { endpoint, queries, users }
+    }
+
+    /// Where the server listens (use as the shadow endpoint)
+    pub fn endpoint(&self) -> Endpoint {
+        self.endpoint.clone()
+    }
+
+    /// Every query the server has received, in order
+    pub fn queries(&self) -> Vec<String> {
+        self.queries.lock().unwrap().clone()
+    }
+
+    /// The user names clients logged in with
+    pub fn users(&self) -> Vec<String> {
+        self.users.lock().unwrap().clone()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Protocol {
+    Mysql,
+    Postgres,
+}
+
+struct Session {
+    script: Script,
+    queries: Arc<Mutex<Vec<String>>>,
+    users: Arc<Mutex<Vec<String>>>,
+}
+
+// =============================================================================
+// MYSQL
+// =============================================================================
+
+pub const CLIENT_COMPRESS: u32 = 0x0020;
+pub const CLIENT_PROTOCOL_41: u32 = 0x0200;
+pub const CLIENT_SSL: u32 = 0x0800;
+pub const CLIENT_SECURE_CONNECTION: u32 = 0x8000;
+pub const CLIENT_ZSTD_COMPRESSION: u32 = 0x0400_0000;
+
+const SERVER_CAPABILITIES: u32 =
+    CLIENT_COMPRESS | CLIENT_PROTOCOL_41 | CLIENT_SSL | CLIENT_SECURE_CONNECTION | CLIENT_ZSTD_COMPRESSION;
+
+/// Frame a MySQL payload
+pub fn mysql_frame(sequence: u8, payload: &[u8]) -> Vec<u8> {
+    let mut packet = (payload.len() as u32).to_le_bytes()[..3].to_vec();
+    packet.push(sequence);
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// Read one MySQL packet, returning its sequence number and payload
+pub async fn read_mysql_packet<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0u8; 4];
+    reader.read_exact(&mut header).await?;
+    let len = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload).await?;
+    Ok((header[3], payload))
+}
+
+fn lenenc_str(out: &mut Vec<u8>, value: &str) {
+    // Mock values stay short
+    out.push(value.len() as u8);
+    out.extend_from_slice(value.as_bytes());
+}
+
+/// The capability flags offered in a greeting payload
+pub fn greeting_capabilities(greeting: &[u8]) -> u32 {
+    let version_end = 1 + greeting[1..].iter().position(|&b| b == 0).unwrap();
+    let low = version_end + 1 + 4 + 8 + 1;
+    let high = low + 2 + 1 + 2;
+    u32::from_le_bytes([greeting[low], greeting[low + 1], greeting[high], greeting[high + 1]])
+}
+
+impl Session {
+    async fn serve_mysql(self, mut socket: TcpStream) -> io::Result<()> {
+        // Protocol 10 greeting
+        let mut greeting = vec![10];
+        greeting.extend_from_slice(b"8.0.0-mock\0");
+        greeting.extend_from_slice(&1u32.to_le_bytes());
+        greeting.extend_from_slice(b"abcdefgh\0");
+        greeting.extend_from_slice(&(SERVER_CAPABILITIES as u16).to_le_bytes());
+        greeting.push(0x21);
+        greeting.extend_from_slice(&0x0002u16.to_le_bytes());
+        greeting.extend_from_slice(&((SERVER_CAPABILITIES >> 16) as u16).to_le_bytes());
+        greeting.push(21);
+        greeting.extend_from_slice(&[0; 10]);
+        greeting.extend_from_slice(b"ijklmnopqrst\0");
+        socket.write_all(&mysql_frame(0, &greeting)).await?;
+
+        let (_, response) = read_mysql_packet(&mut socket).await?;
+        if let Some(rest) = response.get(32..) {
+            let end = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+            self.users.lock().unwrap().push(String::from_utf8_lossy(&rest[..end]).into_owned());
+        }
+        socket.write_all(&mysql_frame(2, &mysql_ok(0))).await?;
+
+        loop {
+            let (_, command) = match read_mysql_packet(&mut socket).await {
+                Ok(packet) => packet,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            match command.first() {
+                // COM_QUIT
+                Some(0x01) => return Ok(()),
+                // COM_QUERY
+                Some(0x03) => {
+                    let query = String::from_utf8_lossy(&command[1..]).into_owned();
+                    self.queries.lock().unwrap().push(query.clone());
+                    let reply = (self.script)(&query);
+                    socket.write_all(&mysql_reply(&reply)).await?;
+                }
+                _ => socket.write_all(&mysql_frame(1, &mysql_ok(0))).await?,
+            }
+        }
+    }
+}
+
+fn mysql_ok(affected_rows: u64) -> Vec<u8> {
+    vec![0x00, affected_rows as u8, 0, 0x02, 0, 0, 0]
+}
+
+fn mysql_eof() -> Vec<u8> {
+    vec![0xfe, 0, 0, 0x02, 0]
+}
+
+/// Encode a reply as the packets that answer a COM_QUERY
+fn mysql_reply(reply: &Reply) -> Vec<u8> {
+    match reply {
+        Reply::Ok { affected_rows } => mysql_frame(1, &mysql_ok(*affected_rows)),
+        Reply::Error { code, sqlstate, message } => {
+            let mut payload = vec![0xff];
+            payload.extend_from_slice(&code.to_le_bytes());
+            payload.push(b'#');
+            payload.extend_from_slice(sqlstate.as_bytes());
+            payload.extend_from_slice(message.as_bytes());
+            mysql_frame(1, &payload)
+        }
+        Reply::Rows { columns, rows } => {
+            let mut sequence = 1u8;
+            let mut next = |payload: &[u8]| {
+                let frame = mysql_frame(sequence, payload);
+                sequence = sequence.wrapping_add(1);
+                frame
+            };
+
+            let mut out = next(&[columns.len() as u8]);
+            for column in columns {
+                let mut def = Vec::new();
+                for field in ["def", "wordpress", "t", "t", column, column] {
+                    lenenc_str(&mut def, field);
+                }
+                def.push(0x0c);
+                def.extend_from_slice(&0x21u16.to_le_bytes());
+                def.extend_from_slice(&255u32.to_le_bytes());
+                def.push(0xfd); // VAR_STRING
+                def.extend_from_slice(&[0, 0, 0, 0, 0]);
+                out.extend(next(&def));
+            }
+            out.extend(next(&mysql_eof()));
+            for row in rows {
+                let mut payload = Vec::new();
+                for value in row {
+                    lenenc_str(&mut payload, value);
+                }
+                out.extend(next(&payload));
+            }
+            out.extend(next(&mysql_eof()));
+            out
+        }
+    }
+}
+
+fn mysql_error_response(payload: &[u8]) -> Response {
+    let code = u16::from_le_bytes([payload[1], payload[2]]);
+    // Skip the '#' and SQLSTATE when present
+    let message = if payload.get(3) == Some(&b'#') { &payload[9..] } else { &payload[3..] };
+    Response::Error {
+        code: code.to_string(),
+        message: String::from_utf8_lossy(message).into_owned(),
+    }
+}
+
+/// A minimal MySQL client
+pub struct MysqlClient<S> {
+    stream: S,
+    /// The greeting payload as the client saw it
+    pub greeting: Vec<u8>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> MysqlClient<S> {
+    /// Read the greeting and log in; an ERR in place of the greeting is returned as `Err`
+    pub async fn connect(mut stream: S, user: &str) -> io::Result<Result<Self, Response>> {
+        let (_, greeting) = read_mysql_packet(&mut stream).await?;
+        if greeting.first() == Some(&0xff) {
+            return Ok(Err(mysql_error_response(&greeting)));
+        }
+
+        let mut client = Self { stream, greeting };
+        client.send_handshake(user, CLIENT_PROTOCOL_41 | CLIENT_SECURE_CONNECTION).await?;
+        let (_, reply) = read_mysql_packet(&mut client.stream).await?;
+        if reply.first() == Some(&0xff) {
+            return Ok(Err(mysql_error_response(&reply)));
+        }
+        Ok(Ok(client))
+    }
+
+    /// Read the greeting only, leaving the handshake to the test
+    pub async fn greet(mut stream: S) -> io::Result<Self> {
+        let (_, greeting) = read_mysql_packet(&mut stream).await?;
+        Ok(Self { stream, greeting })
+    }
+
+    /// Send a handshake response with the given capability flags
+    pub async fn send_handshake(&mut self, user: &str, capabilities: u32) -> io::Result<()> {
+        let mut response = capabilities.to_le_bytes().to_vec();
+        response.extend_from_slice(&(1u32 << 24).to_le_bytes());
+        response.push(0x21);
+        response.extend_from_slice(&[0; 23]);
+        response.extend_from_slice(user.as_bytes());
+        response.push(0);
+        response.push(0); // empty auth response
+        self.stream.write_all(&mysql_frame(1, &response)).await
+    }
+
+    /// Run a query and read its response
+    pub async fn query(&mut self, sql: &str) -> io::Result<Response> {
+        let mut command = vec![0x03];
+        command.extend_from_slice(sql.as_bytes());
+        self.stream.write_all(&mysql_frame(0, &command)).await?;
+
+        let (_, first) = read_mysql_packet(&mut self.stream).await?;
+        match first.first() {
+            Some(0x00) => return Ok(Response::Ok),
+            Some(0xff) => return Ok(mysql_error_response(&first)),
+            _ => {}
+        }
+
+        let columns = first[0] as usize;
+        for _ in 0..columns {
+            read_mysql_packet(&mut self.stream).await?;
+        }
+        read_mysql_packet(&mut self.stream).await?; // EOF after the columns
+
+        let mut rows = Vec::new();
+        loop {
+            let (_, packet) = read_mysql_packet(&mut self.stream).await?;
+            match packet.first() {
+                Some(0xfe) if packet.len() < 9 => return Ok(Response::Rows(rows)),
+                Some(0xff) => return Ok(mysql_error_response(&packet)),
+                _ => {}
+            }
+            let mut row = Vec::new();
+            let mut rest = &packet[..];
+            while let Some((&len, tail)) = rest.split_first() {
+                row.push(String::from_utf8_lossy(&tail[..len as usize]).into_owned());
+                rest = &tail[len as usize..];
+            }
+            rows.push(row);
+        }
+    }
+
+    /// Whether the proxy has closed the connection
+    pub async fn is_closed(&mut self) -> bool {
+        let mut buf = [0u8; 1];
+        matches!(self.stream.read(&mut buf).await, Ok(0) | Err(_))
+    }
+}
+
+// =============================================================================
+// POSTGRESQL
+// =============================================================================
+
+pub const SSL_REQUEST: u32 = 80877103;
+const PROTOCOL_3: u32 = 196608;
+
+/// Frame a tagged PostgreSQL message
+pub fn pg_message(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut message = vec![tag];
+    message.extend_from_slice(&((body.len() + 4) as u32).to_be_bytes());
+    message.extend_from_slice(body);
+    message
+}
+
+/// Read one tagged PostgreSQL message
+pub async fn read_pg_message<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0u8; 5];
+    reader.read_exact(&mut header).await?;
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    let mut body = vec![0u8; len - 4];
+    reader.read_exact(&mut body).await?;
+    Ok((header[0], body))
+}
+
+fn pg_cstr(out: &mut Vec<u8>, value: &str) {
+    out.extend_from_slice(value.as_bytes());
+    out.push(0);
+}
+
+/// Pull the value of one field out of an ErrorResponse body
+fn pg_error_field(body: &[u8], field: u8) -> String {
+    body.split(|&b| b == 0)
+        .find(|f| f.first() == Some(&field))
+        .map(|f| String::from_utf8_lossy(&f[1..]).into_owned())
+        .unwrap_or_default()
+}
+
+fn pg_error_response(body: &[u8]) -> Response {
+    Response::Error {
+        code: pg_error_field(body, b'C'),
+        message: pg_error_field(body, b'M'),
+    }
+}
+
+impl Session {
+    async fn serve_postgres(self, mut socket: TcpStream) -> io::Result<()> {
+        // Startup packets are untagged; encryption requests get a plain 'N'
+        loop {
+            let mut len = [0u8; 4];
+            socket.read_exact(&mut len).await?;
+            let mut packet = vec![0u8; u32::from_be_bytes(len) as usize - 4];
+            socket.read_exact(&mut packet).await?;
+
+            if u32::from_be_bytes([packet[0], packet[1], packet[2], packet[3]]) == SSL_REQUEST {
+                socket.write_all(b"N").await?;
+                continue;
+            }
+
+            let params: Vec<&[u8]> = packet[4..].split(|&b| b == 0).collect();
+            if let Some(pair) = params.chunks(2).find(|pair| pair[0] == b"user") {
+                self.users.lock().unwrap().push(String::from_utf8_lossy(pair[1]).into_owned());
+            }
+            break;
+        }
+
+        let mut ready = pg_message(b'R', &0u32.to_be_bytes());
+        ready.extend(pg_message(b'Z', b"I"));
+        socket.write_all(&ready).await?;
+
+        loop {
+            let (tag, body) = match read_pg_message(&mut socket).await {
+                Ok(message) => message,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            match tag {
+                b'X' => return Ok(()),
+                b'Q' => {
+                    let end = body.iter().position(|&b| b == 0).unwrap_or(body.len());
+                    let query = String::from_utf8_lossy(&body[..end]).into_owned();
+                    self.queries.lock().unwrap().push(query.clone());
+                    let mut out = pg_reply(&(self.script)(&query));
+                    out.extend(pg_message(b'Z', b"I"));
+                    socket.write_all(&out).await?;
+                }
+                // Anything else is only recorded, by its type
+                _ => self.queries.lock().unwrap().push(format!("<{}>", tag as char)),
+            }
+        }
+    }
+}
+
+/// Encode a reply as the messages that answer a simple Query (before ReadyForQuery)
+fn pg_reply(reply: &Reply) -> Vec<u8> {
+    match reply {
+        Reply::Ok { affected_rows } => {
+            let mut body = Vec::new();
+            pg_cstr(&mut body, &format!("INSERT 0 {}", affected_rows));
+            pg_message(b'C', &body)
+        }
+        Reply::Error { sqlstate, message, .. } => {
+            let mut body = Vec::new();
+            for (field, value) in [(b'S', "ERROR"), (b'C', sqlstate.as_str()), (b'M', message.as_str())] {
+                body.push(field);
+                pg_cstr(&mut body, value);
+            }
+            body.push(0);
+            pg_message(b'E', &body)
+        }
+        Reply::Rows { columns, rows } => {
+            let mut description = (columns.len() as u16).to_be_bytes().to_vec();
+            for column in columns {
+                pg_cstr(&mut description, column);
+                description.extend_from_slice(&0u32.to_be_bytes()); // table oid
+                description.extend_from_slice(&0u16.to_be_bytes()); // column number
+                description.extend_from_slice(&25u32.to_be_bytes()); // text
+                description.extend_from_slice(&(-1i16).to_be_bytes());
+                description.extend_from_slice(&(-1i32).to_be_bytes());
+                description.extend_from_slice(&0u16.to_be_bytes()); // text format
+            }
+            let mut out = pg_message(b'T', &description);
+
+            for row in rows {
+                let mut data = (row.len() as u16).to_be_bytes().to_vec();
+                for value in row {
+                    data.extend_from_slice(&(value.len() as u32).to_be_bytes());
+                    data.extend_from_slice(value.as_bytes());
+                }
+                out.extend(pg_message(b'D', &data));
+            }
+
+            let mut complete = Vec::new();
+            pg_cstr(&mut complete, &format!("SELECT {}", rows.len()));
+            out.extend(pg_message(b'C', &complete));
+            out
+        }
+    }
+}
+
+/// A minimal PostgreSQL client (simple query protocol)
+pub struct PostgresClient<S> {
+    stream: S,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> PostgresClient<S> {
+    /// Wrap a stream without starting up
+    pub fn new(stream: S) -> Self {
+        Self { stream }
+    }
+
+    /// Send an SSLRequest and return the one-byte answer
+    pub async fn request_ssl(&mut self) -> io::Result<u8> {
+        let mut request = 8u32.to_be_bytes().to_vec();
+        request.extend_from_slice(&SSL_REQUEST.to_be_bytes());
+        self.stream.write_all(&request).await?;
+        let mut answer = [0u8; 1];
+        self.stream.read_exact(&mut answer).await?;
+        Ok(answer[0])
+    }
+
+    /// Send the StartupMessage and wait for ReadyForQuery
+    pub async fn startup(&mut self, user: &str) -> io::Result<Result<(), Response>> {
+        let mut params = PROTOCOL_3.to_be_bytes().to_vec();
+        pg_cstr(&mut params, "user");
+        pg_cstr(&mut params, user);
+        pg_cstr(&mut params, "database");
+        pg_cstr(&mut params, "wordpress");
+        params.push(0);
+        let mut packet = ((params.len() + 4) as u32).to_be_bytes().to_vec();
+        packet.extend_from_slice(&params);
+        self.stream.write_all(&packet).await?;
+
+        loop {
+            match read_pg_message(&mut self.stream).await? {
+                (b'Z', _) => return Ok(Ok(())),
+                (b'E', body) => return Ok(Err(pg_error_response(&body))),
+                _ => {}
+            }
+        }
+    }
+
+    /// Run a simple query and read its response up to ReadyForQuery
+    ///
+    /// An error followed by the connection closing (a FATAL) is returned as
+    /// the error, not as an I/O failure.
+    pub async fn query(&mut self, sql: &str) -> io::Result<Response> {
+        self.query_bytes(sql.as_bytes()).await
+    }
+
+    /// [`query`](Self::query) with SQL that need not be valid UTF-8
+    pub async fn query_bytes(&mut self, sql: &[u8]) -> io::Result<Response> {
+        let mut body = sql.to_vec();
+        body.push(0);
+        self.send(b'Q', &body).await
+    }
+
+    /// Send any message and read the response up to ReadyForQuery
+    pub async fn send(&mut self, tag: u8, body: &[u8]) -> io::Result<Response> {
+        self.stream.write_all(&pg_message(tag, body)).await?;
+
+        let mut response = Response::Ok;
+        let mut rows = None;
+        loop {
+            let (tag, body) = match read_pg_message(&mut self.stream).await {
+                Ok(message) => message,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && matches!(response, Response::Error { .. }) => {
+                    return Ok(response);
+                }
+                Err(e) => return Err(e),
+            };
+            match tag {
+                b'T' => rows = Some(Vec::new()),
+                b'D' => {
+                    let mut row = Vec::new();
+                    let mut rest = &body[2..];
+                    while rest.len() >= 4 {
+                        let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+                        row.push(String::from_utf8_lossy(&rest[4..4 + len]).into_owned());
+                        rest = &rest[4 + len..];
+                    }
+                    rows.get_or_insert_with(Vec::new).push(row);
+                }
+                b'C' => {
+                    if let Some(rows) = rows.take() {
+                        response = Response::Rows(rows);
+                    }
+                }
+                b'E' => response = pg_error_response(&body),
+                b'Z' => return Ok(response),
+                _ => {}
+            }
+        }
+    }
+
+    /// Whether the proxy has closed the connection
+    pub async fn is_closed(&mut self) -> bool {
+        let mut buf = [0u8; 1];
+        matches!(self.stream.read(&mut buf).await, Ok(0) | Err(_))
+    }
+}
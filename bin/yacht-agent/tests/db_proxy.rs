@@ -0,0 +1,348 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Database Proxy Integration Tests
+//!
+//! Drives `handle_db_connection` end to end against the mock MySQL and
+//! PostgreSQL servers in `common`: framing, blocking, error packets and
+//! pass-through.
+
+mod common;
+
+use std::sync::Arc;
+
+use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::net::TcpListener;
+
+use common::{MockServer, MysqlClient, PostgresClient, Reply, Response};
+use wharf_core::events::SecurityEvent;
+use yacht_agent::net::Endpoint;
+use yacht_agent::proxy::handle_db_connection;
+use yacht_agent::shadow::{ShadowConfig, ShadowDb};
+use yacht_agent::state::AgentState;
+
+/// Start a proxied session to `shadow`, returning the client's end
+fn proxy_to(shadow: Endpoint, protocol: &str, state: Arc<AgentState>) -> DuplexStream {
+    let (client, proxy_side) = duplex(65536);
+    let shadow = ShadowDb::new(ShadowConfig {
+        endpoint: shadow,
+        ..Default::default()
+    });
+    let protocol = protocol.to_string();
+    tokio::spawn(async move { handle_db_connection(proxy_side, "127.0.0.1:40000", &shadow, &protocol, state).await });
+    client
+}
+
+fn users_table() -> Reply {
+    Reply::rows(&["user_login", "user_email"], &[&["alice", "alice@example.com"], &["bob", "bob@example.com"]])
+}
+
+/// An endpoint nothing is listening on
+async fn dead_endpoint() -> Endpoint {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    Endpoint::Tcp(listener.local_addr().unwrap().to_string())
+}
+
+// =============================================================================
+// MYSQL
+// =============================================================================
+
+#[tokio::test]
+async fn test_mysql_result_set_passes_through() {
+    let server = MockServer::mysql(|_| users_table()).await;
+    let state = Arc::new(AgentState::new());
+    let stream = proxy_to(server.endpoint(), "mysql", state.clone());
+
+    let mut client = MysqlClient::connect(stream, "wordpress").await.unwrap().unwrap();
+    let response = client.query("SELECT user_login, user_email FROM wp_users").await.unwrap();
+
+    assert_eq!(
+        response,
+        Response::Rows(vec![
+            vec!["alice".to_string(), "alice@example.com".to_string()],
+            vec!["bob".to_string(), "bob@example.com".to_string()],
+        ])
+    );
+    assert_eq!(server.users(), vec!["wordpress"]);
+    assert_eq!(state.stats.queries_allowed.get(), 1);
+    assert_eq!(state.stats.inspect_latency.snapshot().count, 1);
+    assert_eq!(state.stats.query_latency.snapshot().count, 1);
+}
+
+#[tokio::test]
+async fn test_mysql_session_runs_several_commands() {
+    let server = MockServer::mysql(|query| {
+        if query.starts_with("INSERT") {
+            Reply::ok(1)
+        } else {
+            users_table()
+        }
+    })
+    .await;
+    let stream = proxy_to(server.endpoint(), "mysql", Arc::new(AgentState::new()));
+
+    let mut client = MysqlClient::connect(stream, "wordpress").await.unwrap().unwrap();
+    let insert = "INSERT INTO wp_comments (comment_content) VALUES ('hi')";
+    assert_eq!(client.query(insert).await.unwrap(), Response::Ok);
+    assert!(matches!(client.query("SELECT * FROM wp_users").await.unwrap(), Response::Rows(rows) if rows.len() == 2));
+    assert_eq!(client.query(insert).await.unwrap(), Response::Ok);
+    assert_eq!(server.queries().len(), 3);
+}
+
+#[tokio::test]
+async fn test_mysql_blocked_query_never_reaches_server() {
+    let server = MockServer::mysql(|_| Reply::ok(1)).await;
+    let state = Arc::new(AgentState::new());
+    let mut events = state.events.subscribe();
+    let stream = proxy_to(server.endpoint(), "mysql", state.clone());
+
+    let mut client = MysqlClient::connect(stream, "wordpress").await.unwrap().unwrap();
+    let response = client
+        .query("INSERT INTO wp_users (user_login) VALUES ('attacker')")
+        .await
+        .unwrap();
+
+    assert_eq!(
+        response,
+        Response::Error {
+            code: "1045".to_string(),
+            message: "Query blocked by Wharf security policy".to_string(),
+        }
+    );
+    assert!(client.is_closed().await);
+    assert!(server.queries().is_empty());
+    assert_eq!(state.stats.queries_blocked.get(), 1);
+
+    let event = events.try_recv().unwrap();
+    assert!(
+        matches!(&event.event, SecurityEvent::QueryBlocked { db_user: Some(user), .. } if user == "wordpress"),
+        "{:?}",
+        event
+    );
+}
+
+#[tokio::test]
+async fn test_mysql_unparseable_query_is_blocked() {
+    let server = MockServer::mysql(|_| Reply::ok(0)).await;
+    let stream = proxy_to(server.endpoint(), "mysql", Arc::new(AgentState::new()));
+
+    let mut client = MysqlClient::connect(stream, "wordpress").await.unwrap().unwrap();
+    let response = client.query("SELEKT * FROM wp_users").await.unwrap();
+
+    assert!(matches!(response, Response::Error { code, .. } if code == "1045"));
+    assert!(server.queries().is_empty());
+}
+
+#[tokio::test]
+async fn test_mysql_server_error_passes_through() {
+    let server = MockServer::mysql(|_| Reply::error(1146, "42S02", "Table 'wordpress.wp_nope' doesn't exist")).await;
+    let stream = proxy_to(server.endpoint(), "mysql", Arc::new(AgentState::new()));
+
+    let mut client = MysqlClient::connect(stream, "wordpress").await.unwrap().unwrap();
+    let response = client.query("SELECT * FROM wp_nope").await.unwrap();
+
+    assert_eq!(
+        response,
+        Response::Error {
+            code: "1146".to_string(),
+            message: "Table 'wordpress.wp_nope' doesn't exist".to_string(),
+        }
+    );
+    // A server-side error does not end the session
+    assert_eq!(client.query("SELECT 1").await.unwrap(), response);
+}
+
+#[tokio::test]
+async fn test_mysql_tls_is_not_offered_or_accepted() {
+    let server = MockServer::mysql(|_| Reply::ok(0)).await;
+    let stream = proxy_to(server.endpoint(), "mysql", Arc::new(AgentState::new()));
+
+    let mut client = MysqlClient::greet(stream).await.unwrap();
+    assert_eq!(common::greeting_capabilities(&client.greeting) & common::CLIENT_SSL, 0);
+
+    // A client that asks for TLS anyway is dropped
+    client
+        .send_handshake("wordpress", common::CLIENT_PROTOCOL_41 | common::CLIENT_SSL)
+        .await
+        .unwrap();
+    assert!(client.is_closed().await);
+}
+
+#[tokio::test]
+async fn test_mysql_packet_size_is_capped_until_login() {
+    let server = MockServer::mysql(|_| Reply::ok(0)).await;
+
+    // Before login a client cannot make the proxy wait for a large packet
+    let mut stream = proxy_to(server.endpoint(), "mysql", Arc::new(AgentState::new()));
+    common::read_mysql_packet(&mut stream).await.unwrap();
+    let _ = stream.write_all(&[0, 0, 0x10, 1]).await; // declares 1MB
+    let _ = stream.write_all(&[0; 1024]).await;
+    let mut buf = [0u8; 1];
+    assert!(matches!(stream.read(&mut buf).await, Ok(0) | Err(_)));
+    assert!(server.users().is_empty());
+
+    // After login the configured limit applies
+    let stream = proxy_to(server.endpoint(), "mysql", Arc::new(AgentState::new()));
+    let mut client = MysqlClient::connect(stream, "wordpress").await.unwrap().unwrap();
+    let query = format!("SELECT ID FROM wp_posts WHERE post_content = '{}'", "x".repeat(100_000));
+    assert!(matches!(client.query(&query).await.unwrap(), Response::Ok));
+}
+
+#[tokio::test]
+async fn test_mysql_compression_is_not_offered_or_accepted() {
+    let server = MockServer::mysql(|_| Reply::ok(0)).await;
+    let offered = common::CLIENT_COMPRESS | common::CLIENT_ZSTD_COMPRESSION;
+
+    for requested in [common::CLIENT_COMPRESS, common::CLIENT_ZSTD_COMPRESSION] {
+        let stream = proxy_to(server.endpoint(), "mysql", Arc::new(AgentState::new()));
+        let mut client = MysqlClient::greet(stream).await.unwrap();
+        assert_eq!(common::greeting_capabilities(&client.greeting) & offered, 0);
+
+        client
+            .send_handshake("wordpress", common::CLIENT_PROTOCOL_41 | requested)
+            .await
+            .unwrap();
+        assert!(client.is_closed().await);
+    }
+    assert!(server.queries().is_empty());
+}
+
+#[tokio::test]
+async fn test_mysql_refused_when_shadow_is_down() {
+    let stream = proxy_to(dead_endpoint().await, "mysql", Arc::new(AgentState::new()));
+
+    let refused = MysqlClient::connect(stream, "wordpress").await.unwrap();
+    assert!(matches!(refused, Err(Response::Error { code, .. }) if code == "1053"));
+}
+
+// =============================================================================
+// POSTGRESQL
+// =============================================================================
+
+#[tokio::test]
+async fn test_postgres_result_set_passes_through() {
+    let server = MockServer::postgres(|_| users_table()).await;
+    let state = Arc::new(AgentState::new());
+    let stream = proxy_to(server.endpoint(), "postgres", state.clone());
+
+    let mut client = PostgresClient::new(stream);
+    // The proxy declines encryption itself
+    assert_eq!(client.request_ssl().await.unwrap(), b'N');
+    client.startup("wordpress").await.unwrap().unwrap();
+
+    let response = client.query("SELECT user_login, user_email FROM wp_users").await.unwrap();
+    assert!(matches!(response, Response::Rows(rows) if rows.len() == 2 && rows[1][0] == "bob"));
+    assert_eq!(server.users(), vec!["wordpress"]);
+    assert_eq!(state.stats.query_latency.snapshot().count, 1);
+}
+
+#[tokio::test]
+async fn test_postgres_blocked_query_never_reaches_server() {
+    let server = MockServer::postgres(|_| Reply::ok(1)).await;
+    let state = Arc::new(AgentState::new());
+    let stream = proxy_to(server.endpoint(), "postgres", state.clone());
+
+    let mut client = PostgresClient::new(stream);
+    client.startup("wordpress").await.unwrap().unwrap();
+    let response = client.query("DROP TABLE wp_comments").await.unwrap();
+
+    assert!(matches!(response, Response::Error { code, .. } if code == "42501"));
+    assert!(client.is_closed().await);
+    assert!(server.queries().is_empty());
+    assert_eq!(state.stats.queries_blocked.get(), 1);
+}
+
+#[tokio::test]
+async fn test_postgres_undecodable_query_is_still_checked() {
+    let server = MockServer::postgres(|_| Reply::ok(1)).await;
+    let state = Arc::new(AgentState::new());
+    let stream = proxy_to(server.endpoint(), "postgres", state.clone());
+
+    let mut client = PostgresClient::new(stream);
+    client.startup("wordpress").await.unwrap().unwrap();
+    let response = client.query_bytes(b"DROP TABLE users; -- \xff").await.unwrap();
+
+    assert!(matches!(response, Response::Error { code, .. } if code == "42501"));
+    assert!(server.queries().is_empty());
+    assert_eq!(state.stats.queries_blocked.get(), 1);
+}
+
+#[tokio::test]
+async fn test_postgres_function_call_is_refused() {
+    let server = MockServer::postgres(|_| Reply::ok(1)).await;
+    let state = Arc::new(AgentState::new());
+    let stream = proxy_to(server.endpoint(), "postgres", state.clone());
+
+    let mut client = PostgresClient::new(stream);
+    client.startup("wordpress").await.unwrap().unwrap();
+    // lo_export(oid, path) called by OID, with no SQL for the policy to see
+    let mut call = 765u32.to_be_bytes().to_vec();
+    call.extend_from_slice(&0u16.to_be_bytes());
+    call.extend_from_slice(&0u16.to_be_bytes());
+    call.extend_from_slice(&0u16.to_be_bytes());
+    let response = client.send(b'F', &call).await.unwrap();
+
+    assert!(matches!(response, Response::Error { code, .. } if code == "42501"));
+    assert!(client.is_closed().await);
+    assert!(server.queries().is_empty());
+    assert_eq!(state.stats.queries_blocked.get(), 1);
+}
+
+#[tokio::test]
+async fn test_postgres_server_error_passes_through() {
+    let server = MockServer::postgres(|query| {
+        if query.contains("wp_nope") {
+            Reply::error(0, "42P01", "relation \"wp_nope\" does not exist")
+        } else {
+            Reply::ok(1)
+        }
+    })
+    .await;
+    let stream = proxy_to(server.endpoint(), "postgres", Arc::new(AgentState::new()));
+
+    let mut client = PostgresClient::new(stream);
+    client.startup("wordpress").await.unwrap().unwrap();
+    let response = client.query("SELECT * FROM wp_nope").await.unwrap();
+
+    assert_eq!(
+        response,
+        Response::Error {
+            code: "42P01".to_string(),
+            message: "relation \"wp_nope\" does not exist".to_string(),
+        }
+    );
+    assert_eq!(
+        client.query("INSERT INTO wp_comments (comment_content) VALUES ('hi')").await.unwrap(),
+        Response::Ok
+    );
+}
+
+#[tokio::test]
+async fn test_postgres_refused_when_shadow_is_down() {
+    let stream = proxy_to(dead_endpoint().await, "postgres", Arc::new(AgentState::new()));
+
+    let mut client = PostgresClient::new(stream);
+    let refused = client.startup("wordpress").await.unwrap();
+    assert!(matches!(refused, Err(Response::Error { code, .. }) if code == "57P03"));
+}
+
+// =============================================================================
+// OTHER PROTOCOLS
+// =============================================================================
+
+#[tokio::test]
+async fn test_unknown_protocol_is_copied_verbatim() {
+    let echo = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = Endpoint::Tcp(echo.local_addr().unwrap().to_string());
+    tokio::spawn(async move {
+        let (mut socket, _) = echo.accept().await.unwrap();
+        let (mut read, mut write) = socket.split();
+        let _ = tokio::io::copy(&mut read, &mut write).await;
+    });
+
+    let mut stream = proxy_to(endpoint, "redis", Arc::new(AgentState::new()));
+    stream.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+    let mut echoed = [0u8; 14];
+    stream.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(&echoed, b"*1\r\n$4\r\nPING\r\n");
+}
@@ -0,0 +1,168 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Event Feed Integration Tests
+//!
+//! The `/events` server-sent-events stream as `wharf fleet watch` sees it:
+//! events published by the agent arrive decoded, and the stream ends when
+//! the agent shuts down.
+
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+
+use wharf_core::crypto::{generate_signing_key, SigningKey};
+use wharf_core::events::{EventRecord, EventStreamDecoder, SecurityEvent};
+use wharf_core::mooring::{CommandEnvelope, MooringCommand, TrustStore};
+use wharf_core::policy::PolicyBundle;
+use yacht_agent::api::{self, ApiState};
+use yacht_agent::ebpf::ShieldMonitor;
+use yacht_agent::lifecycle::Shutdown;
+use yacht_agent::health::HealthRegistry;
+use yacht_agent::metrics::Registry;
+use yacht_agent::moor::MooringService;
+use yacht_agent::policy::PolicyStore;
+use yacht_agent::shadow::{ShadowConfig, ShadowDb};
+use yacht_agent::state::AgentState;
+
+struct Agent {
+    url: String,
+    state: Arc<AgentState>,
+    key: SigningKey,
+    shutdown: Shutdown,
+}
+
+impl Agent {
+    async fn start() -> Self {
+        let key = generate_signing_key();
+        let mut trust = TrustStore::new();
+        trust.add("wharf", key.verifying_key());
+
+        let state = Arc::new(AgentState::new());
+        let shield = Arc::new(ShieldMonitor::new("none", None));
+        let policies = Arc::new(PolicyStore::open(state.clone(), 5, None).unwrap());
+        let mooring = MooringService::new(
+            "yacht-01",
+            trust,
+            state.clone(),
+            policies.clone(),
+            shield.clone(),
+            "/nonexistent".into(),
+        );
+        let api_state = Arc::new(ApiState {
+            agent: state.clone(),
+            shadow: Arc::new(ShadowDb::new(ShadowConfig::default())),
+            shield,
+            policies,
+            mooring: Some(mooring),
+            registry: Registry::new(),
+            health: Arc::new(HealthRegistry::new()),
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let shutdown = Shutdown::new();
+        tokio::spawn(api::serve(
+            listener,
+            api::router(api_state, false, None),
+            None,
+            shutdown.signal(),
+            Duration::from_secs(5),
+        ));
+
+        Self {
+            url,
+            state,
+            key,
+            shutdown,
+        }
+    }
+
+    async fn subscribe(&self) -> Feed {
+        let response = reqwest::get(format!("{}/events", self.url)).await.unwrap();
+        assert_eq!(
+            response.headers()["content-type"].to_str().unwrap(),
+            "text/event-stream"
+        );
+        Feed {
+            response,
+            decoder: EventStreamDecoder::new(),
+            pending: Vec::new(),
+        }
+    }
+}
+
+struct Feed {
+    response: reqwest::Response,
+    decoder: EventStreamDecoder,
+    pending: Vec<EventRecord>,
+}
+
+impl Feed {
+    /// The next record, or `None` once the stream has ended
+    async fn next(&mut self) -> Option<EventRecord> {
+        loop {
+            if !self.pending.is_empty() {
+                return Some(self.pending.remove(0));
+            }
+            let chunk = tokio::time::timeout(Duration::from_secs(5), self.response.chunk())
+                .await
+                .expect("no event within 5s")
+                .unwrap()?;
+            self.pending = self.decoder.feed(&chunk);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_events_reach_subscribers() {
+    let agent = Agent::start().await;
+    let mut feed = agent.subscribe().await;
+
+    agent.state.events.publish(SecurityEvent::ShieldUnblock {
+        ip: Ipv4Addr::new(192, 0, 2, 7),
+    });
+    let record = feed.next().await.unwrap();
+    assert_eq!(
+        record.event,
+        SecurityEvent::ShieldUnblock {
+            ip: Ipv4Addr::new(192, 0, 2, 7)
+        }
+    );
+
+    // A pushed policy shows up as a policy change
+    let mut bundle = PolicyBundle::default();
+    bundle.header.allowed_hosts.push("example.org".to_string());
+    let version = bundle.version();
+    let envelope = CommandEnvelope::new("yacht-01", MooringCommand::PushPolicy { bundle: Box::new(bundle), grace_secs: None }, 60)
+        .sign("wharf", &agent.key)
+        .unwrap();
+    let response = reqwest::Client::new()
+        .post(format!("{}/moor", agent.url))
+        .json(&envelope)
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let record = feed.next().await.unwrap();
+    assert_eq!(record.seq, 1);
+    match record.event {
+        SecurityEvent::PolicyChange { version: new, source, .. } => {
+            assert_eq!(new, version);
+            assert_eq!(source, "push by wharf");
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_feed_ends_on_shutdown() {
+    let agent = Agent::start().await;
+    let mut feed = agent.subscribe().await;
+
+    agent.shutdown.trigger();
+    assert!(feed.next().await.is_none());
+}
@@ -0,0 +1,83 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Health Endpoint Integration Tests
+//!
+//! What load balancers see on `/health`, `/health/ready` and `/health/live`
+//! as components degrade and fail.
+
+use std::sync::Arc;
+
+use reqwest::StatusCode;
+use tokio::net::TcpListener;
+
+use yacht_agent::api::{self, ApiState};
+use yacht_agent::ebpf::ShieldMonitor;
+use yacht_agent::health::{ComponentHealth, HealthRegistry, IntegrityCheck};
+use yacht_agent::metrics::Registry;
+use yacht_agent::policy::PolicyStore;
+use yacht_agent::shadow::{ShadowConfig, ShadowDb};
+use yacht_agent::state::{AgentState, IntegrityStatus};
+
+/// Serve the API with `health`; returns the agent URL
+async fn agent(state: Arc<AgentState>, health: Arc<HealthRegistry>) -> String {
+    let api_state = Arc::new(ApiState {
+        agent: state.clone(),
+        shadow: Arc::new(ShadowDb::new(ShadowConfig::default())),
+        shield: Arc::new(ShieldMonitor::new("none", None)),
+        policies: Arc::new(PolicyStore::open(state, 5, None).unwrap()),
+        mooring: None,
+        registry: Registry::new(),
+        health,
+    });
+
+    let app = api::router(api_state, false, None);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    url
+}
+
+async fn status(url: &str, path: &str) -> StatusCode {
+    reqwest::get(format!("{}{}", url, path)).await.unwrap().status()
+}
+
+#[tokio::test]
+async fn test_degraded_stays_in_rotation() {
+    let state = Arc::new(AgentState::new());
+    let mut health = HealthRegistry::new();
+    health.register("shield", Arc::new(ShieldMonitor::new("none", None)));
+    let url = agent(state, Arc::new(health)).await;
+
+    let response = reqwest::get(format!("{}/health", url)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["components"]["shield"]["reason"], "firewall disabled");
+
+    assert_eq!(status(&url, "/health/ready").await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_failed_component_takes_agent_out_of_rotation() {
+    let state = Arc::new(AgentState::new());
+    let mut health = HealthRegistry::new();
+    health.register("integrity", Arc::new(IntegrityCheck(state.clone())));
+    let health = Arc::new(health);
+    health.report("db_proxy 127.0.0.1:3306", ComponentHealth::healthy());
+    let url = agent(state.clone(), health.clone()).await;
+
+    assert_eq!(status(&url, "/health").await, StatusCode::OK);
+
+    state.set_integrity_status(IntegrityStatus::Failed);
+    assert_eq!(status(&url, "/health").await, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(status(&url, "/health/ready").await, StatusCode::SERVICE_UNAVAILABLE);
+    // Still alive: restarting would not fix the web root
+    assert_eq!(status(&url, "/health/live").await, StatusCode::OK);
+
+    state.set_integrity_status(IntegrityStatus::Verified);
+    health.report("db_proxy 127.0.0.1:3306", ComponentHealth::failed("stopped"));
+    let body: serde_json::Value = reqwest::get(format!("{}/health", url)).await.unwrap().json().await.unwrap();
+    assert_eq!(body["status"], "failed");
+    assert_eq!(body["components"]["db_proxy 127.0.0.1:3306"]["reason"], "stopped");
+}
@@ -0,0 +1,115 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Remote Verification Integration Tests
+//!
+//! `integrity::verify_remote_api` on the Wharf side against the agent's
+//! `/verify` route.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio::net::TcpListener;
+
+use wharf_core::crypto::{generate_signing_key, SigningKey};
+use wharf_core::integrity::{generate_manifest, verify_remote_api};
+use wharf_core::mooring::TrustStore;
+use yacht_agent::api::{self, ApiState};
+use yacht_agent::ebpf::ShieldMonitor;
+use yacht_agent::health::HealthRegistry;
+use yacht_agent::metrics::Registry;
+use yacht_agent::moor::MooringService;
+use yacht_agent::policy::PolicyStore;
+use yacht_agent::shadow::{ShadowConfig, ShadowDb};
+use yacht_agent::state::{AgentState, IntegrityStatus};
+
+/// Serve `/verify` for `web_root`, trusting `captain`; returns the agent URL
+async fn agent(web_root: &Path, captain: &SigningKey, state: Arc<AgentState>) -> String {
+    let mut trust = TrustStore::new();
+    trust.add("wharf", captain.verifying_key());
+    let shield = Arc::new(ShieldMonitor::new("none", None));
+    let policies = Arc::new(PolicyStore::open(state.clone(), 5, None).unwrap());
+    let mooring = MooringService::new(
+        "yacht-01",
+        trust,
+        state.clone(),
+        policies.clone(),
+        shield.clone(),
+        web_root.to_path_buf(),
+    );
+    let api_state = Arc::new(ApiState {
+        agent: state,
+        shadow: Arc::new(ShadowDb::new(ShadowConfig::default())),
+        shield,
+        policies,
+        mooring: Some(mooring),
+        registry: Registry::new(),
+        health: Arc::new(HealthRegistry::new()),
+    });
+
+    let app = api::router(api_state, false, None);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    url
+}
+
+fn client() -> reqwest::Client {
+    reqwest::Client::new()
+}
+
+#[tokio::test]
+async fn test_remote_verification_over_api() {
+    let site = tempfile::tempdir().unwrap();
+    std::fs::write(site.path().join("index.php"), "<?php echo 'hi';").unwrap();
+    std::fs::write(site.path().join("wp-config.php"), "<?php define('DB_NAME', 'wp');").unwrap();
+    let manifest = generate_manifest(site.path(), &[]).unwrap();
+
+    let key = generate_signing_key();
+    let state = Arc::new(AgentState::new());
+    let url = agent(site.path(), &key, state.clone()).await;
+
+    let result = verify_remote_api(&client(), &url, "yacht-01", &manifest, "wharf", &key)
+        .await
+        .unwrap();
+    assert!(result.is_ok(), "{:?}", result);
+    assert_eq!(result.files_checked, 2);
+    assert_eq!(state.integrity_status(), IntegrityStatus::Verified);
+
+    std::fs::write(site.path().join("index.php"), "<?php eval($_GET['x']);").unwrap();
+    std::fs::write(site.path().join("shell.php"), "<?php system($_GET['c']);").unwrap();
+    let result = verify_remote_api(&client(), &url, "yacht-01", &manifest, "wharf", &key)
+        .await
+        .unwrap();
+    assert!(!result.is_ok());
+    assert_eq!(result.mismatched, vec!["index.php"]);
+    assert_eq!(result.unexpected, vec!["shell.php"]);
+    assert_eq!(state.integrity_status(), IntegrityStatus::Failed);
+}
+
+#[tokio::test]
+async fn test_untrusted_captain_is_refused() {
+    let site = tempfile::tempdir().unwrap();
+    let manifest = generate_manifest(site.path(), &[]).unwrap();
+    let url = agent(site.path(), &generate_signing_key(), Arc::new(AgentState::new())).await;
+
+    let result = verify_remote_api(&client(), &url, "yacht-01", &manifest, "wharf", &generate_signing_key())
+        .await
+        .unwrap();
+    assert!(!result.is_ok());
+    let error = result.error.unwrap();
+    assert!(error.contains("401") && error.contains("Signature verification failed"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_unreachable_agent_is_reported() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+
+    let manifest = wharf_core::integrity::Manifest::default();
+    let result = verify_remote_api(&client(), &url, "yacht-01", &manifest, "wharf", &generate_signing_key())
+        .await
+        .unwrap();
+    assert!(result.error.unwrap().starts_with("Failed to reach agent"));
+}
@@ -0,0 +1,523 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Agent Configuration
+//!
+//! The yacht agent's configuration file: listeners, protocol, shadow
+//! database, firewall, policies, integrity root, audit log and API access
+//! in one place. The file is TOML, or JSON as exported from Nickel (`.ncl`
+//! files are exported on the fly):
+//!
+//! ```toml
+//! yacht_id = "production"
+//! protocol = "mysql"
+//!
+//! [listen]
+//! port = 3306
+//! socket = "/run/mysqld/mysqld.sock"
+//!
+//! [shadow]
+//! port = 33060
+//!
+//! [policy]
+//! database = "policies/database.json"   # a file, or an inline table
+//! history = "/var/lib/wharf/policy-history.json"
+//!
+//! [airlock]
+//! port = 8080
+//! backend = "http://127.0.0.1:8081"
+//!
+//! [api]
+//! host = "10.42.0.10"
+//! tls_cert = "/etc/wharf/api.pem"
+//! tls_key = "/etc/wharf/api.key"
+//! clients = "/etc/wharf/api-clients.toml"
+//! trust_store = "/etc/wharf/trust.toml"
+//! nonces = "/var/lib/wharf/nonces.json"
+//! ```
+//!
+//! Every setting has a default, so an empty file is a valid configuration.
+//! Relative paths are taken relative to the file. The Wharf can write a
+//! configuration for a yacht from the fleet (`AgentConfig::for_yacht`).
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::db_policy::DatabasePolicy;
+use crate::fleet::Yacht;
+use crate::policy::{load_policy_file, BundleError};
+use crate::types::{HeaderPolicy, PathPolicy};
+
+/// Complete yacht agent configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentConfig {
+    /// This yacht's name in the fleet; mooring commands must be addressed to it
+    pub yacht_id: Option<String>,
+    /// Database protocol to masquerade as (mysql, postgres, redis)
+    pub protocol: String,
+    /// How long open sessions may take to finish on shutdown (seconds)
+    pub drain_timeout_secs: u64,
+    pub listen: ListenConfig,
+    pub shadow: ShadowDbConfig,
+    pub firewall: FirewallConfig,
+    pub policy: PolicyConfig,
+    pub integrity: IntegrityConfig,
+    pub audit: AuditConfig,
+    pub api: ApiConfig,
+    pub airlock: AirlockConfig,
+    pub supervisor: SupervisorConfig,
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        Self {
+            yacht_id: None,
+            protocol: "mysql".to_string(),
+            drain_timeout_secs: 30,
+            listen: ListenConfig::default(),
+            shadow: ShadowDbConfig::default(),
+            firewall: FirewallConfig::default(),
+            policy: PolicyConfig::default(),
+            integrity: IntegrityConfig::default(),
+            audit: AuditConfig::default(),
+            api: ApiConfig::default(),
+            airlock: AirlockConfig::default(),
+            supervisor: SupervisorConfig::default(),
+        }
+    }
+}
+
+/// The masquerade listeners
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenConfig {
+    pub host: String,
+    pub port: u16,
+    /// Also listen on this Unix socket
+    pub socket: Option<PathBuf>,
+    /// Only listen on the Unix socket
+    pub unix_only: bool,
+    /// Octal file mode of the socket
+    pub socket_mode: String,
+    pub socket_owner: Option<String>,
+    pub socket_group: Option<String>,
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 3306,
+            socket: None,
+            unix_only: false,
+            socket_mode: "660".to_string(),
+            socket_owner: None,
+            socket_group: None,
+        }
+    }
+}
+
+/// Where the real database is and how hard to lean on it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShadowDbConfig {
+    pub host: String,
+    pub port: u16,
+    /// Reach the database via this Unix socket instead of host:port
+    pub socket: Option<PathBuf>,
+    pub max_connections: usize,
+    pub connect_timeout_ms: u64,
+    pub probe_interval_secs: u64,
+    /// Consecutive failures before the circuit breaker opens
+    pub failure_threshold: u32,
+    pub breaker_open_secs: u64,
+    /// Largest message relayed once a client has authenticated (bytes)
+    pub max_packet_bytes: usize,
+}
+
+impl Default for ShadowDbConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 33060,
+            socket: None,
+            max_connections: 512,
+            connect_timeout_ms: 3000,
+            probe_interval_secs: 5,
+            failure_threshold: 5,
+            breaker_open_secs: 30,
+            max_packet_bytes: 64 << 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FirewallConfig {
+    /// ebpf, nftables or none
+    pub mode: String,
+    /// Interface the XDP shield attaches to
+    pub interface: String,
+}
+
+impl Default for FirewallConfig {
+    fn default() -> Self {
+        Self {
+            mode: "nftables".to_string(),
+            interface: "eth0".to_string(),
+        }
+    }
+}
+
+/// A policy given inline or as a path to a policy file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PolicyRef<T> {
+    File(PathBuf),
+    Inline(T),
+}
+
+impl<T: DeserializeOwned + Clone> PolicyRef<T> {
+    /// The policy itself, reading the file if there is one
+    pub fn load(&self) -> Result<T, BundleError> {
+        match self {
+            Self::File(path) => load_policy_file(path),
+            Self::Inline(policy) => Ok(policy.clone()),
+        }
+    }
+}
+
+/// Policies and how pushed versions are kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicyConfig {
+    pub database: Option<PolicyRef<DatabasePolicy>>,
+    pub header: Option<PolicyRef<HeaderPolicy>>,
+    /// Keep the policy history here so pushed policies survive a restart
+    pub history: Option<PathBuf>,
+    /// Versions kept for rollback
+    pub versions: usize,
+    /// URL probed after a push; failures roll the push back
+    pub probe_url: Option<String>,
+    /// How long a pushed policy stays on probation (seconds)
+    pub grace_secs: u64,
+}
+
+impl Default for PolicyConfig {
+    fn default() -> Self {
+        Self {
+            database: None,
+            header: None,
+            history: None,
+            versions: 5,
+            probe_url: None,
+            grace_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IntegrityConfig {
+    /// The web root checked by integrity commands
+    pub web_root: PathBuf,
+}
+
+impl Default for IntegrityConfig {
+    fn default() -> Self {
+        Self {
+            web_root: PathBuf::from("/var/www/html"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// Hash-chained audit log (JSON Lines)
+    pub log: PathBuf,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            log: PathBuf::from("/var/log/wharf/audit.log"),
+        }
+    }
+}
+
+/// The HTTP airlock in front of the web backend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AirlockConfig {
+    pub host: String,
+    pub port: u16,
+    /// The web backend (e.g. `http://127.0.0.1:8081`); no backend, no airlock
+    pub backend: Option<String>,
+    /// Longest request line accepted, in bytes (414 beyond)
+    pub max_request_line: usize,
+    /// Largest request head accepted, in bytes (431 beyond)
+    pub max_head_size: usize,
+}
+
+impl Default for AirlockConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            backend: None,
+            max_request_line: 8 * 1024,
+            max_head_size: 64 * 1024,
+        }
+    }
+}
+
+/// The agent API and who may use it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    pub host: IpAddr,
+    pub port: u16,
+    /// Serve over TLS with this certificate (PEM); needs `tls_key`
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    /// Client certificate allowlist and roles; needs TLS
+    pub clients: Option<PathBuf>,
+    /// Captain keys allowed to send mooring commands
+    pub trust_store: Option<PathBuf>,
+    /// Keep spent mooring nonces here so envelopes cannot be replayed after a restart
+    pub nonces: Option<PathBuf>,
+    /// Serve Prometheus metrics on `/metrics`
+    pub metrics: bool,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            host: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 9001,
+            tls_cert: None,
+            tls_key: None,
+            clients: None,
+            trust_store: None,
+            nonces: None,
+            metrics: true,
+        }
+    }
+}
+
+/// What the agent does when an enforcement task cannot be kept running
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailAction {
+    /// Leave the task stopped (its listener closed) and keep the agent up
+    Stop,
+    /// As `stop`, and the airlock answers every request with a maintenance page
+    Maintenance,
+    /// Shut the agent down and exit non-zero
+    Exit,
+}
+
+impl FailAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Stop => "stop",
+            Self::Maintenance => "maintenance",
+            Self::Exit => "exit",
+        }
+    }
+}
+
+impl fmt::Display for FailAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for FailAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stop" => Ok(Self::Stop),
+            "maintenance" => Ok(Self::Maintenance),
+            "exit" => Ok(Self::Exit),
+            _ => Err(format!("'{}' is not a fail-closed action (stop, maintenance, exit)", s)),
+        }
+    }
+}
+
+/// How enforcement tasks are restarted, and what happens when they cannot be
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SupervisorConfig {
+    pub on_failure: FailAction,
+    /// Restarts allowed within `restart_window_secs` before failing closed
+    pub max_restarts: u32,
+    pub restart_window_secs: u64,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            on_failure: FailAction::Exit,
+            max_restarts: 5,
+            restart_window_secs: 60,
+        }
+    }
+}
+
+impl AgentConfig {
+    /// Load a configuration file, resolving relative paths against its directory
+    pub fn load(path: &Path) -> Result<Self, BundleError> {
+        let mut config: Self = load_policy_file(path)?;
+        if let Some(base) = path.parent() {
+            config.resolve_paths(base);
+        }
+        Ok(config)
+    }
+
+    /// The configuration the Wharf would give a yacht from the fleet
+    ///
+    /// The yacht's `PolicyConfig` decides the database policy, whether the
+    /// firewall runs and whether security headers are forced.
+    pub fn for_yacht(yacht: &Yacht) -> Self {
+        Self::for_yacht_with_paths(yacht, PathPolicy::default())
+    }
+
+    /// As [`for_yacht`](Self::for_yacht), starting from the path rules of
+    /// the yacht's adapter (`path_rules::from_adapter`); the yacht's own
+    /// `paths` overrides are applied on top.
+    pub fn for_yacht_with_paths(yacht: &Yacht, paths: PathPolicy) -> Self {
+        let mut config = Self {
+            yacht_id: Some(yacht.name.clone()),
+            protocol: match yacht.database.variant.as_str() {
+                "postgres" | "postgresql" => "postgres",
+                "redis" => "redis",
+                _ => "mysql",
+            }
+            .to_string(),
+            ..Default::default()
+        };
+        config.listen.port = yacht.database.public_port;
+        config.shadow.port = yacht.database.shadow_port;
+        config.integrity.web_root = PathBuf::from(&yacht.web_root);
+        config.api.port = yacht.api_port;
+
+        if !yacht.policy.enable_firewall {
+            config.firewall.mode = "none".to_string();
+        }
+        config.policy.database = Some(PolicyRef::Inline(yacht.policy.database.clone()));
+        let mut header = HeaderPolicy::default();
+        if !yacht.policy.strict_headers {
+            header.forced_headers.clear();
+            header.egress.force.clear();
+        }
+        if !yacht.domain.is_empty() {
+            header.allowed_hosts = vec![yacht.domain.clone()];
+        }
+        header.paths = yacht.paths.apply(paths);
+        config.policy.header = Some(PolicyRef::Inline(header));
+        config
+    }
+
+    fn resolve_paths(&mut self, base: &Path) {
+        let resolve = |path: &mut PathBuf| {
+            if path.is_relative() {
+                *path = base.join(&*path);
+            }
+        };
+
+        for path in [
+            self.listen.socket.as_mut(),
+            self.shadow.socket.as_mut(),
+            self.policy.history.as_mut(),
+            self.api.tls_cert.as_mut(),
+            self.api.tls_key.as_mut(),
+            self.api.clients.as_mut(),
+            self.api.trust_store.as_mut(),
+            self.api.nonces.as_mut(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            resolve(path);
+        }
+        resolve(&mut self.integrity.web_root);
+        resolve(&mut self.audit.log);
+        if let Some(PolicyRef::File(path)) = &mut self.policy.database {
+            resolve(path);
+        }
+        if let Some(PolicyRef::File(path)) = &mut self.policy.header {
+            resolve(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_file_is_all_defaults() {
+        let config: AgentConfig = toml::from_str("").unwrap();
+        assert_eq!(config.protocol, "mysql");
+        assert_eq!(config.listen.port, 3306);
+        assert_eq!(config.api.host, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert!(config.policy.database.is_none());
+    }
+
+    #[test]
+    fn test_typos_are_refused() {
+        assert!(toml::from_str::<AgentConfig>("[listen]\nprot = 3307\n").is_err());
+        assert!(toml::from_str::<AgentConfig>("[supervisor]\non_failure = \"ignore\"\n").is_err());
+    }
+
+    #[test]
+    fn test_policies_inline_or_by_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agent.toml");
+        std::fs::write(
+            &path,
+            "[policy]\ndatabase = \"database.json\"\n\n[policy.header]\nblocked_headers = []\n\
+             forced_headers = {}\nmax_header_length = 4096\nallowed_hosts = [\"example.org\"]\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("database.json"),
+            serde_json::to_string(&DatabasePolicy::default()).unwrap(),
+        )
+        .unwrap();
+
+        let config = AgentConfig::load(&path).unwrap();
+        let Some(PolicyRef::File(database)) = &config.policy.database else {
+            panic!("database policy should be a file");
+        };
+        assert_eq!(database, &dir.path().join("database.json"));
+        assert!(config.policy.database.as_ref().unwrap().load().is_ok());
+        assert_eq!(config.policy.header.unwrap().load().unwrap().max_header_length, 4096);
+        assert_eq!(config.audit.log, PathBuf::from("/var/log/wharf/audit.log"));
+    }
+
+    #[test]
+    fn test_generated_config_round_trips() {
+        let mut yacht = Yacht::new("production", "10.0.1.10", "example.com");
+        yacht.policy.enable_firewall = false;
+        yacht.paths.block_xmlrpc = true;
+        let config = AgentConfig::for_yacht(&yacht);
+        assert_eq!(config.firewall.mode, "none");
+
+        let text = toml::to_string(&config).unwrap();
+        let parsed: AgentConfig = toml::from_str(&text).unwrap();
+        assert_eq!(parsed.yacht_id.as_deref(), Some("production"));
+        let header = parsed.policy.header.unwrap().load().unwrap();
+        assert_eq!(header.allowed_hosts, vec!["example.com".to_string()]);
+        assert_eq!(header.paths.deny, vec!["/xmlrpc.php".to_string()]);
+    }
+}
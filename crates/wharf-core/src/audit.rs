@@ -0,0 +1,309 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Security Audit Log
+//!
+//! The yacht agent writes every security decision worth keeping (audited
+//! and blocked queries, result sets cut off by a limit) to an append-only
+//! JSON Lines file. Each record carries the BLAKE3 hash of the record
+//! before it, so editing, reordering or deleting a record breaks the chain
+//! from that point on.
+//!
+//! The chain alone cannot show that records were cut off the end. The
+//! Wharf therefore remembers the head it last verified and checks that the
+//! next fetch still contains it.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::crypto::hash_blake3;
+
+/// The `prev_hash` of the first record in a chain
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Error, Debug)]
+pub enum AuditError {
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("line {line}: not an audit record: {message}")]
+    ParseError { line: usize, message: String },
+
+    #[error("record {seq}: expected sequence number {expected}")]
+    SequenceGap { seq: u64, expected: u64 },
+
+    #[error("record {seq}: previous hash does not match the record before it")]
+    BrokenChain { seq: u64 },
+
+    #[error("record {seq}: contents do not match its hash")]
+    HashMismatch { seq: u64 },
+
+    #[error("record {seq} no longer has hash {expected} (log rewritten or truncated)")]
+    PinMismatch { seq: u64, expected: String },
+}
+
+/// The decision being recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditDecision {
+    /// Query allowed but flagged for audit
+    Audit,
+    /// Query blocked
+    Block,
+    /// Result set crossed its limit
+    ResultLimit,
+}
+
+/// A security decision, before it is chained
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    /// RFC 3339 timestamp (UTC)
+    pub timestamp: String,
+    /// Client address (or Unix socket and uid)
+    pub client: String,
+    /// Database user the client authenticated as, if seen
+    pub db_user: Option<String>,
+    pub decision: AuditDecision,
+    /// The full normalized query
+    pub query: String,
+    /// The policy rule that made the decision
+    pub rule: String,
+}
+
+impl AuditEvent {
+    /// Create an event stamped with the current time
+    pub fn now(
+        client: &str,
+        db_user: Option<&str>,
+        decision: AuditDecision,
+        query: &str,
+        rule: &str,
+    ) -> Self {
+        Self {
+            timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            client: client.to_string(),
+            db_user: db_user.map(str::to_string),
+            decision,
+            query: query.to_string(),
+            rule: rule.to_string(),
+        }
+    }
+}
+
+/// A chained audit record, one line of the log file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+    /// Hash of the previous record (`GENESIS_HASH` for the first)
+    pub prev_hash: String,
+    /// BLAKE3 hash of this record without this field
+    pub hash: String,
+}
+
+/// The hashed part of a record, in a fixed field order
+#[derive(Serialize)]
+struct Unsealed<'a> {
+    seq: u64,
+    #[serde(flatten)]
+    event: &'a AuditEvent,
+    prev_hash: &'a str,
+}
+
+impl AuditRecord {
+    /// Chain an event onto `prev_hash`
+    pub fn seal(seq: u64, event: AuditEvent, prev_hash: &str) -> Self {
+        let hash = record_hash(seq, &event, prev_hash);
+        Self {
+            seq,
+            event,
+            prev_hash: prev_hash.to_string(),
+            hash,
+        }
+    }
+
+    /// Recompute the hash from the record's contents
+    pub fn compute_hash(&self) -> String {
+        record_hash(self.seq, &self.event, &self.prev_hash)
+    }
+}
+
+fn record_hash(seq: u64, event: &AuditEvent, prev_hash: &str) -> String {
+    let unsealed = Unsealed { seq, event, prev_hash };
+    // Serializing plain strings and numbers cannot fail
+    let bytes = serde_json::to_vec(&unsealed).unwrap_or_default();
+    hash_blake3(&bytes)
+}
+
+/// The tip of a chain: where the next record goes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainHead {
+    /// Sequence number of the next record
+    pub next_seq: u64,
+    /// Hash of the last record
+    pub hash: String,
+}
+
+impl ChainHead {
+    /// The head of an empty chain
+    pub fn genesis() -> Self {
+        Self {
+            next_seq: 0,
+            hash: GENESIS_HASH.to_string(),
+        }
+    }
+
+    /// Seal an event as the next record and advance the head
+    pub fn append(&mut self, event: AuditEvent) -> AuditRecord {
+        let record = AuditRecord::seal(self.next_seq, event, &self.hash);
+        self.next_seq += 1;
+        self.hash = record.hash.clone();
+        record
+    }
+}
+
+/// A record the verifier expects to find unchanged
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainPin {
+    pub seq: u64,
+    pub hash: String,
+}
+
+/// The result of verifying a chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainSummary {
+    /// Number of records verified
+    pub records: u64,
+    /// The head after the last record
+    pub head: ChainHead,
+}
+
+/// Verify a whole audit log, from the genesis record on
+///
+/// A trailing line without a newline is a record still being written and
+/// is ignored. If `pin` is given, that record must still be present with
+/// the same hash.
+pub fn verify_chain(content: &str, pin: Option<&ChainPin>) -> Result<ChainSummary, AuditError> {
+    let complete = match content.rfind('\n') {
+        Some(end) => &content[..=end],
+        None => "",
+    };
+
+    let mut head = ChainHead::genesis();
+    let mut pin_seen = false;
+
+    for (index, line) in complete.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record: AuditRecord = serde_json::from_str(line).map_err(|e| AuditError::ParseError {
+            line: index + 1,
+            message: e.to_string(),
+        })?;
+
+        if record.seq != head.next_seq {
+            return Err(AuditError::SequenceGap {
+                seq: record.seq,
+                expected: head.next_seq,
+            });
+        }
+        if record.prev_hash != head.hash {
+            return Err(AuditError::BrokenChain { seq: record.seq });
+        }
+        if record.compute_hash() != record.hash {
+            return Err(AuditError::HashMismatch { seq: record.seq });
+        }
+
+        if let Some(pin) = pin {
+            if pin.seq == record.seq {
+                if pin.hash != record.hash {
+                    return Err(AuditError::PinMismatch {
+                        seq: pin.seq,
+                        expected: pin.hash.clone(),
+                    });
+                }
+                pin_seen = true;
+            }
+        }
+
+        head.next_seq += 1;
+        head.hash = record.hash;
+    }
+
+    if let Some(pin) = pin {
+        if !pin_seen {
+            return Err(AuditError::PinMismatch {
+                seq: pin.seq,
+                expected: pin.hash.clone(),
+            });
+        }
+    }
+
+    Ok(ChainSummary {
+        records: head.next_seq,
+        head,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_log(n: usize) -> String {
+        let mut head = ChainHead::genesis();
+        let mut log = String::new();
+        for i in 0..n {
+            let event = AuditEvent::now(
+                "127.0.0.1:50000",
+                Some("wordpress"),
+                AuditDecision::Block,
+                &format!("DROP TABLE t{}", i),
+                "blocked_operation:DROP",
+            );
+            log.push_str(&serde_json::to_string(&head.append(event)).unwrap());
+            log.push('\n');
+        }
+        log
+    }
+
+    #[test]
+    fn test_valid_chain_verifies() {
+        let log = sample_log(5);
+        let summary = verify_chain(&log, None).unwrap();
+        assert_eq!(summary.records, 5);
+
+        // A half-written last line is ignored
+        let partial = format!("{}{{\"seq\":5", log);
+        assert_eq!(verify_chain(&partial, None).unwrap(), summary);
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let log = sample_log(3);
+
+        let edited = log.replacen("DROP TABLE t1", "SELECT 1", 1);
+        assert!(matches!(verify_chain(&edited, None), Err(AuditError::HashMismatch { seq: 1 })));
+
+        let lines: Vec<&str> = log.lines().collect();
+        let deleted = format!("{}\n{}\n", lines[0], lines[2]);
+        assert!(matches!(verify_chain(&deleted, None), Err(AuditError::SequenceGap { seq: 2, .. })));
+    }
+
+    #[test]
+    fn test_pin_detects_truncation() {
+        let log = sample_log(4);
+        let summary = verify_chain(&log, None).unwrap();
+        let pin = ChainPin {
+            seq: summary.records - 1,
+            hash: summary.head.hash.clone(),
+        };
+        assert!(verify_chain(&log, Some(&pin)).is_ok());
+
+        // Drop the last record: the chain is still valid but the pin is gone
+        let truncated: String = log.lines().take(3).map(|l| format!("{}\n", l)).collect();
+        assert!(verify_chain(&truncated, None).is_ok());
+        assert!(matches!(verify_chain(&truncated, Some(&pin)), Err(AuditError::PinMismatch { .. })));
+    }
+}
@@ -0,0 +1,317 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Set-Cookie Hardening
+//!
+//! Turns the `cookies` block of the header policy ([`CookiePolicy`]) into
+//! rules the airlock applies to every `Set-Cookie` the application sends:
+//! missing `Secure` and `HttpOnly` are added and `SameSite` is set, per
+//! cookie name pattern.
+//!
+//! A rule with `host_prefix` renames its cookies to `__Host-<name>` on the
+//! way out, which browsers only accept with `Secure`, `Path=/` and no
+//! `Domain`. On the way in ([`CookieRules::unprefix`]) the prefix is taken
+//! off again, and an unprefixed cookie of that name - one a subdomain or a
+//! plain HTTP response could have planted - is dropped.
+
+use thiserror::Error;
+
+use crate::types::CookiePolicy;
+
+/// The prefix browsers tie to the exact host
+pub const HOST_PREFIX: &str = "__Host-";
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CookieError {
+    #[error("cookies.rules: '{0}' is not a cookie name pattern")]
+    InvalidPattern(String),
+
+    #[error("cookies.rules.{0}: {1}")]
+    InvalidRule(String, String),
+}
+
+/// A compiled cookie rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    pattern: String,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<&'static str>,
+    host_prefix: bool,
+}
+
+/// A `Set-Cookie` value the airlock changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hardened {
+    /// The header value to send instead
+    pub value: String,
+    /// The cookie, as the application named it
+    pub name: String,
+    /// The pattern of the rule applied
+    pub rule: String,
+    /// What was changed (`secure`, `httponly`, `samesite`, `host_prefix`)
+    pub changes: Vec<&'static str>,
+}
+
+/// A compiled [`CookiePolicy`]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CookieRules {
+    rules: Vec<Rule>,
+}
+
+impl CookieRules {
+    /// Check and compile a policy; a disabled policy has no rules
+    pub fn compile(policy: &CookiePolicy) -> Result<Self, CookieError> {
+        let mut rules = Vec::new();
+        for rule in &policy.rules {
+            if rule.name.is_empty() || !rule.name.bytes().all(is_token_byte) {
+                return Err(CookieError::InvalidPattern(rule.name.clone()));
+            }
+            let invalid = |problem: &str| CookieError::InvalidRule(rule.name.clone(), problem.to_string());
+            let same_site = match rule.same_site.as_deref().map(str::to_ascii_lowercase).as_deref() {
+                None => None,
+                Some("strict") => Some("Strict"),
+                Some("lax") => Some("Lax"),
+                Some("none") => Some("None"),
+                Some(_) => return Err(invalid("same_site must be Strict, Lax or None")),
+            };
+            if same_site == Some("None") && !rule.secure {
+                return Err(invalid("SameSite=None cookies must be secure"));
+            }
+            if rule.host_prefix && !rule.secure {
+                return Err(invalid("host_prefix cookies must be secure"));
+            }
+            rules.push(Rule {
+                pattern: rule.name.clone(),
+                secure: rule.secure,
+                http_only: rule.http_only,
+                same_site,
+                host_prefix: rule.host_prefix,
+            });
+        }
+
+        Ok(Self {
+            rules: if policy.enabled { rules } else { Vec::new() },
+        })
+    }
+
+    fn rule(&self, name: &str) -> Option<&Rule> {
+        self.rules.iter().find(|rule| matches(&rule.pattern, name))
+    }
+
+    /// Enforce the rule for a `Set-Cookie` value; `None` if nothing changed
+    pub fn harden(&self, set_cookie: &str) -> Option<Hardened> {
+        let (pair, attributes) = set_cookie.split_once(';').unwrap_or((set_cookie, ""));
+        let (name, value) = pair.split_once('=')?;
+        let name = name.trim();
+        let rule = self.rule(name)?;
+
+        let mut attributes: Vec<String> = attributes
+            .split(';')
+            .map(|a| a.trim().to_string())
+            .filter(|a| !a.is_empty())
+            .collect();
+        let mut changes = Vec::new();
+
+        let mut cookie_name = name.to_string();
+        if rule.host_prefix && !name.starts_with(HOST_PREFIX) {
+            cookie_name = format!("{}{}", HOST_PREFIX, name);
+            attributes.retain(|a| attribute_name(a) != "domain" && attribute_name(a) != "path");
+            attributes.push("Path=/".to_string());
+            changes.push("host_prefix");
+        }
+        if rule.secure && !attributes.iter().any(|a| attribute_name(a) == "secure") {
+            attributes.push("Secure".to_string());
+            changes.push("secure");
+        }
+        if rule.http_only && !attributes.iter().any(|a| attribute_name(a) == "httponly") {
+            attributes.push("HttpOnly".to_string());
+            changes.push("httponly");
+        }
+        if let Some(same_site) = rule.same_site {
+            let wanted = format!("SameSite={}", same_site);
+            let current: Vec<&String> = attributes.iter().filter(|a| attribute_name(a) == "samesite").collect();
+            if current.len() != 1 || !current[0].eq_ignore_ascii_case(&wanted) {
+                attributes.retain(|a| attribute_name(a) != "samesite");
+                attributes.push(wanted);
+                changes.push("samesite");
+            }
+        }
+
+        if changes.is_empty() {
+            return None;
+        }
+        let mut hardened = format!("{}={}", cookie_name, value.trim());
+        for attribute in attributes {
+            hardened.push_str("; ");
+            hardened.push_str(&attribute);
+        }
+        Some(Hardened {
+            value: hardened,
+            name: name.to_string(),
+            rule: rule.pattern.clone(),
+            changes,
+        })
+    }
+
+    /// Undo `host_prefix` on a Cookie header; `None` if nothing changed,
+    /// an empty string if no cookie is left
+    pub fn unprefix(&self, cookie: &str) -> Option<String> {
+        if !self.rules.iter().any(|rule| rule.host_prefix) {
+            return None;
+        }
+        let prefixed = |name: &str| self.rule(name).is_some_and(|rule| rule.host_prefix);
+        let mut changed = false;
+        let pairs: Vec<String> = cookie
+            .split(';')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+            .filter_map(|pair| {
+                let name = pair.split_once('=').map_or(pair, |(name, _)| name);
+                match name.strip_prefix(HOST_PREFIX) {
+                    Some(original) if prefixed(original) => {
+                        changed = true;
+                        Some(pair[HOST_PREFIX.len()..].to_string())
+                    }
+                    _ if prefixed(name) => {
+                        changed = true;
+                        None
+                    }
+                    _ => Some(pair.to_string()),
+                }
+            })
+            .collect();
+        changed.then(|| pairs.join("; "))
+    }
+}
+
+/// Lowercase name of a cookie attribute (`Path=/` is `path`)
+fn attribute_name(attribute: &str) -> String {
+    attribute
+        .split_once('=')
+        .map_or(attribute, |(name, _)| name)
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// An RFC 9110 token byte (`*` is one, and the only wildcard)
+fn is_token_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+/// Match a cookie name against a pattern where `*` is any run of characters
+fn matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::CookieRule;
+
+    #[test]
+    fn test_patterns() {
+        assert!(matches("wordpress_logged_in_*", "wordpress_logged_in_5d41402abc4b"));
+        assert!(!matches("wordpress_logged_in_*", "wordpress_sec_5d41402abc4b"));
+        assert!(matches("PHPSESSID", "PHPSESSID"));
+        assert!(!matches("PHPSESSID", "PHPSESSID2"));
+        assert!(matches("*_session", "laravel_session"));
+        assert!(matches("a*b*c", "a-b-b-c"));
+        assert!(!matches("a*b*c", "a-c"));
+    }
+
+    #[test]
+    fn test_default_rules_harden_wordpress() {
+        let rules = CookieRules::compile(&CookiePolicy::default()).unwrap();
+        let hardened = rules
+            .harden("wordpress_logged_in_5d41=admin%7C1700000000; path=/; samesite=none")
+            .unwrap();
+        assert_eq!(hardened.value, "wordpress_logged_in_5d41=admin%7C1700000000; path=/; Secure; HttpOnly; SameSite=Lax");
+        assert_eq!(hardened.name, "wordpress_logged_in_5d41");
+        assert_eq!(hardened.rule, "wordpress_*");
+        assert_eq!(hardened.changes, vec!["secure", "httponly", "samesite"]);
+
+        // Already hardened, or not covered: left alone
+        assert_eq!(rules.harden("PHPSESSID=abc; path=/; secure; HttpOnly; SameSite=lax"), None);
+        assert_eq!(rules.harden("wp-settings-1=libraryContent%3Dbrowse; path=/"), None);
+        assert_eq!(rules.harden("garbage"), None);
+
+        let disabled = CookieRules::compile(&CookiePolicy {
+            enabled: false,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(disabled.harden("PHPSESSID=abc"), None);
+    }
+
+    #[test]
+    fn test_host_prefix_both_ways() {
+        let policy = CookiePolicy {
+            enabled: true,
+            rules: vec![CookieRule {
+                name: "SESS*".to_string(),
+                same_site: Some("strict".to_string()),
+                host_prefix: true,
+                ..Default::default()
+            }],
+        };
+        let rules = CookieRules::compile(&policy).unwrap();
+        let hardened = rules
+            .harden("SESSa1b2=xyz; Domain=.example.com; Path=/drupal; HttpOnly")
+            .unwrap();
+        assert_eq!(hardened.value, "__Host-SESSa1b2=xyz; HttpOnly; Path=/; Secure; SameSite=Strict");
+        assert_eq!(hardened.changes, vec!["host_prefix", "secure", "samesite"]);
+
+        // The application sees its own name; a planted unprefixed cookie is dropped
+        assert_eq!(
+            rules.unprefix("has_js=1; SESSa1b2=planted; __Host-SESSa1b2=xyz"),
+            Some("has_js=1; SESSa1b2=xyz".to_string())
+        );
+        assert_eq!(rules.unprefix("SESSa1b2=planted"), Some(String::new()));
+        assert_eq!(rules.unprefix("has_js=1"), None);
+    }
+
+    #[test]
+    fn test_invalid_rules_rejected() {
+        let policy = |rule: CookieRule| CookiePolicy {
+            enabled: true,
+            rules: vec![rule],
+        };
+        let rule = CookieRule {
+            name: "bad name".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            CookieRules::compile(&policy(rule)),
+            Err(CookieError::InvalidPattern("bad name".to_string()))
+        );
+        let rule = CookieRule {
+            name: "sid".to_string(),
+            same_site: Some("sometimes".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(CookieRules::compile(&policy(rule)), Err(CookieError::InvalidRule(..))));
+        let rule = CookieRule {
+            name: "sid".to_string(),
+            secure: false,
+            host_prefix: true,
+            ..Default::default()
+        };
+        assert!(matches!(CookieRules::compile(&policy(rule)), Err(CookieError::InvalidRule(..))));
+    }
+}
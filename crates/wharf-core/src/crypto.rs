@@ -9,8 +9,12 @@
 //! - Argon2id password hashing
 
 use blake3::Hasher;
+use ed25519_dalek::{Signature, Signer, Verifier};
+use rand_core::{OsRng, RngCore};
 use thiserror::Error;
 
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
 #[derive(Error, Debug)]
 pub enum CryptoError {
     #[error("Key generation failed: {0}")]
@@ -21,6 +25,52 @@ pub enum CryptoError {
 
     #[error("Hash mismatch: expected {expected}, got {actual}")]
     HashMismatch { expected: String, actual: String },
+
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+}
+
+/// Generate a new Ed25519 signing key
+pub fn generate_signing_key() -> SigningKey {
+    SigningKey::generate(&mut OsRng)
+}
+
+/// Sign a message, returning the signature as hex
+pub fn sign(key: &SigningKey, message: &[u8]) -> String {
+    hex::encode(key.sign(message).to_bytes())
+}
+
+/// Verify a hex Ed25519 signature over a message
+pub fn verify_signature(key: &VerifyingKey, message: &[u8], signature: &str) -> Result<(), CryptoError> {
+    let bytes = hex::decode(signature).map_err(|_| CryptoError::SignatureVerificationFailed)?;
+    let signature = Signature::from_slice(&bytes).map_err(|_| CryptoError::SignatureVerificationFailed)?;
+    key.verify(message, &signature)
+        .map_err(|_| CryptoError::SignatureVerificationFailed)
+}
+
+/// Parse a hex-encoded Ed25519 public key
+pub fn decode_verifying_key(encoded: &str) -> Result<VerifyingKey, CryptoError> {
+    let bytes: [u8; 32] = hex::decode(encoded.trim())
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| CryptoError::InvalidKey("expected 32 hex-encoded bytes".to_string()))?;
+    VerifyingKey::from_bytes(&bytes).map_err(|e| CryptoError::InvalidKey(e.to_string()))
+}
+
+/// Parse a hex-encoded Ed25519 secret key
+pub fn decode_signing_key(encoded: &str) -> Result<SigningKey, CryptoError> {
+    let bytes: [u8; 32] = hex::decode(encoded.trim())
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| CryptoError::InvalidKey("expected 32 hex-encoded bytes".to_string()))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+/// A random 128-bit nonce, as hex
+pub fn random_nonce() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
 }
 
 /// Compute a BLAKE3 hash of the given data
@@ -36,6 +86,15 @@ pub fn hash_file(path: &std::path::Path) -> Result<String, std::io::Error> {
     Ok(hash_blake3(&data))
 }
 
+/// Compute a BLAKE3 hash of a value's JSON form, with object keys sorted
+///
+/// Map iteration order does not leak into the hash, so two equal policies
+/// always hash the same.
+pub fn hash_json<T: serde::Serialize>(value: &T) -> Result<String, serde_json::Error> {
+    let canonical = serde_json::to_value(value)?;
+    Ok(hash_blake3(&serde_json::to_vec(&canonical)?))
+}
+
 /// Verify that a file matches an expected hash
 pub fn verify_file_hash(path: &std::path::Path, expected: &str) -> Result<bool, CryptoError> {
     let actual = hash_file(path).map_err(|e| CryptoError::KeyGenerationError(e.to_string()))?;
@@ -52,4 +111,22 @@ fn test_blake3_hash() {
         assert!(!hash.is_empty());
         assert_eq!(hash.len(), 64); // 256 bits = 64 hex chars
     }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = generate_signing_key();
+        let public = decode_verifying_key(&hex::encode(key.verifying_key().to_bytes())).unwrap();
+        let signature = sign(&key, b"moor");
+
+        assert!(verify_signature(&public, b"moor", &signature).is_ok());
+        assert!(verify_signature(&public, b"unmoor", &signature).is_err());
+        assert!(verify_signature(&public, b"moor", "zz").is_err());
+    }
+
+    #[test]
+    fn test_json_hash_ignores_key_order() {
+        let a: std::collections::HashMap<_, _> = (0..32).map(|i| (i.to_string(), i)).collect();
+        let b: std::collections::HashMap<_, _> = (0..32).rev().map(|i| (i.to_string(), i)).collect();
+        assert_eq!(hash_json(&a).unwrap(), hash_json(&b).unwrap());
+    }
 }
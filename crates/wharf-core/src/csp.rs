@@ -0,0 +1,319 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Content Security Policy
+//!
+//! Turns the `csp` block of the header policy ([`CspPolicy`]) into a
+//! `Content-Security-Policy` (or `-Report-Only`) header, and reads the
+//! violation reports browsers send back.
+//!
+//! With `nonce` set, every response gets a fresh nonce in `script-src` and
+//! `style-src`; the airlock hands it to the application in the
+//! [`NONCE_HEADER`] request header so inline scripts can carry it.
+//!
+//! Reports come in two shapes: the classic `application/csp-report` body
+//! (`{"csp-report": {...}}`) and the Reporting API's
+//! `application/reports+json` list. Both are reduced to a
+//! [`CspViolation`]; query strings and fragments are cut from the URIs, as
+//! they tend to carry session tokens.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::crypto::random_nonce;
+use crate::types::CspPolicy;
+
+/// Request header the application reads the response's nonce from
+pub const NONCE_HEADER: &str = "X-CSP-Nonce";
+
+/// Directives a nonce is added to
+const NONCE_DIRECTIVES: &[&str] = &["script-src", "style-src"];
+
+/// The Reporting API endpoint name used in `report-to`
+const REPORT_GROUP: &str = "wharf-csp";
+
+/// Longest URI or directive kept from a report
+const MAX_FIELD: usize = 512;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CspError {
+    #[error("csp: '{0}' is not a directive name")]
+    InvalidDirective(String),
+
+    #[error("csp: the sources of {0} contain a separator or control character")]
+    InvalidSources(String),
+
+    #[error("csp: invalid report_uri '{0}'")]
+    InvalidReportUri(String),
+
+    #[error("Malformed CSP report: {0}")]
+    MalformedReport(String),
+}
+
+// =============================================================================
+// HEADER
+// =============================================================================
+
+/// A compiled CSP, ready to be sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CspHeader {
+    report_only: bool,
+    directives: Vec<(String, String)>,
+    report_uri: Option<String>,
+    nonce: bool,
+}
+
+impl CspHeader {
+    /// Check and compile a policy; `None` if CSP is disabled
+    pub fn compile(policy: &CspPolicy) -> Result<Option<Self>, CspError> {
+        let mut directives = Vec::new();
+        for (name, sources) in &policy.directives {
+            let name = name.to_ascii_lowercase();
+            if name.is_empty() || !name.bytes().all(|b| b.is_ascii_lowercase() || b == b'-') {
+                return Err(CspError::InvalidDirective(name));
+            }
+            if !sources.bytes().all(|b| (b' '..=b'~').contains(&b) && b != b';' && b != b',') {
+                return Err(CspError::InvalidSources(name));
+            }
+            directives.push((name, sources.trim().to_string()));
+        }
+        if let Some(uri) = &policy.report_uri {
+            if uri.is_empty() || !uri.bytes().all(|b| b.is_ascii_graphic() && b != b';' && b != b',' && b != b'"') {
+                return Err(CspError::InvalidReportUri(uri.clone()));
+            }
+        }
+
+        if !policy.enabled {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            report_only: policy.report_only,
+            directives,
+            report_uri: policy.report_uri.clone(),
+            nonce: policy.nonce,
+        }))
+    }
+
+    /// `Content-Security-Policy` or `Content-Security-Policy-Report-Only`
+    pub fn name(&self) -> &'static str {
+        if self.report_only {
+            "Content-Security-Policy-Report-Only"
+        } else {
+            "Content-Security-Policy"
+        }
+    }
+
+    /// Whether every response needs a fresh nonce
+    pub fn uses_nonce(&self) -> bool {
+        self.nonce
+    }
+
+    /// The header value, with `nonce` added to the script and style sources
+    pub fn value(&self, nonce: Option<&str>) -> String {
+        let mut parts: Vec<String> = self
+            .directives
+            .iter()
+            .map(|(name, sources)| {
+                let mut directive = name.clone();
+                if !sources.is_empty() {
+                    directive.push(' ');
+                    directive.push_str(sources);
+                }
+                if let Some(nonce) = nonce.filter(|_| NONCE_DIRECTIVES.contains(&name.as_str())) {
+                    directive.push_str(&format!(" 'nonce-{}'", nonce));
+                }
+                directive
+            })
+            .collect();
+        if let Some(uri) = &self.report_uri {
+            parts.push(format!("report-uri {}", uri));
+            parts.push(format!("report-to {}", REPORT_GROUP));
+        }
+        parts.join("; ")
+    }
+
+    /// The `Reporting-Endpoints` header that goes with `report-to`
+    pub fn reporting_endpoints(&self) -> Option<String> {
+        self.report_uri
+            .as_ref()
+            .map(|uri| format!("{}=\"{}\"", REPORT_GROUP, uri))
+    }
+
+    /// The path the airlock collects reports on, if they are sent to the site itself
+    pub fn report_path(&self) -> Option<&str> {
+        self.report_uri.as_deref().filter(|uri| uri.starts_with('/'))
+    }
+}
+
+/// A fresh nonce for one response
+pub fn generate_nonce() -> String {
+    random_nonce()
+}
+
+// =============================================================================
+// REPORTS
+// =============================================================================
+
+/// One violation, as reported by a browser
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CspViolation {
+    /// The page the violation happened on
+    pub document_uri: String,
+    /// The directive that was violated (e.g. `script-src-elem`)
+    pub directive: String,
+    /// What was blocked: a URL, or `inline`, `eval`, ...
+    pub blocked_uri: String,
+}
+
+/// Violations of one directive by one source, as aggregated by the agent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CspReportSummary {
+    pub directive: String,
+    pub blocked_uri: String,
+    pub count: u64,
+    /// Unix timestamps
+    pub first_seen: i64,
+    pub last_seen: i64,
+    /// The page of the latest report
+    pub document_uri: String,
+}
+
+/// Read the violations out of a report body (either format)
+pub fn parse_reports(body: &[u8]) -> Result<Vec<CspViolation>, CspError> {
+    let value: Value = serde_json::from_slice(body).map_err(|e| CspError::MalformedReport(e.to_string()))?;
+
+    match value {
+        Value::Object(ref object) if object.contains_key("csp-report") => {
+            let report = &object["csp-report"];
+            let directive = report["effective-directive"]
+                .as_str()
+                .or_else(|| report["violated-directive"].as_str())
+                .ok_or_else(|| CspError::MalformedReport("no violated directive".to_string()))?;
+            Ok(vec![violation(
+                report["document-uri"].as_str(),
+                directive,
+                report["blocked-uri"].as_str(),
+            )])
+        }
+        Value::Array(reports) => Ok(reports
+            .iter()
+            .filter(|report| report["type"] == "csp-violation")
+            .filter_map(|report| {
+                let body = &report["body"];
+                let directive = body["effectiveDirective"].as_str()?;
+                Some(violation(body["documentURL"].as_str(), directive, body["blockedURL"].as_str()))
+            })
+            .collect()),
+        _ => Err(CspError::MalformedReport("not a CSP report".to_string())),
+    }
+}
+
+fn violation(document_uri: Option<&str>, directive: &str, blocked_uri: Option<&str>) -> CspViolation {
+    // violated-directive is "script-src 'self'" in older browsers
+    let directive = directive.split_whitespace().next().unwrap_or_default();
+    CspViolation {
+        document_uri: clean_uri(document_uri.unwrap_or_default()),
+        directive: truncate(directive).to_ascii_lowercase(),
+        blocked_uri: clean_uri(blocked_uri.unwrap_or_default()),
+    }
+}
+
+/// Without query string and fragment, and not overlong
+fn clean_uri(uri: &str) -> String {
+    let end = uri.find(['?', '#']).unwrap_or(uri.len());
+    truncate(&uri[..end]).to_string()
+}
+
+fn truncate(value: &str) -> &str {
+    if value.len() <= MAX_FIELD {
+        return value;
+    }
+    let mut end = MAX_FIELD;
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled() -> CspPolicy {
+        CspPolicy {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_default_directives_compile() {
+        let header = CspHeader::compile(&enabled()).unwrap().unwrap();
+        assert_eq!(header.name(), "Content-Security-Policy");
+        let value = header.value(None);
+        assert!(value.starts_with("base-uri 'self'; connect-src 'self'; default-src 'self';"), "{}", value);
+        assert!(value.contains("; upgrade-insecure-requests; "));
+        assert!(value.ends_with("report-uri /.well-known/csp-report; report-to wharf-csp"));
+        assert_eq!(header.report_path(), Some("/.well-known/csp-report"));
+
+        assert_eq!(CspHeader::compile(&CspPolicy::default()), Ok(None));
+    }
+
+    #[test]
+    fn test_nonce_and_report_only() {
+        let mut policy = enabled();
+        policy.report_only = true;
+        policy.nonce = true;
+        policy.report_uri = Some("https://reports.example.com/csp".to_string());
+        let header = CspHeader::compile(&policy).unwrap().unwrap();
+
+        assert_eq!(header.name(), "Content-Security-Policy-Report-Only");
+        assert!(header.uses_nonce());
+        let value = header.value(Some("abc123"));
+        assert!(value.contains("script-src 'self' 'nonce-abc123';"));
+        assert!(value.contains("style-src 'self' 'unsafe-inline' 'nonce-abc123';"));
+        assert!(value.contains("default-src 'self';"));
+        assert_eq!(header.report_path(), None);
+        assert_eq!(generate_nonce().len(), 32);
+    }
+
+    #[test]
+    fn test_invalid_directives_rejected() {
+        let mut policy = enabled();
+        policy.directives.insert("script-src".to_string(), "'self'; report-uri //evil".to_string());
+        assert_eq!(CspHeader::compile(&policy), Err(CspError::InvalidSources("script-src".to_string())));
+
+        let mut policy = enabled();
+        policy.directives.insert("script src".to_string(), "'self'".to_string());
+        assert!(matches!(CspHeader::compile(&policy), Err(CspError::InvalidDirective(_))));
+    }
+
+    #[test]
+    fn test_parse_both_report_formats() {
+        let classic = br#"{"csp-report": {
+            "document-uri": "https://example.com/wp-admin/?token=secret",
+            "violated-directive": "script-src-elem 'self'",
+            "blocked-uri": "https://cdn.evil.test/x.js?v=1"
+        }}"#;
+        assert_eq!(
+            parse_reports(classic).unwrap(),
+            vec![CspViolation {
+                document_uri: "https://example.com/wp-admin/".to_string(),
+                directive: "script-src-elem".to_string(),
+                blocked_uri: "https://cdn.evil.test/x.js".to_string(),
+            }]
+        );
+
+        let reporting_api = br#"[
+            {"type": "csp-violation", "body": {"documentURL": "https://example.com/", "effectiveDirective": "style-src-attr", "blockedURL": "inline"}},
+            {"type": "deprecation", "body": {}}
+        ]"#;
+        let violations = parse_reports(reporting_api).unwrap();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].blocked_uri, "inline");
+
+        assert!(parse_reports(b"{\"hello\": 1}").is_err());
+        assert!(parse_reports(b"not json").is_err());
+    }
+}
@@ -13,8 +13,16 @@
 //! - **Mutable (Blue)**: Allowed to write (e.g., wp_comments, wp_woocommerce_orders)
 //! - **Immutable (Red)**: Read-only, writes blocked unless from Wharf (e.g., wp_users, wp_options)
 //! - **Hybrid (Grey)**: Conditional based on specific columns/values (e.g., transient caches in wp_options)
+//!
+//! Reads are allowed, but a read can still be an exfiltration: `SELECT * FROM
+//! wp_users` dumps every password hash. Result limits cap how many rows and
+//! bytes a single query may pull out of a table; the proxy counts the result
+//! set as it streams back and enforces the limit.
 
-use sqlparser::ast::{Statement, SetExpr, TableFactor};
+use sqlparser::ast::{
+    Array, CopySource, Expr, FunctionArg, FunctionArgExpr, GroupByExpr, JoinConstraint, JoinOperator, Query, Select,
+    SelectItem, SetExpr, Statement, TableFactor, TableWithJoins, WindowSpec, WindowType,
+};
 use sqlparser::dialect::MySqlDialect;
 use sqlparser::parser::Parser;
 use serde::{Deserialize, Serialize};
@@ -32,6 +40,17 @@ pub enum PolicyError {
     BlockedColumnPattern { table: String, pattern: String },
 }
 
+impl PolicyError {
+    /// The policy rule behind a rejection, for the audit log
+    pub fn rule(&self) -> String {
+        match self {
+            Self::ParseError(_) => "parse_error".to_string(),
+            Self::ImmutableTableViolation { table } => format!("lock_down:{}", table),
+            Self::BlockedColumnPattern { table, pattern } => format!("hybrid:{}:{}", table, pattern),
+        }
+    }
+}
+
 /// The action to take for a query
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum QueryAction {
@@ -54,6 +73,64 @@ pub struct HybridRule {
     pub matches: String,
 }
 
+/// What to do when a query's result set crosses its limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LimitAction {
+    /// Log the violation and let the rest of the result through
+    #[default]
+    Alert,
+    /// Cut the result off and close the connection
+    Terminate,
+}
+
+/// Maximum result-set size for queries reading a table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResultLimit {
+    /// The table this limit applies to
+    pub table: String,
+    /// Maximum rows a single query may return
+    #[serde(default)]
+    pub max_rows: Option<u64>,
+    /// Maximum result bytes a single query may return
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    /// What to do when the limit is crossed
+    #[serde(default)]
+    pub action: LimitAction,
+}
+
+/// The result-set budget for one query
+///
+/// When a query reads several limited tables the tightest row and byte
+/// limits apply, and `Terminate` wins over `Alert`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResultBudget {
+    pub max_rows: Option<u64>,
+    pub max_bytes: Option<u64>,
+    pub action: LimitAction,
+    /// The limited tables the query reads
+    pub tables: Vec<String>,
+}
+
+impl ResultBudget {
+    /// Whether a result of this size is over budget
+    pub fn is_exceeded(&self, rows: u64, bytes: u64) -> bool {
+        self.max_rows.is_some_and(|max| rows > max) || self.max_bytes.is_some_and(|max| bytes > max)
+    }
+}
+
+/// The outcome of inspecting a query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryAnalysis {
+    /// What to do with the query
+    pub action: QueryAction,
+    /// The policy rule that decided the action (e.g. `allow_write:wp_comments`)
+    pub rule: String,
+    /// The result-set budget, if the query reads a limited table
+    pub result_budget: Option<ResultBudget>,
+}
+
 /// Database security policy configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DatabasePolicy {
@@ -64,7 +141,12 @@ pub struct DatabasePolicy {
     pub lock_down: Vec<String>,
 
     /// Hybrid rules for tables like wp_options
+    #[serde(default)]
     pub hybrid_rules: Vec<HybridRule>,
+
+    /// Result-set limits for reads
+    #[serde(default)]
+    pub result_limits: Vec<ResultLimit>,
 }
 
 impl Default for DatabasePolicy {
@@ -85,6 +167,13 @@ fn default() -> Self {
                 "wp_options".to_string(),
             ],
             hybrid_rules: vec![],
+            // Nothing on a public site needs to list every user
+            result_limits: vec![ResultLimit {
+                table: "wp_users".to_string(),
+                max_rows: Some(500),
+                max_bytes: None,
+                action: LimitAction::Terminate,
+            }],
         }
     }
 }
@@ -103,13 +192,34 @@ pub fn new(policy: DatabasePolicy) -> Self {
         }
     }
 
+    /// The policy this engine enforces
+    pub fn policy(&self) -> &DatabasePolicy {
+        &self.policy
+    }
+
     /// Analyze a SQL query and determine the action to take
     pub fn analyze(&self, sql: &str) -> Result<QueryAction, PolicyError> {
+        self.inspect(sql).map(|analysis| analysis.action)
+    }
+
+    /// Analyze a SQL query and work out its result-set budget
+    ///
+    /// The query is parsed once for both.
+    pub fn inspect(&self, sql: &str) -> Result<QueryAnalysis, PolicyError> {
         let ast = Parser::parse_sql(&self.dialect, sql)
             .map_err(|e| PolicyError::ParseError(e.to_string()))?;
 
+        let (action, rule) = self.classify(&ast)?;
+        Ok(QueryAnalysis {
+            action,
+            rule,
+            result_budget: self.result_budget(&ast),
+        })
+    }
+
+    fn classify(&self, ast: &[Statement]) -> Result<(QueryAction, String), PolicyError> {
         for statement in ast {
-            match &statement {
+            match statement {
                 Statement::Insert { table_name, .. } => {
                     let table = table_name.to_string();
                     return self.check_write_permission(&table);
@@ -126,27 +236,67 @@ pub fn analyze(&self, sql: &str) -> Result<QueryAction, PolicyError> {
                 }
                 Statement::Drop { .. } => {
                     // DROP is always blocked from the yacht
-                    return Ok(QueryAction::Block);
+                    return Ok((QueryAction::Block, "blocked_operation:DROP".to_string()));
                 }
                 Statement::AlterTable { .. } => {
                     // ALTER is always blocked from the yacht
-                    return Ok(QueryAction::Block);
+                    return Ok((QueryAction::Block, "blocked_operation:ALTER".to_string()));
                 }
                 // SELECT and other read operations are always allowed
                 _ => {}
             }
         }
 
-        Ok(QueryAction::Allow)
+        Ok((QueryAction::Allow, "read".to_string()))
+    }
+
+    fn result_budget(&self, ast: &[Statement]) -> Option<ResultBudget> {
+        if self.policy.result_limits.is_empty() {
+            return None;
+        }
+
+        let mut tables = Vec::new();
+        for statement in ast {
+            match statement {
+                Statement::Query(query) => collect_query_tables(query, &mut tables),
+                Statement::Copy { source, to: true, .. } => match source {
+                    CopySource::Table { table_name, .. } => tables.push(table_name.to_string()),
+                    CopySource::Query(query) => collect_query_tables(query, &mut tables),
+                },
+                _ => {}
+            }
+        }
+        let tables: Vec<String> = tables.iter().map(|t| table_key(t)).collect();
+
+        let mut budget: Option<ResultBudget> = None;
+        for limit in &self.policy.result_limits {
+            if !tables.contains(&table_key(&limit.table)) {
+                continue;
+            }
+
+            let budget = budget.get_or_insert_with(|| ResultBudget {
+                max_rows: None,
+                max_bytes: None,
+                action: LimitAction::Alert,
+                tables: Vec::new(),
+            });
+            budget.max_rows = min_limit(budget.max_rows, limit.max_rows);
+            budget.max_bytes = min_limit(budget.max_bytes, limit.max_bytes);
+            if limit.action == LimitAction::Terminate {
+                budget.action = LimitAction::Terminate;
+            }
+            budget.tables.push(limit.table.clone());
+        }
+        budget
     }
 
-    fn check_write_permission(&self, table: &str) -> Result<QueryAction, PolicyError> {
+    fn check_write_permission(&self, table: &str) -> Result<(QueryAction, String), PolicyError> {
         // Normalize table name (remove schema prefix, backticks, etc.)
         let normalized = table.trim_matches('`').to_lowercase();
 
         // Check if explicitly allowed
-        if self.policy.allow_write.iter().any(|t| normalized.contains(&t.to_lowercase())) {
-            return Ok(QueryAction::Allow);
+        if let Some(t) = self.policy.allow_write.iter().find(|t| normalized.contains(&t.to_lowercase())) {
+            return Ok((QueryAction::Allow, format!("allow_write:{}", t)));
         }
 
         // Check if explicitly locked
@@ -156,7 +306,7 @@ fn check_write_permission(&self, table: &str) -> Result<QueryAction, PolicyError
 
         // Default: audit and allow (fail-open for unknown tables)
         // In production, you might want this to be Block (fail-closed)
-        Ok(QueryAction::Audit)
+        Ok((QueryAction::Audit, format!("default:{}", normalized)))
     }
 
     fn extract_table_name(&self, table: &sqlparser::ast::TableWithJoins) -> String {
@@ -171,6 +321,279 @@ fn extract_table_factor(&self, factor: &TableFactor) -> String {
     }
 }
 
+/// Normalize a query for logging
+///
+/// The query is parsed and printed back, which strips comments and
+/// canonicalizes whitespace and keyword case. Queries that do not parse
+/// just have their whitespace collapsed.
+pub fn normalize_query(sql: &str) -> String {
+    match Parser::parse_sql(&MySqlDialect {}, sql) {
+        Ok(ast) if !ast.is_empty() => ast.iter().map(|s| s.to_string()).collect::<Vec<_>>().join("; "),
+        _ => sql.split_whitespace().collect::<Vec<_>>().join(" "),
+    }
+}
+
+/// A table name without its schema and quoting, lower case
+/// (`` `wordpress`.`WP_Users` `` is `wp_users`)
+fn table_key(name: &str) -> String {
+    let table = name.rsplit('.').next().unwrap_or(name);
+    table.trim_matches(['`', '"', '[', ']']).to_lowercase()
+}
+
+/// Collect every table a query reads, including CTEs, joins and subqueries
+/// anywhere in its expressions
+fn collect_query_tables(query: &Query, tables: &mut Vec<String>) {
+    if let Some(with) = &query.with {
+        for cte in &with.cte_tables {
+            collect_query_tables(&cte.query, tables);
+        }
+    }
+    collect_set_expr_tables(&query.body, tables);
+    for order in &query.order_by {
+        collect_expr_tables(&order.expr, tables);
+    }
+    collect_exprs_tables(query.limit.iter().chain(&query.limit_by), tables);
+    if let Some(offset) = &query.offset {
+        collect_expr_tables(&offset.value, tables);
+    }
+}
+
+fn collect_set_expr_tables(expr: &SetExpr, tables: &mut Vec<String>) {
+    match expr {
+        SetExpr::Select(select) => collect_select_tables(select, tables),
+        SetExpr::Query(query) => collect_query_tables(query, tables),
+        SetExpr::SetOperation { left, right, .. } => {
+            collect_set_expr_tables(left, tables);
+            collect_set_expr_tables(right, tables);
+        }
+        SetExpr::Table(table) => tables.extend(table.table_name.clone()),
+        SetExpr::Values(values) => collect_exprs_tables(values.rows.iter().flatten(), tables),
+        _ => {}
+    }
+}
+
+fn collect_select_tables(select: &Select, tables: &mut Vec<String>) {
+    for item in &select.projection {
+        match item {
+            SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => collect_expr_tables(expr, tables),
+            _ => {}
+        }
+    }
+    for from in &select.from {
+        collect_join_tables(from, tables);
+    }
+    for view in &select.lateral_views {
+        collect_expr_tables(&view.lateral_view, tables);
+    }
+    if let GroupByExpr::Expressions(exprs) = &select.group_by {
+        collect_exprs_tables(exprs, tables);
+    }
+    collect_exprs_tables(
+        select
+            .selection
+            .iter()
+            .chain(&select.having)
+            .chain(&select.qualify)
+            .chain(&select.cluster_by)
+            .chain(&select.distribute_by)
+            .chain(&select.sort_by),
+        tables,
+    );
+    for window in &select.named_window {
+        collect_window_tables(&window.1, tables);
+    }
+}
+
+fn collect_join_tables(from: &TableWithJoins, tables: &mut Vec<String>) {
+    collect_factor_tables(&from.relation, tables);
+    for join in &from.joins {
+        collect_factor_tables(&join.relation, tables);
+        match &join.join_operator {
+            JoinOperator::Inner(JoinConstraint::On(on))
+            | JoinOperator::LeftOuter(JoinConstraint::On(on))
+            | JoinOperator::RightOuter(JoinConstraint::On(on))
+            | JoinOperator::FullOuter(JoinConstraint::On(on))
+            | JoinOperator::LeftSemi(JoinConstraint::On(on))
+            | JoinOperator::RightSemi(JoinConstraint::On(on))
+            | JoinOperator::LeftAnti(JoinConstraint::On(on))
+            | JoinOperator::RightAnti(JoinConstraint::On(on)) => collect_expr_tables(on, tables),
+            _ => {}
+        }
+    }
+}
+
+fn collect_factor_tables(factor: &TableFactor, tables: &mut Vec<String>) {
+    match factor {
+        TableFactor::Table { name, args, .. } => {
+            tables.push(name.to_string());
+            for arg in args.iter().flatten() {
+                collect_arg_tables(arg, tables);
+            }
+        }
+        TableFactor::Derived { subquery, .. } => collect_query_tables(subquery, tables),
+        TableFactor::NestedJoin { table_with_joins, .. } => collect_join_tables(table_with_joins, tables),
+        TableFactor::TableFunction { expr, .. } => collect_expr_tables(expr, tables),
+        TableFactor::Function { args, .. } => {
+            for arg in args {
+                collect_arg_tables(arg, tables);
+            }
+        }
+        TableFactor::UNNEST { array_exprs, .. } => collect_exprs_tables(array_exprs, tables),
+        TableFactor::Pivot { table, aggregate_function, .. } => {
+            collect_factor_tables(table, tables);
+            collect_expr_tables(aggregate_function, tables);
+        }
+        TableFactor::Unpivot { table, .. } => collect_factor_tables(table, tables),
+    }
+}
+
+fn collect_exprs_tables<'a>(exprs: impl IntoIterator<Item = &'a Expr>, tables: &mut Vec<String>) {
+    for expr in exprs {
+        collect_expr_tables(expr, tables);
+    }
+}
+
+/// Collect the tables read by subqueries inside an expression
+fn collect_expr_tables(expr: &Expr, tables: &mut Vec<String>) {
+    match expr {
+        Expr::Subquery(query) | Expr::ArraySubquery(query) | Expr::Exists { subquery: query, .. } => {
+            collect_query_tables(query, tables)
+        }
+        Expr::InSubquery { expr, subquery, .. } => {
+            collect_expr_tables(expr, tables);
+            collect_query_tables(subquery, tables);
+        }
+        Expr::JsonAccess { left, right, .. }
+        | Expr::IsDistinctFrom(left, right)
+        | Expr::IsNotDistinctFrom(left, right)
+        | Expr::BinaryOp { left, right, .. }
+        | Expr::AnyOp { left, right, .. }
+        | Expr::AllOp { left, right, .. }
+        | Expr::Like { expr: left, pattern: right, .. }
+        | Expr::ILike { expr: left, pattern: right, .. }
+        | Expr::SimilarTo { expr: left, pattern: right, .. }
+        | Expr::RLike { expr: left, pattern: right, .. }
+        | Expr::InUnnest { expr: left, array_expr: right, .. }
+        | Expr::Position { expr: left, r#in: right }
+        | Expr::AggregateExpressionWithFilter { expr: left, filter: right } => {
+            collect_expr_tables(left, tables);
+            collect_expr_tables(right, tables);
+        }
+        Expr::CompositeAccess { expr, .. }
+        | Expr::IsFalse(expr)
+        | Expr::IsNotFalse(expr)
+        | Expr::IsTrue(expr)
+        | Expr::IsNotTrue(expr)
+        | Expr::IsNull(expr)
+        | Expr::IsNotNull(expr)
+        | Expr::IsUnknown(expr)
+        | Expr::IsNotUnknown(expr)
+        | Expr::UnaryOp { expr, .. }
+        | Expr::Cast { expr, .. }
+        | Expr::TryCast { expr, .. }
+        | Expr::SafeCast { expr, .. }
+        | Expr::AtTimeZone { timestamp: expr, .. }
+        | Expr::Extract { expr, .. }
+        | Expr::Ceil { expr, .. }
+        | Expr::Floor { expr, .. }
+        | Expr::Collate { expr, .. }
+        | Expr::Nested(expr)
+        | Expr::Named { expr, .. } => collect_expr_tables(expr, tables),
+        Expr::InList { expr, list, .. } => {
+            collect_expr_tables(expr, tables);
+            collect_exprs_tables(list, tables);
+        }
+        Expr::Between { expr, low, high, .. } => collect_exprs_tables([&**expr, low, high], tables),
+        Expr::Substring { expr, substring_from, substring_for, .. } => {
+            collect_expr_tables(expr, tables);
+            collect_exprs_tables(substring_from.iter().chain(substring_for).map(|e| &**e), tables);
+        }
+        Expr::Trim { expr, trim_what, trim_characters, .. } => {
+            collect_expr_tables(expr, tables);
+            collect_exprs_tables(trim_what.as_deref(), tables);
+            collect_exprs_tables(trim_characters.iter().flatten(), tables);
+        }
+        Expr::Overlay { expr, overlay_what, overlay_from, overlay_for } => {
+            collect_exprs_tables([&**expr, overlay_what, overlay_from], tables);
+            collect_exprs_tables(overlay_for.as_deref(), tables);
+        }
+        Expr::MapAccess { column, keys } => {
+            collect_expr_tables(column, tables);
+            collect_exprs_tables(keys, tables);
+        }
+        Expr::ArrayIndex { obj, indexes } => {
+            collect_expr_tables(obj, tables);
+            collect_exprs_tables(indexes, tables);
+        }
+        Expr::Function(function) => {
+            for arg in &function.args {
+                collect_arg_tables(arg, tables);
+            }
+            collect_exprs_tables(function.filter.as_deref(), tables);
+            for order in &function.order_by {
+                collect_expr_tables(&order.expr, tables);
+            }
+            if let Some(WindowType::WindowSpec(window)) = &function.over {
+                collect_window_tables(window, tables);
+            }
+        }
+        Expr::Case { operand, conditions, results, else_result } => {
+            collect_exprs_tables(operand.as_deref(), tables);
+            collect_exprs_tables(conditions.iter().chain(results), tables);
+            collect_exprs_tables(else_result.as_deref(), tables);
+        }
+        Expr::ListAgg(agg) => {
+            collect_expr_tables(&agg.expr, tables);
+            collect_exprs_tables(agg.separator.as_deref(), tables);
+            for order in &agg.within_group {
+                collect_expr_tables(&order.expr, tables);
+            }
+        }
+        Expr::ArrayAgg(agg) => {
+            collect_expr_tables(&agg.expr, tables);
+            collect_exprs_tables(agg.limit.as_deref(), tables);
+            for order in agg.order_by.iter().flatten() {
+                collect_expr_tables(&order.expr, tables);
+            }
+        }
+        Expr::GroupingSets(sets) | Expr::Cube(sets) | Expr::Rollup(sets) => {
+            collect_exprs_tables(sets.iter().flatten(), tables)
+        }
+        Expr::Tuple(exprs) | Expr::Struct { values: exprs, .. } | Expr::Array(Array { elem: exprs, .. }) => {
+            collect_exprs_tables(exprs, tables)
+        }
+        Expr::Interval(interval) => collect_expr_tables(&interval.value, tables),
+        Expr::Identifier(_)
+        | Expr::CompoundIdentifier(_)
+        | Expr::Value(_)
+        | Expr::IntroducedString { .. }
+        | Expr::TypedString { .. }
+        | Expr::MatchAgainst { .. } => {}
+    }
+}
+
+fn collect_arg_tables(arg: &FunctionArg, tables: &mut Vec<String>) {
+    match arg {
+        FunctionArg::Named { arg: FunctionArgExpr::Expr(expr), .. }
+        | FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => collect_expr_tables(expr, tables),
+        _ => {}
+    }
+}
+
+fn collect_window_tables(window: &WindowSpec, tables: &mut Vec<String>) {
+    collect_exprs_tables(&window.partition_by, tables);
+    for order in &window.order_by {
+        collect_expr_tables(&order.expr, tables);
+    }
+}
+
+fn min_limit(current: Option<u64>, new: Option<u64>) -> Option<u64> {
+    match (current, new) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -195,4 +618,98 @@ fn test_insert_to_users_blocked() {
         let result = engine.analyze("INSERT INTO wp_users (user_login) VALUES ('hacker')");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_result_budget_for_limited_table() {
+        let engine = PolicyEngine::new(DatabasePolicy::default());
+
+        let analysis = engine.inspect("SELECT u.ID FROM wp_posts p JOIN `wp_users` u ON p.post_author = u.ID").unwrap();
+        assert_eq!(analysis.action, QueryAction::Allow);
+        let budget = analysis.result_budget.unwrap();
+        assert_eq!(budget.max_rows, Some(500));
+        assert_eq!(budget.action, LimitAction::Terminate);
+        assert!(!budget.is_exceeded(500, 1_000_000));
+        assert!(budget.is_exceeded(501, 0));
+
+        // Subqueries count too
+        let analysis = engine.inspect("SELECT * FROM (SELECT user_pass FROM wp_users) t").unwrap();
+        assert!(analysis.result_budget.is_some());
+
+        let analysis = engine.inspect("SELECT * FROM wp_posts").unwrap();
+        assert!(analysis.result_budget.is_none());
+
+        // Tables are matched by name, whatever the schema or quoting
+        let analysis = engine.inspect("SELECT * FROM `wordpress`.`WP_Users`").unwrap();
+        assert!(analysis.result_budget.is_some());
+        for query in ["SELECT * FROM wp_users_archive", "SELECT * FROM old_wp_users"] {
+            assert!(engine.inspect(query).unwrap().result_budget.is_none(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_result_budget_sees_subqueries_in_expressions() {
+        let engine = PolicyEngine::new(DatabasePolicy::default());
+        for query in [
+            "SELECT p.ID, (SELECT user_pass FROM wp_users u WHERE u.ID = p.post_author) FROM wp_posts p",
+            "SELECT * FROM wp_posts WHERE post_author IN (SELECT ID FROM wp_users)",
+            "SELECT * FROM wp_posts WHERE EXISTS (SELECT 1 FROM wp_users)",
+            "SELECT post_author FROM wp_posts GROUP BY post_author HAVING COUNT(*) > (SELECT COUNT(*) FROM wp_users)",
+            "SELECT * FROM wp_posts ORDER BY (SELECT MAX(ID) FROM wp_users)",
+            "SELECT * FROM wp_posts p JOIN wp_postmeta m ON m.post_id = p.ID AND p.post_author IN (SELECT ID FROM wp_users)",
+            "SELECT CONCAT('x', (SELECT user_pass FROM wp_users LIMIT 1)) FROM wp_posts",
+            "SELECT CASE WHEN 1 THEN (SELECT user_login FROM wp_users) END FROM wp_posts",
+            "COPY wp_users TO STDOUT",
+            "COPY (SELECT * FROM wp_users) TO STDOUT",
+        ] {
+            let analysis = engine.inspect(query).unwrap();
+            assert_eq!(analysis.action, QueryAction::Allow, "{}", query);
+            assert_eq!(analysis.result_budget.map(|b| b.tables), Some(vec!["wp_users".to_string()]), "{}", query);
+        }
+
+        let analysis = engine.inspect("SELECT * FROM wp_posts WHERE post_title = 'wp_users'").unwrap();
+        assert!(analysis.result_budget.is_none());
+    }
+
+    #[test]
+    fn test_result_budget_takes_tightest_limit() {
+        let mut policy = DatabasePolicy::default();
+        policy.result_limits.push(ResultLimit {
+            table: "wp_usermeta".to_string(),
+            max_rows: Some(100),
+            max_bytes: Some(65536),
+            action: LimitAction::Alert,
+        });
+        let engine = PolicyEngine::new(policy);
+
+        let budget = engine
+            .inspect("SELECT * FROM wp_users UNION SELECT * FROM wp_usermeta")
+            .unwrap()
+            .result_budget
+            .unwrap();
+        assert_eq!(budget.max_rows, Some(100));
+        assert_eq!(budget.max_bytes, Some(65536));
+        assert_eq!(budget.action, LimitAction::Terminate);
+    }
+
+    #[test]
+    fn test_rules_are_reported() {
+        let engine = PolicyEngine::new(DatabasePolicy::default());
+
+        let analysis = engine.inspect("INSERT INTO wp_comments (comment_content) VALUES ('hi')").unwrap();
+        assert_eq!(analysis.rule, "allow_write:wp_comments");
+        assert_eq!(engine.inspect("DROP TABLE wp_posts").unwrap().rule, "blocked_operation:DROP");
+        assert_eq!(engine.inspect("UPDATE wp_links SET link_url = 'x'").unwrap().rule, "default:wp_links");
+
+        let err = engine.inspect("UPDATE wp_users SET user_pass = 'x'").unwrap_err();
+        assert_eq!(err.rule(), "lock_down:wp_users");
+    }
+
+    #[test]
+    fn test_normalize_query() {
+        assert_eq!(
+            normalize_query("select  *\n  from wp_users /* dump */ where ID=1"),
+            "SELECT * FROM wp_users WHERE ID = 1"
+        );
+        assert_eq!(normalize_query("not   valid\tsql"), "not valid sql");
+    }
 }
@@ -0,0 +1,249 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Security Events
+//!
+//! The live feed a yacht agent publishes on `/events`: blocked queries,
+//! integrity violations, shield blocks, repeated rate limit violations,
+//! policy changes and enforcement failures, as they happen.
+//! The feed is a server-sent-events stream; each event carries its type
+//! in the `event:` field and an `EventRecord` as JSON in `data:`.
+//!
+//! The feed is for watching, not for evidence. It is best effort (a slow
+//! subscriber skips events rather than slowing the agent down) and is not
+//! chained; the audit log remains the record of what happened.
+
+use std::fmt;
+use std::net::Ipv4Addr;
+
+use serde::{Deserialize, Serialize};
+
+/// Something a yacht operator wants to know about right away
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SecurityEvent {
+    /// A query was refused (or a result set cut off)
+    QueryBlocked {
+        client: String,
+        db_user: Option<String>,
+        /// Normalized query
+        query: String,
+        /// The policy rule that refused it
+        rule: String,
+    },
+    /// The web root does not match the manifest
+    IntegrityViolation {
+        modified: Vec<String>,
+        missing: Vec<String>,
+        unexpected: Vec<String>,
+    },
+    /// The firewall started dropping an address
+    ShieldBlock { ip: Ipv4Addr, reason: String },
+    /// The firewall stopped dropping an address
+    ShieldUnblock { ip: Ipv4Addr },
+    /// A client kept hitting an airlock rate limit
+    RateLimited {
+        /// Client address (the /64 for IPv6)
+        client: String,
+        class: String,
+        /// Violations within the escalation window
+        violations: u32,
+    },
+    /// A different policy version is now enforced
+    PolicyChange {
+        version: String,
+        previous: String,
+        source: String,
+    },
+    /// An enforcement task died for good and the agent failed closed
+    EnforcementFailed {
+        component: String,
+        reason: String,
+        /// The fail-closed action taken (stop, maintenance, exit)
+        action: String,
+    },
+}
+
+impl SecurityEvent {
+    /// The event type, as used for the SSE `event:` field
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::QueryBlocked { .. } => "query_blocked",
+            Self::IntegrityViolation { .. } => "integrity_violation",
+            Self::ShieldBlock { .. } => "shield_block",
+            Self::ShieldUnblock { .. } => "shield_unblock",
+            Self::RateLimited { .. } => "rate_limited",
+            Self::PolicyChange { .. } => "policy_change",
+            Self::EnforcementFailed { .. } => "enforcement_failed",
+        }
+    }
+}
+
+impl fmt::Display for SecurityEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::QueryBlocked {
+                client,
+                db_user,
+                query,
+                rule,
+            } => {
+                let query: String = query.chars().take(100).collect();
+                match db_user {
+                    Some(user) => write!(f, "query blocked ({}) from {} as {}: {}", rule, client, user, query),
+                    None => write!(f, "query blocked ({}) from {}: {}", rule, client, query),
+                }
+            }
+            Self::IntegrityViolation {
+                modified,
+                missing,
+                unexpected,
+            } => write!(
+                f,
+                "integrity violation: {} modified, {} missing, {} unexpected",
+                modified.len(),
+                missing.len(),
+                unexpected.len()
+            ),
+            Self::ShieldBlock { ip, reason } => write!(f, "shield blocked {} ({})", ip, reason),
+            Self::ShieldUnblock { ip } => write!(f, "shield unblocked {}", ip),
+            Self::RateLimited {
+                client,
+                class,
+                violations,
+            } => write!(f, "{} rate limited on {} ({} violations)", client, class, violations),
+            Self::PolicyChange {
+                version,
+                previous,
+                source,
+            } => write!(
+                f,
+                "policy {} -> {} ({})",
+                short(previous),
+                short(version),
+                source
+            ),
+            Self::EnforcementFailed {
+                component,
+                reason,
+                action,
+            } => write!(f, "{} failed ({}), fail-closed: {}", component, reason, action),
+        }
+    }
+}
+
+fn short(version: &str) -> &str {
+    &version[..version.len().min(12)]
+}
+
+/// An event as published, numbered per agent run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventRecord {
+    /// Sequence number; a gap means the subscriber missed events
+    pub seq: u64,
+    /// RFC 3339 timestamp (UTC)
+    pub timestamp: String,
+    #[serde(flatten)]
+    pub event: SecurityEvent,
+}
+
+impl EventRecord {
+    /// Stamp an event with the current time
+    pub fn now(seq: u64, event: SecurityEvent) -> Self {
+        Self {
+            seq,
+            timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            event,
+        }
+    }
+}
+
+/// Splits a server-sent-events byte stream back into records
+///
+/// Only `data:` lines are used; events that are not records (keep-alive
+/// comments, unknown types) are skipped.
+#[derive(Debug, Default)]
+pub struct EventStreamDecoder {
+    buffer: Vec<u8>,
+    data: String,
+}
+
+impl EventStreamDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the next chunk, returning the records it completed
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<EventRecord> {
+        self.buffer.extend_from_slice(chunk);
+        let mut records = Vec::new();
+
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            if line.is_empty() {
+                // Blank line: dispatch
+                if let Ok(record) = serde_json::from_str(&self.data) {
+                    records.push(record);
+                }
+                self.data.clear();
+            } else if let Some(data) = line.strip_prefix("data:") {
+                if !self.data.is_empty() {
+                    self.data.push('\n');
+                }
+                self.data.push_str(data.strip_prefix(' ').unwrap_or(data));
+            }
+        }
+        records
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocked() -> SecurityEvent {
+        SecurityEvent::QueryBlocked {
+            client: "127.0.0.1:50000".to_string(),
+            db_user: Some("wordpress".to_string()),
+            query: "DROP TABLE wp_users".to_string(),
+            rule: "blocked_operation:DROP".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_record_json_is_flat() {
+        let record = EventRecord::now(7, blocked());
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(json["type"], "query_blocked");
+        assert_eq!(json["seq"], 7);
+        assert_eq!(json["rule"], "blocked_operation:DROP");
+        assert_eq!(serde_json::from_value::<EventRecord>(json).unwrap(), record);
+    }
+
+    #[test]
+    fn test_decoder_handles_split_chunks() {
+        let record = EventRecord::now(1, blocked());
+        let frame = format!(
+            ": keep-alive\n\nid: 1\nevent: {}\ndata: {}\n\n",
+            record.event.name(),
+            serde_json::to_string(&record).unwrap()
+        );
+
+        let mut decoder = EventStreamDecoder::new();
+        let (head, tail) = frame.as_bytes().split_at(frame.len() / 2);
+        assert!(decoder.feed(head).is_empty());
+        assert_eq!(decoder.feed(tail), vec![record]);
+    }
+
+    #[test]
+    fn test_display_is_one_line() {
+        let text = blocked().to_string();
+        assert_eq!(
+            text,
+            "query blocked (blocked_operation:DROP) from 127.0.0.1:50000 as wordpress: DROP TABLE wp_users"
+        );
+    }
+}
@@ -13,6 +13,7 @@
 use thiserror::Error;
 
 use crate::db_policy::DatabasePolicy;
+use crate::path_rules::PathOverrides;
 
 #[derive(Error, Debug)]
 pub enum FleetError {
@@ -60,9 +61,10 @@ fn default() -> Self {
 }
 
 /// CMS adapter type
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum Adapter {
+    #[default]
     WordPress,
     Drupal,
     Moodle,
@@ -70,9 +72,16 @@ pub enum Adapter {
     Custom,
 }
 
-impl Default for Adapter {
-    fn default() -> Self {
-        Self::WordPress
+impl Adapter {
+    /// The adapter's directory under `adapters/`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::WordPress => "wordpress",
+            Self::Drupal => "drupal",
+            Self::Moodle => "moodle",
+            Self::Joomla => "joomla",
+            Self::Custom => "generic-lamp",
+        }
     }
 }
 
@@ -113,12 +122,21 @@ pub struct Yacht {
     pub ssh_port: u16,
     /// SSH user for deployments
     pub ssh_user: String,
+    /// Port of the yacht agent API
+    #[serde(default = "default_api_port")]
+    pub api_port: u16,
+    /// Whether the yacht agent API is served over TLS
+    #[serde(default)]
+    pub api_tls: bool,
     /// CMS adapter type
     pub adapter: Adapter,
     /// Database configuration
     pub database: DatabaseConfig,
     /// Security policy
     pub policy: PolicyConfig,
+    /// Changes to the adapter's path rules for the airlock
+    #[serde(default)]
+    pub paths: PathOverrides,
     /// Path to web root on the yacht
     pub web_root: String,
     /// Tags for grouping/filtering
@@ -127,6 +145,10 @@ pub struct Yacht {
     pub enabled: bool,
 }
 
+fn default_api_port() -> u16 {
+    9001
+}
+
 impl Default for Yacht {
     fn default() -> Self {
         Self {
@@ -135,9 +157,12 @@ fn default() -> Self {
             domain: String::new(),
             ssh_port: 22,
             ssh_user: "wharf".to_string(),
+            api_port: default_api_port(),
+            api_tls: false,
             adapter: Adapter::default(),
             database: DatabaseConfig::default(),
             policy: PolicyConfig::default(),
+            paths: PathOverrides::default(),
             web_root: "/var/www/html".to_string(),
             tags: Vec::new(),
             enabled: true,
@@ -165,6 +190,12 @@ pub fn ssh_destination(&self) -> String {
     pub fn rsync_destination(&self) -> String {
         format!("{}@{}:{}", self.ssh_user, self.ip, self.web_root)
     }
+
+    /// Get the base URL of the yacht agent API
+    pub fn agent_url(&self) -> String {
+        let scheme = if self.api_tls { "https" } else { "http" };
+        format!("{}://{}:{}", scheme, self.ip, self.api_port)
+    }
 }
 
 /// The complete fleet configuration
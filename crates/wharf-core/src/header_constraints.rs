@@ -0,0 +1,393 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Header Constraints
+//!
+//! The per-header checks of the Airlock (`ingress.constraints` in
+//! `airlock.ncl`, [`HeaderConstraint`] here), compiled once per policy:
+//!
+//! - `max_length`: the longest value allowed for that header; headers
+//!   without one fall back to the policy's `max_header_length`.
+//! - `deny_patterns`: regular expressions the value must not match.
+//! - `must_match_domains`: for Host, the domains this yacht serves. They
+//!   are merged with `HeaderPolicy::allowed_hosts`; with neither set any
+//!   well-formed host is accepted.
+//! - `max_value`: the value must be a plain decimal number up to this.
+//! - `strict_parse`: the value is parsed as a Cookie header and written
+//!   out again from the pairs that are well-formed (RFC 6265), so nothing
+//!   the parser did not understand reaches the application.
+
+use std::collections::HashMap;
+
+use regex::bytes::{Regex, RegexBuilder};
+use thiserror::Error;
+
+use crate::types::{HeaderConstraint, HeaderPolicy};
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ConstraintError {
+    #[error("constraint on {header}: invalid deny pattern '{pattern}': {reason}")]
+    InvalidPattern {
+        header: String,
+        pattern: String,
+        reason: String,
+    },
+}
+
+/// Why a header got a request refused
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ConstraintViolation {
+    #[error("{header} is longer than {max} bytes")]
+    TooLong { header: String, max: usize },
+
+    #[error("{header} matches a denied pattern ('{pattern}')")]
+    DeniedPattern { header: String, pattern: String },
+
+    #[error("host '{host}' is not served here")]
+    UnknownHost { host: String },
+
+    #[error("{header} is not a number")]
+    NotANumber { header: String },
+
+    #[error("{header} is larger than {max}")]
+    ValueTooLarge { header: String, max: u64 },
+}
+
+impl ConstraintViolation {
+    /// The header at fault (lowercase)
+    pub fn header(&self) -> &str {
+        match self {
+            Self::UnknownHost { .. } => "host",
+            Self::TooLong { header, .. }
+            | Self::DeniedPattern { header, .. }
+            | Self::NotANumber { header }
+            | Self::ValueTooLarge { header, .. } => header,
+        }
+    }
+}
+
+/// What to do with a header that passed its constraint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// Pass it on as it is
+    Keep,
+    /// Pass on this value instead (a re-serialized cookie)
+    Replace(String),
+    /// Nothing usable was left in it; drop the header
+    Drop,
+}
+
+struct Compiled {
+    max_length: Option<usize>,
+    deny_patterns: Vec<(String, Regex)>,
+    max_value: Option<u64>,
+    strict_parse: bool,
+}
+
+/// The constraints of one header policy, ready to check values against
+pub struct ConstraintSet {
+    /// By lowercase header name
+    constraints: HashMap<String, Compiled>,
+    /// Lowercase domains Host must be one of (empty: any)
+    hosts: Vec<String>,
+    max_header_length: usize,
+}
+
+impl ConstraintSet {
+    /// Compile the constraints of `policy`
+    pub fn compile(policy: &HeaderPolicy) -> Result<Self, ConstraintError> {
+        let mut constraints = HashMap::new();
+        let mut hosts: Vec<String> = policy.allowed_hosts.iter().map(|h| normalize_host(h)).collect();
+
+        for (name, constraint) in &policy.ingress.constraints {
+            let name = name.to_ascii_lowercase();
+            if name == "host" {
+                hosts.extend(constraint.must_match_domains.iter().map(|h| normalize_host(h)));
+            }
+            constraints.insert(name.clone(), compile(&name, constraint)?);
+        }
+        hosts.sort();
+        hosts.dedup();
+
+        Ok(Self {
+            constraints,
+            hosts,
+            max_header_length: policy.max_header_length,
+        })
+    }
+
+    /// Check a header's length: its own `max_length` on the value, or the
+    /// policy's `max_header_length` on name and value together
+    pub fn check_length(&self, name: &str, value_len: usize) -> Result<(), ConstraintViolation> {
+        let header = name.to_ascii_lowercase();
+        let (length, max) = match self.constraints.get(&header).and_then(|c| c.max_length) {
+            Some(max) => (value_len, max),
+            None => (name.len() + value_len, self.max_header_length),
+        };
+        if length > max {
+            return Err(ConstraintViolation::TooLong { header, max });
+        }
+        Ok(())
+    }
+
+    /// Check an allowed header's value against its constraint
+    pub fn check(&self, name: &str, value: &[u8]) -> Result<Verdict, ConstraintViolation> {
+        let header = name.to_ascii_lowercase();
+        self.check_length(&header, value.len())?;
+
+        if header == "host" {
+            self.check_host(value)?;
+        }
+        let Some(constraint) = self.constraints.get(&header) else {
+            return Ok(Verdict::Keep);
+        };
+
+        if let Some((pattern, _)) = constraint.deny_patterns.iter().find(|(_, re)| re.is_match(value)) {
+            return Err(ConstraintViolation::DeniedPattern {
+                header,
+                pattern: pattern.clone(),
+            });
+        }
+        if let Some(max) = constraint.max_value {
+            let number = std::str::from_utf8(value)
+                .ok()
+                .filter(|v| !v.is_empty() && v.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|v| v.parse::<u64>().ok());
+            match number {
+                None => return Err(ConstraintViolation::NotANumber { header }),
+                Some(n) if n > max => return Err(ConstraintViolation::ValueTooLarge { header, max }),
+                Some(_) => {}
+            }
+        }
+        if constraint.strict_parse {
+            return Ok(reserialize_cookie(value));
+        }
+        Ok(Verdict::Keep)
+    }
+
+    /// Check a request that has no such header at all
+    ///
+    /// Only Host is required, and only when the served domains are known.
+    pub fn check_missing(&self, name: &str) -> Result<(), ConstraintViolation> {
+        if name.eq_ignore_ascii_case("host") && !self.hosts.is_empty() {
+            return Err(ConstraintViolation::UnknownHost { host: String::new() });
+        }
+        Ok(())
+    }
+
+    fn check_host(&self, value: &[u8]) -> Result<(), ConstraintViolation> {
+        let unknown = || ConstraintViolation::UnknownHost {
+            host: String::from_utf8_lossy(value).into_owned(),
+        };
+        let host = std::str::from_utf8(value).map_err(|_| unknown())?;
+        let host = normalize_host(strip_port(host).ok_or_else(unknown)?);
+        if !is_host(&host) {
+            return Err(unknown());
+        }
+        if !self.hosts.is_empty() && !self.hosts.contains(&host) {
+            return Err(unknown());
+        }
+        Ok(())
+    }
+}
+
+fn compile(name: &str, constraint: &HeaderConstraint) -> Result<Compiled, ConstraintError> {
+    let deny_patterns = constraint
+        .deny_patterns
+        .iter()
+        .map(|pattern| {
+            RegexBuilder::new(pattern)
+                .case_insensitive(true)
+                .build()
+                .map(|re| (pattern.clone(), re))
+                .map_err(|e| ConstraintError::InvalidPattern {
+                    header: name.to_string(),
+                    pattern: pattern.clone(),
+                    reason: e.to_string(),
+                })
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Compiled {
+        max_length: constraint.max_length,
+        deny_patterns,
+        max_value: constraint.max_value,
+        strict_parse: constraint.strict_parse,
+    })
+}
+
+/// Lowercase, without the trailing dot of a fully qualified name
+fn normalize_host(host: &str) -> String {
+    host.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// `example.com:8080` -> `example.com`, `[::1]:80` -> `[::1]`; None if the port is not a number
+fn strip_port(host: &str) -> Option<&str> {
+    let (name, port) = match host.rfind(':') {
+        Some(colon) if !host[colon..].contains(']') => (&host[..colon], &host[colon + 1..]),
+        _ => return Some(host),
+    };
+    (!port.is_empty() && port.bytes().all(|b| b.is_ascii_digit())).then_some(name)
+}
+
+/// A DNS name, an IPv4 address or a bracketed IPv6 address
+fn is_host(host: &str) -> bool {
+    if let Some(literal) = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+        return literal.parse::<std::net::Ipv6Addr>().is_ok();
+    }
+    !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        })
+}
+
+/// Keep the well-formed `name=value` pairs of a Cookie header
+fn reserialize_cookie(value: &[u8]) -> Verdict {
+    let Ok(value) = std::str::from_utf8(value) else {
+        return Verdict::Drop;
+    };
+    let pairs: Vec<&str> = value
+        .split(';')
+        .map(|pair| pair.trim_matches([' ', '\t']))
+        .filter(|pair| !pair.is_empty())
+        .filter(|pair| match pair.split_once('=') {
+            Some((name, value)) => is_cookie_name(name) && is_cookie_value(value),
+            None => false,
+        })
+        .collect();
+
+    match pairs.join("; ") {
+        cleaned if cleaned.is_empty() => Verdict::Drop,
+        cleaned if cleaned == value => Verdict::Keep,
+        cleaned => Verdict::Replace(cleaned),
+    }
+}
+
+/// A cookie name is an RFC 9110 token
+fn is_cookie_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// `cookie-value` of RFC 6265: cookie-octets, optionally in double quotes
+fn is_cookie_value(value: &str) -> bool {
+    let value = match value.strip_prefix('"') {
+        Some(quoted) => match quoted.strip_suffix('"') {
+            Some(inner) => inner,
+            None => return false,
+        },
+        None => value,
+    };
+    value
+        .bytes()
+        .all(|b| matches!(b, 0x21 | 0x23..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults() -> ConstraintSet {
+        ConstraintSet::compile(&HeaderPolicy::default()).unwrap()
+    }
+
+    #[test]
+    fn test_user_agent_patterns() {
+        let set = defaults();
+        assert_eq!(set.check("User-Agent", b"Mozilla/5.0"), Ok(Verdict::Keep));
+        assert!(matches!(
+            set.check("user-agent", b"x<SCRIPT>alert(1)"),
+            Err(ConstraintViolation::DeniedPattern { ref pattern, .. }) if pattern == "<script"
+        ));
+        assert!(set.check("User-Agent", b"../../etc/passwd").is_err());
+        assert!(matches!(
+            set.check("User-Agent", "a".repeat(501).as_bytes()),
+            Err(ConstraintViolation::TooLong { max: 500, .. })
+        ));
+    }
+
+    #[test]
+    fn test_host_matches_served_domains() {
+        let mut policy = HeaderPolicy {
+            allowed_hosts: vec!["example.com".to_string()],
+            ..Default::default()
+        };
+        policy
+            .ingress
+            .constraints
+            .get_mut("Host")
+            .unwrap()
+            .must_match_domains = vec!["www.example.com".to_string()];
+        let set = ConstraintSet::compile(&policy).unwrap();
+
+        assert_eq!(set.check("Host", b"example.com"), Ok(Verdict::Keep));
+        assert_eq!(set.check("Host", b"WWW.Example.com.:443"), Ok(Verdict::Keep));
+        assert!(set.check("Host", b"attacker.test").is_err());
+        assert!(set.check("Host", b"example.com:evil").is_err());
+        assert!(set.check_missing("host").is_err());
+
+        // No domains configured: any well-formed host
+        let set = defaults();
+        assert_eq!(set.check("Host", b"anything.test:8080"), Ok(Verdict::Keep));
+        assert_eq!(set.check("Host", b"[::1]:8080"), Ok(Verdict::Keep));
+        assert!(set.check("Host", b"evil.test/x").is_err());
+        assert!(set.check_missing("host").is_ok());
+    }
+
+    #[test]
+    fn test_content_length_max_value() {
+        let set = defaults();
+        assert_eq!(set.check("Content-Length", b"1024"), Ok(Verdict::Keep));
+        assert!(matches!(
+            set.check("Content-Length", b"104857601"),
+            Err(ConstraintViolation::ValueTooLarge { .. })
+        ));
+        assert!(matches!(
+            set.check("Content-Length", b"+12"),
+            Err(ConstraintViolation::NotANumber { .. })
+        ));
+    }
+
+    #[test]
+    fn test_cookie_reserialized() {
+        let set = defaults();
+        assert_eq!(set.check("Cookie", b"a=1; b=\"two\""), Ok(Verdict::Keep));
+        assert_eq!(
+            set.check("Cookie", b"a=1;;b=2 ;bad name=x; c=\"open; d=3"),
+            Ok(Verdict::Replace("a=1; b=2; d=3".to_string()))
+        );
+        assert_eq!(set.check("Cookie", b"=orphan; junk"), Ok(Verdict::Drop));
+    }
+
+    #[test]
+    fn test_global_length_fallback() {
+        let set = defaults();
+        assert!(set.check_length("Accept", 1990).is_ok());
+        assert!(set.check_length("Accept", 1995).is_err());
+        // Cookie has its own, larger limit
+        assert!(set.check_length("Cookie", 4096).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let mut policy = HeaderPolicy::default();
+        policy.ingress.constraints.insert(
+            "Referer".to_string(),
+            HeaderConstraint {
+                deny_patterns: vec!["(unclosed".to_string()],
+                ..Default::default()
+            },
+        );
+        assert!(matches!(
+            ConstraintSet::compile(&policy),
+            Err(ConstraintError::InvalidPattern { ref header, .. }) if header == "referer"
+        ));
+    }
+}
@@ -11,10 +11,13 @@
 use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::{BufReader, Read};
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::time::SystemTime;
 use thiserror::Error;
 
+use crate::crypto::SigningKey;
+use crate::mooring::{CommandEnvelope, MooringCommand};
+
 #[derive(Error, Debug)]
 pub enum IntegrityError {
     #[error("IO error: {0}")]
@@ -35,6 +38,9 @@ pub enum IntegrityError {
 
     #[error("Manifest parse error: {0}")]
     ParseError(String),
+
+    #[error("Signing error: {0}")]
+    SigningError(String),
 }
 
 /// A single file entry in the manifest
@@ -175,15 +181,13 @@ fn walk_directory(
 
 fn should_exclude(path: &str, excludes: &[String]) -> bool {
     for pattern in excludes {
-        if pattern.starts_with('*') {
+        if let Some(suffix) = pattern.strip_prefix('*') {
             // Suffix match (e.g., "*.log")
-            let suffix = &pattern[1..];
             if path.ends_with(suffix) {
                 return true;
             }
-        } else if pattern.ends_with('*') {
+        } else if let Some(prefix) = pattern.strip_suffix('*') {
             // Prefix match (e.g., "test_*")
-            let prefix = &pattern[..pattern.len() - 1];
             if path.starts_with(prefix) {
                 return true;
             }
@@ -455,42 +459,73 @@ pub fn verify_remote_ssh(
     })
 }
 
+/// How long a signed verification request stays valid (seconds)
+const VERIFY_REQUEST_LIFETIME_SECS: i64 = 60;
+
 /// Request verification from a yacht agent via HTTP API
+///
+/// The manifest is sent as a signed `verify_integrity` mooring command to
+/// the agent's `/verify` endpoint, so the agent only hashes its web root
+/// for a captain it trusts. `client` carries the TLS settings for agents
+/// that require a client certificate. Transport and agent errors are
+/// reported in `RemoteVerifyResult::error`, like SSH failures in
+/// `verify_remote_ssh`.
 pub async fn verify_remote_api(
+    client: &reqwest::Client,
     agent_url: &str,
-    _manifest: &Manifest,
+    yacht: &str,
+    manifest: &Manifest,
+    captain: &str,
+    key: &SigningKey,
 ) -> Result<RemoteVerifyResult, IntegrityError> {
-    // This would call the yacht agent's /verify endpoint
-    // For now, return an error indicating it's not implemented
-    // The actual implementation would use reqwest or similar HTTP client
-
-    let yacht_name = agent_url.to_string();
-    let timestamp = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .map(|d| d.as_secs())
-        .unwrap_or(0);
-
-    // In production, this would:
-    // 1. POST the manifest to {agent_url}/verify
-    // 2. The agent would verify against its local files
-    // 3. Return the results
+    let command = MooringCommand::VerifyIntegrity {
+        manifest: manifest.clone(),
+    };
+    let signed = CommandEnvelope::new(yacht, command, VERIFY_REQUEST_LIFETIME_SECS)
+        .sign(captain, key)
+        .map_err(|e| IntegrityError::SigningError(e.to_string()))?;
 
-    Ok(RemoteVerifyResult {
-        yacht: yacht_name,
+    let failed = |error: String| RemoteVerifyResult {
+        yacht: yacht.to_string(),
         passed: false,
         files_checked: 0,
         mismatched: Vec::new(),
         missing: Vec::new(),
         unexpected: Vec::new(),
-        timestamp,
-        error: Some("Remote API verification not yet implemented - use SSH mode".to_string()),
-    })
+        timestamp: SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        error: Some(error),
+    };
+
+    let url = format!("{}/verify", agent_url.trim_end_matches('/'));
+    let response = match client.post(&url).json(&signed).send().await {
+        Ok(response) => response,
+        Err(e) => return Ok(failed(format!("Failed to reach agent at {}: {}", url, e))),
+    };
+
+    let status = response.status();
+    let body = match response.text().await {
+        Ok(body) => body,
+        Err(e) => return Ok(failed(format!("Failed to read agent response: {}", e))),
+    };
+
+    if !status.is_success() {
+        // The agent explains refusals as {"ok": false, "error": "..."}
+        let reason = serde_json::from_str::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|v| v.get("error").and_then(|e| e.as_str()).map(String::from))
+            .unwrap_or(body);
+        return Ok(failed(format!("Agent refused verification ({}): {}", status, reason)));
+    }
+
+    serde_json::from_str(&body).map_err(|e| IntegrityError::ParseError(e.to_string()))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Write;
     use tempfile::tempdir;
 
     #[test]
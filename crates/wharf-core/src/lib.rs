@@ -9,18 +9,40 @@
 //! - SQL AST parsing for the database proxy ("Virtual Sharding")
 //! - Cryptographic utilities (Ed25519 signing, BLAKE3 hashing, Argon2id)
 //! - File integrity verification (BLAKE3 manifests)
+//! - The hash-chained security audit log
+//! - The live security event feed (typed events, SSE decoding)
+//! - Signed mooring commands (envelopes, trust store, replay guard)
+//! - Versioned policy bundles (database + header policy) and their checks
+//! - Per-header constraints for the HTTP airlock
+//! - Content-Security-Policy headers and violation reports
+//! - Request path rules for the airlock, from adapter filesystem policies
+//! - Route classes for the airlock's rate limits
+//! - Set-Cookie attributes enforced by the airlock
+//! - Multipart upload inspection for the airlock
+//! - The yacht agent's configuration file
 //! - File synchronization (rsync over SSH)
 //! - Fleet configuration management
 //! - Configuration types for Nickel schema validation
 //! - Common error types
 
+pub mod agent_config;
+pub mod audit;
+pub mod cookies;
 pub mod crypto;
+pub mod csp;
 pub mod db_policy;
 pub mod errors;
+pub mod events;
 pub mod fleet;
+pub mod header_constraints;
 pub mod integrity;
+pub mod mooring;
+pub mod path_rules;
+pub mod policy;
+pub mod rate_limit;
 pub mod sync;
 pub mod types;
+pub mod uploads;
 
 /// The current version of the Wharf protocol
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -0,0 +1,448 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Mooring Commands
+//!
+//! The Wharf steers a yacht by posting commands to the agent's `/moor`
+//! endpoint. Every command travels in an envelope naming the yacht it is
+//! for, a random nonce and a validity window, signed with a captain's
+//! Ed25519 key.
+//!
+//! The agent only acts on an envelope that:
+//!
+//! 1. is signed by a key in its trust store,
+//! 2. names this yacht,
+//! 3. is inside its validity window (with a little clock skew allowed), and
+//! 4. carries a nonce it has not seen before (across restarts, when the
+//!    spent nonces are kept in a file).
+//!
+//! The signature covers the exact payload bytes, so the envelope is kept as
+//! a JSON string rather than re-serialized on the receiving side.
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::crypto::{self, SigningKey, VerifyingKey};
+use crate::integrity::Manifest;
+use crate::policy::PolicyBundle;
+
+/// Longest validity window an envelope may claim, in seconds
+pub const MAX_LIFETIME_SECS: i64 = 300;
+
+/// Clock skew tolerated between the Wharf and the yacht, in seconds
+pub const CLOCK_SKEW_SECS: i64 = 30;
+
+#[derive(Error, Debug)]
+pub enum MooringError {
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Malformed envelope: {0}")]
+    Malformed(String),
+
+    #[error("Trust store error: {0}")]
+    TrustStore(String),
+
+    #[error("No trusted key for captain '{0}'")]
+    UnknownCaptain(String),
+
+    #[error("Signature verification failed")]
+    BadSignature,
+
+    #[error("Command is addressed to yacht '{0}'")]
+    WrongYacht(String),
+
+    #[error("Command expired")]
+    Expired,
+
+    #[error("Command is not valid yet")]
+    NotYetValid,
+
+    #[error("Command validity window exceeds {MAX_LIFETIME_SECS} seconds")]
+    LifetimeTooLong,
+
+    #[error("Nonce has already been used")]
+    Replay,
+}
+
+/// A command the Wharf can send to a yacht
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MooringCommand {
+    /// Validate and enforce a policy bundle
+    ///
+    /// With a grace period the agent probes the site afterwards and rolls
+    /// back on its own if the site stops answering.
+    PushPolicy {
+        bundle: Box<PolicyBundle>,
+        #[serde(default)]
+        grace_secs: Option<u64>,
+    },
+    /// Enforce an earlier policy version (the previous one by default)
+    RollbackPolicy {
+        #[serde(default)]
+        version: Option<String>,
+    },
+    /// Check the web root against a manifest
+    VerifyIntegrity { manifest: Manifest },
+    /// Add an address to the firewall blocklist
+    BlockIp { ip: Ipv4Addr },
+    /// Remove an address from the firewall blocklist
+    UnblockIp { ip: Ipv4Addr },
+    /// The Wharf is leaving; the yacht stops reporting itself moored
+    Unmoor,
+}
+
+impl MooringCommand {
+    /// Short name for logs
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::PushPolicy { .. } => "push_policy",
+            Self::RollbackPolicy { .. } => "rollback_policy",
+            Self::VerifyIntegrity { .. } => "verify_integrity",
+            Self::BlockIp { .. } => "block_ip",
+            Self::UnblockIp { .. } => "unblock_ip",
+            Self::Unmoor => "unmoor",
+        }
+    }
+}
+
+/// A command addressed to one yacht, before signing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandEnvelope {
+    /// Name of the yacht the command is for
+    pub yacht: String,
+    /// Random value, accepted once
+    pub nonce: String,
+    /// Unix timestamp the command was issued at
+    pub issued_at: i64,
+    /// Unix timestamp after which the command is refused
+    pub expires_at: i64,
+    pub command: MooringCommand,
+}
+
+impl CommandEnvelope {
+    /// Address a command to a yacht, valid for `lifetime_secs` from now
+    pub fn new(yacht: &str, command: MooringCommand, lifetime_secs: i64) -> Self {
+        let now = chrono::Utc::now().timestamp();
+        Self {
+            yacht: yacht.to_string(),
+            nonce: crypto::random_nonce(),
+            issued_at: now,
+            expires_at: now + lifetime_secs,
+            command,
+        }
+    }
+
+    /// Sign the envelope as `captain`
+    pub fn sign(&self, captain: &str, key: &SigningKey) -> Result<SignedEnvelope, MooringError> {
+        let payload = serde_json::to_string(self).map_err(|e| MooringError::Malformed(e.to_string()))?;
+        let signature = crypto::sign(key, payload.as_bytes());
+        Ok(SignedEnvelope {
+            captain: captain.to_string(),
+            payload,
+            signature,
+        })
+    }
+}
+
+/// What is posted to `/moor`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedEnvelope {
+    /// Name of the key in the trust store
+    pub captain: String,
+    /// The `CommandEnvelope` as JSON, exactly as signed
+    pub payload: String,
+    /// Hex Ed25519 signature over `payload`
+    pub signature: String,
+}
+
+/// Trust store file: captain name to hex public key
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TrustStoreFile {
+    #[serde(default)]
+    captains: HashMap<String, String>,
+}
+
+/// The captain keys a yacht accepts commands from
+#[derive(Debug, Default, Clone)]
+pub struct TrustStore {
+    captains: HashMap<String, VerifyingKey>,
+}
+
+impl TrustStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a trust store (TOML if the extension says so, JSON otherwise)
+    pub fn load(path: &Path) -> Result<Self, MooringError> {
+        let content = std::fs::read_to_string(path)?;
+        let file: TrustStoreFile = match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => toml::from_str(&content).map_err(|e| MooringError::TrustStore(e.to_string()))?,
+            _ => serde_json::from_str(&content).map_err(|e| MooringError::TrustStore(e.to_string()))?,
+        };
+
+        let mut store = Self::new();
+        for (name, key) in file.captains {
+            let key = crypto::decode_verifying_key(&key)
+                .map_err(|e| MooringError::TrustStore(format!("captain '{}': {}", name, e)))?;
+            store.add(&name, key);
+        }
+        Ok(store)
+    }
+
+    /// Trust a captain's key
+    pub fn add(&mut self, captain: &str, key: VerifyingKey) {
+        self.captains.insert(captain.to_string(), key);
+    }
+
+    pub fn len(&self) -> usize {
+        self.captains.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.captains.is_empty()
+    }
+
+    /// Check the signature, addressee and validity window of an envelope
+    ///
+    /// Replays are not caught here; see [`ReplayGuard`].
+    pub fn open(&self, signed: &SignedEnvelope, yacht: &str, now: i64) -> Result<CommandEnvelope, MooringError> {
+        let key = self
+            .captains
+            .get(&signed.captain)
+            .ok_or_else(|| MooringError::UnknownCaptain(signed.captain.clone()))?;
+        crypto::verify_signature(key, signed.payload.as_bytes(), &signed.signature)
+            .map_err(|_| MooringError::BadSignature)?;
+
+        let envelope: CommandEnvelope =
+            serde_json::from_str(&signed.payload).map_err(|e| MooringError::Malformed(e.to_string()))?;
+
+        if envelope.yacht != yacht {
+            return Err(MooringError::WrongYacht(envelope.yacht));
+        }
+        if envelope.expires_at - envelope.issued_at > MAX_LIFETIME_SECS {
+            return Err(MooringError::LifetimeTooLong);
+        }
+        if envelope.issued_at > now + CLOCK_SKEW_SECS {
+            return Err(MooringError::NotYetValid);
+        }
+        if envelope.expires_at + CLOCK_SKEW_SECS < now {
+            return Err(MooringError::Expired);
+        }
+        Ok(envelope)
+    }
+}
+
+/// Remembers nonces until their envelopes expire
+///
+/// An expired envelope is refused by [`TrustStore::open`] anyway, so a nonce
+/// only needs to be kept for as long as its envelope could still be valid.
+/// A guard opened on a file writes every nonce it accepts there before
+/// accepting it, so a restart does not make spent envelopes valid again.
+#[derive(Debug, Default)]
+pub struct ReplayGuard {
+    seen: HashMap<String, i64>,
+    path: Option<PathBuf>,
+}
+
+/// Spent nonces file: nonce to the expiry of its envelope
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct NonceFile {
+    #[serde(default)]
+    nonces: HashMap<String, i64>,
+}
+
+impl ReplayGuard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A guard kept in `path`, starting from the nonces already spent there
+    pub fn open(path: &Path) -> Result<Self, MooringError> {
+        let seen = match std::fs::read(path) {
+            Ok(content) => {
+                let file: NonceFile =
+                    serde_json::from_slice(&content).map_err(|e| MooringError::Malformed(e.to_string()))?;
+                file.nonces
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            seen,
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    /// Accept an envelope's nonce once
+    ///
+    /// If the nonce cannot be written to the file the envelope is refused,
+    /// but the nonce still counts as spent.
+    pub fn check(&mut self, envelope: &CommandEnvelope, now: i64) -> Result<(), MooringError> {
+        self.seen.retain(|_, expires_at| *expires_at + CLOCK_SKEW_SECS >= now);
+        if self.seen.contains_key(&envelope.nonce) {
+            return Err(MooringError::Replay);
+        }
+        self.seen.insert(envelope.nonce.clone(), envelope.expires_at);
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), MooringError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = NonceFile {
+            nonces: self.seen.clone(),
+        };
+        let content = serde_json::to_vec(&file).map_err(|e| MooringError::Malformed(e.to_string()))?;
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Number of nonces currently remembered
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn captain() -> (SigningKey, TrustStore) {
+        let key = crypto::generate_signing_key();
+        let mut store = TrustStore::new();
+        store.add("harbourmaster", key.verifying_key());
+        (key, store)
+    }
+
+    fn block(ip: [u8; 4]) -> MooringCommand {
+        MooringCommand::BlockIp { ip: Ipv4Addr::from(ip) }
+    }
+
+    #[test]
+    fn test_signed_command_opens() {
+        let (key, store) = captain();
+        let envelope = CommandEnvelope::new("yacht-01", block([192, 0, 2, 1]), 60);
+        let signed = envelope.sign("harbourmaster", &key).unwrap();
+
+        let opened = store.open(&signed, "yacht-01", envelope.issued_at).unwrap();
+        assert_eq!(opened.nonce, envelope.nonce);
+        assert!(matches!(opened.command, MooringCommand::BlockIp { ip } if ip == Ipv4Addr::new(192, 0, 2, 1)));
+    }
+
+    #[test]
+    fn test_tampered_payload_is_rejected() {
+        let (key, store) = captain();
+        let envelope = CommandEnvelope::new("yacht-01", block([192, 0, 2, 1]), 60);
+        let mut signed = envelope.sign("harbourmaster", &key).unwrap();
+        signed.payload = signed.payload.replace("192.0.2.1", "192.0.2.2");
+
+        assert!(matches!(store.open(&signed, "yacht-01", envelope.issued_at), Err(MooringError::BadSignature)));
+    }
+
+    #[test]
+    fn test_untrusted_key_is_rejected() {
+        let (_, store) = captain();
+        let stranger = crypto::generate_signing_key();
+        let envelope = CommandEnvelope::new("yacht-01", block([192, 0, 2, 1]), 60);
+
+        // Claiming a trusted name does not help
+        let signed = envelope.sign("harbourmaster", &stranger).unwrap();
+        assert!(matches!(store.open(&signed, "yacht-01", envelope.issued_at), Err(MooringError::BadSignature)));
+
+        let signed = envelope.sign("pirate", &stranger).unwrap();
+        assert!(matches!(
+            store.open(&signed, "yacht-01", envelope.issued_at),
+            Err(MooringError::UnknownCaptain(name)) if name == "pirate"
+        ));
+    }
+
+    #[test]
+    fn test_envelope_bounds() {
+        let (key, store) = captain();
+        let envelope = CommandEnvelope::new("yacht-01", block([192, 0, 2, 1]), 60);
+        let signed = envelope.sign("harbourmaster", &key).unwrap();
+        let now = envelope.issued_at;
+
+        assert!(matches!(store.open(&signed, "yacht-02", now), Err(MooringError::WrongYacht(_))));
+        assert!(matches!(store.open(&signed, "yacht-01", now + 60 + CLOCK_SKEW_SECS + 1), Err(MooringError::Expired)));
+        assert!(matches!(store.open(&signed, "yacht-01", now - CLOCK_SKEW_SECS - 1), Err(MooringError::NotYetValid)));
+
+        let long = CommandEnvelope::new("yacht-01", block([192, 0, 2, 1]), MAX_LIFETIME_SECS + 1);
+        let signed = long.sign("harbourmaster", &key).unwrap();
+        assert!(matches!(store.open(&signed, "yacht-01", now), Err(MooringError::LifetimeTooLong)));
+    }
+
+    #[test]
+    fn test_replay_guard() {
+        let envelope = CommandEnvelope::new("yacht-01", block([192, 0, 2, 1]), 60);
+        let mut guard = ReplayGuard::new();
+        let now = envelope.issued_at;
+
+        assert!(guard.check(&envelope, now).is_ok());
+        assert!(matches!(guard.check(&envelope, now + 1), Err(MooringError::Replay)));
+
+        // Nonces are forgotten once their envelope could no longer be opened
+        let later = CommandEnvelope::new("yacht-01", block([192, 0, 2, 1]), 60);
+        guard.check(&later, envelope.expires_at + CLOCK_SKEW_SECS + 1).unwrap();
+        assert_eq!(guard.len(), 1);
+    }
+
+    #[test]
+    fn test_replay_guard_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state/nonces.json");
+        let envelope = CommandEnvelope::new("yacht-01", block([192, 0, 2, 1]), 60);
+        let now = envelope.issued_at;
+
+        let mut guard = ReplayGuard::open(&path).unwrap();
+        assert!(guard.is_empty());
+        guard.check(&envelope, now).unwrap();
+        drop(guard);
+
+        let mut guard = ReplayGuard::open(&path).unwrap();
+        assert_eq!(guard.len(), 1);
+        assert!(matches!(guard.check(&envelope, now + 1), Err(MooringError::Replay)));
+
+        // A file that cannot be written refuses the envelope, and its nonce stays spent
+        let mut guard = ReplayGuard::open(&path).unwrap();
+        std::fs::create_dir(path.with_extension("tmp")).unwrap();
+        let next = CommandEnvelope::new("yacht-01", block([192, 0, 2, 1]), 60);
+        assert!(matches!(guard.check(&next, now), Err(MooringError::IoError(_))));
+        assert!(matches!(guard.check(&next, now), Err(MooringError::Replay)));
+    }
+
+    #[test]
+    fn test_trust_store_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = crypto::generate_signing_key();
+        let path = dir.path().join("captains.toml");
+        std::fs::write(
+            &path,
+            format!("[captains]\nharbourmaster = \"{}\"\n", hex::encode(key.verifying_key().to_bytes())),
+        )
+        .unwrap();
+
+        let store = TrustStore::load(&path).unwrap();
+        assert_eq!(store.len(), 1);
+
+        std::fs::write(&path, "[captains]\nharbourmaster = \"not-a-key\"\n").unwrap();
+        assert!(matches!(TrustStore::load(&path), Err(MooringError::TrustStore(_))));
+    }
+}
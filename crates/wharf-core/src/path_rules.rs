@@ -0,0 +1,818 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Path Rules
+//!
+//! Which request paths the airlock lets reach the application
+//! ([`PathPolicy`], the `paths` block of the header policy).
+//!
+//! The rules come from the adapter's filesystem policy
+//! (`adapters/<adapter>/filesystem.ncl`, see [`from_adapter`]): a directory
+//! with `php_execution = false` becomes a `no_execution` directory, and its
+//! `blocked_extensions` are refused there too. Dotfiles and backup files are
+//! refused everywhere, and a yacht can adjust all of it with
+//! [`PathOverrides`] in the fleet.
+//!
+//! A path is checked the way the web server will read it: percent-decoded,
+//! with `.` and `..` segments resolved and case ignored. A script anywhere
+//! under a no-execution directory is refused, not just at the end, since
+//! `/uploads/shell.php/x.jpg` runs `shell.php` on a server with
+//! `cgi.fix_pathinfo`; so is `shell.php.jpg`, which Apache runs with
+//! `AddHandler`.
+//!
+//! With admin lockdown on, the adapter's admin pages and login forms
+//! ([`admin_paths`]) are only served to the networks in `allow_from`;
+//! administration happens from the Wharf, so the public never needs them.
+//! That includes the routes a front controller takes from the query string
+//! ([`admin_route_params`]), like Drupal 7's `/?q=user/login`, and, for
+//! adapters with language prefixes ([`admin_language_prefix`]), the same
+//! pages under a language code, like `/fr/admin`.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::types::PathPolicy;
+
+/// The only dot-directory requests may name
+const WELL_KNOWN: &str = ".well-known";
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PathRuleError {
+    #[error("paths.{0}: '{1}' is not an absolute path")]
+    InvalidPath(&'static str, String),
+
+    #[error("paths.{0}: '{1}' is not a file extension")]
+    InvalidExtension(&'static str, String),
+
+    #[error("paths.admin.allow_from: '{0}' is not a network")]
+    InvalidNetwork(String),
+
+    #[error("paths.admin: {0}")]
+    InvalidAdmin(String),
+
+    #[error("Invalid adapter filesystem policy: {0}")]
+    InvalidAdapter(String),
+}
+
+/// The rule that refused a path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathRule {
+    /// Not decodable, or climbing out of the root with `..`
+    Malformed,
+    /// A script under a no-execution directory
+    NoExecution,
+    /// A blocked extension under a no-execution directory
+    BlockedExtension,
+    /// On the deny list
+    Denied,
+    /// A dotfile or dot-directory
+    Dotfile,
+    /// A backup or dump file
+    Backup,
+    /// The admin, from outside the admin networks
+    Admin,
+}
+
+impl PathRule {
+    /// Every rule, in metrics order
+    pub const ALL: [PathRule; 7] = [
+        Self::Malformed,
+        Self::NoExecution,
+        Self::BlockedExtension,
+        Self::Denied,
+        Self::Dotfile,
+        Self::Backup,
+        Self::Admin,
+    ];
+
+    /// The rule as a metrics label
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Malformed => "malformed",
+            Self::NoExecution => "no_execution",
+            Self::BlockedExtension => "blocked_extension",
+            Self::Denied => "denied",
+            Self::Dotfile => "dotfile",
+            Self::Backup => "backup",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+/// An IP network in CIDR notation; a bare address is a network of one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    /// Whether `ip` is in the network (IPv4-mapped IPv6 counts as IPv4)
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix)).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix)).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = PathRuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || PathRuleError::InvalidNetwork(s.to_string());
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.trim().parse().map_err(|_| invalid())?;
+        let max: u8 = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().ok().filter(|p| *p <= max).ok_or_else(invalid)?,
+            None => max,
+        };
+        Ok(Self {
+            addr: addr.to_canonical(),
+            prefix: if addr.to_canonical() != addr { prefix.saturating_sub(96) } else { prefix },
+        })
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+// =============================================================================
+// RULES
+// =============================================================================
+
+/// A compiled [`PathPolicy`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathRules {
+    no_execution: Vec<String>,
+    script_extensions: Vec<String>,
+    blocked_extensions: Vec<String>,
+    deny: Vec<String>,
+    allow: Vec<String>,
+    deny_dotfiles: bool,
+    backup_extensions: Vec<String>,
+    admin: Option<Admin>,
+}
+
+/// Compiled admin lockdown
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Admin {
+    paths: Vec<String>,
+    except: Vec<String>,
+    route_params: Vec<String>,
+    language_prefix: bool,
+    allow_from: Vec<Network>,
+    status: u16,
+}
+
+impl PathRules {
+    /// Check and compile a policy
+    pub fn compile(policy: &PathPolicy) -> Result<Self, PathRuleError> {
+        let paths = |list: &'static str, entries: &[String]| -> Result<Vec<String>, PathRuleError> {
+            entries
+                .iter()
+                .map(|entry| {
+                    normalize(entry)
+                        .filter(|_| entry.starts_with('/'))
+                        .ok_or_else(|| PathRuleError::InvalidPath(list, entry.clone()))
+                })
+                .collect()
+        };
+        let extensions = |list: &'static str, entries: &[String]| -> Result<Vec<String>, PathRuleError> {
+            entries
+                .iter()
+                .map(|entry| {
+                    let valid = entry == "~"
+                        || (entry.len() > 1 && entry.starts_with('.') && !entry.contains(['/', '\\']));
+                    if valid {
+                        Ok(entry.to_ascii_lowercase())
+                    } else {
+                        Err(PathRuleError::InvalidExtension(list, entry.clone()))
+                    }
+                })
+                .collect()
+        };
+
+        let backup_extensions = extensions("backup_extensions", &policy.backup_extensions)?;
+        let admin = &policy.admin;
+        if admin.status != 403 && admin.status != 404 {
+            return Err(PathRuleError::InvalidAdmin(format!("status must be 403 or 404, not {}", admin.status)));
+        }
+        if admin.enabled && admin.paths.is_empty() {
+            return Err(PathRuleError::InvalidAdmin("lockdown is enabled but there are no admin paths".to_string()));
+        }
+        if let Some(param) = admin.route_params.iter().find(|p| p.is_empty() || p.contains(['&', '='])) {
+            return Err(PathRuleError::InvalidAdmin(format!("'{}' is not a query parameter", param)));
+        }
+        let allow_from = admin.allow_from.iter().map(|n| n.parse()).collect::<Result<Vec<Network>, _>>()?;
+        let admin = Admin {
+            paths: paths("admin.paths", &admin.paths)?,
+            except: paths("admin.except", &admin.except)?,
+            route_params: admin.route_params.clone(),
+            language_prefix: admin.language_prefix,
+            allow_from,
+            status: admin.status,
+        };
+
+        Ok(Self {
+            no_execution: paths("no_execution", &policy.no_execution)?,
+            script_extensions: extensions("script_extensions", &policy.script_extensions)?,
+            blocked_extensions: extensions("blocked_extensions", &policy.blocked_extensions)?,
+            deny: paths("deny", &policy.deny)?,
+            allow: paths("allow", &policy.allow)?,
+            deny_dotfiles: policy.deny_dotfiles,
+            backup_extensions: if policy.deny_backups { backup_extensions } else { Vec::new() },
+            admin: policy.admin.enabled.then_some(admin),
+        })
+    }
+
+    /// Check the path and query string of a request from `peer`
+    pub fn check(&self, path: &str, query: Option<&str>, peer: IpAddr) -> Result<(), PathRule> {
+        let path = normalize(path).ok_or(PathRule::Malformed)?;
+        if let Some(admin) = &self.admin {
+            if admin.covers(&path, query) && !admin.allow_from.iter().any(|network| network.contains(peer)) {
+                return Err(PathRule::Admin);
+            }
+        }
+        if self.allow.iter().any(|allowed| is_under(&path, allowed)) {
+            return Ok(());
+        }
+        if self.deny.iter().any(|denied| is_under(&path, denied)) {
+            return Err(PathRule::Denied);
+        }
+
+        let segments: Vec<&str> = path.split('/').skip(1).collect();
+        if self.deny_dotfiles
+            && segments
+                .iter()
+                .enumerate()
+                .any(|(i, segment)| segment.starts_with('.') && !(i == 0 && *segment == WELL_KNOWN))
+        {
+            return Err(PathRule::Dotfile);
+        }
+        let file = segments.last().copied().unwrap_or_default();
+        if self.backup_extensions.iter().any(|ext| file.ends_with(ext.as_str())) {
+            return Err(PathRule::Backup);
+        }
+
+        for directory in &self.no_execution {
+            if !is_under(&path, directory) {
+                continue;
+            }
+            let inside = &path[directory.len()..];
+            if inside.split('/').any(|segment| self.is_script(segment)) {
+                return Err(PathRule::NoExecution);
+            }
+            if self.blocked_extensions.iter().any(|ext| file.ends_with(ext.as_str())) {
+                return Err(PathRule::BlockedExtension);
+            }
+        }
+        Ok(())
+    }
+
+    /// The status a request refused by `rule` gets
+    pub fn status(&self, rule: PathRule) -> u16 {
+        match (rule, &self.admin) {
+            (PathRule::Admin, Some(admin)) => admin.status,
+            _ => 403,
+        }
+    }
+
+    /// Whether a file name has a script extension anywhere (`x.php`, `x.php.jpg`)
+    fn is_script(&self, segment: &str) -> bool {
+        segment
+            .split('.')
+            .skip(1)
+            .any(|part| self.script_extensions.iter().any(|ext| ext.strip_prefix('.') == Some(part)))
+    }
+}
+
+impl Admin {
+    /// Whether a request is for the admin, by its path or a route parameter
+    fn covers(&self, path: &str, query: Option<&str>) -> bool {
+        let listed = |path: &str| {
+            self.paths.iter().any(|admin| is_under(path, admin))
+                && !self.except.iter().any(|except| is_under(path, except))
+        };
+        let covered = |path: &str| {
+            listed(path) || (self.language_prefix && without_language(path).is_some_and(|path| listed(&path)))
+        };
+        if covered(path) {
+            return true;
+        }
+        if self.route_params.is_empty() {
+            return false;
+        }
+        query_params(query.unwrap_or_default()).iter().any(|(name, route)| {
+            self.route_params.contains(name) && normalize(&format!("/{}", route)).is_some_and(|route| covered(&route))
+        })
+    }
+}
+
+/// A normalized path without the language code that starts its route
+/// (`/fr/admin` and `/index.php/pt-br/admin` are `/admin` and `/index.php/admin`)
+fn without_language(path: &str) -> Option<String> {
+    let (front, route) = match path.strip_prefix("/index.php/") {
+        Some(route) => ("/index.php", route),
+        None => ("", path.strip_prefix('/')?),
+    };
+    let (language, rest) = route.split_once('/')?;
+    let mut subtags = language.split('-');
+    let primary = subtags.next().unwrap_or_default();
+    let is_language = (2..=3).contains(&primary.len())
+        && primary.bytes().all(|b| b.is_ascii_alphabetic())
+        && subtags.all(|tag| (1..=8).contains(&tag.len()) && tag.bytes().all(|b| b.is_ascii_alphanumeric()));
+    is_language.then(|| format!("{}/{}", front, rest))
+}
+
+/// Whether `path` is `prefix` or inside it
+fn is_under(path: &str, prefix: &str) -> bool {
+    prefix == "/" || (path.starts_with(prefix) && matches!(path.as_bytes().get(prefix.len()), None | Some(b'/')))
+}
+
+/// A path as the web server reads it: decoded, dot-segments resolved, lower case
+///
+/// `None` for undecodable paths, NUL bytes and `..` above the root.
+pub fn normalize(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = match bytes[i] {
+            b'%' => {
+                let hex = bytes.get(i + 1..i + 3).filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+                i += 2;
+                u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?
+            }
+            b => b,
+        };
+        if b == 0 {
+            return None;
+        }
+        decoded.push(if b == b'\\' { b'/' } else { b });
+        i += 1;
+    }
+    let decoded = String::from_utf8(decoded).ok()?.to_lowercase();
+
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            segment => segments.push(segment),
+        }
+    }
+    Some(format!("/{}", segments.join("/")))
+}
+
+/// A query string as PHP reads it into `$_GET`
+///
+/// Names and values are percent-decoded (`+` is a space), `.` and spaces
+/// in a name become `_`, and `name[...]` is filed under `name`, so
+/// `?%73=x`, `?rest.route=x` and `?s[]=x` name `s`, `rest_route` and `s`.
+pub fn query_params(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Some((php_name(&form_decode(name))?, form_decode(value)))
+        })
+        .collect()
+}
+
+/// Percent-decode a query component; invalid escapes are kept as they are
+fn form_decode(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if bytes.get(i + 1..i + 3).is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit)) => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+                decoded.push(u8::from_str_radix(hex, 16).unwrap_or_default());
+                i += 2;
+            }
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// The `$_GET` key PHP files a parameter name under (`None` if it drops it)
+fn php_name(name: &str) -> Option<String> {
+    let name = name.trim_start_matches(' ');
+    let name = name.split('\0').next().unwrap_or_default();
+    let (base, rest) = match name.find('[') {
+        Some(open) if name[open..].contains(']') => (&name[..open], None),
+        Some(open) => (&name[..open], Some(&name[open + 1..])),
+        None => (name, None),
+    };
+    if base.is_empty() {
+        return None;
+    }
+    let mut key: String = base.chars().map(|c| if c == ' ' || c == '.' { '_' } else { c }).collect();
+    if let Some(rest) = rest {
+        key.push('_');
+        key.push_str(rest);
+    }
+    Some(key)
+}
+
+// =============================================================================
+// ADAPTERS AND OVERRIDES
+// =============================================================================
+
+/// Compile an adapter's filesystem policy (`filesystem.ncl`, as exported)
+///
+/// Every directory under `paths` with `php_execution = false` becomes a
+/// no-execution directory. Directories that are not web accessible, or lie
+/// outside a declared `web_root`, are skipped; paths inside the web root
+/// are made relative to it.
+pub fn from_adapter(filesystem: &Value) -> Result<PathPolicy, PathRuleError> {
+    let paths = filesystem
+        .get("paths")
+        .filter(|paths| paths.is_object())
+        .ok_or_else(|| PathRuleError::InvalidAdapter("no paths section".to_string()))?;
+    let web_root = paths
+        .get("web_root")
+        .and_then(|root| root.get("path"))
+        .and_then(Value::as_str)
+        .map(|root| root.trim_end_matches('/'));
+
+    let mut policy = PathPolicy::default();
+    collect_directories(paths, web_root, &mut policy);
+    let adapter = filesystem.get("adapter").and_then(Value::as_str).unwrap_or_default();
+    let (admin, except) = admin_paths(adapter);
+    policy.admin.paths = admin.iter().map(|p| p.to_string()).collect();
+    policy.admin.except = except.iter().map(|p| p.to_string()).collect();
+    policy.admin.route_params = admin_route_params(adapter).iter().map(|p| p.to_string()).collect();
+    policy.admin.language_prefix = admin_language_prefix(adapter);
+    Ok(policy)
+}
+
+/// An adapter's admin pages and login forms, and what under them stays public
+pub fn admin_paths(adapter: &str) -> (&'static [&'static str], &'static [&'static str]) {
+    match adapter {
+        "wordpress" => (
+            &["/wp-admin", "/wp-login.php"],
+            // Front-end AJAX and form handlers live under /wp-admin
+            &["/wp-admin/admin-ajax.php", "/wp-admin/admin-post.php"],
+        ),
+        "drupal" => (
+            &[
+                "/admin",
+                // Login, password reset and one-time login links (and profiles)
+                "/user",
+                // The front controller routes its path info too
+                "/index.php/admin",
+                "/index.php/user",
+            ],
+            &[],
+        ),
+        "joomla" => (&["/administrator"], &[]),
+        "moodle" => (&["/admin"], &[]),
+        _ => (&[], &[]),
+    }
+}
+
+/// Query parameters an adapter takes its route from, besides the path
+pub fn admin_route_params(adapter: &str) -> &'static [&'static str] {
+    match adapter {
+        "drupal" => &["q"],
+        _ => &[],
+    }
+}
+
+/// Whether an adapter's routes may start with a language code
+pub fn admin_language_prefix(adapter: &str) -> bool {
+    adapter == "drupal"
+}
+
+fn collect_directories(value: &Value, web_root: Option<&str>, policy: &mut PathPolicy) {
+    match value {
+        Value::Object(object) => {
+            let no_execution = object.get("php_execution") == Some(&Value::Bool(false))
+                && object.get("web_accessible") != Some(&Value::Bool(false));
+            let path = object.get("path").and_then(Value::as_str);
+            if let (true, Some(path)) = (no_execution, path) {
+                let path = match web_root {
+                    Some(root) => path.strip_prefix(root).filter(|rest| rest.starts_with('/')),
+                    None => Some(path),
+                };
+                if let Some(path) = path.filter(|p| p.starts_with('/') && *p != "/") {
+                    push_unique(&mut policy.no_execution, path.to_string());
+                    let blocked = object.get("blocked_extensions").and_then(Value::as_array);
+                    for ext in blocked.into_iter().flatten().filter_map(Value::as_str) {
+                        push_unique(&mut policy.blocked_extensions, ext.to_ascii_lowercase());
+                    }
+                }
+            }
+            for child in object.values() {
+                collect_directories(child, web_root, policy);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                collect_directories(item, web_root, policy);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn push_unique(list: &mut Vec<String>, item: String) {
+    if !list.contains(&item) {
+        list.push(item);
+    }
+}
+
+/// A yacht's changes to its adapter's path rules (`paths` in the fleet)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathOverrides {
+    /// Refuse `/xmlrpc.php` (pingbacks and credential stuffing)
+    pub block_xmlrpc: bool,
+
+    /// Replaces the policy's `deny_dotfiles`
+    pub deny_dotfiles: Option<bool>,
+
+    /// Replaces the policy's `deny_backups`
+    pub deny_backups: Option<bool>,
+
+    /// More directories nothing is executed from
+    pub no_execution: Vec<String>,
+
+    /// More paths refused outright
+    pub deny: Vec<String>,
+
+    /// More paths let through whatever the rules say
+    pub allow: Vec<String>,
+
+    /// Serve the admin only to `admin_allow_from`
+    pub admin_lockdown: Option<bool>,
+
+    /// More networks the admin is served to
+    pub admin_allow_from: Vec<String>,
+
+    /// Replaces the lockdown's status (403 or 404)
+    pub admin_status: Option<u16>,
+}
+
+impl PathOverrides {
+    /// The policy with these overrides applied
+    pub fn apply(&self, mut policy: PathPolicy) -> PathPolicy {
+        if self.block_xmlrpc {
+            push_unique(&mut policy.deny, "/xmlrpc.php".to_string());
+        }
+        policy.deny_dotfiles = self.deny_dotfiles.unwrap_or(policy.deny_dotfiles);
+        policy.deny_backups = self.deny_backups.unwrap_or(policy.deny_backups);
+        let admin = &mut policy.admin;
+        admin.enabled = self.admin_lockdown.unwrap_or(admin.enabled);
+        admin.status = self.admin_status.unwrap_or(admin.status);
+        for (list, extra) in [
+            (&mut policy.no_execution, &self.no_execution),
+            (&mut policy.deny, &self.deny),
+            (&mut policy.allow, &self.allow),
+            (&mut policy.admin.allow_from, &self.admin_allow_from),
+        ] {
+            for entry in extra {
+                push_unique(list, entry.clone());
+            }
+        }
+        policy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// adapters/wordpress/filesystem.ncl, as exported (abridged)
+    fn wordpress() -> Value {
+        serde_json::json!({
+            "adapter": "wordpress",
+            "paths": {
+                "immutable": ["/wp-admin", "/xmlrpc.php"],
+                "uploads": {
+                    "path": "/wp-content/uploads",
+                    "writable": true,
+                    "php_execution": false,
+                    "blocked_extensions": [".php", ".exe", ".htaccess"],
+                },
+                "cache": { "path": "/wp-content/cache", "php_execution": false },
+                "upgrade": { "path": "/wp-content/upgrade", "writable_normally": false },
+            },
+        })
+    }
+
+    fn public() -> IpAddr {
+        "203.0.113.7".parse().unwrap()
+    }
+
+    fn rules(overrides: PathOverrides) -> PathRules {
+        PathRules::compile(&overrides.apply(from_adapter(&wordpress()).unwrap())).unwrap()
+    }
+
+    #[test]
+    fn test_adapter_compiles_to_no_execution() {
+        let policy = from_adapter(&wordpress()).unwrap();
+        assert_eq!(policy.no_execution, vec!["/wp-content/cache", "/wp-content/uploads"]);
+        assert_eq!(policy.blocked_extensions, vec![".php", ".exe", ".htaccess"]);
+
+        // generic-lamp declares filesystem paths under its web root
+        let lamp = serde_json::json!({"paths": {
+            "web_root": { "path": "/var/www/html" },
+            "writable_dirs": [
+                { "path": "/var/www/html/uploads", "php_execution": false },
+                { "path": "/tmp/php-sessions", "php_execution": false },
+            ],
+            "moodledata": { "path": "/var/moodledata", "php_execution": false, "web_accessible": false },
+        }});
+        assert_eq!(from_adapter(&lamp).unwrap().no_execution, vec!["/uploads"]);
+        assert!(from_adapter(&serde_json::json!({"adapter": "x"})).is_err());
+    }
+
+    #[test]
+    fn test_scripts_refused_under_uploads() {
+        let rules = rules(PathOverrides::default());
+        assert_eq!(rules.check("/wp-content/uploads/2024/01/photo.jpg", None, public()), Ok(()));
+        assert_eq!(rules.check("/wp-content/themes/x/functions.php", None, public()), Ok(()));
+        for path in [
+            "/wp-content/uploads/shell.php",
+            "/wp-content/uploads/shell.PHP",
+            "/wp-content/uploads/shell.php/x.jpg",
+            "/wp-content/uploads/shell.php.jpg",
+            "/wp-content/uploads/2024/shell.phtml",
+            "/wp-content/./uploads//shell.php",
+            "/wp-content/uploads%2fshell%2ephp",
+            "/wp-includes/../wp-content/uploads/shell.php",
+            "/WP-CONTENT/Uploads/shell.php",
+        ] {
+            assert_eq!(rules.check(path, None, public()), Err(PathRule::NoExecution), "{}", path);
+        }
+        assert_eq!(rules.check("/wp-content/uploads/run.exe", None, public()), Err(PathRule::BlockedExtension));
+        assert_eq!(rules.check("/wp-content/uploads-old/shell.php", None, public()), Ok(()));
+        assert_eq!(rules.check("/../etc/passwd", None, public()), Err(PathRule::Malformed));
+        assert_eq!(rules.check("/index.php%00.jpg", None, public()), Err(PathRule::Malformed));
+    }
+
+    #[test]
+    fn test_dotfiles_backups_and_overrides() {
+        let rules = rules(PathOverrides::default());
+        assert_eq!(rules.check("/.git/config", None, public()), Err(PathRule::Dotfile));
+        assert_eq!(rules.check("/wp-content/.env", None, public()), Err(PathRule::Dotfile));
+        assert_eq!(rules.check("/.well-known/acme-challenge/abc", None, public()), Ok(()));
+        assert_eq!(rules.check("/backup.sql", None, public()), Err(PathRule::Backup));
+        assert_eq!(rules.check("/wp-config.php.bak", None, public()), Err(PathRule::Backup));
+        assert_eq!(rules.check("/wp-config.php~", None, public()), Err(PathRule::Backup));
+        assert_eq!(rules.check("/xmlrpc.php", None, public()), Ok(()));
+
+        let rules = self::rules(PathOverrides {
+            block_xmlrpc: true,
+            deny_backups: Some(false),
+            allow: vec!["/wp-content/uploads/legacy/index.php".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(rules.check("/xmlrpc.php", None, public()), Err(PathRule::Denied));
+        assert_eq!(rules.check("/XmlRpc.php", None, public()), Err(PathRule::Denied));
+        assert_eq!(rules.check("/backup.sql", None, public()), Ok(()));
+        assert_eq!(rules.check("/wp-content/uploads/legacy/index.php", None, public()), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_policy_rejected() {
+        let mut policy = PathPolicy::default();
+        policy.deny.push("xmlrpc.php".to_string());
+        assert_eq!(
+            PathRules::compile(&policy),
+            Err(PathRuleError::InvalidPath("deny", "xmlrpc.php".to_string()))
+        );
+
+        let mut policy = PathPolicy::default();
+        policy.script_extensions.push("php".to_string());
+        assert!(matches!(PathRules::compile(&policy), Err(PathRuleError::InvalidExtension(..))));
+    }
+
+    #[test]
+    fn test_admin_lockdown() {
+        let unlocked = rules(PathOverrides::default());
+        assert_eq!(unlocked.check("/wp-login.php", None, public()), Ok(()));
+
+        let rules = rules(PathOverrides {
+            admin_lockdown: Some(true),
+            admin_allow_from: vec!["10.42.0.0/16".to_string(), "fd00:42::/32".to_string()],
+            ..Default::default()
+        });
+        for path in ["/wp-login.php", "/wp-admin/", "/wp-admin/options.php", "/WP-ADMIN/../wp-admin/users.php"] {
+            assert_eq!(rules.check(path, None, public()), Err(PathRule::Admin), "{}", path);
+        }
+        assert_eq!(rules.status(PathRule::Admin), 404);
+        assert_eq!(rules.status(PathRule::Dotfile), 403);
+        // The public site still needs AJAX
+        assert_eq!(rules.check("/wp-admin/admin-ajax.php", None, public()), Ok(()));
+        assert_eq!(rules.check("/wp-admin-theme/style.css", None, public()), Ok(()));
+
+        for captain in ["10.42.1.5", "::ffff:10.42.1.5", "fd00:42::1"] {
+            assert_eq!(rules.check("/wp-admin/options.php", None, captain.parse().unwrap()), Ok(()), "{}", captain);
+        }
+        // Lockdown does not open anything else to the captain
+        assert_eq!(rules.check("/.env", None, "10.42.1.5".parse().unwrap()), Err(PathRule::Dotfile));
+
+        // Drupal, through its front controller and Drupal 7's `q` route
+        let mut drupal = from_adapter(&serde_json::json!({"adapter": "drupal", "paths": {}})).unwrap();
+        drupal.admin.enabled = true;
+        let drupal = PathRules::compile(&drupal).unwrap();
+        for path in [
+            "/user",
+            "/user/login",
+            "/user/reset/1/123/abc",
+            "/index.php/user/login",
+            "/index.php/admin/config",
+            "/INDEX.PHP/./admin",
+            "/en/user/login",
+            "/fr/admin",
+            "/pt-br/admin/config",
+            "/index.php/fr/user",
+        ] {
+            assert_eq!(drupal.check(path, None, public()), Err(PathRule::Admin), "{}", path);
+        }
+        for path in ["/users", "/fr", "/fr/node/1", "/france/admin", "/en/fr/admin"] {
+            assert_eq!(drupal.check(path, None, public()), Ok(()), "{}", path);
+        }
+        for query in [
+            "q=user",
+            "q=user/login",
+            "q=admin",
+            "q=admin/config&destination=node",
+            "%71=user%2Flogin",
+            "q=/Admin/",
+            "q=fr/admin",
+        ] {
+            assert_eq!(drupal.check("/", Some(query), public()), Err(PathRule::Admin), "{}", query);
+            assert_eq!(drupal.check("/index.php", Some(query), public()), Err(PathRule::Admin), "{}", query);
+        }
+        assert_eq!(drupal.check("/index.php", Some("q=node/1"), public()), Ok(()));
+        assert_eq!(drupal.check("/index.php/node/1", Some("q=administrator"), public()), Ok(()));
+        // WordPress takes no route from `q`
+        assert_eq!(rules.check("/", Some("q=wp-admin"), public()), Ok(()));
+    }
+
+    #[test]
+    fn test_query_params_as_php_reads_them() {
+        let names = |query: &str| query_params(query).into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+        assert_eq!(names("s=boats&paged=2"), vec!["s", "paged"]);
+        assert_eq!(names("%73=x&rest.route=&rest%20route=&s[]=x&a[b]=1"), vec!["s", "rest_route", "rest_route", "s", "a"]);
+        assert_eq!(names("+s=x&a[b.c=1&=x&[]=y&"), vec!["s", "a_b.c"]);
+        assert_eq!(
+            query_params("q=user%2Flogin&x=%zz+y"),
+            vec![("q".to_string(), "user/login".to_string()), ("x".to_string(), "%zz y".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_networks() {
+        let network: Network = "10.42.0.0/16".parse().unwrap();
+        assert!(network.contains("10.42.255.1".parse().unwrap()));
+        assert!(!network.contains("10.43.0.1".parse().unwrap()));
+        assert!(!network.contains("fd00::1".parse().unwrap()));
+        assert!("0.0.0.0/0".parse::<Network>().unwrap().contains("192.0.2.1".parse().unwrap()));
+        assert_eq!("192.0.2.1".parse::<Network>().unwrap().to_string(), "192.0.2.1/32");
+        for invalid in ["10.42.0.0/33", "10.42.0/16", "nebula", ""] {
+            assert!(invalid.parse::<Network>().is_err(), "{}", invalid);
+        }
+
+        let mut policy = from_adapter(&wordpress()).unwrap();
+        policy.admin.status = 401;
+        assert!(matches!(PathRules::compile(&policy), Err(PathRuleError::InvalidAdmin(_))));
+        policy.admin.status = 403;
+        policy.admin.allow_from.push("captains".to_string());
+        assert_eq!(PathRules::compile(&policy), Err(PathRuleError::InvalidNetwork("captains".to_string())));
+
+        let drupal = from_adapter(&serde_json::json!({"adapter": "drupal", "paths": {}})).unwrap();
+        assert!(drupal.admin.paths.contains(&"/user".to_string()));
+        assert!(drupal.admin.language_prefix);
+    }
+}
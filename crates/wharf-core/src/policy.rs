@@ -0,0 +1,346 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Policy Bundles
+//!
+//! What the Wharf pushes to a yacht: the database policy and the header
+//! policy together, compiled down to plain data. A bundle is identified by
+//! the hash of its contents, so the same policy always has the same
+//! version on every yacht.
+//!
+//! Bundles are checked before they leave the Wharf and again before an
+//! agent swaps them in. The checks catch mistakes the type system cannot,
+//! such as an empty table name in `allow_write` (which matches every table)
+//! or a header value with a line break in it.
+
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::cookies::CookieRules;
+use crate::crypto::hash_json;
+use crate::csp::CspHeader;
+use crate::db_policy::DatabasePolicy;
+use crate::header_constraints::ConstraintSet;
+use crate::path_rules::PathRules;
+use crate::rate_limit::RateClasses;
+use crate::types::HeaderPolicy;
+use crate::uploads::UploadRules;
+
+#[derive(Error, Debug)]
+pub enum BundleError {
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Policy parse error: {0}")]
+    ParseError(String),
+
+    #[error("Invalid policy: {}", .0.join("; "))]
+    Invalid(Vec<String>),
+}
+
+/// What the airlock may do with a request header that is not allow-listed
+const INGRESS_ACTIONS: &[&str] = &["drop", "allow"];
+
+/// Actions a hybrid rule may take
+const HYBRID_ACTIONS: &[&str] = &["allow", "audit", "block", "deny"];
+
+/// A complete set of policies for one yacht
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PolicyBundle {
+    pub database: DatabasePolicy,
+    pub header: HeaderPolicy,
+}
+
+impl PolicyBundle {
+    /// Content hash of the bundle (BLAKE3 of its canonical JSON)
+    pub fn version(&self) -> String {
+        hash_json(self).unwrap_or_default()
+    }
+
+    /// Check the bundle for mistakes, reporting all of them at once
+    pub fn validate(&self) -> Result<(), BundleError> {
+        let mut problems = Vec::new();
+        check_database(&self.database, &mut problems);
+        check_header(&self.header, &mut problems);
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(BundleError::Invalid(problems))
+        }
+    }
+}
+
+fn check_database(policy: &DatabasePolicy, problems: &mut Vec<String>) {
+    // Table matching is by substring, so an empty name matches every table
+    for (list, tables) in [("allow_write", &policy.allow_write), ("lock_down", &policy.lock_down)] {
+        if tables.iter().any(|t| t.trim().is_empty()) {
+            problems.push(format!("database.{} contains an empty table name", list));
+        }
+    }
+    for table in &policy.allow_write {
+        if policy.lock_down.iter().any(|t| t.eq_ignore_ascii_case(table)) {
+            problems.push(format!("database: table '{}' is both writable and locked down", table));
+        }
+    }
+
+    for rule in &policy.hybrid_rules {
+        if !HYBRID_ACTIONS.contains(&rule.action.as_str()) {
+            problems.push(format!(
+                "database.hybrid_rules: unknown action '{}' (expected one of {})",
+                rule.action,
+                HYBRID_ACTIONS.join(", ")
+            ));
+        }
+        if rule.column.trim().is_empty() {
+            problems.push("database.hybrid_rules: rule without a column".to_string());
+        }
+    }
+
+    for limit in &policy.result_limits {
+        if limit.table.trim().is_empty() {
+            problems.push("database.result_limits: limit without a table".to_string());
+        }
+        if limit.max_rows.is_none() && limit.max_bytes.is_none() {
+            problems.push(format!(
+                "database.result_limits: limit on '{}' sets neither max_rows nor max_bytes",
+                limit.table
+            ));
+        }
+    }
+}
+
+fn check_header(policy: &HeaderPolicy, problems: &mut Vec<String>) {
+    for (list, names) in [
+        ("blocked_headers", &policy.blocked_headers),
+        ("ingress.allow_list", &policy.ingress.allow_list),
+        ("ingress.force_drop", &policy.ingress.force_drop),
+        ("egress.strip", &policy.egress.strip),
+    ] {
+        for name in names {
+            if !is_header_name(name) {
+                problems.push(format!("header.{}: '{}' is not a valid header name", list, name));
+            }
+        }
+    }
+    for (list, forced) in [("forced_headers", &policy.forced_headers), ("egress.force", &policy.egress.force)] {
+        for (name, value) in forced {
+            if !is_header_name(name) {
+                problems.push(format!("header.{}: '{}' is not a valid header name", list, name));
+            }
+            if value.bytes().any(|b| b == b'\r' || b == b'\n' || b == 0) {
+                problems.push(format!("header.{}: value of '{}' contains a line break", list, name));
+            }
+        }
+    }
+    if !INGRESS_ACTIONS.contains(&policy.ingress.default_action.as_str()) {
+        problems.push(format!(
+            "header.ingress.default_action: unknown action '{}' (expected drop or allow)",
+            policy.ingress.default_action
+        ));
+    }
+    for (name, constraint) in &policy.ingress.constraints {
+        if !is_header_name(name) {
+            problems.push(format!("header.ingress.constraints: '{}' is not a valid header name", name));
+        }
+        if constraint.max_length == Some(0) {
+            problems.push(format!("header.ingress.constraints.{}: max_length must be greater than zero", name));
+        }
+        if !constraint.must_match_domains.is_empty() && !name.eq_ignore_ascii_case("host") {
+            problems.push(format!("header.ingress.constraints.{}: must_match_domains only applies to Host", name));
+        }
+        if constraint.must_match_domains.iter().any(|d| d.trim().is_empty()) {
+            problems.push(format!("header.ingress.constraints.{}: must_match_domains contains an empty domain", name));
+        }
+    }
+    if let Err(e) = ConstraintSet::compile(policy) {
+        problems.push(format!("header.{}", e));
+    }
+    if let Err(e) = CspHeader::compile(&policy.csp) {
+        problems.push(format!("header.{}", e));
+    }
+    if let Err(e) = PathRules::compile(&policy.paths) {
+        problems.push(format!("header.{}", e));
+    }
+    if let Err(e) = RateClasses::compile(&policy.rate_limits) {
+        problems.push(format!("header.{}", e));
+    }
+    if let Err(e) = CookieRules::compile(&policy.cookies) {
+        problems.push(format!("header.{}", e));
+    }
+    if let Err(e) = UploadRules::compile(&policy.uploads) {
+        problems.push(format!("header.{}", e));
+    }
+    if policy.max_header_length == 0 {
+        problems.push("header.max_header_length must be greater than zero".to_string());
+    }
+    if policy.allowed_hosts.iter().any(|h| h.trim().is_empty()) {
+        problems.push("header.allowed_hosts contains an empty host".to_string());
+    }
+}
+
+/// An HTTP field name is a non-empty RFC 9110 token
+fn is_header_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Load a policy (or bundle) file, picking the format from the extension
+///
+/// `.toml` and `.ncl` (via `nickel export`, like fleet configs) are
+/// recognised; anything else is read as JSON.
+pub fn load_policy_file<T: DeserializeOwned>(path: &Path) -> Result<T, BundleError> {
+    let parse_error = |e: &dyn std::fmt::Display| BundleError::ParseError(format!("{}: {}", path.display(), e));
+
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => {
+            let content = std::fs::read_to_string(path)?;
+            toml::from_str(&content).map_err(|e| parse_error(&e))
+        }
+        Some("ncl") => {
+            let output = std::process::Command::new("nickel").arg("export").arg(path).output()?;
+            if !output.status.success() {
+                return Err(parse_error(&format!(
+                    "nickel export failed: {}",
+                    String::from_utf8_lossy(&output.stderr)
+                )));
+            }
+            serde_json::from_slice(&output.stdout).map_err(|e| parse_error(&e))
+        }
+        _ => {
+            let content = std::fs::read_to_string(path)?;
+            serde_json::from_str(&content).map_err(|e| parse_error(&e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_policy::{HybridRule, ResultLimit};
+
+    #[test]
+    fn test_default_bundle_is_valid() {
+        assert!(PolicyBundle::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_version_follows_content() {
+        let a = PolicyBundle::default();
+        let mut b = PolicyBundle::default();
+        assert_eq!(a.version(), b.version());
+
+        b.database.allow_write.push("wp_links".to_string());
+        assert_ne!(a.version(), b.version());
+    }
+
+    #[test]
+    fn test_every_problem_is_reported() {
+        let mut bundle = PolicyBundle::default();
+        bundle.database.allow_write.push(String::new());
+        bundle.database.allow_write.push("wp_users".to_string());
+        bundle.database.hybrid_rules.push(HybridRule {
+            action: "permit".to_string(),
+            column: "option_name".to_string(),
+            matches: "^_transient_".to_string(),
+        });
+        bundle.database.result_limits.push(ResultLimit {
+            table: "wp_posts".to_string(),
+            max_rows: None,
+            max_bytes: None,
+            action: Default::default(),
+        });
+        bundle
+            .header
+            .forced_headers
+            .insert("X-Injected".to_string(), "a\r\nSet-Cookie: x=1".to_string());
+        bundle.header.blocked_headers.push("Bad Header".to_string());
+
+        let Err(BundleError::Invalid(problems)) = bundle.validate() else {
+            panic!("bundle should be invalid");
+        };
+        assert_eq!(problems.len(), 6, "{:?}", problems);
+        assert!(problems.iter().any(|p| p.contains("'wp_users' is both writable and locked down")));
+    }
+
+    #[test]
+    fn test_airlock_policy_shape_loads() {
+        // configs/policies/airlock.ncl, as exported
+        let json = serde_json::json!({
+            "ingress": {
+                "default_action": "drop",
+                "allow_list": ["Host", "Cookie"],
+                "constraints": {
+                    "Cookie": { "max_length": 8192, "strict_parse": true },
+                    "Host": { "must_match_domains": [], "max_length": 253 },
+                },
+                "force_drop": ["Proxy"],
+            },
+            "egress": {
+                "strip": ["Server"],
+                "force": { "X-Frame-Options": "DENY" },
+            },
+            "csp": { "enabled": true },
+        });
+        let header: HeaderPolicy = serde_json::from_value(json).unwrap();
+        assert_eq!(header.ingress.allow_list, vec!["Host".to_string(), "Cookie".to_string()]);
+        assert_eq!(header.egress.force["X-Frame-Options"], "DENY");
+        assert_eq!(header.max_header_length, HeaderPolicy::default().max_header_length);
+        assert!(header.ingress.constraints["Cookie"].strict_parse);
+
+        let mut bundle = PolicyBundle {
+            header,
+            ..Default::default()
+        };
+        assert!(bundle.validate().is_ok());
+        bundle.header.ingress.default_action = "pass".to_string();
+        assert!(bundle.validate().is_err());
+
+        bundle.header.ingress.default_action = "drop".to_string();
+        bundle.header.ingress.constraints.get_mut("Cookie").unwrap().deny_patterns = vec!["[".to_string()];
+        let problems = bundle.validate().unwrap_err();
+        assert!(problems.to_string().contains("invalid deny pattern"), "{}", problems);
+
+        bundle.header.ingress.constraints.clear();
+        assert!(bundle.header.csp.enabled);
+        bundle.header.csp.directives.insert("img-src".to_string(), "* ; sandbox".to_string());
+        assert!(bundle.validate().is_err());
+
+        bundle.header.csp = Default::default();
+        bundle.header.paths.no_execution.push("wp-content/uploads".to_string());
+        let problems = bundle.validate().unwrap_err();
+        assert!(problems.to_string().contains("header.paths.no_execution"), "{}", problems);
+
+        bundle.header.paths = Default::default();
+        bundle.header.cookies.rules[0].same_site = Some("None".to_string());
+        bundle.header.cookies.rules[0].secure = false;
+        let problems = bundle.validate().unwrap_err();
+        assert!(problems.to_string().contains("header.cookies.rules.wordpress_*"), "{}", problems);
+
+        bundle.header.cookies = Default::default();
+        bundle.header.uploads.blocked_extensions.push("php".to_string());
+        let problems = bundle.validate().unwrap_err();
+        assert!(problems.to_string().contains("header.uploads.blocked_extensions"), "{}", problems);
+    }
+
+    #[test]
+    fn test_load_bundle_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bundle.json");
+        std::fs::write(&path, serde_json::to_string(&PolicyBundle::default()).unwrap()).unwrap();
+
+        let bundle: PolicyBundle = load_policy_file(&path).unwrap();
+        assert_eq!(bundle.version(), PolicyBundle::default().version());
+
+        std::fs::write(&path, "{ \"database\": 1 }").unwrap();
+        assert!(matches!(
+            load_policy_file::<PolicyBundle>(&path),
+            Err(BundleError::ParseError(_))
+        ));
+    }
+}
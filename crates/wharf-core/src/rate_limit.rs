@@ -0,0 +1,257 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Rate Limit Classes
+//!
+//! Sorts airlock requests into the route classes of the rate limit policy
+//! ([`RateLimitPolicy`]): logins, XML-RPC, the REST API, search. The
+//! buckets themselves are kept by the agent; this is the part that is
+//! checked before a policy is pushed.
+//!
+//! Paths are compared after the same normalization as the path rules
+//! (`path_rules::normalize`), so `/WP-LOGIN.PHP` and `/./wp-login.php` are
+//! limited like `/wp-login.php`. Query parameters are named the way PHP
+//! names them (`path_rules::query_params`), so `?%73=` and `?s[]=` are
+//! searches too.
+
+use std::net::IpAddr;
+
+use thiserror::Error;
+
+use crate::path_rules::{normalize, query_params, Network};
+use crate::types::RateLimitPolicy;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum RateLimitError {
+    #[error("rate_limits.classes: '{0}' is not a valid class name")]
+    InvalidName(String),
+
+    #[error("rate_limits.classes.{0}: {1}")]
+    InvalidClass(String, String),
+
+    #[error("rate_limits.exempt: '{0}' is not a network")]
+    InvalidNetwork(String),
+
+    #[error("rate_limits: escalate_window_secs must be greater than zero")]
+    InvalidWindow,
+}
+
+/// A compiled route class
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateClassRule {
+    pub name: String,
+    /// Tokens added per second
+    pub per_second: f64,
+    /// Bucket size
+    pub burst: f64,
+    paths: Vec<String>,
+    /// Parameter names, with the route they must name if any
+    query_params: Vec<(String, Option<String>)>,
+    methods: Vec<String>,
+}
+
+/// A compiled [`RateLimitPolicy`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateClasses {
+    classes: Vec<RateClassRule>,
+    exempt: Vec<Network>,
+    /// Violations that get a client reported (0: never)
+    pub escalate_after: u32,
+    pub escalate_window_secs: u64,
+}
+
+impl RateClasses {
+    /// Check and compile a policy; a disabled policy has no classes
+    pub fn compile(policy: &RateLimitPolicy) -> Result<Self, RateLimitError> {
+        let mut classes = Vec::new();
+        for (name, class) in &policy.classes {
+            if name.is_empty() || !name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_') {
+                return Err(RateLimitError::InvalidName(name.clone()));
+            }
+            let invalid = |problem: String| RateLimitError::InvalidClass(name.clone(), problem);
+            if class.per_minute == 0 || class.burst == 0 {
+                return Err(invalid("per_minute and burst must be greater than zero".to_string()));
+            }
+            if class.paths.is_empty() && class.query_params.is_empty() {
+                return Err(invalid("matches nothing (no paths or query_params)".to_string()));
+            }
+            let paths = class
+                .paths
+                .iter()
+                .map(|path| {
+                    normalize(path)
+                        .filter(|_| path.starts_with('/'))
+                        .ok_or_else(|| invalid(format!("'{}' is not an absolute path", path)))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if let Some(method) = class.methods.iter().find(|m| m.is_empty() || !m.bytes().all(|b| b.is_ascii_alphabetic())) {
+                return Err(invalid(format!("'{}' is not a method", method)));
+            }
+            let query_params = class
+                .query_params
+                .iter()
+                .map(|param| match param.split_once('=') {
+                    None if !param.is_empty() && !param.contains('&') => Ok((param.clone(), None)),
+                    Some((name, route)) if !name.is_empty() && !param.contains('&') => {
+                        let route = normalize(&format!("/{}", route)).filter(|route| route != "/");
+                        Ok((name.to_string(), Some(route.ok_or_else(|| invalid(format!("'{}' has no route", param)))?)))
+                    }
+                    _ => Err(invalid(format!("'{}' is not a query parameter", param))),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            classes.push(RateClassRule {
+                name: name.clone(),
+                per_second: f64::from(class.per_minute) / 60.0,
+                burst: f64::from(class.burst),
+                paths,
+                query_params,
+                methods: class.methods.iter().map(|m| m.to_ascii_uppercase()).collect(),
+            });
+        }
+        let exempt = policy
+            .exempt
+            .iter()
+            .map(|n| n.parse().map_err(|_| RateLimitError::InvalidNetwork(n.clone())))
+            .collect::<Result<Vec<Network>, _>>()?;
+        if policy.escalate_after > 0 && policy.escalate_window_secs == 0 {
+            return Err(RateLimitError::InvalidWindow);
+        }
+
+        Ok(Self {
+            classes: if policy.enabled { classes } else { Vec::new() },
+            exempt,
+            escalate_after: policy.escalate_after,
+            escalate_window_secs: policy.escalate_window_secs,
+        })
+    }
+
+    /// The class of a request from `peer`, if it is limited at all
+    pub fn classify(&self, method: &str, path: &str, query: Option<&str>, peer: IpAddr) -> Option<&RateClassRule> {
+        if self.classes.is_empty() || self.exempt.iter().any(|network| network.contains(peer)) {
+            return None;
+        }
+        // An undecodable path is refused by the path rules; limit it anyway
+        let path = normalize(path).unwrap_or_default();
+        let params = query_params(query.unwrap_or_default());
+        let under = |path: &str, prefix: &str| {
+            path.starts_with(prefix) && matches!(path.as_bytes().get(prefix.len()), None | Some(b'/'))
+        };
+
+        self.classes.iter().find(|class| {
+            let method_matches = class.methods.is_empty() || class.methods.iter().any(|m| m.eq_ignore_ascii_case(method));
+            let path_matches = class.paths.iter().any(|prefix| under(&path, prefix));
+            let param_matches = class.query_params.iter().any(|(wanted, route)| {
+                params.iter().any(|(name, value)| {
+                    name == wanted
+                        && route.as_ref().is_none_or(|route| {
+                            normalize(&format!("/{}", value)).is_some_and(|value| under(&value, route))
+                        })
+                })
+            });
+            method_matches && (path_matches || param_matches)
+        })
+    }
+}
+
+/// The address a client is counted as: itself, or its /64 for IPv6
+pub fn client_key(ip: IpAddr) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V6(v6) => IpAddr::V6((u128::from(v6) & (u128::MAX << 64)).into()),
+        v4 => v4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::RateClass;
+
+    fn public() -> IpAddr {
+        "203.0.113.7".parse().unwrap()
+    }
+
+    fn class_of(classes: &RateClasses, method: &str, target: &str) -> Option<String> {
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (target, None),
+        };
+        classes.classify(method, path, query, public()).map(|c| c.name.clone())
+    }
+
+    #[test]
+    fn test_default_classes() {
+        let classes = RateClasses::compile(&RateLimitPolicy::default()).unwrap();
+        assert_eq!(class_of(&classes, "POST", "/wp-login.php"), Some("login".to_string()));
+        assert_eq!(class_of(&classes, "POST", "/WP-Login.php"), Some("login".to_string()));
+        // Showing the login form is not an attempt
+        assert_eq!(class_of(&classes, "GET", "/wp-login.php"), None);
+        assert_eq!(class_of(&classes, "POST", "/xmlrpc.php"), Some("xmlrpc".to_string()));
+        assert_eq!(class_of(&classes, "GET", "/wp-json/wp/v2/users"), Some("rest_api".to_string()));
+        assert_eq!(class_of(&classes, "GET", "/?rest_route=/wp/v2/users"), Some("rest_api".to_string()));
+        assert_eq!(class_of(&classes, "GET", "/?s=shoes&paged=2"), Some("search".to_string()));
+        assert_eq!(class_of(&classes, "GET", "/?p=12&sort=s"), None);
+        // Spelled the ways PHP still reads as `s` and `rest_route`
+        for target in ["/?%73=x", "/?s[]=x", "/?+s=x"] {
+            assert_eq!(class_of(&classes, "GET", target), Some("search".to_string()), "{}", target);
+        }
+        for target in ["/?rest.route=/wp/v2/users", "/?rest%20route=/", "/?rest_route[]=/"] {
+            assert_eq!(class_of(&classes, "GET", target), Some("rest_api".to_string()), "{}", target);
+        }
+        // Drupal logins through the front controller
+        for target in ["/index.php/user/login", "/?q=user/login", "/index.php?q=User/Login&destination=node"] {
+            assert_eq!(class_of(&classes, "POST", target), Some("login".to_string()), "{}", target);
+        }
+        assert_eq!(class_of(&classes, "POST", "/?q=node/1"), None);
+        assert_eq!(class_of(&classes, "GET", "/apiary/"), None);
+        assert_eq!(class_of(&classes, "GET", "/2024/01/hello-world/"), None);
+    }
+
+    #[test]
+    fn test_exempt_and_disabled() {
+        let mut policy = RateLimitPolicy {
+            exempt: vec!["10.42.0.0/16".to_string()],
+            ..Default::default()
+        };
+        let classes = RateClasses::compile(&policy).unwrap();
+        assert!(classes.classify("POST", "/xmlrpc.php", None, "10.42.0.9".parse().unwrap()).is_none());
+        assert!(classes.classify("POST", "/xmlrpc.php", None, public()).is_some());
+
+        policy.enabled = false;
+        let classes = RateClasses::compile(&policy).unwrap();
+        assert!(classes.classify("POST", "/xmlrpc.php", None, public()).is_none());
+
+        assert_eq!(client_key("2001:db8:1:2:3:4:5:6".parse().unwrap()), "2001:db8:1:2::".parse::<IpAddr>().unwrap());
+        assert_eq!(client_key("::ffff:192.0.2.1".parse().unwrap()), "192.0.2.1".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn test_invalid_policy_rejected() {
+        let mut policy = RateLimitPolicy::default();
+        policy.classes.insert(
+            "Feeds".to_string(),
+            RateClass {
+                paths: vec!["/feed".to_string()],
+                ..Default::default()
+            },
+        );
+        assert_eq!(RateClasses::compile(&policy), Err(RateLimitError::InvalidName("Feeds".to_string())));
+
+        let mut policy = RateLimitPolicy::default();
+        policy.classes.get_mut("login").unwrap().burst = 0;
+        assert!(matches!(RateClasses::compile(&policy), Err(RateLimitError::InvalidClass(..))));
+
+        let mut policy = RateLimitPolicy::default();
+        policy.classes.insert("everything".to_string(), RateClass::default());
+        assert!(matches!(RateClasses::compile(&policy), Err(RateLimitError::InvalidClass(..))));
+
+        let mut policy = RateLimitPolicy::default();
+        policy.classes.get_mut("login").unwrap().query_params.push("q=".to_string());
+        assert!(matches!(RateClasses::compile(&policy), Err(RateLimitError::InvalidClass(..))));
+
+        let policy = RateLimitPolicy {
+            escalate_window_secs: 0,
+            ..Default::default()
+        };
+        assert_eq!(RateClasses::compile(&policy), Err(RateLimitError::InvalidWindow));
+    }
+}
@@ -4,7 +4,7 @@
 //! Common types for Project Wharf
 
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 /// A Yacht (runtime server) definition
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -80,7 +80,13 @@ pub struct ZoneVariables {
 }
 
 /// HTTP Header policy for the Airlock
+///
+/// Loads from `configs/policies/airlock.ncl` as well as from the flat
+/// form. `blocked_headers` and `forced_headers` predate the ingress/egress
+/// split: blocked headers are dropped in both directions and forced headers
+/// are added to every response, alongside `egress.force`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct HeaderPolicy {
     /// Headers to strip from incoming requests
     pub blocked_headers: Vec<String>,
@@ -93,6 +99,27 @@ pub struct HeaderPolicy {
 
     /// Allowed hosts (for Host header validation)
     pub allowed_hosts: Vec<String>,
+
+    /// What the client may send to the application
+    pub ingress: IngressPolicy,
+
+    /// What the application may send back
+    pub egress: EgressPolicy,
+
+    /// The Content-Security-Policy header
+    pub csp: CspPolicy,
+
+    /// Which request paths reach the application at all
+    pub paths: PathPolicy,
+
+    /// Request rates per client on login, XML-RPC, API and search routes
+    pub rate_limits: RateLimitPolicy,
+
+    /// Attributes forced on the cookies the application sets
+    pub cookies: CookiePolicy,
+
+    /// What may be uploaded through multipart forms
+    pub uploads: UploadPolicy,
 }
 
 impl Default for HeaderPolicy {
@@ -117,6 +144,521 @@ fn default() -> Self {
             forced_headers: forced,
             max_header_length: 2000,
             allowed_hosts: vec![],
+            ingress: IngressPolicy::default(),
+            egress: EgressPolicy::default(),
+            csp: CspPolicy::default(),
+            paths: PathPolicy::default(),
+            rate_limits: RateLimitPolicy::default(),
+            cookies: CookiePolicy::default(),
+            uploads: UploadPolicy::default(),
+        }
+    }
+}
+
+/// Request headers: a positive model (only the allow list reaches the application)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IngressPolicy {
+    /// What happens to headers not on the allow list: "drop" or "allow"
+    pub default_action: String,
+
+    /// Headers passed to the application
+    pub allow_list: Vec<String>,
+
+    /// Headers always dropped, even if allowed
+    pub force_drop: Vec<String>,
+
+    /// Checks on the value of allowed headers, by header name
+    pub constraints: HashMap<String, HeaderConstraint>,
+}
+
+/// What an allowed request header's value must look like
+///
+/// Enforced by `header_constraints::ConstraintSet`; a header that breaks
+/// its constraint gets the whole request refused.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeaderConstraint {
+    /// Longest value allowed, in bytes (replaces `max_header_length`)
+    pub max_length: Option<usize>,
+
+    /// Regular expressions the value must not match (case-insensitive)
+    pub deny_patterns: Vec<String>,
+
+    /// Host only: the domains served, on top of `allowed_hosts`
+    pub must_match_domains: Vec<String>,
+
+    /// The value must be a decimal number no larger than this
+    pub max_value: Option<u64>,
+
+    /// Parse the value as a Cookie header and pass on only well-formed pairs
+    pub strict_parse: bool,
+}
+
+impl Default for IngressPolicy {
+    fn default() -> Self {
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+        Self {
+            default_action: "drop".to_string(),
+            allow_list: names(&[
+                "Host",
+                "User-Agent",
+                "Accept",
+                "Accept-Language",
+                "Accept-Encoding",
+                "Cookie",
+                "Content-Type",
+                "Content-Length",
+                "Origin",
+                "Referer",
+                "Authorization",
+                "X-Requested-With",
+            ]),
+            force_drop: names(&[
+                "X-Forwarded-For",
+                "X-Real-IP",
+                "X-Original-URL",
+                "X-Rewrite-URL",
+                "Proxy",
+                "X-HTTP-Method-Override",
+            ]),
+            constraints: HashMap::from([
+                (
+                    "User-Agent".to_string(),
+                    HeaderConstraint {
+                        max_length: Some(500),
+                        deny_patterns: names(&["\\x00", "<script", "\\.\\./"]),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "Host".to_string(),
+                    HeaderConstraint {
+                        max_length: Some(253),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "Content-Length".to_string(),
+                    HeaderConstraint {
+                        max_value: Some(104_857_600),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "Cookie".to_string(),
+                    HeaderConstraint {
+                        max_length: Some(8192),
+                        strict_parse: true,
+                        ..Default::default()
+                    },
+                ),
+            ]),
+        }
+    }
+}
+
+/// Response headers: leaks stripped, hardening forced
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EgressPolicy {
+    /// Headers removed from every response
+    pub strip: Vec<String>,
+
+    /// Headers set on every response, replacing what the application sent
+    pub force: HashMap<String, String>,
+}
+
+impl Default for EgressPolicy {
+    fn default() -> Self {
+        Self {
+            strip: ["Server", "X-Powered-By", "X-AspNet-Version", "X-AspNetMvc-Version"]
+                .iter()
+                .map(|n| n.to_string())
+                .collect(),
+            force: HashMap::new(),
+        }
+    }
+}
+
+/// The Content-Security-Policy sent with every response (compiled by `csp::CspHeader`)
+///
+/// Off by default: a CSP that does not fit the site breaks it, so start
+/// with `report_only` and tighten from the violation reports.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CspPolicy {
+    pub enabled: bool,
+
+    /// Report violations without blocking anything
+    pub report_only: bool,
+
+    /// Directive name to source list ("" for directives without one)
+    pub directives: BTreeMap<String, String>,
+
+    /// Where browsers send violation reports; the airlock collects them
+    /// itself when this is a path on the site
+    pub report_uri: Option<String>,
+
+    /// Add a fresh nonce to `script-src` and `style-src` on every response
+    /// (the application reads it from the `X-CSP-Nonce` request header)
+    pub nonce: bool,
+}
+
+impl Default for CspPolicy {
+    fn default() -> Self {
+        let directives = [
+            ("default-src", "'self'"),
+            ("script-src", "'self'"),
+            ("style-src", "'self' 'unsafe-inline'"),
+            ("img-src", "'self' data: https:"),
+            ("font-src", "'self' data:"),
+            ("connect-src", "'self'"),
+            ("frame-ancestors", "'none'"),
+            ("base-uri", "'self'"),
+            ("form-action", "'self'"),
+            ("upgrade-insecure-requests", ""),
+        ];
+        Self {
+            enabled: false,
+            report_only: false,
+            directives: directives
+                .iter()
+                .map(|(name, sources)| (name.to_string(), sources.to_string()))
+                .collect(),
+            report_uri: Some("/.well-known/csp-report".to_string()),
+            nonce: false,
+        }
+    }
+}
+
+/// Request paths the airlock refuses (compiled by `path_rules::PathRules`)
+///
+/// Usually derived from the adapter's `filesystem.ncl` (see
+/// `path_rules::from_adapter`) with the yacht's overrides on top. Paths
+/// are matched after percent-decoding and dot-segment removal; a path is
+/// matched as itself and as a directory prefix.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathPolicy {
+    /// Directories nothing is executed from (uploads, caches)
+    pub no_execution: Vec<String>,
+
+    /// Extensions that make a request a script request
+    pub script_extensions: Vec<String>,
+
+    /// Extensions refused under `no_execution` as well
+    pub blocked_extensions: Vec<String>,
+
+    /// Paths refused outright
+    pub deny: Vec<String>,
+
+    /// Refuse paths with a dot-segment (`/.git/`, `/.env`) except `/.well-known/`
+    pub deny_dotfiles: bool,
+
+    /// Refuse files with a `backup_extensions` extension
+    pub deny_backups: bool,
+
+    /// Extensions of dumps, backups and editor leftovers
+    pub backup_extensions: Vec<String>,
+
+    /// Paths let through whatever the rules above say
+    pub allow: Vec<String>,
+
+    /// The admin surface, served only to the captain's networks
+    pub admin: AdminLockdown,
+}
+
+impl Default for PathPolicy {
+    fn default() -> Self {
+        let list = |items: &[&str]| items.iter().map(|i| i.to_string()).collect();
+        Self {
+            no_execution: Vec::new(),
+            script_extensions: list(&[".php", ".php3", ".php4", ".php5", ".php7", ".phtml", ".pht", ".phar"]),
+            blocked_extensions: Vec::new(),
+            deny: Vec::new(),
+            deny_dotfiles: true,
+            deny_backups: true,
+            backup_extensions: list(&[".sql", ".bak", ".old", ".orig", ".save", ".swp", "~"]),
+            allow: Vec::new(),
+            admin: AdminLockdown::default(),
+        }
+    }
+}
+
+/// Admin lockdown: the CMS admin is used from the Wharf, not the internet
+///
+/// `paths` come from the adapter (`path_rules::from_adapter`). Requests for
+/// them from outside `allow_from` get `status`: 404 hides that there is an
+/// admin at all, 403 tells a lost editor it is locked.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminLockdown {
+    pub enabled: bool,
+
+    /// Admin pages and login forms
+    pub paths: Vec<String>,
+
+    /// Paths under `paths` the public site needs (WordPress' `admin-ajax.php`)
+    pub except: Vec<String>,
+
+    /// Query parameters the application also takes its route from (`q` on
+    /// Drupal 7, so `/?q=user/login` is the login form)
+    pub route_params: Vec<String>,
+
+    /// Whether paths may also start with a language code, as on a
+    /// multilingual Drupal where `/fr/admin` is the admin too
+    pub language_prefix: bool,
+
+    /// Networks the admin is served to (CIDR or single addresses), e.g. the
+    /// Nebula captain group
+    pub allow_from: Vec<String>,
+
+    /// 403 or 404
+    pub status: u16,
+}
+
+impl Default for AdminLockdown {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            paths: Vec::new(),
+            except: Vec::new(),
+            route_params: Vec::new(),
+            language_prefix: false,
+            allow_from: Vec::new(),
+            status: 404,
+        }
+    }
+}
+
+/// Token-bucket rate limits per client address and route class
+/// (compiled by `rate_limit::RateClasses`)
+///
+/// A request is in the first class (by name) that matches it; requests in
+/// no class are not limited. IPv6 clients are counted per /64.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitPolicy {
+    pub enabled: bool,
+
+    /// Route classes, by name
+    pub classes: BTreeMap<String, RateClass>,
+
+    /// Limit violations that get a client reported to the shield (0: never)
+    pub escalate_after: u32,
+
+    /// Window the violations are counted in (seconds)
+    pub escalate_window_secs: u64,
+
+    /// Networks never limited (CIDR or single addresses)
+    pub exempt: Vec<String>,
+}
+
+/// One class of routes and its limit
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateClass {
+    /// Paths in the class (a path is matched as itself and as a directory)
+    pub paths: Vec<String>,
+
+    /// Query parameters that put any path in the class (`s` is WordPress
+    /// search); `name=path` only when the parameter routes to that path
+    /// (`q=user/login` on Drupal 7)
+    pub query_params: Vec<String>,
+
+    /// Methods limited (empty: all)
+    pub methods: Vec<String>,
+
+    /// Sustained requests per minute
+    pub per_minute: u32,
+
+    /// Requests allowed in a burst
+    pub burst: u32,
+}
+
+impl Default for RateClass {
+    fn default() -> Self {
+        Self {
+            paths: Vec::new(),
+            query_params: Vec::new(),
+            methods: Vec::new(),
+            per_minute: 60,
+            burst: 10,
+        }
+    }
+}
+
+impl Default for RateLimitPolicy {
+    fn default() -> Self {
+        let list = |items: &[&str]| items.iter().map(|i| i.to_string()).collect::<Vec<_>>();
+        let classes = [
+            (
+                "login",
+                RateClass {
+                    paths: list(&[
+                        "/wp-login.php",
+                        "/user/login",
+                        "/index.php/user/login",
+                        "/administrator/index.php",
+                        "/login/index.php",
+                    ]),
+                    query_params: list(&["q=user/login"]),
+                    methods: list(&["POST"]),
+                    per_minute: 10,
+                    burst: 5,
+                },
+            ),
+            (
+                "xmlrpc",
+                RateClass {
+                    paths: list(&["/xmlrpc.php"]),
+                    per_minute: 6,
+                    burst: 3,
+                    ..Default::default()
+                },
+            ),
+            (
+                "rest_api",
+                RateClass {
+                    paths: list(&["/wp-json", "/jsonapi", "/api"]),
+                    query_params: list(&["rest_route"]),
+                    per_minute: 120,
+                    burst: 60,
+                    ..Default::default()
+                },
+            ),
+            (
+                "search",
+                RateClass {
+                    paths: list(&["/search"]),
+                    query_params: list(&["s"]),
+                    per_minute: 30,
+                    burst: 10,
+                    ..Default::default()
+                },
+            ),
+        ];
+        Self {
+            enabled: true,
+            classes: classes.into_iter().map(|(name, class)| (name.to_string(), class)).collect(),
+            escalate_after: 20,
+            escalate_window_secs: 600,
+            exempt: Vec::new(),
+        }
+    }
+}
+
+/// Set-Cookie attributes enforced per cookie name (compiled by
+/// `cookies::CookieRules`)
+///
+/// The first rule whose pattern matches a cookie applies; attributes the
+/// application already set are kept unless the rule says otherwise.
+/// `Secure` cookies only work over HTTPS, which the airlock assumes is
+/// terminated in front of it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CookiePolicy {
+    pub enabled: bool,
+
+    /// Rules by cookie name, in order
+    pub rules: Vec<CookieRule>,
+}
+
+/// What one kind of cookie must carry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CookieRule {
+    /// Cookie name; `*` matches any run of characters (`wordpress_logged_in_*`)
+    pub name: String,
+
+    pub secure: bool,
+
+    pub http_only: bool,
+
+    /// `Strict`, `Lax` or `None` (unset: left as sent)
+    pub same_site: Option<String>,
+
+    /// Send the cookie to browsers as `__Host-<name>` (Secure, `Path=/`, no
+    /// `Domain`) so no subdomain can plant or overwrite it; the application
+    /// keeps seeing `<name>`
+    pub host_prefix: bool,
+}
+
+impl Default for CookieRule {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            secure: true,
+            http_only: true,
+            same_site: Some("Lax".to_string()),
+            host_prefix: false,
+        }
+    }
+}
+
+impl Default for CookiePolicy {
+    fn default() -> Self {
+        // Session and login cookies of the supported CMSes and plain PHP
+        let sessions = ["wordpress_*", "wp-postpass_*", "SESS*", "SSESS*", "MoodleSession*", "PHPSESSID"];
+        Self {
+            enabled: true,
+            rules: sessions
+                .iter()
+                .map(|name| CookieRule {
+                    name: name.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Multipart uploads inspected by the airlock (compiled by
+/// `uploads::UploadRules`)
+///
+/// The defaults are the `uploads` overlay of the filesystem policy
+/// (`configs/policies/filesystem.ncl`). Files are checked by name,
+/// declared type and content; see `uploads` for what counts as a mismatch.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadPolicy {
+    pub enabled: bool,
+
+    /// Largest file accepted (bytes)
+    pub max_file_size: u64,
+
+    /// Largest multipart body, all fields and files together (bytes)
+    pub max_body_size: u64,
+
+    /// Types a file may be (`image/*` for a whole family; empty: any)
+    pub allowed_mimes: Vec<String>,
+
+    /// Extensions refused anywhere in a file name (`x.php.jpg` too)
+    pub blocked_extensions: Vec<String>,
+}
+
+impl Default for UploadPolicy {
+    fn default() -> Self {
+        let list = |items: &[&str]| items.iter().map(|i| i.to_string()).collect();
+        Self {
+            enabled: true,
+            max_file_size: 104_857_600,
+            max_body_size: 104_857_600,
+            allowed_mimes: list(&[
+                "image/jpeg",
+                "image/png",
+                "image/gif",
+                "image/webp",
+                "image/svg+xml",
+                "application/pdf",
+                "video/mp4",
+                "audio/mpeg",
+            ]),
+            blocked_extensions: list(&[
+                ".php", ".php3", ".php4", ".php5", ".php7", ".php8", ".phtml", ".pht", ".phps", ".phar", ".pgif",
+                ".shtml", ".htaccess", ".cgi", ".pl", ".py", ".sh", ".asp", ".aspx", ".jsp", ".exe",
+            ]),
         }
     }
 }
@@ -0,0 +1,852 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: 2025 Jonathan D. A. Jewell <hyperpolymath>
+
+//! # Upload Inspection
+//!
+//! Checks `multipart/form-data` bodies as they stream through the airlock
+//! against the upload policy ([`UploadPolicy`]). For every file part:
+//!
+//! - the name must not carry a blocked extension anywhere (`x.php.jpg`)
+//!   and must not be a dotfile (`.htaccess`, `.user.ini`);
+//! - the declared type must be allowed;
+//! - the content is sniffed by its magic bytes, and must be what the
+//!   declared type and the extension say it is (a "JPEG" that starts
+//!   with `PK` is not one);
+//! - executables and PHP are refused whatever they claim to be: ELF and
+//!   PE headers, `#!` scripts, and `<?php` or `__HALT_COMPILER` (the phar
+//!   stub) anywhere in the file, which catches image/PHP polyglots. The
+//!   short tags `<?=` and `<?` are short enough to turn up by chance in
+//!   compressed data, so they are looked for throughout text-like files
+//!   but only in the first `SHORT_TAG_SPAN` bytes (the metadata) of images.
+//!
+//! Each file is held to `max_file_size` and the body to `max_body_size`.
+//!
+//! The [`Inspector`] passes bytes on as soon as they are checked, but holds
+//! back the boundary that ends a part until the part has passed, so the
+//! backend never sees a refused file complete. The parser is stricter
+//! than PHP's: a body PHP would split differently (a bare LF before a
+//! boundary, a second `boundary` parameter, folded part headers) is
+//! refused rather than guessed at.
+
+use thiserror::Error;
+
+use crate::types::UploadPolicy;
+
+/// Longest part header block
+const MAX_PART_HEAD: usize = 16 * 1024;
+
+/// Bytes of each file kept for sniffing
+const SNIFF_LEN: usize = 1024;
+
+/// Found anywhere in a file, these make it PHP (compared case-insensitively)
+const PHP_MARKERS: &[&[u8]] = &[b"<?php", b"__halt_compiler"];
+
+/// Short open tags: `<?=`, and `<?` before whitespace
+const PHP_SHORT_TAGS: &[&[u8]] = &[b"<?=", b"<? ", b"<?\t", b"<?\r", b"<?\n"];
+
+/// Bytes at the start of an image the short tags are looked for in
+const SHORT_TAG_SPAN: u64 = 64 * 1024;
+
+/// What `sniff` reports for executables
+const EXECUTABLE: &str = "application/x-executable";
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum UploadError {
+    #[error("uploads.allowed_mimes: '{0}' is not a media type")]
+    InvalidMime(String),
+
+    #[error("uploads.blocked_extensions: '{0}' is not an extension (like '.php')")]
+    InvalidExtension(String),
+
+    #[error("uploads: {0} must be greater than zero")]
+    InvalidLimit(&'static str),
+}
+
+/// Why an upload was refused, as a metric label
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UploadRule {
+    Malformed,
+    TooLarge,
+    BlockedExtension,
+    MimeNotAllowed,
+    TypeMismatch,
+    Executable,
+}
+
+impl UploadRule {
+    pub const ALL: [UploadRule; 6] = [
+        Self::Malformed,
+        Self::TooLarge,
+        Self::BlockedExtension,
+        Self::MimeNotAllowed,
+        Self::TypeMismatch,
+        Self::Executable,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Malformed => "malformed",
+            Self::TooLarge => "too_large",
+            Self::BlockedExtension => "blocked_extension",
+            Self::MimeNotAllowed => "mime_not_allowed",
+            Self::TypeMismatch => "type_mismatch",
+            Self::Executable => "executable",
+        }
+    }
+
+    /// The status a refused upload gets
+    pub fn status(self) -> u16 {
+        match self {
+            Self::Malformed => 400,
+            Self::TooLarge => 413,
+            Self::MimeNotAllowed | Self::TypeMismatch => 415,
+            Self::BlockedExtension | Self::Executable => 403,
+        }
+    }
+}
+
+/// A refused upload
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("{}: {detail}", .rule.as_str())]
+pub struct UploadViolation {
+    pub rule: UploadRule,
+    pub detail: String,
+}
+
+fn violation(rule: UploadRule, detail: impl Into<String>) -> UploadViolation {
+    UploadViolation {
+        rule,
+        detail: detail.into(),
+    }
+}
+
+fn malformed(detail: &str) -> UploadViolation {
+    violation(UploadRule::Malformed, detail)
+}
+
+/// A compiled [`UploadPolicy`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadRules {
+    max_file_size: u64,
+    max_body_size: u64,
+    allowed_mimes: Vec<String>,
+    /// Without the leading dot
+    blocked_extensions: Vec<String>,
+}
+
+impl UploadRules {
+    /// Check and compile a policy; `None` if inspection is disabled
+    pub fn compile(policy: &UploadPolicy) -> Result<Option<Self>, UploadError> {
+        let allowed_mimes = policy
+            .allowed_mimes
+            .iter()
+            .map(|mime| {
+                let lower = mime.to_ascii_lowercase();
+                match lower.split_once('/') {
+                    Some((kind, sub)) if is_token(kind) && (sub == "*" || is_token(sub)) => Ok(canonical(&lower).to_string()),
+                    _ => Err(UploadError::InvalidMime(mime.clone())),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let blocked_extensions = policy
+            .blocked_extensions
+            .iter()
+            .map(|ext| match ext.strip_prefix('.') {
+                Some(bare) if !bare.is_empty() && !bare.contains(['.', '/', '\\']) => Ok(bare.to_ascii_lowercase()),
+                _ => Err(UploadError::InvalidExtension(ext.clone())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if policy.max_file_size == 0 {
+            return Err(UploadError::InvalidLimit("max_file_size"));
+        }
+        if policy.max_body_size == 0 {
+            return Err(UploadError::InvalidLimit("max_body_size"));
+        }
+
+        if !policy.enabled {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            max_file_size: policy.max_file_size,
+            max_body_size: policy.max_body_size,
+            allowed_mimes,
+            blocked_extensions,
+        }))
+    }
+
+    /// An inspector for a request body of `content_type`; `None` if it is
+    /// not a multipart form
+    pub fn inspector(&self, content_type: &str) -> Result<Option<Inspector>, UploadViolation> {
+        let lower = content_type.to_ascii_lowercase();
+        let (media_type, _) = lower.split_once(';').unwrap_or((&lower, ""));
+        let media_type = media_type.trim();
+        if media_type != "multipart/form-data" {
+            if media_type.starts_with("multipart/") {
+                return Err(violation(UploadRule::Malformed, format!("{} bodies are not accepted", media_type)));
+            }
+            return Ok(None);
+        }
+
+        // PHP finds the boundary by substring, so any second mention is ambiguous
+        let at = match lower.match_indices("boundary").map(|(at, _)| at).collect::<Vec<_>>()[..] {
+            [at] => at,
+            _ => return Err(malformed("the content type needs exactly one boundary")),
+        };
+        let before = lower[..at].trim_end_matches([' ', '\t']);
+        let after = lower[at + "boundary".len()..].trim_start_matches([' ', '\t']);
+        if !before.ends_with(';') || !after.starts_with('=') {
+            return Err(malformed("the content type needs exactly one boundary"));
+        }
+        let start = content_type.len() - after.len() + 1;
+        let value = content_type[start..].split(';').next().unwrap_or_default().trim();
+        let boundary = value
+            .strip_prefix('"')
+            .and_then(|quoted| quoted.strip_suffix('"'))
+            .unwrap_or(value);
+        let valid = !boundary.is_empty()
+            && boundary.len() <= 70
+            && !boundary.ends_with(' ')
+            && boundary
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"'()+_,-./:=? ".contains(&b));
+        if !valid {
+            return Err(malformed("invalid boundary"));
+        }
+
+        Ok(Some(Inspector {
+            rules: self.clone(),
+            delimiter: format!("\n--{}", boundary).into_bytes(),
+            state: State::Start,
+            pending: Vec::new(),
+            total: 0,
+            part: None,
+        }))
+    }
+
+    fn blocked(&self, name: &str) -> bool {
+        name.to_ascii_lowercase()
+            .split('.')
+            .skip(1)
+            .map(|ext| ext.trim_end_matches([' ', '\t']))
+            .any(|ext| self.blocked_extensions.iter().any(|blocked| blocked == ext))
+    }
+
+    fn allowed(&self, mime: &str) -> bool {
+        self.allowed_mimes.is_empty()
+            || self.allowed_mimes.iter().any(|allowed| match allowed.strip_suffix("/*") {
+                Some(kind) => mime.split_once('/').is_some_and(|(k, _)| k == kind),
+                None => allowed == mime,
+            })
+    }
+}
+
+// =============================================================================
+// INSPECTOR
+// =============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Before the first boundary
+    Start,
+    /// After a boundary: `--` ends the body, CRLF starts a part
+    AfterDelimiter,
+    Headers,
+    Body,
+    /// After the closing boundary; ignored by everyone
+    Epilogue,
+}
+
+/// A file part being read
+#[derive(Debug)]
+struct File {
+    name: String,
+    declared: String,
+    size: u64,
+    /// The first `SNIFF_LEN` bytes
+    head: Vec<u8>,
+    /// The last bytes seen, so markers split across chunks are found
+    tail: Vec<u8>,
+    /// Bytes at the start of the file the short tags are looked for in
+    short_tag_span: u64,
+}
+
+/// Inspects one multipart body, chunk by chunk
+#[derive(Debug)]
+pub struct Inspector {
+    rules: UploadRules,
+    /// `\n--boundary`; the CR before it is checked separately
+    delimiter: Vec<u8>,
+    state: State,
+    /// Bytes not yet passed on
+    pending: Vec<u8>,
+    total: u64,
+    /// The current part, if it is a file
+    part: Option<File>,
+}
+
+impl Inspector {
+    /// Check `input`, appending what may be passed on to `out`
+    pub fn feed(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<(), UploadViolation> {
+        self.total += input.len() as u64;
+        if self.total > self.rules.max_body_size {
+            return Err(violation(
+                UploadRule::TooLarge,
+                format!("the body is larger than {} bytes", self.rules.max_body_size),
+            ));
+        }
+        self.pending.extend_from_slice(input);
+
+        loop {
+            match self.state {
+                State::Start => {
+                    let open = &self.delimiter[1..];
+                    if self.pending.len() < open.len() {
+                        if !open.starts_with(&self.pending) {
+                            return Err(malformed("the body does not start with the boundary"));
+                        }
+                        return Ok(());
+                    }
+                    if !self.pending.starts_with(open) {
+                        return Err(malformed("the body does not start with the boundary"));
+                    }
+                    self.release(open.len(), out);
+                    self.state = State::AfterDelimiter;
+                }
+                State::AfterDelimiter => {
+                    if self.pending.len() < 2 {
+                        return Ok(());
+                    }
+                    self.state = match &self.pending[..2] {
+                        b"--" => State::Epilogue,
+                        b"\r\n" => State::Headers,
+                        _ => return Err(malformed("a boundary is followed by something other than CRLF or --")),
+                    };
+                    self.release(2, out);
+                }
+                State::Headers => match find(&self.pending, b"\r\n\r\n") {
+                    Some(end) => {
+                        self.start_part(end)?;
+                        self.release(end + 4, out);
+                        self.state = State::Body;
+                    }
+                    None if self.pending.len() > MAX_PART_HEAD => return Err(malformed("part headers too long")),
+                    None => return Ok(()),
+                },
+                State::Body => match find(&self.pending, &self.delimiter) {
+                    Some(at) => {
+                        if at == 0 || self.pending[at - 1] != b'\r' {
+                            return Err(malformed("a line before a boundary does not end in CRLF"));
+                        }
+                        self.content(at - 1, out)?;
+                        self.finish_part()?;
+                        self.release(1 + self.delimiter.len(), out);
+                        self.state = State::AfterDelimiter;
+                    }
+                    None => {
+                        // Keep enough to see a boundary (and its CR) that is cut off
+                        let safe = self.pending.len().saturating_sub(self.delimiter.len());
+                        return self.content(safe, out);
+                    }
+                },
+                State::Epilogue => {
+                    self.release(self.pending.len(), out);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// The body has ended; append what is left to `out`
+    pub fn finish(&mut self, out: &mut Vec<u8>) -> Result<(), UploadViolation> {
+        if self.state != State::Epilogue {
+            return Err(malformed("the body ends before the closing boundary"));
+        }
+        self.release(self.pending.len(), out);
+        Ok(())
+    }
+
+    fn release(&mut self, len: usize, out: &mut Vec<u8>) {
+        out.extend(self.pending.drain(..len));
+    }
+
+    /// Read the headers of a part (`pending[..end]`)
+    fn start_part(&mut self, end: usize) -> Result<(), UploadViolation> {
+        let block = String::from_utf8_lossy(&self.pending[..end]).into_owned();
+        let mut disposition = None;
+        let mut content_type = None;
+        for line in block.split("\r\n") {
+            if line.contains(['\r', '\n']) || line.starts_with([' ', '\t']) {
+                return Err(malformed("part headers with bare line breaks or folding"));
+            }
+            let (name, value) = line.split_once(':').ok_or_else(|| malformed("invalid part header"))?;
+            let slot = match name.trim().to_ascii_lowercase().as_str() {
+                "content-disposition" => &mut disposition,
+                "content-type" => &mut content_type,
+                _ => continue,
+            };
+            if slot.replace(value.trim().to_string()).is_some() {
+                return Err(malformed("a part header appears twice"));
+            }
+        }
+
+        let disposition = disposition.ok_or_else(|| malformed("a part has no Content-Disposition"))?;
+        let (kind, parameters) = parameters(&disposition)?;
+        if kind != "form-data" {
+            return Err(malformed("a part is not form-data"));
+        }
+        let mut names = Vec::new();
+        for (name, value) in &parameters {
+            match name.as_str() {
+                "filename" => names.push(value.clone()),
+                "filename*" => names.push(extended_value(value)),
+                _ => {}
+            }
+        }
+        let declared = content_type
+            .as_deref()
+            .map(|t| t.split(';').next().unwrap_or_default().trim().to_ascii_lowercase())
+            .unwrap_or_else(|| "text/plain".to_string());
+        if declared.starts_with("multipart/") {
+            return Err(malformed("nested multipart parts are not accepted"));
+        }
+
+        self.part = None;
+        if names.iter().all(|name| name.is_empty()) {
+            return Ok(());
+        }
+        for name in &names {
+            if name.chars().any(char::is_control) {
+                return Err(violation(UploadRule::Malformed, format!("file name {:?} has control characters", name)));
+            }
+            let base = name.rsplit(['/', '\\']).next().unwrap_or_default();
+            if base.starts_with('.') || self.rules.blocked(base) {
+                return Err(violation(UploadRule::BlockedExtension, format!("file name {:?} is not accepted", name)));
+            }
+        }
+        let declared = canonical(&declared).to_string();
+        if !self.rules.allowed(&declared) {
+            return Err(violation(UploadRule::MimeNotAllowed, format!("{} files are not accepted", declared)));
+        }
+        self.part = Some(File {
+            name: names.into_iter().find(|n| !n.is_empty()).unwrap_or_default(),
+            short_tag_span: short_tag_span(&declared),
+            declared,
+            size: 0,
+            head: Vec::new(),
+            tail: Vec::new(),
+        });
+        Ok(())
+    }
+
+    /// Check and pass on `pending[..len]`, part content
+    fn content(&mut self, len: usize, out: &mut Vec<u8>) -> Result<(), UploadViolation> {
+        if let Some(file) = self.part.as_mut() {
+            let data = &self.pending[..len];
+            file.size += len as u64;
+            if file.size > self.rules.max_file_size {
+                return Err(violation(
+                    UploadRule::TooLarge,
+                    format!("{:?} is larger than {} bytes", file.name, self.rules.max_file_size),
+                ));
+            }
+            let wanted = SNIFF_LEN.saturating_sub(file.head.len()).min(data.len());
+            file.head.extend_from_slice(&data[..wanted]);
+
+            let mut window = std::mem::take(&mut file.tail);
+            let start = (file.size - len as u64).saturating_sub(window.len() as u64);
+            window.extend_from_slice(data);
+            let short_tags = start < file.short_tag_span && PHP_SHORT_TAGS.iter().any(|tag| find(&window, tag).is_some());
+            if short_tags || PHP_MARKERS.iter().any(|marker| contains_ignore_case(&window, marker)) {
+                return Err(violation(UploadRule::Executable, format!("{:?} contains PHP", file.name)));
+            }
+            let keep = PHP_MARKERS.iter().map(|m| m.len()).max().unwrap_or(1) - 1;
+            file.tail = window.split_off(window.len().saturating_sub(keep));
+        }
+        self.release(len, out);
+        Ok(())
+    }
+
+    /// A part is complete: check what its content turned out to be
+    fn finish_part(&mut self) -> Result<(), UploadViolation> {
+        let Some(file) = self.part.take() else {
+            return Ok(());
+        };
+        let sniffed = sniff(&file.head);
+        let described = |what: Option<&str>| what.unwrap_or("not a recognized type").to_string();
+        if sniffed == Some(EXECUTABLE) {
+            return Err(violation(UploadRule::Executable, format!("{:?} is an executable", file.name)));
+        }
+
+        let extension = file.name.rsplit_once('.').and_then(|(_, ext)| extension_type(ext));
+        let declared = Some(family(&file.declared)).filter(|t| SNIFFABLE.contains(t));
+        for expected in [declared, extension].into_iter().flatten() {
+            if sniffed.map(family) != Some(expected) {
+                return Err(violation(
+                    UploadRule::TypeMismatch,
+                    format!("{:?} should be {} but is {}", file.name, expected, described(sniffed)),
+                ));
+            }
+        }
+        if let Some(actual) = sniffed.filter(|actual| !self.rules.allowed(actual)) {
+            return Err(violation(UploadRule::MimeNotAllowed, format!("{:?} is {}", file.name, actual)));
+        }
+        Ok(())
+    }
+}
+
+// =============================================================================
+// TYPES
+// =============================================================================
+
+/// Families `sniff` can recognize
+const SNIFFABLE: &[&str] = &[
+    "image/jpeg",
+    "image/png",
+    "image/gif",
+    "image/webp",
+    "image/svg+xml",
+    "application/pdf",
+    "video/mp4",
+    "audio/mpeg",
+    "application/zip",
+];
+
+/// The type of a file by its first bytes
+fn sniff(head: &[u8]) -> Option<&'static str> {
+    let at = |offset: usize, magic: &[u8]| head.get(offset..offset + magic.len()) == Some(magic);
+    if at(0, b"\x7fELF") || at(0, b"MZ") || at(0, b"#!") {
+        return Some(EXECUTABLE);
+    }
+    if at(0, b"\xff\xd8\xff") {
+        return Some("image/jpeg");
+    }
+    if at(0, b"\x89PNG\r\n\x1a\n") {
+        return Some("image/png");
+    }
+    if at(0, b"GIF87a") || at(0, b"GIF89a") {
+        return Some("image/gif");
+    }
+    if at(0, b"RIFF") && at(8, b"WEBP") {
+        return Some("image/webp");
+    }
+    if at(0, b"%PDF-") {
+        return Some("application/pdf");
+    }
+    if at(4, b"ftyp") {
+        return Some("video/mp4");
+    }
+    if at(0, b"ID3") || (head.len() >= 2 && head[0] == 0xff && head[1] & 0xe0 == 0xe0) {
+        return Some("audio/mpeg");
+    }
+    if at(0, b"PK\x03\x04") {
+        return Some("application/zip");
+    }
+    let text = head.strip_prefix(b"\xef\xbb\xbf").unwrap_or(head);
+    let text = &text[text.iter().take_while(|b| b.is_ascii_whitespace()).count()..];
+    if text.starts_with(b"<?xml") || (text.starts_with(b"<") && contains_ignore_case(text, b"<svg")) {
+        return Some("image/svg+xml");
+    }
+    None
+}
+
+/// The type an extension promises, for extensions `sniff` can check
+fn extension_type(extension: &str) -> Option<&'static str> {
+    Some(match extension.to_ascii_lowercase().as_str() {
+        "jpg" | "jpeg" | "jpe" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "pdf" => "application/pdf",
+        "mp4" | "m4v" | "m4a" | "mov" => "video/mp4",
+        "mp3" => "audio/mpeg",
+        "zip" | "docx" | "xlsx" | "pptx" | "odt" | "ods" | "odp" | "epub" => "application/zip",
+        _ => return None,
+    })
+}
+
+/// How much of a file of the declared type the short tags are looked for in
+///
+/// All of a text-like file; the metadata of an image; none of other
+/// binary types, whose compressed content would match them by chance.
+fn short_tag_span(declared: &str) -> u64 {
+    match family(declared) {
+        "image/svg+xml" => u64::MAX,
+        image if image.starts_with("image/") => SHORT_TAG_SPAN,
+        binary if SNIFFABLE.contains(&binary) => 0,
+        _ => u64::MAX,
+    }
+}
+
+/// The usual name of a type browsers send under several
+fn canonical(mime: &str) -> &str {
+    match mime {
+        "image/jpg" | "image/pjpeg" => "image/jpeg",
+        "image/x-png" => "image/png",
+        "audio/mp3" | "audio/mpeg3" | "audio/x-mpeg" => "audio/mpeg",
+        other => other,
+    }
+}
+
+/// Types that share a container, and so a signature
+fn family(mime: &str) -> &str {
+    match canonical(mime) {
+        "audio/mp4" | "audio/x-m4a" | "video/quicktime" | "video/x-m4v" => "video/mp4",
+        "application/x-zip-compressed" | "application/epub+zip" => "application/zip",
+        other if other.starts_with("application/vnd.openxmlformats-officedocument.") => "application/zip",
+        other if other.starts_with("application/vnd.oasis.opendocument.") => "application/zip",
+        other => other,
+    }
+}
+
+// =============================================================================
+// PARSING
+// =============================================================================
+
+/// The value and parameters of a Content-Disposition (names lowercased)
+fn parameters(value: &str) -> Result<(String, Vec<(String, String)>), UploadViolation> {
+    let (kind, mut rest) = value.split_once(';').unwrap_or((value, ""));
+    let mut parameters: Vec<(String, String)> = Vec::new();
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ';']);
+        if rest.is_empty() {
+            break;
+        }
+        let end = rest.find(['=', ';']).unwrap_or(rest.len());
+        let name = rest[..end].trim().to_ascii_lowercase();
+        rest = &rest[end..];
+        let mut value = String::new();
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start_matches([' ', '\t']);
+            if let Some(quoted) = after.strip_prefix('"') {
+                let mut chars = quoted.char_indices();
+                let mut closed = None;
+                while let Some((at, c)) = chars.next() {
+                    match c {
+                        '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                        '"' => {
+                            closed = Some(at + 1);
+                            break;
+                        }
+                        c => value.push(c),
+                    }
+                }
+                rest = &quoted[closed.ok_or_else(|| malformed("unterminated quoted parameter"))?..];
+            } else {
+                let end = after.find(';').unwrap_or(after.len());
+                value = after[..end].trim().to_string();
+                rest = &after[end..];
+            }
+        }
+        if parameters.iter().any(|(seen, _)| *seen == name) {
+            return Err(malformed("a part parameter appears twice"));
+        }
+        parameters.push((name, value));
+    }
+    Ok((kind.trim().to_ascii_lowercase(), parameters))
+}
+
+/// Decode an RFC 8187 value (`UTF-8''shell%2Ephp`)
+fn extended_value(value: &str) -> String {
+    let encoded = value.splitn(3, '\'').nth(2).unwrap_or(value);
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.filter(|h| h.bytes().all(|b| b.is_ascii_hexdigit()))) {
+            (b'%', Some(hex)) => {
+                decoded.push(u8::from_str_radix(hex, 16).unwrap_or_default());
+                i += 3;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$&-^_.+".contains(&b))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn contains_ignore_case(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window.eq_ignore_ascii_case(needle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    fn rules() -> UploadRules {
+        UploadRules::compile(&UploadPolicy::default()).unwrap().unwrap()
+    }
+
+    fn form(parts: &[(&str, &str, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (disposition, content_type, content) in parts {
+            body.extend_from_slice(format!("--XyZ\r\nContent-Disposition: form-data; {}\r\n", disposition).as_bytes());
+            if !content_type.is_empty() {
+                body.extend_from_slice(format!("Content-Type: {}\r\n", content_type).as_bytes());
+            }
+            body.extend_from_slice(b"\r\n");
+            body.extend_from_slice(content);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(b"--XyZ--\r\n");
+        body
+    }
+
+    /// Feed `body` in chunks of `size`; what was passed on, or why not
+    fn inspect(body: &[u8], size: usize) -> Result<Vec<u8>, UploadViolation> {
+        let mut inspector = rules().inspector("multipart/form-data; boundary=XyZ")?.unwrap();
+        let mut out = Vec::new();
+        for chunk in body.chunks(size) {
+            inspector.feed(chunk, &mut out)?;
+        }
+        inspector.finish(&mut out)?;
+        Ok(out)
+    }
+
+    fn rule(body: &[u8]) -> UploadRule {
+        inspect(body, 7).unwrap_err().rule
+    }
+
+    #[test]
+    fn test_valid_upload_passes_unchanged() {
+        let body = form(&[
+            (r#"name="title""#, "", b"Holiday <?php in a caption is fine"),
+            (r#"name="file"; filename="beach.png""#, "image/png", PNG),
+            (r#"name="empty"; filename="""#, "application/octet-stream", b""),
+        ]);
+        for size in [1, 3, 64, body.len()] {
+            assert_eq!(inspect(&body, size).unwrap(), body, "chunks of {}", size);
+        }
+    }
+
+    #[test]
+    fn test_dangerous_files_refused() {
+        let php = form(&[(r#"name="f"; filename="shell.php""#, "image/png", PNG)]);
+        assert_eq!(rule(&php), UploadRule::BlockedExtension);
+        let double = form(&[(r#"name="f"; filename="shell.PHP.png""#, "image/png", PNG)]);
+        assert_eq!(rule(&double), UploadRule::BlockedExtension);
+        let encoded = form(&[(r#"name="f"; filename="a.png"; filename*=UTF-8''a%2Ephtml"#, "image/png", PNG)]);
+        assert_eq!(rule(&encoded), UploadRule::BlockedExtension);
+        let dotfile = form(&[(r#"name="f"; filename=".htaccess""#, "image/png", PNG)]);
+        assert_eq!(rule(&dotfile), UploadRule::BlockedExtension);
+
+        let polyglot = form(&[(r#"name="f"; filename="cat.gif""#, "image/gif", b"GIF89a\x01\x00<?PhP system($_GET[1]);")]);
+        assert_eq!(rule(&polyglot), UploadRule::Executable);
+        let phar = form(&[(r#"name="f"; filename="x.jpg""#, "image/jpeg", b"\xff\xd8\xff\xe0 __HALT_COMPILER(); ?>")]);
+        assert_eq!(rule(&phar), UploadRule::Executable);
+        let short = form(&[(r#"name="f"; filename="cat.gif""#, "image/gif", b"GIF89a\x01\x00<?=`$_GET[1]`?>")]);
+        assert_eq!(rule(&short), UploadRule::Executable);
+        let text = inspect_with(
+            &UploadPolicy {
+                allowed_mimes: vec!["text/plain".to_string()],
+                ..Default::default()
+            },
+            &form(&[(r#"name="f"; filename="notes.txt""#, "text/plain", b"notes\n<?\nsystem($_GET[1]);")]),
+        );
+        assert_eq!(text, Some(UploadRule::Executable));
+
+        // Past the metadata, a short tag in an image is taken for pixel data
+        let mut photo = b"\xff\xd8\xff\xe0".to_vec();
+        photo.resize(SHORT_TAG_SPAN as usize + 4096, 0x55);
+        photo.extend_from_slice(b"<?=");
+        let photo = form(&[(r#"name="f"; filename="photo.jpg""#, "image/jpeg", &photo)]);
+        assert!(inspect(&photo, 1000).is_ok());
+        let elf = form(&[(r#"name="f"; filename="x""#, "", b"\x7fELF\x02\x01")]);
+        assert_eq!(inspect_with(&UploadPolicy { allowed_mimes: vec![], ..Default::default() }, &elf), Some(UploadRule::Executable));
+    }
+
+    fn inspect_with(policy: &UploadPolicy, body: &[u8]) -> Option<UploadRule> {
+        let mut inspector = UploadRules::compile(policy)
+            .unwrap()
+            .unwrap()
+            .inspector("multipart/form-data; boundary=XyZ")
+            .unwrap()
+            .unwrap();
+        let mut out = Vec::new();
+        inspector
+            .feed(body, &mut out)
+            .and_then(|()| inspector.finish(&mut out))
+            .err()
+            .map(|v| v.rule)
+    }
+
+    #[test]
+    fn test_types_checked_against_content() {
+        let disguised = form(&[(r#"name="f"; filename="photo.jpg""#, "image/jpeg", PNG)]);
+        assert_eq!(rule(&disguised), UploadRule::TypeMismatch);
+        let renamed = form(&[(r#"name="f"; filename="photo.jpg""#, "image/png", PNG)]);
+        assert_eq!(rule(&renamed), UploadRule::TypeMismatch);
+        let zip = form(&[(r#"name="f"; filename="doc.pdf""#, "application/pdf", b"PK\x03\x04rest")]);
+        assert_eq!(rule(&zip), UploadRule::TypeMismatch);
+        let html = form(&[(r#"name="f"; filename="page.html""#, "text/html", b"<html>")]);
+        assert_eq!(rule(&html), UploadRule::MimeNotAllowed);
+        let aliased = form(&[(r#"name="f"; filename="photo.jpg""#, "image/pjpeg", b"\xff\xd8\xff\xe0")]);
+        assert!(inspect(&aliased, 5).is_ok());
+
+        let policy = UploadPolicy {
+            max_file_size: 4,
+            ..Default::default()
+        };
+        let big = form(&[(r#"name="f"; filename="beach.png""#, "image/png", PNG)]);
+        assert_eq!(inspect_with(&policy, &big), Some(UploadRule::TooLarge));
+    }
+
+    #[test]
+    fn test_ambiguous_framing_refused() {
+        // A bare LF before the boundary: PHP would see a second part
+        let mut body = form(&[(r#"name="a""#, "", b"x")]);
+        body.splice(0..0, b"--XyZ\r\nContent-Disposition: form-data; name=\"b\"\r\n\r\nhidden\n".to_vec());
+        assert_eq!(rule(&body), UploadRule::Malformed);
+
+        let preamble = [b"junk\r\n".as_slice(), &form(&[(r#"name="a""#, "", b"x")])].concat();
+        assert_eq!(rule(&preamble), UploadRule::Malformed);
+        let folded = b"--XyZ\r\nContent-Disposition: form-data;\r\n name=\"a\"\r\n\r\nx\r\n--XyZ--".to_vec();
+        assert_eq!(rule(&folded), UploadRule::Malformed);
+        let truncated = form(&[(r#"name="a""#, "", b"x")]);
+        assert_eq!(rule(&truncated[..truncated.len() - 9]), UploadRule::Malformed);
+        let twice = form(&[(r#"name="f"; filename="a.png"; filename="b.php""#, "image/png", PNG)]);
+        assert_eq!(rule(&twice), UploadRule::Malformed);
+
+        let rules = rules();
+        assert!(rules.inspector("application/x-www-form-urlencoded").unwrap().is_none());
+        assert!(rules.inspector("multipart/form-data; boundary=\"XyZ\"").unwrap().is_some());
+        assert!(rules.inspector("multipart/form-data; xboundary=a; boundary=b").is_err());
+        assert!(rules.inspector("multipart/form-data").is_err());
+        assert!(rules.inspector("multipart/mixed; boundary=a").is_err());
+    }
+
+    #[test]
+    fn test_invalid_policy_rejected() {
+        let policy = UploadPolicy {
+            allowed_mimes: vec!["jpeg".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(UploadRules::compile(&policy), Err(UploadError::InvalidMime("jpeg".to_string())));
+        let policy = UploadPolicy {
+            blocked_extensions: vec!["php".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(UploadRules::compile(&policy), Err(UploadError::InvalidExtension("php".to_string())));
+        let policy = UploadPolicy {
+            enabled: false,
+            ..Default::default()
+        };
+        assert_eq!(UploadRules::compile(&policy), Ok(None));
+    }
+}
@@ -26,7 +26,7 @@
 use aya_ebpf::{
     bindings::xdp_action,
     macros::{map, xdp},
-    maps::HashMap,
+    maps::{HashMap, PerCpuArray},
     programs::XdpContext,
 };
 use aya_log_ebpf::info;
@@ -66,15 +66,31 @@
 #[map]
 static RATE_LIMIT: HashMap<u32, u64> = HashMap::with_max_entries(10_000, 0);
 
+/// Packet counters, indexed by XDP action (ABORTED, DROP, PASS, TX, REDIRECT)
+/// Value: u64 (packets, per CPU; userspace sums the CPUs)
+#[map]
+static PACKET_STATS: PerCpuArray<u64> = PerCpuArray::with_max_entries(5, 0);
+
 // =============================================================================
 // THE XDP PROGRAM - The Force Field
 // =============================================================================
 
 #[xdp]
 pub fn wharf_shield(ctx: XdpContext) -> u32 {
-    match try_wharf_shield(ctx) {
+    let action = match try_wharf_shield(ctx) {
         Ok(ret) => ret,
         Err(_) => xdp_action::XDP_ABORTED, // Fail-closed on error
+    };
+    count_packet(action);
+    action
+}
+
+/// Count a verdict for the agent's metrics
+#[inline(always)]
+fn count_packet(action: u32) {
+    if let Some(counter) = PACKET_STATS.get_ptr_mut(action) {
+        // Per-CPU slot: no other CPU writes it, so no atomics needed
+        unsafe { *counter += 1 };
     }
 }
 